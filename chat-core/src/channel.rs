@@ -0,0 +1,1159 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::Utc;
+use lunatic::{
+    abstract_process,
+    ap::{Config, ProcessRef},
+    Mailbox, Process,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::coordinator::JoinError;
+use crate::feed::{self, Entry, Feed, FeedError};
+use crate::fun::Game;
+use crate::protocol::{SessionEvent, SessionRef};
+use crate::storage::{
+    ChannelCheckpoint, HistoryEntry, StorageProcess, StorageProcessMessages, StorageProcessRequests,
+};
+use crate::trace;
+
+/// Returns the name under which a channel of a network is registered, e.g.
+/// `channel:default:#general`.
+///
+/// Other subsystems can use it to look up a channel directly, without going through the
+/// coordinator.
+pub fn registry_name(network: &str, channel: &str) -> String {
+    format!("channel:{}:{}", network, channel)
+}
+
+/// Marker shown after the name of users that identified with their account.
+pub const IDENTIFIED_MARKER: &str = "✓";
+
+/// Returns the name shown next to a message, identified users are marked so that impersonation of
+/// registered names is visible.
+pub fn display_name(name: &str, identified: bool) -> String {
+    if identified {
+        format!("{} {}", name, IDENTIFIED_MARKER)
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Descriptive information about a channel, set by the channel operator.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct ChannelMeta {
+    pub language: String,
+    pub description: String,
+}
+
+/// A message broadcast by a channel to all of its clients.
+///
+/// Messages are delivered to clients in batches, so that broadcasting to a big channel only clones
+/// one payload per client and batch.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatMessage {
+    pub channel: String,
+    /// Position of the message in the channel's history.
+    ///
+    /// Ids of a channel increase by one with every message, clients use them as sequence numbers.
+    pub id: u64,
+    pub timestamp: String,
+    pub name: String,
+    /// If the sender identified with the account of its name.
+    pub identified: bool,
+    /// Id of the sending session, `None` for messages of the server and the channel.
+    pub session: Option<u64>,
+    pub message: String,
+    pub trace: Option<String>,
+}
+
+/// Load of a channel, used by operators to find hot channels.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ChannelStats {
+    /// Messages received per second, measured over the last `RATE_WINDOW`.
+    pub messages_per_sec: f64,
+    /// Wall time spent delivering the last batch of messages to all clients.
+    pub fan_out: Duration,
+}
+
+/// State of a channel written to `/dump` files, used to debug state drift.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChannelSnapshot {
+    /// Ids of the member clients, sorted.
+    pub members: Vec<u64>,
+    pub operator: Option<u64>,
+    pub meta: ChannelMeta,
+    pub banned: Vec<String>,
+    /// Messages kept in the history.
+    pub history: usize,
+    pub next_message_id: u64,
+    /// Messages waiting for the next batch delivery.
+    pub pending: usize,
+}
+
+/// A message the channel repeats on its own, scheduled by the operator.
+///
+/// Announcements are sent under the name of the channel, so that they can't be mistaken for a
+/// message of a user.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Announcement {
+    /// Identifies the announcement when it's cancelled.
+    pub id: u64,
+    pub interval: Duration,
+    pub message: String,
+    /// Username of the operator that scheduled it.
+    pub by: String,
+}
+
+impl Announcement {
+    /// Parses an interval like `30m`, `1h` or `2d`.
+    pub fn parse_interval(interval: &str) -> Option<Duration> {
+        let unit = match interval.chars().last()? {
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        let count: u64 = interval[..interval.len() - 1].parse().ok()?;
+        Some(Duration::from_secs(count.checked_mul(unit)?))
+    }
+
+    /// Formats the interval the way `parse_interval` reads it.
+    pub fn interval_text(&self) -> String {
+        interval_text(self.interval)
+    }
+}
+
+/// Formats an interval the way `Announcement::parse_interval` reads it.
+pub fn interval_text(interval: Duration) -> String {
+    let minutes = interval.as_secs() / 60;
+    if minutes % (24 * 60) == 0 {
+        format!("{}d", minutes / (24 * 60))
+    } else if minutes % 60 == 0 {
+        format!("{}h", minutes / 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+/// What a ban applies to, see `ChannelProcess::ban`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ban {
+    /// The account with the banned name.
+    Account,
+    /// The sessions of the guests that used the name, until they disconnect.
+    Sessions,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum AnnouncementError {
+    /// Only the channel operator can schedule and cancel announcements.
+    NotOperator,
+    /// The channel already has `MAX_ANNOUNCEMENTS`.
+    TooMany,
+    /// The interval is shorter than `MIN_ANNOUNCEMENT_INTERVAL`.
+    TooFrequent,
+    UnknownId,
+}
+
+/// Most announcements scheduled in a channel at once.
+pub const MAX_ANNOUNCEMENTS: usize = 10;
+/// Shortest interval of an announcement.
+pub const MIN_ANNOUNCEMENT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// How long messages are counted before the message rate is updated.
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+// How many messages a channel keeps in its history.
+const HISTORY_LIMIT: usize = 1000;
+// How often a channel saves a checkpoint of its state, if it changed.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// How many of the newest messages are part of a checkpoint.
+const CHECKPOINT_MESSAGES: usize = 100;
+// How long the operator of a checkpoint keeps its claim, afterwards a member takes over.
+const OPERATOR_RESERVATION: Duration = Duration::from_secs(15 * 60);
+// Messages arriving within this time share one entry of the arrival times, retention policies
+// expire them up to this much late.
+const ARRIVAL_GRANULARITY: Duration = Duration::from_secs(60);
+// How long messages are collected before they are delivered to the clients as one batch.
+const BATCH_DELAY: Duration = Duration::from_millis(5);
+// How many batches a client can fall behind before it's skipped. A client that doesn't keep up
+// would otherwise fill its mailbox until it hits the memory limit.
+const MAX_UNACKED_BATCHES: u32 = 16;
+
+/// A channel dispatches messages to all clients that are part of it.
+///
+/// It also keeps a history of messages, so that it can bootstrap a new client that joins and
+/// clients can page back through older messages.
+///
+/// Messages are delivered to every client in the order the channel received them, lunatic keeps
+/// the order of messages between two processes. Clients still check the ids as sequence numbers:
+/// a message can be lost when a client sheds messages it can't keep up with, or repeated when it
+/// arrives while the client loads the history after joining. Missing messages are fetched again
+/// with `get_history`, repeated ones are skipped.
+///
+/// Clients acknowledge each batch once it's shown. Batches are not sent to a client that is too
+/// far behind, it's told once that it missed messages and gets batches again after it caught up.
+///
+/// Every `CHECKPOINT_INTERVAL` the channel saves a checkpoint of its state to the storage. A
+/// channel that is opened again, e.g. after the server crashed, starts from the checkpoint and
+/// only loads the messages stored after it. The operator gets its rights back when it joins
+/// again with the same account within `OPERATOR_RESERVATION`.
+///
+/// Channels with a retention policy stop handing out messages once they are older than the
+/// retention time, neither from the history nor from the storage. The arrival times and the
+/// expired ids are part of the checkpoint. Messages stored after the last checkpoint count as
+/// received when the channel is opened again.
+///
+/// Announcements are part of the checkpoint, each of them has a linked timer process that asks
+/// the channel to send it again after every interval. The timer stops once the announcement was
+/// cancelled. Feeds work the same way, their poller process is described in the `feed` module.
+pub struct ChannelProcess {
+    this: ProcessRef<ChannelProcess>,
+    name: String,
+    clients: HashMap<u64, SessionRef>,
+    // Clients that receive the messages, but are not members and don't post.
+    followers: HashMap<u64, SessionRef>,
+    // Usernames of the members and followers, as they joined or followed.
+    usernames: HashMap<u64, String>,
+    // Messages waiting for the next batch delivery, a flush is scheduled if not empty.
+    pending: Vec<ChatMessage>,
+    // Batches sent to each client that it didn't acknowledge yet.
+    unacked: HashMap<u64, u32>,
+    // Clients that were told they miss batches, until they get one again.
+    skipped: HashSet<u64>,
+    // Messages with increasing ids, the oldest first.
+    history: Vec<HistoryEntry>,
+    // Storage of the network, messages and descriptions are saved to it and loaded from it.
+    storage: ProcessRef<StorageProcess>,
+    next_message_id: u64,
+    // The client that created the channel.
+    operator: Option<u64>,
+    // Lowercase accounts of the members and followers that are identified.
+    accounts: HashMap<u64, String>,
+    // Account of the operator from the checkpoint, until it joins again.
+    restored_operator: Option<String>,
+    // The state changed since the last checkpoint.
+    changed: bool,
+    // How long messages are visible, `None` if the channel keeps them forever.
+    retention: Option<Duration>,
+    // When messages arrived, the oldest first. An entry covers all messages with a lower id that
+    // are not covered by the entry before it.
+    arrivals: VecDeque<(u64, Instant)>,
+    // Messages with a lower id expired.
+    expired_before: u64,
+    // Reserved channels are configured by the server and have no operator.
+    reserved: bool,
+    meta: ChannelMeta,
+    // Lowercase accounts that are not allowed to join or follow the channel.
+    banned: HashSet<String>,
+    // Sessions of guests that are not allowed to join or follow the channel, guests are banned
+    // by their session so that a new name doesn't get them around the ban.
+    banned_sessions: HashSet<u64>,
+    stats: ChannelStats,
+    // Start of the current message rate window and the messages received since then.
+    rate_window: (Instant, u32),
+    announcements: Vec<Announcement>,
+    next_announcement_id: u64,
+    feeds: Vec<Feed>,
+    next_feed_id: u64,
+    // The server was started with `--feeds`, see the `feed` module.
+    feeds_enabled: bool,
+}
+
+#[abstract_process(visibility = pub)]
+impl ChannelProcess {
+    #[init]
+    fn init(
+        config: Config<Self>,
+        args: (
+            String,
+            HashSet<String>,
+            ProcessRef<StorageProcess>,
+            Option<Duration>,
+            bool,
+        ),
+    ) -> Result<Self, ()> {
+        // The ban list is kept by the coordinator, so that it survives the channel being closed.
+        let (name, banned, storage, retention, feeds_enabled) = args;
+        let banned = banned
+            .into_iter()
+            .map(|username| username.to_lowercase())
+            .collect();
+        // Continue where the stored history left off, so that message ids and permalinks stay
+        // unique
+        let (mut history, restored_operator, announcements, feeds, saved_expiry) =
+            match storage.checkpoint(name.clone()) {
+                Some(checkpoint) => {
+                    let ChannelCheckpoint {
+                        next_message_id,
+                        operator,
+                        mut history,
+                        announcements,
+                        feeds,
+                        expired_before,
+                        arrivals,
+                        ..
+                    } = checkpoint;
+                    let newer = storage.load_since(name.clone(), next_message_id, HISTORY_LIMIT);
+                    // Too many messages since the checkpoint would leave a gap
+                    if newer.len() == HISTORY_LIMIT {
+                        history.clear();
+                    }
+                    history.extend(newer);
+                    let expiry = (expired_before, arrivals);
+                    (history, operator, announcements, feeds, expiry)
+                }
+                None => (
+                    storage.load_recent(name.clone(), HISTORY_LIMIT),
+                    None,
+                    Vec::new(),
+                    Vec::new(),
+                    (0, Vec::new()),
+                ),
+            };
+        let next_announcement_id = announcements
+            .iter()
+            .map(|announcement| announcement.id + 1)
+            .max()
+            .unwrap_or(0);
+        let next_feed_id = feeds.iter().map(|feed| feed.id + 1).max().unwrap_or(0);
+        let next_message_id = history.last().map_or(0, |(id, _)| id + 1);
+        let mut arrivals = VecDeque::new();
+        let mut expired_before = 0;
+        if let Some(retention) = retention {
+            let (saved_expired_before, saved_arrivals) = saved_expiry;
+            expired_before = saved_expired_before;
+            // Arrivals that expired while the channel was closed are applied right away
+            for (end, arrived) in saved_arrivals {
+                match instant_of(arrived) {
+                    Some(arrived) if arrived.elapsed() < retention + ARRIVAL_GRANULARITY => {
+                        arrivals.push_back((end, arrived))
+                    }
+                    _ => expired_before = expired_before.max(end),
+                }
+            }
+            arrivals.push_back((next_message_id, Instant::now()));
+            history.retain(|(id, _)| *id >= expired_before);
+        }
+        let meta = storage.channel_meta(name.clone()).unwrap_or_default();
+        // Linked to the channel, the timer dies together with it
+        Process::spawn_link(config.self_ref(), |channel, _: Mailbox<()>| loop {
+            lunatic::sleep(CHECKPOINT_INTERVAL);
+            channel.checkpoint();
+        });
+        if restored_operator.is_some() {
+            Process::spawn_link(config.self_ref(), |channel, _: Mailbox<()>| {
+                lunatic::sleep(OPERATOR_RESERVATION);
+                channel.release_operator();
+            });
+        }
+        let channel = ChannelProcess {
+            this: config.self_ref(),
+            name,
+            clients: HashMap::new(),
+            followers: HashMap::new(),
+            usernames: HashMap::new(),
+            pending: Vec::new(),
+            unacked: HashMap::new(),
+            skipped: HashSet::new(),
+            history,
+            storage,
+            next_message_id,
+            operator: None,
+            accounts: HashMap::new(),
+            restored_operator,
+            changed: false,
+            retention,
+            arrivals,
+            expired_before,
+            reserved: false,
+            meta,
+            banned,
+            banned_sessions: HashSet::new(),
+            stats: ChannelStats::default(),
+            rate_window: (Instant::now(), 0),
+            announcements,
+            next_announcement_id,
+            feeds,
+            next_feed_id,
+            feeds_enabled,
+        };
+        for announcement in &channel.announcements {
+            channel.start_timer(announcement);
+        }
+        // Feeds of a checkpoint are kept, but only polled while the server allows feeds
+        if feeds_enabled {
+            for feed in &channel.feeds {
+                channel.start_poller(feed);
+            }
+        }
+        Ok(channel)
+    }
+
+    /// join the channel.
+    ///
+    /// The first client to join becomes the channel operator, unless the channel is reserved or
+    /// the operator of the checkpoint didn't join again yet. `account` is the account the client
+    /// identified with, as told by the directory. Fails if the account or session is banned.
+    #[handle_request]
+    fn join(
+        &mut self,
+        client: SessionRef,
+        username: String,
+        account: Option<String>,
+    ) -> Result<(), JoinError> {
+        let account = account.filter(|account| *account == username.to_lowercase());
+        if self.is_banned(client.id(), account.as_ref()) {
+            return Err(JoinError::Banned);
+        }
+        if account.is_some() && account == self.restored_operator {
+            self.restored_operator = None;
+            self.operator = Some(client.id());
+        } else if !self.reserved && self.restored_operator.is_none() {
+            self.operator.get_or_insert(client.id());
+        }
+        match account {
+            Some(account) => self.accounts.insert(client.id(), account),
+            None => self.accounts.remove(&client.id()),
+        };
+        self.changed = true;
+        // A follower that joins gets the messages only once
+        self.followers.remove(&client.id());
+        self.usernames.insert(client.id(), username);
+        self.clients.insert(client.id(), client);
+        Ok(())
+    }
+
+    /// Receive the channel's messages without becoming a member.
+    ///
+    /// Returns `false` if the account or session is banned.
+    #[handle_request]
+    fn follow(&mut self, client: SessionRef, username: String, account: Option<String>) -> bool {
+        let account = account.filter(|account| *account == username.to_lowercase());
+        if self.is_banned(client.id(), account.as_ref()) {
+            return false;
+        }
+        match account {
+            Some(account) => self.accounts.insert(client.id(), account),
+            None => self.accounts.remove(&client.id()),
+        };
+        self.usernames.insert(client.id(), username);
+        self.followers.insert(client.id(), client);
+        true
+    }
+
+    /// A member or follower identified with an account or dropped the identification, see
+    /// `DirectoryProcess::set_account`.
+    #[handle_message]
+    fn set_account(&mut self, client: SessionRef, account: Option<String>) {
+        let member = self.clients.contains_key(&client.id());
+        if !member && !self.followers.contains_key(&client.id()) {
+            return;
+        }
+        match account {
+            Some(account) => {
+                if member && Some(&account) == self.restored_operator.as_ref() {
+                    self.restored_operator = None;
+                    self.operator = Some(client.id());
+                }
+                self.accounts.insert(client.id(), account);
+            }
+            None => {
+                self.accounts.remove(&client.id());
+            }
+        }
+        self.changed = true;
+    }
+
+    /// Warn members and followers that the temporary channel is closed in `minutes`.
+    #[handle_message]
+    fn warn_expiry(&mut self, minutes: u64) {
+        let event = SessionEvent::ChannelExpiring {
+            channel: self.name.clone(),
+            minutes,
+        };
+        for client in self.clients.values().chain(self.followers.values()) {
+            client.send(event.clone());
+        }
+    }
+
+    /// Tell members and followers that the temporary channel is closed, the directory shuts it
+    /// down afterwards.
+    #[handle_message]
+    fn close_expired(&mut self) {
+        let event = SessionEvent::ChannelExpired(self.name.clone());
+        for client in self.clients.values().chain(self.followers.values()) {
+            client.send(event.clone());
+        }
+    }
+
+    /// Tell the followers that the channel is shut down, it has no members left.
+    #[handle_message]
+    fn close(&mut self) {
+        let event = SessionEvent::ChannelClosed(self.name.clone());
+        for client in self.followers.values() {
+            client.send(event.clone());
+        }
+    }
+
+    /// leave the channel, as a member or a follower.
+    #[handle_message]
+    fn leave(&mut self, client: SessionRef) {
+        self.clients.remove(&client.id());
+        self.remove(client.id());
+        self.changed = true;
+    }
+
+    /// The client showed a batch of messages.
+    #[handle_message]
+    fn ack(&mut self, client: SessionRef) {
+        if let Some(unacked) = self.unacked.get_mut(&client.id()) {
+            *unacked = unacked.saturating_sub(1);
+        }
+    }
+
+    /// Returns the number of clients that are part of the channel, followers don't count.
+    #[handle_request]
+    fn member_count(&mut self) -> usize {
+        self.clients.len()
+    }
+
+    /// Returns up to `limit` messages older than the message with id `before`, or the newest
+    /// messages if `before` is `None`.
+    ///
+    /// Messages are returned with their ids, the oldest first. An empty result means that there
+    /// is no older history.
+    #[handle_request]
+    fn get_history(&mut self, before: Option<u64>, limit: usize) -> Vec<HistoryEntry> {
+        self.expire();
+        let end = match before {
+            Some(before) => self.history.partition_point(|(id, _)| *id < before),
+            None => self.history.len(),
+        };
+        let start = end.saturating_sub(limit);
+        let mut page = self.history[start..end].to_vec();
+        // Messages that were dropped from the history are still in the storage
+        let oldest = page
+            .first()
+            .map(|(id, _)| *id)
+            .or(before)
+            .unwrap_or(self.next_message_id);
+        if page.len() < limit && oldest > self.expired_before {
+            let mut older = self
+                .storage
+                .load_range(self.name.clone(), oldest, limit - page.len());
+            let expired_before = self.expired_before;
+            older.retain(|(id, _)| *id >= expired_before);
+            older.append(&mut page);
+            page = older;
+        }
+        page
+    }
+
+    /// Returns the id of the oldest message that is still visible, if older messages expired by
+    /// the channel's retention policy.
+    ///
+    /// Clients show that the history was cut off instead of paging further back.
+    #[handle_request]
+    fn expired_before(&mut self) -> Option<u64> {
+        self.expire();
+        (self.expired_before > 0).then(|| self.expired_before)
+    }
+
+    #[handle_request]
+    fn get_meta(&mut self) -> ChannelMeta {
+        self.meta.clone()
+    }
+
+    #[handle_request]
+    fn get_stats(&mut self) -> ChannelStats {
+        self.update_rate();
+        self.stats.clone()
+    }
+
+    #[handle_request]
+    fn snapshot(&mut self) -> ChannelSnapshot {
+        let mut members: Vec<u64> = self.clients.keys().copied().collect();
+        members.sort_unstable();
+        let mut banned: Vec<String> = self.banned.iter().cloned().collect();
+        banned.sort();
+        ChannelSnapshot {
+            members,
+            operator: self.operator,
+            meta: self.meta.clone(),
+            banned,
+            history: self.history.len(),
+            next_message_id: self.next_message_id,
+            pending: self.pending.len(),
+        }
+    }
+
+    /// The operator of the checkpoint didn't come back in time, the member that is connected the
+    /// longest becomes the operator.
+    #[handle_message]
+    fn release_operator(&mut self) {
+        if self.restored_operator.take().is_none() || self.reserved {
+            return;
+        }
+        // Session ids grow with every connection
+        self.operator = self.clients.keys().min().copied();
+        self.changed = true;
+    }
+
+    /// Make this a reserved channel with the topic configured by the server.
+    #[handle_message]
+    fn reserve(&mut self, meta: ChannelMeta) {
+        self.reserved = true;
+        self.operator = None;
+        self.restored_operator = None;
+        self.meta = meta;
+    }
+
+    /// Save the state of the channel to the storage, if it changed since the last checkpoint.
+    #[handle_message]
+    fn checkpoint(&mut self) {
+        self.expire();
+        if !std::mem::take(&mut self.changed) {
+            return;
+        }
+        // An operator that didn't come back yet keeps its claim
+        let operator = self
+            .operator
+            .and_then(|id| self.accounts.get(&id).cloned())
+            .or_else(|| self.restored_operator.clone());
+        let start = self.history.len().saturating_sub(CHECKPOINT_MESSAGES);
+        let arrivals = self
+            .arrivals
+            .iter()
+            .map(|(end, arrived)| (*end, unix_seconds(*arrived)))
+            .collect();
+        let checkpoint = ChannelCheckpoint {
+            next_message_id: self.next_message_id,
+            operator,
+            history: self.history[start..].to_vec(),
+            announcements: self.announcements.clone(),
+            feeds: self.feeds.clone(),
+            expired_before: self.expired_before,
+            arrivals,
+        };
+        self.storage.save_checkpoint(self.name.clone(), checkpoint);
+    }
+
+    #[handle_message]
+    fn crash(&mut self) {
+        panic!("channel crashed on request");
+    }
+
+    /// Change the language and description of the channel.
+    ///
+    /// Returns `false` if the client is not the channel operator.
+    #[handle_request]
+    fn describe(&mut self, client: SessionRef, meta: ChannelMeta) -> bool {
+        if self.operator != Some(client.id()) {
+            return false;
+        }
+        self.storage
+            .save_channel_meta(self.name.clone(), meta.clone());
+        self.meta = meta;
+        true
+    }
+
+    #[handle_request]
+    fn is_operator(&mut self, client: SessionRef) -> bool {
+        self.operator == Some(client.id())
+    }
+
+    /// Ban a user from the channel, banned members and followers are removed from it.
+    ///
+    /// Guests that are part of the channel under the name are banned by their sessions, otherwise
+    /// the account with the name is banned. Returns `None` if the client is not the channel
+    /// operator.
+    #[handle_request]
+    fn ban(&mut self, client: SessionRef, username: String) -> Option<Ban> {
+        if self.operator != Some(client.id()) {
+            return None;
+        }
+        Some(self.ban_name(&username))
+    }
+
+    /// Send `message` under the name of the channel every `interval`, returns the id of the
+    /// announcement.
+    #[handle_request]
+    fn schedule_announcement(
+        &mut self,
+        client: SessionRef,
+        username: String,
+        interval: Duration,
+        message: String,
+    ) -> Result<u64, AnnouncementError> {
+        if self.operator != Some(client.id()) {
+            return Err(AnnouncementError::NotOperator);
+        }
+        if self.announcements.len() >= MAX_ANNOUNCEMENTS {
+            return Err(AnnouncementError::TooMany);
+        }
+        if interval < MIN_ANNOUNCEMENT_INTERVAL {
+            return Err(AnnouncementError::TooFrequent);
+        }
+        let announcement = Announcement {
+            id: self.next_announcement_id,
+            interval,
+            message,
+            by: username,
+        };
+        self.next_announcement_id += 1;
+        self.start_timer(&announcement);
+        self.announcements.push(announcement.clone());
+        // Saved right away, an announcement shouldn't get lost if the server crashes soon
+        self.changed = true;
+        self.checkpoint();
+        Ok(announcement.id)
+    }
+
+    #[handle_request]
+    fn announcements(&mut self) -> Vec<Announcement> {
+        self.announcements.clone()
+    }
+
+    #[handle_request]
+    fn cancel_announcement(
+        &mut self,
+        client: SessionRef,
+        id: u64,
+    ) -> Result<(), AnnouncementError> {
+        if self.operator != Some(client.id()) {
+            return Err(AnnouncementError::NotOperator);
+        }
+        let before = self.announcements.len();
+        self.announcements
+            .retain(|announcement| announcement.id != id);
+        if self.announcements.len() == before {
+            return Err(AnnouncementError::UnknownId);
+        }
+        self.changed = true;
+        self.checkpoint();
+        Ok(())
+    }
+
+    /// Send the announcement again, returns `false` if it was cancelled.
+    #[handle_request]
+    fn announce(&mut self, id: u64) -> bool {
+        let message = match self
+            .announcements
+            .iter()
+            .find(|announcement| announcement.id == id)
+        {
+            Some(announcement) => announcement.message.clone(),
+            None => return false,
+        };
+        let timestamp = format!("[{}] ", Utc::now().format("%H:%M UTC"));
+        let channel = self.name.clone();
+        self.broadcast_message(channel.clone(), timestamp, channel, None, message, None);
+        true
+    }
+
+    /// Poll the feed at `url` every `interval` and post its new entries, returns the id of the
+    /// feed.
+    #[handle_request]
+    fn add_feed(
+        &mut self,
+        client: SessionRef,
+        username: String,
+        url: String,
+        interval: Duration,
+    ) -> Result<u64, FeedError> {
+        if !self.feeds_enabled {
+            return Err(FeedError::Disabled);
+        }
+        if self.operator != Some(client.id()) {
+            return Err(FeedError::NotOperator);
+        }
+        if self.feeds.len() >= feed::MAX_FEEDS {
+            return Err(FeedError::TooMany);
+        }
+        if interval < feed::MIN_FEED_INTERVAL {
+            return Err(FeedError::TooFrequent);
+        }
+        if !feed::is_valid_url(&url) {
+            return Err(FeedError::InvalidUrl);
+        }
+        let feed = Feed {
+            id: self.next_feed_id,
+            url,
+            interval,
+            by: username,
+        };
+        self.next_feed_id += 1;
+        self.start_poller(&feed);
+        self.feeds.push(feed.clone());
+        self.changed = true;
+        self.checkpoint();
+        Ok(feed.id)
+    }
+
+    #[handle_request]
+    fn feeds(&mut self) -> Vec<Feed> {
+        self.feeds.clone()
+    }
+
+    #[handle_request]
+    fn remove_feed(&mut self, client: SessionRef, id: u64) -> Result<(), FeedError> {
+        if self.operator != Some(client.id()) {
+            return Err(FeedError::NotOperator);
+        }
+        let before = self.feeds.len();
+        self.feeds.retain(|feed| feed.id != id);
+        if self.feeds.len() == before {
+            return Err(FeedError::UnknownId);
+        }
+        self.changed = true;
+        self.checkpoint();
+        Ok(())
+    }
+
+    /// Send new entries of a feed, the oldest first. Returns `false` if the feed was removed.
+    #[handle_request]
+    fn post_feed_entries(&mut self, id: u64, entries: Vec<Entry>) -> bool {
+        if !self.feeds.iter().any(|feed| feed.id == id) {
+            return false;
+        }
+        let timestamp = format!("[{}] ", Utc::now().format("%H:%M UTC"));
+        let channel = self.name.clone();
+        for entry in entries {
+            let message = format!("{} {}", entry.title, entry.link);
+            let message = message.trim().to_owned();
+            self.broadcast_message(
+                channel.clone(),
+                timestamp.clone(),
+                channel.clone(),
+                None,
+                message,
+                None,
+            );
+        }
+        true
+    }
+
+    /// Ban a username without being asked by the operator, see `DirectoryProcess::force_ban`.
+    #[handle_message]
+    fn force_ban(&mut self, username: String) {
+        // The account is banned too, the ban is persisted under the name
+        self.banned.insert(username.to_lowercase());
+        self.ban_name(&username);
+    }
+
+    /// Reply to the client through the same path as broadcast messages.
+    ///
+    /// Used by `/ping` to measure the channel's echo latency.
+    #[handle_message]
+    fn ping(&mut self, client: SessionRef, channel: String) {
+        client.send(SessionEvent::Pong(channel));
+    }
+
+    /// Sent a new message to the channel.
+    ///
+    /// `sender` is the session that sent it, `None` for messages of the server. Only messages of
+    /// members identified with the account of `name` are marked as identified.
+    #[handle_message]
+    fn broadcast_message(
+        &mut self,
+        channel: String,
+        timestamp: String,
+        name: String,
+        sender: Option<SessionRef>,
+        message: String,
+        trace: Option<String>,
+    ) {
+        trace::log(&trace, "channel");
+        let identified = sender
+            .and_then(|sender| self.accounts.get(&sender.id()))
+            .map_or(false, |account| *account == name.to_lowercase());
+        self.changed = true;
+        self.rate_window.1 += 1;
+        self.update_rate();
+        // Save
+        let id = self.next_message_id;
+        self.next_message_id += 1;
+        let entry = (
+            id,
+            (timestamp.clone(), name.clone(), message.clone(), identified),
+        );
+        self.storage.append_message(channel.clone(), entry.clone());
+        self.history.push(entry);
+        // If the history is too long, drain
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.drain(0..HISTORY_LIMIT / 2);
+        }
+        if self.retention.is_some() {
+            match self.arrivals.back_mut() {
+                Some((end, arrived)) if arrived.elapsed() < ARRIVAL_GRANULARITY => *end = id + 1,
+                _ => self.arrivals.push_back((id + 1, Instant::now())),
+            }
+            self.expire();
+        }
+        // Messages arriving in quick succession are delivered together, so that clients only
+        // re-render once per batch.
+        if self.pending.is_empty() {
+            Process::spawn_link(self.this, |channel, _: Mailbox<()>| {
+                lunatic::sleep(BATCH_DELAY);
+                channel.flush();
+            });
+        }
+        self.pending.push(ChatMessage {
+            channel,
+            id,
+            timestamp,
+            name,
+            identified,
+            session: sender.map(|sender| sender.id()),
+            message,
+            trace,
+        });
+    }
+
+    /// Play a game for a member and post the result like an announcement, from the channel
+    /// itself, see `fun`.
+    #[handle_message]
+    fn play(&mut self, client: SessionRef, timestamp: String, name: String, game: Game) {
+        if !self.clients.contains_key(&client.id()) || !game.is_valid() {
+            return;
+        }
+        let message = format!("{} {}", name, game.play());
+        let channel = self.name.clone();
+        self.broadcast_message(channel.clone(), timestamp, channel, None, message, None);
+    }
+
+    /// Deliver all pending messages to the clients.
+    #[handle_message]
+    fn flush(&mut self) {
+        let batch = std::mem::take(&mut self.pending);
+        if batch.is_empty() {
+            return;
+        }
+        // The last client gets the batch without a clone. Clients that are too far behind skip
+        // it, only sent batches count until they are acknowledged.
+        let start = Instant::now();
+        let name = &self.name;
+        let unacked = &mut self.unacked;
+        let skipped = &mut self.skipped;
+        let mut clients = self
+            .clients
+            .values()
+            .chain(self.followers.values())
+            .filter(|client| {
+                let count = unacked.entry(client.id()).or_default();
+                if *count >= MAX_UNACKED_BATCHES {
+                    if skipped.insert(client.id()) {
+                        client.send(SessionEvent::MessagesSkipped(name.clone()));
+                    }
+                    return false;
+                }
+                *count += 1;
+                skipped.remove(&client.id());
+                true
+            })
+            .peekable();
+        while let Some(client) = clients.next() {
+            if clients.peek().is_some() {
+                client.send(SessionEvent::Messages(batch.clone()));
+            } else {
+                client.send(SessionEvent::Messages(batch));
+                break;
+            }
+        }
+        self.stats.fan_out = start.elapsed();
+    }
+}
+
+impl ChannelProcess {
+    // Forget a member or follower.
+    fn remove(&mut self, id: u64) {
+        self.clients.remove(&id);
+        self.followers.remove(&id);
+        self.usernames.remove(&id);
+        self.unacked.remove(&id);
+        self.skipped.remove(&id);
+        self.accounts.remove(&id);
+    }
+
+    // Returns `true` if the session or the lowercase account it identified with is banned.
+    fn is_banned(&self, id: u64, account: Option<&String>) -> bool {
+        self.banned_sessions.contains(&id)
+            || account.map_or(false, |account| self.banned.contains(account))
+    }
+
+    // Ban the guests using the name by their sessions, or the account with the name if there are
+    // none. The banned members and followers are removed and told about it.
+    fn ban_name(&mut self, username: &str) -> Ban {
+        let username = username.to_lowercase();
+        let guests: Vec<u64> = self
+            .usernames
+            .iter()
+            .filter(|(id, name)| {
+                name.to_lowercase() == username && !self.accounts.contains_key(*id)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        let ban = if guests.is_empty() {
+            self.banned.insert(username);
+            Ban::Account
+        } else {
+            self.banned_sessions.extend(guests);
+            Ban::Sessions
+        };
+        let banned: Vec<u64> = self
+            .clients
+            .keys()
+            .chain(self.followers.keys())
+            .filter(|id| self.is_banned(**id, self.accounts.get(*id)))
+            .copied()
+            .collect();
+        for id in banned {
+            if let Some(client) = self.clients.get(&id).or_else(|| self.followers.get(&id)) {
+                client.send(SessionEvent::Banned(self.name.clone()));
+            }
+            self.remove(id);
+            self.changed = true;
+        }
+        ban
+    }
+
+    // Start the linked process that sends the announcement after every interval.
+    fn start_timer(&self, announcement: &Announcement) {
+        Process::spawn_link(
+            (self.this, announcement.id, announcement.interval),
+            |(channel, id, interval), _: Mailbox<()>| loop {
+                lunatic::sleep(interval);
+                if !channel.announce(id) {
+                    break;
+                }
+            },
+        );
+    }
+
+    // Start the linked process that polls the feed after every interval. Errors of a poll are
+    // ignored, the feed is tried again after the next interval.
+    fn start_poller(&self, feed: &Feed) {
+        Process::spawn_link(
+            (self.this, feed.id, feed.url.clone(), feed.interval),
+            |(channel, id, url, interval), _: Mailbox<()>| {
+                let ids = |entries: &[Entry]| -> HashSet<String> {
+                    entries.iter().map(|entry| entry.id.clone()).collect()
+                };
+                let mut seen = feed::fetch(&url)
+                    .map(|entries| ids(&entries))
+                    .unwrap_or_default();
+                loop {
+                    lunatic::sleep(interval);
+                    let entries = feed::fetch(&url).unwrap_or_default();
+                    // Until a poll succeeded, all entries count as already posted
+                    let mut new: Vec<Entry> = entries
+                        .iter()
+                        .filter(|entry| !seen.is_empty() && !seen.contains(&entry.id))
+                        .take(feed::MAX_ENTRIES_PER_POLL)
+                        .cloned()
+                        .collect();
+                    // Feeds list the newest entries first
+                    new.reverse();
+                    // Only the entries of the last successful poll are remembered
+                    if !entries.is_empty() {
+                        seen = ids(&entries);
+                    }
+                    if !channel.post_feed_entries(id, new) {
+                        break;
+                    }
+                }
+            },
+        );
+    }
+
+    // Calculate the message rate once the current window is over and start a new one.
+    fn update_rate(&mut self) {
+        let (start, messages) = self.rate_window;
+        let elapsed = start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            self.stats.messages_per_sec = messages as f64 / elapsed.as_secs_f64();
+            self.rate_window = (Instant::now(), 0);
+        }
+    }
+
+    // Drop the messages that are older than the retention time from the history.
+    //
+    // The time of an entry is the arrival of its oldest message, the entry expires once all of
+    // its messages are older than the retention time.
+    fn expire(&mut self) {
+        let retention = match self.retention {
+            Some(retention) => retention + ARRIVAL_GRANULARITY,
+            None => return,
+        };
+        while let Some((end, arrived)) = self.arrivals.front() {
+            if arrived.elapsed() < retention {
+                break;
+            }
+            self.expired_before = *end;
+            self.arrivals.pop_front();
+        }
+        let expired_before = self.expired_before;
+        let expired = self.history.partition_point(|(id, _)| *id < expired_before);
+        if expired > 0 {
+            self.history.drain(..expired);
+            self.changed = true;
+        }
+    }
+}
+
+// Returns the Unix timestamp in seconds of an instant in the past, arrivals are saved like this.
+fn unix_seconds(instant: Instant) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.saturating_sub(instant.elapsed()).as_secs()
+}
+
+// Returns the instant of a saved Unix timestamp, `None` if it's too long ago to be represented.
+fn instant_of(seconds: u64) -> Option<Instant> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Instant::now().checked_sub(now.saturating_sub(Duration::from_secs(seconds)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_interval_units() {
+        assert_eq!(
+            Announcement::parse_interval("30m"),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(
+            Announcement::parse_interval("1h"),
+            Some(Duration::from_secs(60 * 60))
+        );
+        assert_eq!(
+            Announcement::parse_interval("2d"),
+            Some(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn parse_interval_rejects_invalid_intervals() {
+        for invalid in ["", "m", "5x", "1.5h", "-1h", "5é"] {
+            assert_eq!(Announcement::parse_interval(invalid), None, "{}", invalid);
+        }
+        let overflow = format!("{}d", u64::MAX);
+        assert_eq!(Announcement::parse_interval(&overflow), None);
+    }
+
+    #[test]
+    fn interval_text_reads_back() {
+        for interval in ["45m", "90m", "3h", "7d"] {
+            let parsed = Announcement::parse_interval(interval).unwrap();
+            assert_eq!(interval_text(parsed), interval);
+        }
+    }
+}
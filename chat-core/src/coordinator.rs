@@ -0,0 +1,1961 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::{
+    channel::{self, ChannelMeta, ChannelProcess, ChannelProcessMessages, ChannelSnapshot},
+    directory::{
+        self, Directory, DirectoryProcess, DirectoryProcessMessages, DirectoryProcessRequests,
+    },
+    event_log::{
+        CoordinatorEvent, EventLogProcess, EventLogProcessMessages, EventLogProcessRequests,
+    },
+    filter::{self, FilterRule},
+    geoip::{self, GeoIpProcess, Location},
+    mailbox::{self, DirectMessage, MailboxProcess, MailboxProcessMessages},
+    metrics::{self, MetricsProcess, RequestTimer},
+    motd,
+    password::PasswordHash,
+    protocol::{SessionEvent, SessionRef},
+    storage::{
+        self, ConnectionRecord, ModerationAction, StorageConfig, StorageProcess,
+        StorageProcessMessages, StorageProcessRequests,
+    },
+    templates::CustomTemplates,
+    trace,
+};
+
+use lunatic::{
+    abstract_process,
+    ap::{Config, ProcessRef},
+    host,
+    supervisor::Supervisor,
+    AbstractProcess, Mailbox, Process,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Info {
+    pub username: String,
+    pub total_clients: usize,
+    /// Message of the day generated by the MOTD plugin, empty if there is none.
+    pub motd: String,
+    /// If clients should attach trace ids to their messages.
+    pub trace: bool,
+    /// Word filter rules applied to all sent messages.
+    pub filters: Vec<FilterRule>,
+    /// Channel that new clients join right after connecting.
+    pub auto_join: Option<String>,
+    /// Screens customized by the server operator.
+    pub templates: CustomTemplates,
+}
+
+/// How often clients let the coordinator know that they are still alive.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+// How often the coordinator cleans up after clients that died unexpectedly.
+const GC_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the channel list is pushed to subscribed clients.
+pub const LIST_PUSH_INTERVAL: Duration = Duration::from_secs(5);
+// How often the message of the day is regenerated by the plugin.
+const MOTD_INTERVAL: Duration = Duration::from_secs(300);
+// How many connections `/history` lists.
+const CONNECTION_HISTORY_LIMIT: usize = 50;
+// The period that `channels_created_per_hour` applies to.
+const CREATION_WINDOW: Duration = Duration::from_secs(60 * 60);
+// Password checks a session gets before it has to wait between them, see `password`.
+const FREE_PASSWORD_ATTEMPTS: u32 = 3;
+// How long a session waits after its first extra password check, doubled with each further one
+// up to `MAX_PASSWORD_BACKOFF`.
+const PASSWORD_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_PASSWORD_BACKOFF: Duration = Duration::from_secs(10 * 60);
+/// Returns the name under which the coordinator of a network is registered.
+pub fn registry_name(network: &str) -> String {
+    format!("coordinator:{}", network)
+}
+
+/// Returns the name under which the event log of a network is registered.
+pub fn event_log_name(network: &str) -> String {
+    format!("coordinator_events:{}", network)
+}
+
+/// Settings of a chat network, each network has its own coordinator.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CoordinatorConfig {
+    /// Name of the network, the coordinator and its channels are registered under it.
+    pub network: String,
+    /// How many channels a client can be part of at the same time.
+    pub max_channels_per_client: usize,
+    /// Lower channel limit for guests, clients that are not identified with an account.
+    pub max_channels_per_guest: Option<usize>,
+    /// How many messages of a channel's history guests can scroll back, unlimited if `None`.
+    pub guest_history: Option<usize>,
+    /// How many new channels a client can create within an hour, 0 disables the limit.
+    /// Administrators are not limited.
+    pub channels_created_per_hour: usize,
+    /// Password that grants a client administrator rights, `None` disables administrators.
+    pub admin_password: Option<String>,
+    /// Named groups of channels that administrators can broadcast to, e.g. `announcements`.
+    pub groups: HashMap<String, Vec<String>>,
+    /// Channel that new clients join right after connecting, e.g. a reserved `#lobby`.
+    pub auto_join: Option<String>,
+    /// Channels that always exist with their topic, they are never closed when empty.
+    pub reserved_channels: Vec<(String, String)>,
+    /// How long the messages of a channel are kept visible, channels without a policy keep them
+    /// forever.
+    pub retention: HashMap<String, Duration>,
+    /// Let channel operators add RSS and Atom feeds, see the `feed` module.
+    pub feeds: bool,
+    /// Path to a WASM module generating the message of the day, see the `motd` module.
+    pub motd_plugin: Option<String>,
+    /// Path to the word filter rules, see the `filter` module.
+    pub filter_file: Option<String>,
+    /// Directory with custom screens, see the `templates` module.
+    pub template_dir: Option<String>,
+    /// Where messages, channel descriptions and accounts are kept, see the `storage` module.
+    pub storage: StorageConfig,
+    /// Path to the MMDB file that client addresses are located in, see the `geoip` module.
+    pub geoip: Option<String>,
+    /// Log trace ids of all messages, see the `trace` module.
+    pub trace: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum JoinError {
+    /// The client already joined the maximum number of channels.
+    TooManyChannels(usize),
+    /// The guest already joined the maximum number of channels for guests, identified clients
+    /// can join more.
+    GuestChannelLimit(usize),
+    /// The client created `limit` channels within the last hour, it can create the next one in
+    /// `minutes`.
+    CreationLimit { limit: usize, minutes: u64 },
+    /// The client's account or session is banned from the channel.
+    Banned,
+    /// The directory shard in charge of the channel is not running, e.g. while it's restarted.
+    Unavailable,
+    /// The coordinator doesn't know the client, e.g. because it was restarted in the meantime.
+    UnknownClient,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum BroadcastError {
+    /// Only administrators can broadcast to groups.
+    NotAdmin,
+    /// There is no group with this name.
+    UnknownGroup,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum DirectMessageError {
+    /// Nobody is connected under this name and it's not registered.
+    UnknownUser,
+    /// The recipient blocked the sender.
+    Blocked,
+    /// Encrypted messages need both users to be identified and connected.
+    NotSecure,
+}
+
+/// Preferences kept with an account, its sessions load them after `identify`.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct AccountSettings {
+    /// Commands defined with `/alias`, without the leading `/`, and what they expand to.
+    pub aliases: BTreeMap<String, String>,
+    /// Messages are shown without formatting, see `/format`.
+    pub plain: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum PasswordError {
+    /// No account is registered under the name.
+    UnknownAccount,
+    /// The session checked too many passwords without identifying, it has to wait.
+    TooManyAttempts,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum BlockError {
+    /// Only identified clients have a block list.
+    NotIdentified,
+    /// Nobody is connected under this name and it's not registered.
+    UnknownUser,
+}
+
+/// Blocked users of an account, or the part of them a `/block` or `/unblock` changed.
+///
+/// Registered users are blocked by their account, guests by their sessions, so that they can't
+/// get around the block by changing their name.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct BlockList {
+    /// Lowercase accounts, blocked in all of their sessions.
+    pub accounts: Vec<String>,
+    /// Sessions of guests, blocked until they disconnect.
+    pub sessions: Vec<u64>,
+}
+
+/// A connection of a user, listed by `/whois`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Connection {
+    /// Id of the session.
+    pub id: u64,
+    pub address: SocketAddr,
+    pub location: Option<Location>,
+    pub identified: bool,
+    /// Joined channels, sorted.
+    pub channels: Vec<String>,
+}
+
+/// Why a username chosen when joining the server was refused.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum UsernameError {
+    /// Another client uses the name.
+    Taken,
+    /// The name is empty, too long, contains other characters than letters, digits, `_` and `-`,
+    /// or looks like a generated `user_<n>` name.
+    Invalid,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum WhoisError {
+    /// Only administrators can look up users.
+    NotAdmin,
+    /// Nobody uses the name.
+    UnknownUser,
+}
+
+/// A connection of an account, listed by `/sessions`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    /// Id of the session.
+    pub id: u64,
+    /// If this is the session that asked.
+    pub current: bool,
+    /// Joined channels, sorted.
+    pub channels: Vec<String>,
+}
+
+/// The membership state of a session, returned by `snapshot_session`.
+///
+/// A client that is restarted after a crash restores its username, rights and tabs from it.
+/// Messages it missed are fetched from each channel, starting after the last id it saw.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SessionSnapshot {
+    pub username: String,
+    pub admin: bool,
+    pub identified: bool,
+    /// Joined channels, sorted.
+    pub channels: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum RehashError {
+    /// Only administrators can reload the configuration.
+    NotAdmin,
+    /// The filter file can't be loaded, the old rules stay active.
+    InvalidFilters(String),
+    /// The template directory can't be loaded, the old templates stay active.
+    InvalidTemplates(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum ConnectionsError {
+    /// Only administrators can see the connection history.
+    NotAdmin,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum DumpError {
+    /// Only administrators can dump the state.
+    NotAdmin,
+    /// The dump file can't be written.
+    Io(String),
+}
+
+/// A process that `/crash` panics on purpose, to exercise the supervision and cleanup paths.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum CrashTarget {
+    Channel(String),
+    /// A client by username.
+    Client(String),
+    Coordinator,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum CrashError {
+    /// Only administrators can crash processes.
+    NotAdmin,
+    /// Crashing processes is only possible in debug builds.
+    Disabled,
+    /// The channel or user doesn't exist.
+    UnknownTarget,
+}
+
+/// A client as seen by the coordinator, part of a `Snapshot`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClientSnapshot {
+    pub id: u64,
+    pub username: String,
+    pub address: SocketAddr,
+    pub location: Option<Location>,
+    pub admin: bool,
+    pub identified: bool,
+    pub missed_heartbeat: bool,
+    pub channels: Vec<String>,
+}
+
+/// State of the coordinator and all channels, written to a JSON file by `/dump`.
+///
+/// The coordinator's and the channels' view are taken one after the other, so a snapshot of a
+/// busy server can be slightly off. Consistent differences point to state drift.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub network: String,
+    pub total_clients: usize,
+    pub total_channels: usize,
+    pub accounts: usize,
+    pub list_subscribers: usize,
+    /// Clients sorted by id.
+    pub clients: Vec<ClientSnapshot>,
+    pub channels: BTreeMap<String, ChannelSnapshot>,
+}
+
+/// Something that happened on the server, pushed to clients that subscribed with
+/// `subscribe_events`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum ServerEvent {
+    UserJoined(String),
+    UserLeft(String),
+    ChannelCreated(String),
+    /// The number of connected users changed.
+    UserCount(usize),
+}
+
+/// What happened to a direct message that was accepted.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum Delivery {
+    /// The recipient received the message.
+    Delivered,
+    /// The recipient is offline, the message waits in its mailbox.
+    Stored,
+}
+
+// A reference to a client that joined the server.
+struct Client {
+    process: SessionRef,
+    username: String,
+    // Source address of the connection, taken from the PROXY header behind a load balancer.
+    address: SocketAddr,
+    // Country of the address, if a GeoIP database is loaded.
+    location: Option<Location>,
+    // Unix timestamp of when the client connected.
+    connected: i64,
+    // All usernames used during the connection, for the connection history.
+    usernames: Vec<String>,
+    // Lowercase username of the last account the client identified with.
+    account: Option<String>,
+    // Set on each garbage collection and cleared by heartbeats.
+    missed_heartbeat: bool,
+    // Authenticated with the administrator password.
+    admin: bool,
+    // Identified with the password of the account registered under `username`.
+    identified: bool,
+    // Published after identifying, used to encrypt direct messages to the client.
+    public_key: Option<[u8; 32]>,
+    // Names of all channels that the client joined
+    channels: HashSet<String>,
+    // When the client created channels within the last `CREATION_WINDOW`, the oldest first.
+    channels_created: VecDeque<Instant>,
+}
+
+impl Client {
+    // The connection of the client for the history, `now` finishes it.
+    fn connection(&self, now: Option<i64>) -> ConnectionRecord {
+        ConnectionRecord {
+            usernames: self.usernames.clone(),
+            account: self.account.clone(),
+            address: self.address,
+            connected: self.connected,
+            duration: now.map(|now| now.saturating_sub(self.connected) as u64),
+        }
+    }
+}
+
+// Usernames handed out to new clients (`user_<n>`) and all usernames in use.
+//
+// Numbers of generated usernames are given back when the client leaves or picks another name, and
+// new clients always get the smallest free number. Usernames are unique ignoring case, only the
+// identified sessions of an account share its name.
+#[derive(Default)]
+struct UsernamePool {
+    // The highest number handed out so far.
+    highest: u64,
+    // Numbers up to `highest` that are free again.
+    free: BTreeSet<u64>,
+    // Lowercase usernames in use and the ids of the clients using them, the first one first.
+    taken: HashMap<String, Vec<u64>>,
+}
+
+impl UsernamePool {
+    // Returns the smallest free number whose generated username is not taken by another client,
+    // `None` if all numbers are used up.
+    fn next(&self) -> Option<u64> {
+        let unused = self
+            .highest
+            .checked_add(1)
+            .map(|start| start..=u64::MAX)
+            .into_iter()
+            .flatten();
+        self.free
+            .iter()
+            .copied()
+            .chain(unused)
+            .find(|n| !self.taken.contains_key(&generated_username(*n)))
+    }
+
+    // Returns the id of the client that took the username first.
+    fn owner(&self, username: &str) -> Option<u64> {
+        self.owners(username).first().copied()
+    }
+
+    // Returns the ids of all clients using the username.
+    fn owners(&self, username: &str) -> &[u64] {
+        self.taken
+            .get(&username.to_lowercase())
+            .map_or(&[], |owners| owners.as_slice())
+    }
+
+    fn claim(&mut self, client: u64, username: &str) {
+        if let Some(n) = generated_number(username) {
+            self.free.remove(&n);
+            self.highest = self.highest.max(n);
+        }
+        let owners = self.taken.entry(username.to_lowercase()).or_default();
+        if !owners.contains(&client) {
+            owners.push(client);
+        }
+    }
+
+    fn release(&mut self, client: u64, username: &str) {
+        let lowercase = username.to_lowercase();
+        if let Some(owners) = self.taken.get_mut(&lowercase) {
+            owners.retain(|owner| *owner != client);
+            if !owners.is_empty() {
+                return;
+            }
+        }
+        self.taken.remove(&lowercase);
+        // Numbers above `highest` are handed out in order anyway
+        if let Some(n) = generated_number(username) {
+            if n <= self.highest {
+                self.free.insert(n);
+            }
+        }
+    }
+}
+
+fn generated_username(n: u64) -> String {
+    format!("user_{}", n)
+}
+
+// Usernames chosen when joining can't be longer than this, in characters.
+const MAX_USERNAME_LENGTH: usize = 24;
+
+// Checks a username chosen when joining, generated names are reserved for the server.
+fn valid_username(username: &str) -> bool {
+    let length = username.chars().count();
+    (1..=MAX_USERNAME_LENGTH).contains(&length)
+        && username
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-')
+        && generated_number(username).is_none()
+}
+
+// Returns `n` if the username is exactly `user_<n>`, ignoring case.
+fn generated_number(username: &str) -> Option<u64> {
+    let n = username
+        .to_lowercase()
+        .strip_prefix("user_")?
+        .parse()
+        .ok()?;
+    (generated_username(n) == username.to_lowercase()).then_some(n)
+}
+
+/// The `CoordinatorSup` is supervising the `CoordinatorProcess` of a network, its
+/// `EventLogProcess`, `MetricsProcess`, `MailboxProcess`, `StorageProcess`, `GeoIpProcess` and
+/// the `DirectoryProcess` shards.
+pub struct CoordinatorSup;
+impl Supervisor for CoordinatorSup {
+    type Arg = CoordinatorConfig;
+    type Children = (
+        EventLogProcess,
+        MetricsProcess,
+        MailboxProcess,
+        StorageProcess,
+        GeoIpProcess,
+        DirectoryProcess,
+        DirectoryProcess,
+        DirectoryProcess,
+        DirectoryProcess,
+        CoordinatorProcess,
+    );
+
+    fn init(
+        config: &mut lunatic::supervisor::SupervisorConfig<Self>,
+        coordinator_config: Self::Arg,
+    ) {
+        // The event log, metrics, mailbox, storage and GeoIP database are started first, so that
+        // the directory shards, their channels and the coordinator can find them during `init`.
+        // The number of shard children must match `directory::SHARDS`. Always register the
+        // `CoordinatorProcess` under the name of its network.
+        let network = coordinator_config.network.clone();
+        let names = (
+            Some(event_log_name(&network)),
+            Some(metrics::registry_name(&network)),
+            Some(mailbox::registry_name(&network)),
+            Some(storage::registry_name(&network)),
+            Some(geoip::registry_name(&network)),
+            Some(directory::registry_name(&network, 0)),
+            Some(directory::registry_name(&network, 1)),
+            Some(directory::registry_name(&network, 2)),
+            Some(directory::registry_name(&network, 3)),
+            Some(registry_name(&network)),
+        );
+        let reserved = coordinator_config.reserved_channels.clone();
+        let retention = coordinator_config.retention.clone();
+        let feeds = coordinator_config.feeds;
+        let shard = |index| {
+            (
+                network.clone(),
+                index,
+                reserved.clone(),
+                retention.clone(),
+                feeds,
+            )
+        };
+        let storage = (network.clone(), coordinator_config.storage.clone());
+        let args = (
+            (),
+            (),
+            (),
+            storage,
+            coordinator_config.geoip.clone(),
+            shard(0),
+            shard(1),
+            shard(2),
+            shard(3),
+            coordinator_config,
+        );
+        config.set_args(args);
+        config.set_names(names);
+    }
+}
+
+/// The coordinator is the user registry of a network, it keeps track of all connected clients.
+///
+/// A client will inform the coordinator that it joined the server, request a name change or join
+/// a channel. The channels themselves are managed by the `DirectoryProcess` shards, the
+/// coordinator only checks the client's channel limit and remembers which channels to leave when
+/// the client disconnects.
+///
+/// All state changes are recorded as events in the `EventLogProcess`. If the coordinator crashes
+/// and is restarted by the supervisor, it replays the events to rebuild its state.
+pub struct CoordinatorProcess {
+    config: CoordinatorConfig,
+    event_log: ProcessRef<EventLogProcess>,
+    metrics: ProcessRef<MetricsProcess>,
+    mailbox: ProcessRef<MailboxProcess>,
+    storage: ProcessRef<StorageProcess>,
+    directory: Directory,
+    usernames: UsernamePool,
+    // Password hashes of registered lowercase usernames.
+    accounts: HashMap<String, PasswordHash>,
+    // Lowercase accounts blocked by each account.
+    blocks: HashMap<String, HashSet<String>>,
+    // Sessions of guests blocked by each account, they are not persisted.
+    blocked_sessions: HashMap<String, HashSet<u64>>,
+    // Preferences saved by each account.
+    settings: HashMap<String, AccountSettings>,
+    // Password checks of each session since it last identified, and when the last one started.
+    password_attempts: HashMap<u64, (u32, Instant)>,
+    clients: HashMap<u64, Client>,
+    // Clients that keep the channel list open and the namespace they are looking at.
+    list_subscribers: HashMap<u64, (SessionRef, String)>,
+    // Clients that receive server events.
+    event_subscribers: HashMap<u64, SessionRef>,
+    // The last message of the day generated by the plugin.
+    motd: String,
+    // Word filter rules handed to clients.
+    filters: Vec<FilterRule>,
+    // Custom screens handed to clients.
+    templates: CustomTemplates,
+    // Set while the server drains before it stops, new clients are turned away.
+    draining: bool,
+}
+
+#[abstract_process(visibility = pub)]
+impl CoordinatorProcess {
+    #[init]
+    fn init(this: Config<Self>, config: CoordinatorConfig) -> Result<Self, ()> {
+        // Coordinator shouldn't die when a client dies. This makes the link one-directional.
+        unsafe { host::api::process::die_when_link_dies(0) };
+
+        // Periodically trigger the garbage collection. The timer is linked to the coordinator and
+        // will die together with it.
+        Process::spawn_link(this.self_ref(), |coordinator, _: Mailbox<()>| loop {
+            lunatic::sleep(GC_INTERVAL);
+            coordinator.collect_garbage();
+        });
+
+        // Periodically push the channel list to subscribed clients. The list is collected from
+        // the directory shards by the timer, so that the coordinator doesn't block on them.
+        Process::spawn_link(
+            (this.self_ref(), Directory::new(&config.network)),
+            |(coordinator, directory), _: Mailbox<()>| loop {
+                lunatic::sleep(LIST_PUSH_INTERVAL);
+                if coordinator.has_list_subscribers() {
+                    coordinator.push_channel_list(directory.list_channels(""));
+                }
+            },
+        );
+
+        // Periodically regenerate the message of the day. The plugin is spawned from a separate
+        // process, so that the coordinator doesn't block while waiting for it.
+        if let Some(path) = config.motd_plugin.clone() {
+            Process::spawn_link(
+                (this.self_ref(), path),
+                |(coordinator, path), mailbox: Mailbox<String>| {
+                    let plugin = match motd::load(&path) {
+                        Some(plugin) => plugin,
+                        None => return,
+                    };
+                    loop {
+                        if let Some(motd) = motd::generate(&plugin, &mailbox) {
+                            coordinator.set_motd(motd);
+                        }
+                        lunatic::sleep(MOTD_INTERVAL);
+                    }
+                },
+            );
+        }
+
+        let event_log =
+            ProcessRef::<EventLogProcess>::lookup(&event_log_name(&config.network)).unwrap();
+        let metrics =
+            ProcessRef::<MetricsProcess>::lookup(&metrics::registry_name(&config.network)).unwrap();
+        let mailbox =
+            ProcessRef::<MailboxProcess>::lookup(&mailbox::registry_name(&config.network)).unwrap();
+        let storage =
+            ProcessRef::<StorageProcess>::lookup(&storage::registry_name(&config.network)).unwrap();
+        // The file was checked on startup, but may have changed since
+        let filters = config
+            .filter_file
+            .as_deref()
+            .and_then(|path| filter::load(path).ok())
+            .unwrap_or_default();
+        let templates = config
+            .template_dir
+            .as_deref()
+            .and_then(|dir| CustomTemplates::load(dir).ok())
+            .unwrap_or_default();
+        // Block lists of previous server runs, like the accounts they belong to
+        let mut blocks: HashMap<String, HashSet<String>> = HashMap::new();
+        for (account, username) in storage.blocks() {
+            blocks.entry(account).or_default().insert(username);
+        }
+        let mut coordinator = CoordinatorProcess {
+            directory: Directory::new(&config.network),
+            config,
+            event_log,
+            metrics,
+            mailbox,
+            storage,
+            usernames: UsernamePool::default(),
+            // Accounts of previous server runs, the event log only has those of this run
+            accounts: storage.accounts().into_iter().collect(),
+            blocks,
+            blocked_sessions: HashMap::new(),
+            settings: storage.settings().into_iter().collect(),
+            password_attempts: HashMap::new(),
+            clients: HashMap::new(),
+            list_subscribers: HashMap::new(),
+            event_subscribers: HashMap::new(),
+            motd: String::new(),
+            filters,
+            templates,
+            draining: false,
+        };
+        // Rebuild the state from a previous run
+        for event in event_log.events() {
+            coordinator.apply(&event);
+        }
+        Ok(coordinator)
+    }
+
+    /// Connect to the server.
+    ///
+    /// The client joins under the `username` it chose, or the coordinator will assign a unique one
+    /// if it's `None`. Sends back some server info, like the total count of connected clients.
+    #[handle_request]
+    fn join_server(
+        &mut self,
+        client: SessionRef,
+        address: SocketAddr,
+        location: Option<Location>,
+        username: Option<String>,
+    ) -> Result<Info, UsernameError> {
+        let _timer = self.time("join_server");
+        let client_username = match username {
+            Some(username) if !valid_username(&username) => return Err(UsernameError::Invalid),
+            Some(username) if !self.usernames.owners(&username).is_empty() => {
+                return Err(UsernameError::Taken)
+            }
+            Some(username) => username,
+            None => match self.usernames.next() {
+                Some(n) => generated_username(n),
+                None => return Err(UsernameError::Taken),
+            },
+        };
+
+        self.record(CoordinatorEvent::ClientJoined {
+            client,
+            username: client_username.clone(),
+            address,
+            location,
+            connected: chrono::Utc::now().timestamp(),
+        });
+        self.publish(ServerEvent::UserJoined(client_username.clone()));
+        self.publish(ServerEvent::UserCount(self.clients.len()));
+
+        Ok(self.info(client_username))
+    }
+
+    /// Returns how many messages of a channel's history the client can scroll back.
+    ///
+    /// Only guests are limited, `None` means that the whole history is available.
+    #[handle_request]
+    fn history_limit(&mut self, client: SessionRef) -> Option<usize> {
+        match self.clients.get(&client.id()) {
+            Some(client) if client.identified => None,
+            _ => self.config.guest_history,
+        }
+    }
+
+    /// Returns the server info without joining, for clients that let the user choose a name first.
+    ///
+    /// The `username` of the info is empty.
+    #[handle_request]
+    fn server_info(&mut self) -> Info {
+        self.info(String::new())
+    }
+
+    /// Replace the message of the day shown to newly connected clients.
+    #[handle_message]
+    fn set_motd(&mut self, motd: String) {
+        self.motd = motd;
+    }
+
+    /// Connect to the server again after the coordinator was restarted.
+    ///
+    /// If the client is already known from the event log, it keeps its state. Otherwise it's
+    /// added under its old `username`.
+    #[handle_request]
+    fn rejoin_server(
+        &mut self,
+        client: SessionRef,
+        username: String,
+        address: SocketAddr,
+        location: Option<Location>,
+    ) {
+        let _timer = self.time("rejoin_server");
+        if !self.clients.contains_key(&client.id()) {
+            self.record(CoordinatorEvent::ClientJoined {
+                client,
+                username,
+                address,
+                location,
+                connected: chrono::Utc::now().timestamp(),
+            });
+        }
+    }
+
+    /// leave the server.
+    ///
+    /// If the client fails unexpectedly, it will be cleaned up by the next garbage collection.
+    #[handle_message]
+    fn leave_server(&mut self, client: SessionRef) {
+        self.remove_client(client.id());
+    }
+
+    /// Let the coordinator know that the client is still alive.
+    #[handle_message]
+    fn heartbeat(&mut self, client: SessionRef) {
+        if let Some(client) = self.clients.get_mut(&client.id()) {
+            client.missed_heartbeat = false;
+        }
+    }
+
+    /// Does nothing, clients use it to measure the round-trip latency to the coordinator.
+    #[handle_request]
+    fn ping(&mut self) {
+        let _timer = self.time("ping");
+    }
+
+    /// Returns the number of connected clients.
+    #[handle_request]
+    fn client_count(&mut self) -> usize {
+        self.clients.len()
+    }
+
+    /// Disconnect all sessions of the user with the name `username`.
+    ///
+    /// Returns the number of disconnected sessions.
+    #[handle_request]
+    fn kick(&mut self, username: String) -> usize {
+        let _timer = self.time("kick");
+        let sessions = self.usernames.owners(&username);
+        for session in sessions.iter().filter_map(|id| self.clients.get(id)) {
+            session.process.send(SessionEvent::Exit);
+        }
+        sessions.len()
+    }
+
+    /// Returns false while the server drains, clients refuse to start then.
+    #[handle_request]
+    fn accepts_clients(&mut self) -> bool {
+        !self.draining
+    }
+
+    /// Stop accepting new clients and warn the connected ones that the server stops in `seconds`.
+    ///
+    /// Returns the number of connected clients.
+    #[handle_request]
+    fn drain(&mut self, seconds: u64) -> usize {
+        self.record(CoordinatorEvent::Draining);
+        for client in self.clients.values() {
+            client.process.send(SessionEvent::Draining(seconds));
+        }
+        self.clients.len()
+    }
+
+    /// Disconnect all clients, used before the server shuts down.
+    #[handle_request]
+    fn disconnect_all(&mut self) {
+        for client in self.clients.values() {
+            client.process.send(SessionEvent::Exit);
+        }
+    }
+
+    /// Remove clients that stopped sending heartbeats.
+    #[handle_message]
+    fn collect_garbage(&mut self) {
+        let dead: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.missed_heartbeat)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in dead {
+            self.remove_client(id);
+        }
+        self.clients
+            .values_mut()
+            .for_each(|client| client.missed_heartbeat = true);
+    }
+
+    /// Request for a name change by the client.
+    ///
+    /// Returns the client's new username. The name must be valid like the ones picked when joining,
+    /// and is not changed if another client uses it, ignoring case.
+    #[handle_request]
+    fn change_name(
+        &mut self,
+        client: SessionRef,
+        new_name: String,
+    ) -> Result<String, UsernameError> {
+        let _timer = self.time("change_name");
+        if !self.clients.contains_key(&client.id()) {
+            return Err(UsernameError::Invalid);
+        }
+        if !valid_username(&new_name) {
+            return Err(UsernameError::Invalid);
+        }
+        let taken = self
+            .usernames
+            .owners(&new_name)
+            .iter()
+            .any(|owner| *owner != client.id());
+        if taken {
+            return Err(UsernameError::Taken);
+        }
+        self.record(CoordinatorEvent::NameChanged {
+            client: client.id(),
+            username: new_name.clone(),
+        });
+        Ok(new_name)
+    }
+
+    /// Push the channel list to the client every `LIST_PUSH_INTERVAL`, until it unsubscribes.
+    ///
+    /// Subscribing again replaces the namespace of the previous subscription.
+    #[handle_message]
+    fn subscribe_list(&mut self, client: SessionRef, namespace: String) {
+        self.list_subscribers
+            .insert(client.id(), (client, namespace));
+    }
+
+    #[handle_message]
+    fn unsubscribe_list(&mut self, client: SessionRef) {
+        self.list_subscribers.remove(&client.id());
+    }
+
+    /// Push server events to the client, until it unsubscribes.
+    #[handle_message]
+    fn subscribe_events(&mut self, client: SessionRef) {
+        self.event_subscribers.insert(client.id(), client);
+    }
+
+    #[handle_message]
+    fn unsubscribe_events(&mut self, client: SessionRef) {
+        self.event_subscribers.remove(&client.id());
+    }
+
+    /// Called by the directory shards when they start a new channel.
+    #[handle_message]
+    fn channel_created(&mut self, name: String) {
+        self.publish(ServerEvent::ChannelCreated(name));
+    }
+
+    #[handle_request]
+    fn has_list_subscribers(&mut self) -> bool {
+        !self.list_subscribers.is_empty()
+    }
+
+    /// Send the channel list to all subscribed clients, each one gets its namespace.
+    ///
+    /// The channels are only queried once for all subscribers.
+    #[handle_message]
+    fn push_channel_list(&mut self, list: Vec<(String, usize, ChannelMeta)>) {
+        for (client, namespace) in self.list_subscribers.values() {
+            let filtered = list
+                .iter()
+                .filter(|(name, _, _)| directory::in_namespace(name, namespace))
+                .cloned()
+                .collect();
+            client.send(SessionEvent::ChannelList {
+                namespace: namespace.clone(),
+                list: filtered,
+            });
+        }
+    }
+
+    /// Reserve a place for the channel among the client's channels.
+    ///
+    /// The client joins the channel itself through the `DirectoryProcess` shard in charge of it
+    /// afterwards. If that fails, the client needs to call `leave_channel` to give the place back.
+    ///
+    /// Fails if the client is already part of `max_channels_per_client` channels, or of
+    /// `max_channels_per_guest` channels if it's not identified, or if it would create more than
+    /// `channels_created_per_hour` channels.
+    #[handle_request]
+    fn join_channel(&mut self, client: SessionRef, channel: String) -> Result<(), JoinError> {
+        let _timer = self.time("join_channel");
+        let max_channels = self.config.max_channels_per_client;
+        let current = self
+            .clients
+            .get(&client.id())
+            .ok_or(JoinError::UnknownClient)?;
+        let client_channels = &current.channels;
+        // Joining a channel twice doesn't change anything
+        if client_channels.contains(&channel) {
+            return Ok(());
+        }
+        match self.config.max_channels_per_guest {
+            Some(max_guest) if !current.identified && client_channels.len() >= max_guest => {
+                return Err(JoinError::GuestChannelLimit(max_guest));
+            }
+            _ => (),
+        }
+        if client_channels.len() >= max_channels {
+            return Err(JoinError::TooManyChannels(max_channels));
+        }
+        let shard = self
+            .directory
+            .shard(&channel)
+            .ok_or(JoinError::Unavailable)?;
+        // Joining a channel that doesn't exist creates it
+        if !shard.channel_exists(channel.clone()) {
+            self.spend_creation_budget(client.id())?;
+        }
+        self.record(CoordinatorEvent::ChannelJoined {
+            client: client.id(),
+            channel: channel.clone(),
+        });
+        // The other sessions of the account join too, they are already members when it comes back
+        for session in self.other_sessions(client.id()) {
+            session.send(SessionEvent::SessionJoined(channel.clone()));
+        }
+        Ok(())
+    }
+
+    /// Grant the client administrator rights if the `password` matches the configured one.
+    #[handle_request]
+    fn authenticate(&mut self, client: SessionRef, password: String) -> bool {
+        let _timer = self.time("authenticate");
+        if self.config.admin_password.as_ref() != Some(&password) {
+            return false;
+        }
+        self.record(CoordinatorEvent::AdminGranted {
+            client: client.id(),
+        });
+        true
+    }
+
+    /// Register the client's current username as an account, protected by the `password`.
+    ///
+    /// The client hashes the password itself, hashing takes long enough to hold up everyone else
+    /// if it ran in the coordinator. The client is identified right away. Returns `false` if the
+    /// username is already registered or was generated by the server.
+    #[handle_request]
+    fn register(&mut self, client: SessionRef, password: PasswordHash) -> bool {
+        let _timer = self.time("register");
+        let username = match self.clients.get(&client.id()) {
+            Some(client) => client.username.to_lowercase(),
+            None => return false,
+        };
+        if self.accounts.contains_key(&username) || generated_number(&username).is_some() {
+            return false;
+        }
+        self.storage
+            .save_account(username.clone(), password.clone());
+        self.record(CoordinatorEvent::AccountRegistered { password, username });
+        self.record(CoordinatorEvent::Identified {
+            client: client.id(),
+        });
+        true
+    }
+
+    /// Returns the password hash of the account `account`, so that the client can check a
+    /// password without blocking the coordinator.
+    ///
+    /// Every check counts as failed until the session identifies. After `FREE_PASSWORD_ATTEMPTS`
+    /// of them the session has to wait longer and longer before it gets the hash again.
+    #[handle_request]
+    fn password(
+        &mut self,
+        client: SessionRef,
+        account: String,
+    ) -> Result<PasswordHash, PasswordError> {
+        let _timer = self.time("password");
+        if !self.clients.contains_key(&client.id()) {
+            return Err(PasswordError::UnknownAccount);
+        }
+        let now = Instant::now();
+        let (attempts, last) = self
+            .password_attempts
+            .entry(client.id())
+            .or_insert((0, now));
+        if *attempts >= FREE_PASSWORD_ATTEMPTS {
+            let doublings = (*attempts - FREE_PASSWORD_ATTEMPTS).min(16);
+            let wait = (PASSWORD_BACKOFF * 2u32.pow(doublings)).min(MAX_PASSWORD_BACKOFF);
+            if now.duration_since(*last) < wait {
+                return Err(PasswordError::TooManyAttempts);
+            }
+        }
+        *attempts += 1;
+        *last = now;
+        self.accounts
+            .get(&account.to_lowercase())
+            .cloned()
+            .ok_or(PasswordError::UnknownAccount)
+    }
+
+    /// Identify as the owner of the account registered under the client's current username.
+    ///
+    /// The client checked the password against the hash it got from `password`, and hands the
+    /// hash back. Direct messages that arrived while the owner was offline are delivered from the
+    /// mailbox. Changing the name drops the identification.
+    #[handle_request]
+    fn identify(&mut self, client: SessionRef, password: PasswordHash) -> bool {
+        let _timer = self.time("identify");
+        let username = match self.clients.get(&client.id()) {
+            Some(client) => client.username.clone(),
+            None => return false,
+        };
+        if !self.password_matches(client.id(), &username, &password) {
+            return false;
+        }
+        self.record(CoordinatorEvent::Identified {
+            client: client.id(),
+        });
+        self.mailbox.deliver(username.to_lowercase(), client);
+        true
+    }
+
+    /// Publish the public key of an identified client, so that others can encrypt direct
+    /// messages to it.
+    #[handle_message]
+    fn publish_key(&mut self, client: SessionRef, key: [u8; 32]) {
+        let _timer = self.time("publish_key");
+        match self.clients.get(&client.id()) {
+            Some(client) if client.identified => (),
+            _ => return,
+        }
+        self.record(CoordinatorEvent::KeyPublished {
+            client: client.id(),
+            key,
+        });
+    }
+
+    /// Keep the settings with the account of an identified client, they replace the saved ones.
+    #[handle_message]
+    fn save_settings(&mut self, client: SessionRef, settings: AccountSettings) {
+        let _timer = self.time("save_settings");
+        let account = match self.clients.get(&client.id()) {
+            Some(client) if client.identified => client.username.to_lowercase(),
+            _ => return,
+        };
+        self.storage
+            .save_settings(account.clone(), settings.clone());
+        self.record(CoordinatorEvent::SettingsSaved { account, settings });
+    }
+
+    /// Returns the settings saved with the account of an identified client, `None` if it never
+    /// saved any.
+    #[handle_request]
+    fn settings(&mut self, client: SessionRef) -> Option<AccountSettings> {
+        let _timer = self.time("settings");
+        match self.clients.get(&client.id()) {
+            Some(client) if client.identified => {
+                self.settings.get(&client.username.to_lowercase()).cloned()
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the public key of the connected user with the name `username`.
+    ///
+    /// Keys are only handed out if both users are identified.
+    #[handle_request]
+    fn public_key(&mut self, client: SessionRef, username: String) -> Option<[u8; 32]> {
+        let _timer = self.time("public_key");
+        if !self.clients.get(&client.id())?.identified {
+            return None;
+        }
+        let peer = self.clients.get(&self.usernames.owner(&username)?)?;
+        if !peer.identified {
+            return None;
+        }
+        peer.public_key
+    }
+
+    /// Connect the client as another session of the account `account`, it takes over the name of
+    /// the account and is identified right away.
+    ///
+    /// The client checked the password like for `identify`. The session joins all channels of the
+    /// account's other sessions. Fails if the password hash doesn't match or the name is used by
+    /// a client that is not identified.
+    #[handle_request]
+    fn add_session(
+        &mut self,
+        client: SessionRef,
+        account: String,
+        password: PasswordHash,
+    ) -> Option<String> {
+        let _timer = self.time("add_session");
+        let lowercase = account.to_lowercase();
+        if !self.password_matches(client.id(), &account, &password) {
+            return None;
+        }
+        let sessions: Vec<&Client> = self
+            .usernames
+            .owners(&account)
+            .iter()
+            .filter(|id| **id != client.id())
+            .filter_map(|id| self.clients.get(id))
+            .collect();
+        if sessions.iter().any(|session| !session.identified) {
+            return None;
+        }
+        // Keep the spelling of the sessions that are already connected
+        let username = sessions
+            .first()
+            .map_or(account.clone(), |session| session.username.clone());
+        let channels: BTreeSet<String> = sessions
+            .iter()
+            .flat_map(|session| session.channels.iter().cloned())
+            .collect();
+        self.record(CoordinatorEvent::NameChanged {
+            client: client.id(),
+            username: username.clone(),
+        });
+        self.record(CoordinatorEvent::Identified {
+            client: client.id(),
+        });
+        self.mailbox.deliver(lowercase, client);
+        for channel in channels {
+            client.send(SessionEvent::SessionJoined(channel));
+        }
+        Some(username)
+    }
+
+    /// Returns the sessions of the client's account, or only the client if it's not identified.
+    #[handle_request]
+    fn sessions(&mut self, client: SessionRef) -> Vec<Session> {
+        let _timer = self.time("sessions");
+        let ids = match self.clients.get(&client.id()) {
+            Some(current) if current.identified => {
+                self.usernames.owners(&current.username).to_vec()
+            }
+            Some(_) => vec![client.id()],
+            None => Vec::new(),
+        };
+        ids.iter()
+            .filter_map(|id| self.clients.get(id))
+            .map(|session| {
+                let mut channels: Vec<String> = session.channels.iter().cloned().collect();
+                channels.sort();
+                Session {
+                    id: session.process.id(),
+                    current: session.process.id() == client.id(),
+                    channels,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the membership state of the session with the id `session`.
+    ///
+    /// A crashed client stays known until the garbage collection notices its missed heartbeats,
+    /// so its replacement can re-attach to the same channels in the meantime. `None` if the
+    /// process left or was already cleaned up.
+    #[handle_request]
+    fn snapshot_session(&mut self, session: u64) -> Option<SessionSnapshot> {
+        let _timer = self.time("snapshot_session");
+        let client = self.clients.get(&session)?;
+        let mut channels: Vec<String> = client.channels.iter().cloned().collect();
+        channels.sort();
+        Some(SessionSnapshot {
+            username: client.username.clone(),
+            admin: client.admin,
+            identified: client.identified,
+            channels,
+        })
+    }
+
+    /// Send a direct message to the user with the name `to`.
+    ///
+    /// Registered users only receive messages while they are identified, so that nobody else using
+    /// the name can read them. Otherwise the message is kept in the mailbox until they identify.
+    /// If the message is `encrypted`, it's only delivered to a connected recipient that can still
+    /// decrypt it.
+    #[handle_request]
+    fn direct_message(
+        &mut self,
+        client: SessionRef,
+        to: String,
+        timestamp: String,
+        message: String,
+        encrypted: bool,
+    ) -> Result<Delivery, DirectMessageError> {
+        let _timer = self.time("direct_message");
+        let sender = match self.clients.get(&client.id()) {
+            Some(sender) => sender,
+            None => return Err(DirectMessageError::UnknownUser),
+        };
+        let account = to.to_lowercase();
+        if self.is_blocked(&account, client.id()) {
+            return Err(DirectMessageError::Blocked);
+        }
+        let sender_key = match (encrypted, sender.public_key) {
+            (false, _) => None,
+            (true, Some(key)) if sender.identified => Some(key),
+            (true, _) => return Err(DirectMessageError::NotSecure),
+        };
+        let direct = DirectMessage {
+            from: sender.username.clone(),
+            identified: sender.identified,
+            timestamp,
+            message,
+            sender_key,
+        };
+        let registered = self.accounts.contains_key(&account);
+        let sessions: Vec<&Client> = self
+            .usernames
+            .owners(&to)
+            .iter()
+            .filter_map(|id| self.clients.get(id))
+            .collect();
+        if encrypted {
+            // Each session has its own keys, `public_key` hands out the key of the first one. Only
+            // identified clients publish a key, so encrypted messages are never stored.
+            return match sessions.first() {
+                Some(recipient) if recipient.public_key.is_some() => {
+                    recipient.process.send(SessionEvent::Direct(direct));
+                    Ok(Delivery::Delivered)
+                }
+                _ => Err(DirectMessageError::NotSecure),
+            };
+        }
+        // Only the identified sessions of an account share a name
+        match sessions.first() {
+            Some(recipient) if !registered || recipient.identified => {
+                for session in &sessions {
+                    session.process.send(SessionEvent::Direct(direct.clone()));
+                }
+                Ok(Delivery::Delivered)
+            }
+            _ if registered => {
+                self.mailbox.store(account, direct);
+                Ok(Delivery::Stored)
+            }
+            _ => Err(DirectMessageError::UnknownUser),
+        }
+    }
+
+    /// Invite the connected user with the name `to` to a channel.
+    ///
+    /// Fails like a direct message, but invitations are never stored for offline users.
+    #[handle_request]
+    fn invite(
+        &mut self,
+        client: SessionRef,
+        to: String,
+        channel: String,
+    ) -> Result<(), DirectMessageError> {
+        let _timer = self.time("invite");
+        let sender = match self.clients.get(&client.id()) {
+            Some(sender) => sender,
+            None => return Err(DirectMessageError::UnknownUser),
+        };
+        if self.is_blocked(&to.to_lowercase(), client.id()) {
+            return Err(DirectMessageError::Blocked);
+        }
+        let sessions = self.usernames.owners(&to);
+        if sessions.is_empty() {
+            return Err(DirectMessageError::UnknownUser);
+        }
+        for session in sessions.iter().filter_map(|id| self.clients.get(id)) {
+            session.process.send(SessionEvent::Invitation {
+                from: sender.username.clone(),
+                channel: channel.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Block or unblock a user for the account of an identified client, returns the accounts and
+    /// sessions that were blocked or unblocked.
+    ///
+    /// Guests connected under the name are blocked by their sessions, otherwise the registered
+    /// account with the name is blocked. Blocked accounts are kept with the account, so they apply
+    /// again after the next `identify`. Blocked users can't send direct messages to the account.
+    #[handle_request]
+    fn block(
+        &mut self,
+        client: SessionRef,
+        username: String,
+        block: bool,
+    ) -> Result<BlockList, BlockError> {
+        let _timer = self.time("block");
+        let account = match self.clients.get(&client.id()) {
+            Some(client) if client.identified => client.username.to_lowercase(),
+            _ => return Err(BlockError::NotIdentified),
+        };
+        let guests: Vec<u64> = self
+            .usernames
+            .owners(&username)
+            .iter()
+            .filter(|id| {
+                self.clients
+                    .get(*id)
+                    .map_or(false, |client| !client.identified)
+            })
+            .copied()
+            .collect();
+        let username = username.to_lowercase();
+        if block && !guests.is_empty() {
+            self.blocked_sessions
+                .entry(account)
+                .or_default()
+                .extend(guests.iter().copied());
+            return Ok(BlockList {
+                accounts: Vec::new(),
+                sessions: guests,
+            });
+        }
+        if !block {
+            if let Some(blocked) = self.blocked_sessions.get_mut(&account) {
+                blocked.retain(|id| !guests.contains(id));
+            }
+        }
+        if !self.accounts.contains_key(&username) {
+            return if block {
+                Err(BlockError::UnknownUser)
+            } else {
+                Ok(BlockList {
+                    accounts: Vec::new(),
+                    sessions: guests,
+                })
+            };
+        }
+        let (event, action) = if block {
+            (
+                CoordinatorEvent::UserBlocked {
+                    account: account.clone(),
+                    username: username.clone(),
+                },
+                ModerationAction::Block { account, username },
+            )
+        } else {
+            (
+                CoordinatorEvent::UserUnblocked {
+                    account: account.clone(),
+                    username: username.clone(),
+                },
+                ModerationAction::Unblock { account, username },
+            )
+        };
+        self.storage.record_moderation(action);
+        self.record(event);
+        Ok(BlockList {
+            accounts: vec![username],
+            sessions: guests,
+        })
+    }
+
+    /// Returns the accounts and sessions blocked by the account of an identified client.
+    #[handle_request]
+    fn blocked_users(&mut self, client: SessionRef) -> BlockList {
+        let _timer = self.time("blocked_users");
+        let account = match self.clients.get(&client.id()) {
+            Some(client) if client.identified => client.username.to_lowercase(),
+            _ => return BlockList::default(),
+        };
+        BlockList {
+            accounts: self
+                .blocks
+                .get(&account)
+                .map(|blocked| blocked.iter().cloned().collect())
+                .unwrap_or_default(),
+            sessions: self
+                .blocked_sessions
+                .get(&account)
+                .map(|blocked| blocked.iter().copied().collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the usernames of all clients in the channel, sorted, and if they are identified.
+    #[handle_request]
+    fn who(&mut self, channel: String) -> Vec<(String, bool)> {
+        let _timer = self.time("who");
+        let mut members: Vec<(String, bool)> = self
+            .clients
+            .values()
+            .filter(|client| client.channels.contains(&channel))
+            .map(|client| (client.username.clone(), client.identified))
+            .collect();
+        members.sort();
+        members
+    }
+
+    /// Reload the word filter rules and custom screens and send them to all connected clients.
+    ///
+    /// Nothing is replaced if one of them fails to load. Returns the number of loaded rules.
+    #[handle_request]
+    fn rehash(&mut self, client: SessionRef) -> Result<usize, RehashError> {
+        let _timer = self.time("rehash");
+        match self.clients.get(&client.id()) {
+            Some(client) if client.admin => self.load_config(),
+            _ => Err(RehashError::NotAdmin),
+        }
+    }
+
+    /// Like `rehash`, but on behalf of the server operator, see the `control` module.
+    #[handle_request]
+    fn reload(&mut self) -> Result<usize, RehashError> {
+        let _timer = self.time("reload");
+        self.load_config()
+    }
+
+    /// Returns where all sessions of the user with the name `username` connected from, only
+    /// administrators can ask.
+    #[handle_request]
+    fn whois(
+        &mut self,
+        client: SessionRef,
+        username: String,
+    ) -> Result<Vec<Connection>, WhoisError> {
+        let _timer = self.time("whois");
+        match self.clients.get(&client.id()) {
+            Some(client) if client.admin => {}
+            _ => return Err(WhoisError::NotAdmin),
+        }
+        let connections: Vec<Connection> = self
+            .usernames
+            .owners(&username)
+            .iter()
+            .filter_map(|id| self.clients.get(id))
+            .map(|session| {
+                let mut channels: Vec<String> = session.channels.iter().cloned().collect();
+                channels.sort();
+                Connection {
+                    id: session.process.id(),
+                    address: session.address,
+                    location: session.location.clone(),
+                    identified: session.identified,
+                    channels,
+                }
+            })
+            .collect();
+        if connections.is_empty() {
+            return Err(WhoisError::UnknownUser);
+        }
+        Ok(connections)
+    }
+
+    /// Returns the newest connections of a username, account or IP address, only administrators
+    /// can ask.
+    ///
+    /// Current connections come first, followed by the finished ones kept by the storage.
+    #[handle_request]
+    fn connections(
+        &mut self,
+        client: SessionRef,
+        user: String,
+    ) -> Result<Vec<ConnectionRecord>, ConnectionsError> {
+        let _timer = self.time("connections");
+        match self.clients.get(&client.id()) {
+            Some(client) if client.admin => {}
+            _ => return Err(ConnectionsError::NotAdmin),
+        }
+        let mut connections: Vec<ConnectionRecord> = self
+            .clients
+            .values()
+            .map(|client| client.connection(None))
+            .filter(|connection| connection.matches(&user))
+            .collect();
+        connections.sort_by_key(|connection| std::cmp::Reverse(connection.connected));
+        connections.truncate(CONNECTION_HISTORY_LIMIT);
+        let stored = CONNECTION_HISTORY_LIMIT - connections.len();
+        if stored > 0 {
+            connections.extend(self.storage.connections(user, stored));
+        }
+        Ok(connections)
+    }
+
+    /// Write the state of the coordinator and all channels to a JSON file in the working directory.
+    ///
+    /// Returns the path of the file.
+    #[handle_request]
+    fn dump(&mut self, client: SessionRef) -> Result<String, DumpError> {
+        let _timer = self.time("dump");
+        match self.clients.get(&client.id()) {
+            Some(client) if client.admin => {}
+            _ => return Err(DumpError::NotAdmin),
+        }
+        let mut clients: Vec<ClientSnapshot> = self
+            .clients
+            .iter()
+            .map(|(id, client)| {
+                let mut channels: Vec<String> = client.channels.iter().cloned().collect();
+                channels.sort();
+                ClientSnapshot {
+                    id: *id,
+                    username: client.username.clone(),
+                    address: client.address,
+                    location: client.location.clone(),
+                    admin: client.admin,
+                    identified: client.identified,
+                    missed_heartbeat: client.missed_heartbeat,
+                    channels,
+                }
+            })
+            .collect();
+        clients.sort_by_key(|client| client.id);
+        let channels = self.directory.snapshot();
+        let snapshot = Snapshot {
+            network: self.config.network.clone(),
+            total_clients: clients.len(),
+            total_channels: channels.len(),
+            accounts: self.accounts.len(),
+            list_subscribers: self.list_subscribers.len(),
+            clients,
+            channels,
+        };
+        let json = serde_json::to_string_pretty(&snapshot)
+            .map_err(|err| DumpError::Io(err.to_string()))?;
+        let path = format!(
+            "dump-{}-{}.json",
+            self.config.network,
+            chrono::Utc::now().format("%Y%m%d-%H%M%S")
+        );
+        std::fs::write(&path, json).map_err(|err| DumpError::Io(err.to_string()))?;
+        Ok(path)
+    }
+
+    /// Panic the target process, only available to administrators of debug builds.
+    ///
+    /// The coordinator crashes itself after replying, so that the requesting client isn't left
+    /// waiting.
+    #[handle_request]
+    fn crash_process(&mut self, client: SessionRef, target: CrashTarget) -> Result<(), CrashError> {
+        if !cfg!(debug_assertions) {
+            return Err(CrashError::Disabled);
+        }
+        match self.clients.get(&client.id()) {
+            Some(client) if client.admin => {}
+            _ => return Err(CrashError::NotAdmin),
+        }
+        match target {
+            CrashTarget::Channel(name) => {
+                let name = channel::registry_name(&self.config.network, &name);
+                let channel =
+                    ProcessRef::<ChannelProcess>::lookup(&name).ok_or(CrashError::UnknownTarget)?;
+                channel.crash();
+            }
+            CrashTarget::Client(username) => {
+                let client = self
+                    .usernames
+                    .owner(&username)
+                    .and_then(|id| self.clients.get(&id))
+                    .ok_or(CrashError::UnknownTarget)?;
+                client.process.send(SessionEvent::Crash);
+            }
+            CrashTarget::Coordinator => {
+                let name = registry_name(&self.config.network);
+                ProcessRef::<CoordinatorProcess>::lookup(&name)
+                    .ok_or(CrashError::UnknownTarget)?
+                    .crash();
+            }
+        }
+        Ok(())
+    }
+
+    #[handle_message]
+    fn crash(&mut self) {
+        panic!("coordinator crashed on request");
+    }
+
+    /// Send a message to all channels of a group.
+    ///
+    /// The message is handed to the directory shards of the channels while handling a single
+    /// request. Channels of the group that don't exist at the moment are skipped.
+    #[handle_request]
+    fn broadcast(
+        &mut self,
+        client: SessionRef,
+        group: String,
+        timestamp: String,
+        message: String,
+        trace: Option<String>,
+    ) -> Result<(), BroadcastError> {
+        let _timer = self.time("broadcast");
+        trace::log(&trace, "coordinator");
+        let sender = match self.clients.get(&client.id()) {
+            Some(sender) if sender.admin => sender,
+            _ => return Err(BroadcastError::NotAdmin),
+        };
+        let channels = self
+            .config
+            .groups
+            .get(&group)
+            .ok_or(BroadcastError::UnknownGroup)?;
+        for name in channels {
+            let shard = match self.directory.shard(name) {
+                Some(shard) => shard,
+                None => continue,
+            };
+            shard.broadcast(
+                name.clone(),
+                timestamp.clone(),
+                sender.username.clone(),
+                Some(sender.process),
+                message.clone(),
+                trace.clone(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Leave a channel, the directory shard shuts the channel down if it was the last client.
+    #[handle_message]
+    fn leave_channel(&mut self, client: SessionRef, channel: String) {
+        if let Some(shard) = self.directory.shard(&channel) {
+            shard.leave_channel(client, channel.clone());
+        }
+        let member = match self.clients.get(&client.id()) {
+            Some(client) => client.channels.contains(&channel),
+            None => false,
+        };
+        self.record(CoordinatorEvent::ChannelLeft {
+            client: client.id(),
+            channel: channel.clone(),
+        });
+        if member {
+            for session in self.other_sessions(client.id()) {
+                session.send(SessionEvent::SessionLeft(channel.clone()));
+            }
+        }
+    }
+}
+
+impl CoordinatorProcess {
+    // Start measuring a request, it's reported to the metrics process at the end of the handler.
+    fn time(&self, request: &'static str) -> RequestTimer {
+        RequestTimer::start(self.metrics, request)
+    }
+
+    // Returns `true` if `password` is the current hash of the account `username`, the client
+    // checked the password against it. The password checks of the client start over.
+    fn password_matches(&mut self, client: u64, username: &str, password: &PasswordHash) -> bool {
+        let matches = self.accounts.get(&username.to_lowercase()) == Some(password);
+        if matches {
+            self.password_attempts.remove(&client);
+        }
+        matches
+    }
+
+    // Apply the event to the state and save it in the event log.
+    fn record(&mut self, event: CoordinatorEvent) {
+        self.apply(&event);
+        // Channels mark the messages of identified members, they learn who is identified from here
+        if let CoordinatorEvent::Identified { client }
+        | CoordinatorEvent::NameChanged { client, .. } = &event
+        {
+            if let Some(client) = self.clients.get(client) {
+                let account = client.identified.then(|| client.username.to_lowercase());
+                self.directory.set_account(client.process, account);
+            }
+        }
+        self.event_log.append(event);
+    }
+
+    fn apply(&mut self, event: &CoordinatorEvent) {
+        match event {
+            CoordinatorEvent::ClientJoined {
+                client,
+                username,
+                address,
+                location,
+                connected,
+            } => {
+                self.usernames.claim(client.id(), username);
+                self.clients.insert(
+                    client.id(),
+                    Client {
+                        process: *client,
+                        username: username.clone(),
+                        address: *address,
+                        location: location.clone(),
+                        connected: *connected,
+                        usernames: vec![username.clone()],
+                        account: None,
+                        missed_heartbeat: false,
+                        admin: false,
+                        identified: false,
+                        public_key: None,
+                        channels: HashSet::new(),
+                        channels_created: VecDeque::new(),
+                    },
+                );
+            }
+            CoordinatorEvent::ClientLeft { client } => {
+                if let Some(client) = self.clients.remove(client) {
+                    self.usernames
+                        .release(client.process.id(), &client.username);
+                }
+            }
+            CoordinatorEvent::NameChanged { client, username } => {
+                if let Some(client) = self.clients.get_mut(client) {
+                    self.usernames
+                        .release(client.process.id(), &client.username);
+                    self.usernames.claim(client.process.id(), username);
+                    if !client.usernames.contains(username) {
+                        client.usernames.push(username.clone());
+                    }
+                    client.username = username.clone();
+                    client.identified = false;
+                    client.public_key = None;
+                }
+            }
+            CoordinatorEvent::AdminGranted { client } => {
+                if let Some(client) = self.clients.get_mut(client) {
+                    client.admin = true;
+                }
+            }
+            CoordinatorEvent::AccountRegistered { username, password } => {
+                self.accounts.insert(username.clone(), password.clone());
+            }
+            CoordinatorEvent::Identified { client } => {
+                if let Some(client) = self.clients.get_mut(client) {
+                    client.identified = true;
+                    client.account = Some(client.username.to_lowercase());
+                }
+            }
+            CoordinatorEvent::KeyPublished { client, key } => {
+                if let Some(client) = self.clients.get_mut(client) {
+                    client.public_key = Some(*key);
+                }
+            }
+            CoordinatorEvent::UserBlocked { account, username } => {
+                self.blocks
+                    .entry(account.clone())
+                    .or_default()
+                    .insert(username.clone());
+            }
+            CoordinatorEvent::UserUnblocked { account, username } => {
+                if let Some(blocked) = self.blocks.get_mut(account) {
+                    blocked.remove(username);
+                }
+            }
+            CoordinatorEvent::SettingsSaved { account, settings } => {
+                self.settings.insert(account.clone(), settings.clone());
+            }
+            CoordinatorEvent::ChannelJoined { client, channel } => {
+                if let Some(client) = self.clients.get_mut(client) {
+                    client.channels.insert(channel.clone());
+                }
+            }
+            CoordinatorEvent::ChannelLeft { client, channel } => {
+                if let Some(client) = self.clients.get_mut(client) {
+                    client.channels.remove(channel);
+                }
+            }
+            CoordinatorEvent::Draining => {
+                self.draining = true;
+            }
+            // Channel events are applied by the directory shards
+            CoordinatorEvent::ChannelCreated { .. }
+            | CoordinatorEvent::ChannelClosed { .. }
+            | CoordinatorEvent::ChannelExpires { .. }
+            | CoordinatorEvent::UserBanned { .. }
+            | CoordinatorEvent::HookCreated { .. }
+            | CoordinatorEvent::HookRevoked { .. } => {}
+        }
+    }
+
+    // Count a channel created by the client, fails if it used up its budget for the last hour.
+    fn spend_creation_budget(&mut self, id: u64) -> Result<(), JoinError> {
+        let limit = self.config.channels_created_per_hour;
+        let client = match self.clients.get_mut(&id) {
+            Some(client) if limit > 0 && !client.admin => client,
+            _ => return Ok(()),
+        };
+        let now = Instant::now();
+        while let Some(created) = client.channels_created.front() {
+            if now.duration_since(*created) < CREATION_WINDOW {
+                break;
+            }
+            client.channels_created.pop_front();
+        }
+        if client.channels_created.len() >= limit {
+            let oldest = client.channels_created[0];
+            let wait = CREATION_WINDOW - now.duration_since(oldest);
+            return Err(JoinError::CreationLimit {
+                limit,
+                minutes: wait.as_secs() / 60 + 1,
+            });
+        }
+        client.channels_created.push_back(now);
+        Ok(())
+    }
+
+    fn info(&self, username: String) -> Info {
+        Info {
+            username,
+            total_clients: self.clients.len(),
+            motd: self.motd.clone(),
+            trace: self.config.trace,
+            filters: self.filters.clone(),
+            auto_join: self.config.auto_join.clone(),
+            templates: self.templates.clone(),
+        }
+    }
+
+    // Returns `true` if the account blocked the client, by its account or its session.
+    fn is_blocked(&self, account: &str, client: u64) -> bool {
+        let blocked_account = self.clients.get(&client).map_or(false, |client| {
+            client.identified
+                && self.blocks.get(account).map_or(false, |blocked| {
+                    blocked.contains(&client.username.to_lowercase())
+                })
+        });
+        blocked_account
+            || self
+                .blocked_sessions
+                .get(account)
+                .map_or(false, |blocked| blocked.contains(&client))
+    }
+
+    // Remove the client from the server and all channels it joined.
+    fn remove_client(&mut self, id: u64) {
+        self.list_subscribers.remove(&id);
+        self.event_subscribers.remove(&id);
+        // Session ids are never reused, blocks of the session are useless from now on
+        for blocked in self.blocked_sessions.values_mut() {
+            blocked.remove(&id);
+        }
+        self.password_attempts.remove(&id);
+        if let Some(client) = self.clients.get(&id) {
+            for channel in &client.channels {
+                if let Some(shard) = self.directory.shard(channel) {
+                    shard.leave_channel(client.process, channel.clone());
+                }
+            }
+            if client.identified {
+                self.directory.set_account(client.process, None);
+            }
+            let username = client.username.clone();
+            let connection = client.connection(Some(chrono::Utc::now().timestamp()));
+            self.storage.record_connection(connection);
+            self.record(CoordinatorEvent::ClientLeft { client: id });
+            self.publish(ServerEvent::UserLeft(username));
+            self.publish(ServerEvent::UserCount(self.clients.len()));
+        }
+    }
+
+    // Reload the word filter rules and custom screens and send them to all connected clients.
+    fn load_config(&mut self) -> Result<usize, RehashError> {
+        let filters = match &self.config.filter_file {
+            Some(path) => filter::load(path).map_err(RehashError::InvalidFilters)?,
+            None => Vec::new(),
+        };
+        let templates = match &self.config.template_dir {
+            Some(dir) => CustomTemplates::load(dir).map_err(RehashError::InvalidTemplates)?,
+            None => CustomTemplates::default(),
+        };
+        self.filters = filters;
+        self.templates = templates;
+        for client in self.clients.values() {
+            client
+                .process
+                .send(SessionEvent::Filters(self.filters.clone()));
+            client
+                .process
+                .send(SessionEvent::Templates(self.templates.clone()));
+        }
+        Ok(self.filters.len())
+    }
+
+    // Returns the other sessions of an identified client's account.
+    fn other_sessions(&self, id: u64) -> Vec<SessionRef> {
+        let client = match self.clients.get(&id) {
+            Some(client) if client.identified => client,
+            _ => return Vec::new(),
+        };
+        self.usernames
+            .owners(&client.username)
+            .iter()
+            .filter(|owner| **owner != id)
+            .filter_map(|owner| self.clients.get(owner))
+            .map(|session| session.process)
+            .collect()
+    }
+
+    // Send the event to all subscribed clients.
+    fn publish(&self, event: ServerEvent) {
+        for client in self.event_subscribers.values() {
+            client.send(SessionEvent::Server(event.clone()));
+        }
+    }
+}
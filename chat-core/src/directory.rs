@@ -0,0 +1,718 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use lunatic::{
+    abstract_process,
+    ap::{Config, ProcessRef},
+    AbstractProcess, Mailbox, Process,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::{
+        self, Ban, ChannelMeta, ChannelProcess, ChannelProcessMessages, ChannelProcessRequests,
+        ChannelSnapshot, ChannelStats,
+    },
+    coordinator::{self, CoordinatorProcess, CoordinatorProcessMessages, JoinError},
+    event_log::{
+        CoordinatorEvent, EventLogProcess, EventLogProcessMessages, EventLogProcessRequests,
+    },
+    metrics::{self, MetricsProcess, RequestTimer},
+    protocol::SessionRef,
+    random,
+    storage::{
+        self, ModerationAction, StorageProcess, StorageProcessMessages, StorageProcessRequests,
+    },
+    trace,
+};
+
+/// How many directory processes share the channels of a network.
+pub const SHARDS: usize = 4;
+
+// How often a shard is looked up before giving up, it may be restarted by its supervisor.
+const LOOKUP_ATTEMPTS: u32 = 5;
+// The delay before the second lookup attempt, doubled after each failed attempt.
+const LOOKUP_BACKOFF: Duration = Duration::from_millis(50);
+
+// How often empty channels are shut down.
+const GC_INTERVAL: Duration = Duration::from_secs(30);
+// How long the result of `list_channels` is reused before channels are queried again.
+const LIST_CACHE_TTL: Duration = Duration::from_secs(5);
+// Members of a temporary channel are warned this long before it's closed.
+const EXPIRY_WARNING: Duration = Duration::from_secs(5 * 60);
+
+/// Shortest lifetime of a temporary channel.
+pub const MIN_TTL: Duration = Duration::from_secs(10 * 60);
+/// Longest lifetime of a temporary channel.
+pub const MAX_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Returns the name under which a directory shard of a network is registered.
+pub fn registry_name(network: &str, shard: usize) -> String {
+    format!("directory:{}:{}", network, shard)
+}
+
+// Returns the shard that is in charge of a channel.
+fn shard_of(channel: &str) -> usize {
+    // `DefaultHasher::new` always uses the same keys, so all processes agree on the shard.
+    let mut hasher = DefaultHasher::new();
+    channel.hash(&mut hasher);
+    (hasher.finish() % SHARDS as u64) as usize
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum FollowError {
+    /// Only existing channels can be followed, followers don't keep a channel open.
+    NoSuchChannel,
+    /// The client's account or session is banned from the channel.
+    Banned,
+}
+
+/// Handle to all directory shards of a network.
+///
+/// The shards are looked up by name on each use, so that the handle keeps working after the
+/// supervisor restarted a shard.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Directory {
+    network: String,
+}
+
+impl Directory {
+    pub fn new(network: &str) -> Self {
+        Directory {
+            network: network.to_owned(),
+        }
+    }
+
+    /// Returns the shard that is in charge of the channel, `None` if it's not running.
+    pub fn shard(&self, channel: &str) -> Option<ProcessRef<DirectoryProcess>> {
+        self.get(shard_of(channel))
+    }
+
+    /// Returns the name, member count and metadata of all channels inside the `namespace`, sorted
+    /// by name.
+    pub fn list_channels(&self, namespace: &str) -> Vec<(String, usize, ChannelMeta)> {
+        let mut list: Vec<(String, usize, ChannelMeta)> = (0..SHARDS)
+            .filter_map(|shard| self.get(shard))
+            .flat_map(|shard| shard.list_channels(namespace.to_owned()))
+            .collect();
+        list.sort_by(|a, b| a.0.cmp(&b.0));
+        list
+    }
+
+    /// Returns the name, member count and load of all channels, the busiest first.
+    pub fn channel_stats(&self) -> Vec<(String, usize, ChannelStats)> {
+        let mut stats: Vec<(String, usize, ChannelStats)> = (0..SHARDS)
+            .filter_map(|shard| self.get(shard))
+            .flat_map(|shard| shard.channel_stats())
+            .collect();
+        stats.sort_by(|a, b| b.2.messages_per_sec.total_cmp(&a.2.messages_per_sec));
+        stats
+    }
+
+    /// Returns the state of all channels, see `CoordinatorProcess::dump`.
+    pub fn snapshot(&self) -> BTreeMap<String, ChannelSnapshot> {
+        (0..SHARDS)
+            .filter_map(|shard| self.get(shard))
+            .flat_map(|shard| shard.snapshot())
+            .collect()
+    }
+
+    /// Returns the number of active channels.
+    pub fn channel_count(&self) -> usize {
+        (0..SHARDS)
+            .filter_map(|shard| self.get(shard))
+            .map(|shard| shard.channel_count())
+            .sum()
+    }
+
+    /// Tell all shards the account a session identified with, `None` once it isn't identified.
+    pub fn set_account(&self, client: SessionRef, account: Option<String>) {
+        for shard in (0..SHARDS).filter_map(|shard| self.get(shard)) {
+            shard.set_account(client, account.clone());
+        }
+    }
+
+    // Look up a shard, retrying with an exponential backoff while it's not registered.
+    fn get(&self, shard: usize) -> Option<ProcessRef<DirectoryProcess>> {
+        let name = registry_name(&self.network, shard);
+        let mut delay = LOOKUP_BACKOFF;
+        for attempt in 1..=LOOKUP_ATTEMPTS {
+            if let Some(process) = ProcessRef::<DirectoryProcess>::lookup(&name) {
+                return Some(process);
+            }
+            if attempt < LOOKUP_ATTEMPTS {
+                lunatic::sleep(delay);
+                delay *= 2;
+            }
+        }
+        None
+    }
+}
+
+/// A directory shard keeps track of the active channels whose name hashes to it.
+///
+/// Clients join and leave channels through the shard in charge of the channel, so that channel
+/// traffic is spread over `SHARDS` processes instead of going through the coordinator. The
+/// coordinator only keeps the per-client bookkeeping, like the channel limit.
+///
+/// Channel events are recorded in the network's `EventLogProcess`. A restarted shard replays the
+/// events of its own channels.
+pub struct DirectoryProcess {
+    network: String,
+    shard: usize,
+    event_log: ProcessRef<EventLogProcess>,
+    metrics: ProcessRef<MetricsProcess>,
+    storage: ProcessRef<StorageProcess>,
+    channels: HashMap<String, ProcessRef<ChannelProcess>>,
+    // The last `list_channels` result and when it was created.
+    list_cache: Option<(Instant, Vec<(String, usize, ChannelMeta)>)>,
+    // All channels inside of a namespace (e.g. "games/"), including nested namespaces.
+    namespaces: HashMap<String, BTreeSet<String>>,
+    // Banned usernames of each channel, handed to the channel process when it's started.
+    bans: HashMap<String, HashSet<String>>,
+    // Tokens that integrations use to post to a channel through the inbound webhook.
+    hooks: HashMap<String, BTreeSet<String>>,
+    // Channels that stay open without members, e.g. reserved channels or the channel of the demo
+    // bots.
+    pinned: HashSet<String>,
+    // How long messages of channels with a retention policy are kept visible.
+    retention: HashMap<String, Duration>,
+    // Channels can poll feeds, see the `feed` module.
+    feeds: bool,
+    // Lowercase accounts of the identified sessions, told by the coordinator. A restarted shard
+    // starts without them, sessions are marked again once they identify.
+    accounts: HashMap<u64, String>,
+    // Unix timestamps at which temporary channels are closed.
+    expiring: HashMap<String, i64>,
+    // Temporary channels whose members were warned that the channel closes soon.
+    warned: HashSet<String>,
+}
+
+#[abstract_process(visibility = pub)]
+impl DirectoryProcess {
+    #[init]
+    fn init(
+        this: Config<Self>,
+        (network, shard, reserved, retention, feeds): (
+            String,
+            usize,
+            Vec<(String, String)>,
+            HashMap<String, Duration>,
+            bool,
+        ),
+    ) -> Result<Self, ()> {
+        // Periodically shut down channels without members. The timer is linked to the shard and
+        // will die together with it.
+        Process::spawn_link(this.self_ref(), |directory, _: Mailbox<()>| loop {
+            lunatic::sleep(GC_INTERVAL);
+            directory.collect_garbage();
+        });
+
+        let event_log =
+            ProcessRef::<EventLogProcess>::lookup(&coordinator::event_log_name(&network)).unwrap();
+        let metrics =
+            ProcessRef::<MetricsProcess>::lookup(&metrics::registry_name(&network)).unwrap();
+        let storage =
+            ProcessRef::<StorageProcess>::lookup(&storage::registry_name(&network)).unwrap();
+        let mut directory = DirectoryProcess {
+            network,
+            shard,
+            event_log,
+            metrics,
+            storage,
+            channels: HashMap::new(),
+            list_cache: None,
+            namespaces: HashMap::new(),
+            bans: HashMap::new(),
+            hooks: HashMap::new(),
+            pinned: HashSet::new(),
+            retention,
+            feeds,
+            accounts: HashMap::new(),
+            expiring: HashMap::new(),
+            warned: HashSet::new(),
+        };
+        // Bans and webhook tokens of previous server runs, the event log only has those of this run
+        for (channel, username) in storage.bans() {
+            if shard_of(&channel) == shard {
+                directory.bans.entry(channel).or_default().insert(username);
+            }
+        }
+        for (channel, token) in storage.hooks() {
+            if shard_of(&channel) == shard {
+                directory.hooks.entry(channel).or_default().insert(token);
+            }
+        }
+        // Rebuild the channels of this shard from a previous run
+        for event in event_log.events() {
+            directory.apply(&event);
+        }
+        // Reserved channels exist from the start, even before anyone joins them
+        for (channel, topic) in reserved {
+            if shard_of(&channel) == shard {
+                let channel_proc = directory.open_channel(channel.clone());
+                channel_proc.reserve(ChannelMeta {
+                    language: String::new(),
+                    description: topic,
+                });
+                directory.pinned.insert(channel);
+            }
+        }
+        Ok(directory)
+    }
+
+    /// Join a channel, creating it if it doesn't exist yet.
+    ///
+    /// The channel limit is checked by the coordinator before. Fails if the client's account or
+    /// session is banned from the channel.
+    #[handle_request]
+    fn join_channel(
+        &mut self,
+        client: SessionRef,
+        username: String,
+        channel: String,
+    ) -> Result<ProcessRef<ChannelProcess>, JoinError> {
+        let _timer = self.time("directory.join_channel");
+        let channel_proc = self.open_channel(channel);
+        let account = self.accounts.get(&client.id()).cloned();
+        channel_proc.join(client, username, account)?;
+        Ok(channel_proc)
+    }
+
+    /// Remember the account a session identified with, `None` once it isn't identified anymore,
+    /// and tell the channels of the shard. Only the coordinator knows who is identified.
+    #[handle_message]
+    fn set_account(&mut self, client: SessionRef, account: Option<String>) {
+        match &account {
+            Some(account) => self.accounts.insert(client.id(), account.clone()),
+            None => self.accounts.remove(&client.id()),
+        };
+        for channel_proc in self.channels.values() {
+            channel_proc.set_account(client, account.clone());
+        }
+    }
+
+    /// Open a channel if it doesn't exist yet and keep it open while it has no members.
+    ///
+    /// Pinned channels are not persisted, a restarted shard closes them again until they are
+    /// pinned the next time.
+    #[handle_message]
+    fn keep_open(&mut self, channel: String) {
+        self.open_channel(channel.clone());
+        self.pinned.insert(channel);
+    }
+
+    /// Follow a channel, receiving its messages without being a member.
+    ///
+    /// Followers don't count towards the channel limit and don't keep the channel open.
+    #[handle_request]
+    fn follow_channel(
+        &mut self,
+        client: SessionRef,
+        username: String,
+        channel: String,
+    ) -> Result<ProcessRef<ChannelProcess>, FollowError> {
+        let _timer = self.time("directory.follow_channel");
+        let channel_proc = *self
+            .channels
+            .get(&channel)
+            .ok_or(FollowError::NoSuchChannel)?;
+        let account = self.accounts.get(&client.id()).cloned();
+        if !channel_proc.follow(client, username, account) {
+            return Err(FollowError::Banned);
+        }
+        Ok(channel_proc)
+    }
+
+    /// Leave a channel as a member or follower, the channel is shut down if this was its last
+    /// member.
+    #[handle_message]
+    fn leave_channel(&mut self, client: SessionRef, channel: String) {
+        // If the channel doesn't exist, attempting to remove it will not have any effect
+        let channel_proc = match self.channels.get(&channel) {
+            Some(channel_proc) => *channel_proc,
+            None => return,
+        };
+        channel_proc.leave(client);
+        if channel_proc.member_count() == 0 && !self.pinned.contains(&channel) {
+            channel_proc.close();
+            channel_proc.shutdown();
+            self.record(CoordinatorEvent::ChannelClosed { name: channel });
+        }
+    }
+
+    /// Close the channel after `ttl`, even if it still has members. Only the operator can make a
+    /// channel temporary, calling it again changes the time.
+    ///
+    /// Returns `false` if the client is not the operator, the channel is pinned or the `ttl` is
+    /// not between `MIN_TTL` and `MAX_TTL`.
+    #[handle_request]
+    fn expire_channel(&mut self, client: SessionRef, channel: String, ttl: Duration) -> bool {
+        let _timer = self.time("directory.expire_channel");
+        if ttl < MIN_TTL || ttl > MAX_TTL {
+            return false;
+        }
+        if self.pinned.contains(&channel) || !self.is_operator(client, &channel) {
+            return false;
+        }
+        self.warned.remove(&channel);
+        self.record(CoordinatorEvent::ChannelExpires {
+            name: channel,
+            at: Utc::now().timestamp() + ttl.as_secs() as i64,
+        });
+        true
+    }
+
+    /// Returns the name, member count and metadata of the shard's channels inside the
+    /// `namespace`.
+    ///
+    /// An empty namespace returns all channels.
+    ///
+    /// The member count is queried from each channel, but the result is cached for a few seconds
+    /// so that frequent `/list` commands don't flood the channels with requests.
+    #[handle_request]
+    fn list_channels(&mut self, namespace: String) -> Vec<(String, usize, ChannelMeta)> {
+        let _timer = self.time("directory.list_channels");
+        let list = self.all_channels();
+        if namespace.is_empty() {
+            return list;
+        }
+        match self.namespaces.get(&namespace_key(&namespace)) {
+            Some(channels) => list
+                .into_iter()
+                .filter(|(name, _, _)| channels.contains(name))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the name, member count and load of the shard's channels.
+    #[handle_request]
+    fn channel_stats(&mut self) -> Vec<(String, usize, ChannelStats)> {
+        let _timer = self.time("directory.channel_stats");
+        self.channels
+            .iter()
+            .map(|(name, channel)| (name.clone(), channel.member_count(), channel.get_stats()))
+            .collect()
+    }
+
+    /// Returns the name and state of the shard's channels.
+    #[handle_request]
+    fn snapshot(&mut self) -> Vec<(String, ChannelSnapshot)> {
+        let _timer = self.time("directory.snapshot");
+        self.channels
+            .iter()
+            .map(|(name, channel)| (name.clone(), channel.snapshot()))
+            .collect()
+    }
+
+    #[handle_request]
+    fn channel_count(&mut self) -> usize {
+        self.channels.len()
+    }
+
+    /// Returns `true` if the channel is open, joining it doesn't create a new one.
+    #[handle_request]
+    fn channel_exists(&mut self, channel: String) -> bool {
+        self.channels.contains_key(&channel)
+    }
+
+    /// Ban a user from a channel, only the channel operator is allowed to do this. Bans of
+    /// accounts are persisted, guests are banned by their sessions until they disconnect.
+    ///
+    /// Returns `false` if the channel doesn't exist or the client is not the operator.
+    #[handle_request]
+    fn ban_user(&mut self, client: SessionRef, channel: String, username: String) -> bool {
+        let _timer = self.time("directory.ban_user");
+        let channel_proc = match self.channels.get(&channel) {
+            Some(channel_proc) => *channel_proc,
+            None => return false,
+        };
+        match channel_proc.ban(client, username.clone()) {
+            Some(Ban::Account) => {}
+            Some(Ban::Sessions) => return true,
+            None => return false,
+        }
+        self.storage.record_moderation(ModerationAction::Ban {
+            channel: channel.clone(),
+            username: username.clone(),
+        });
+        self.record(CoordinatorEvent::UserBanned { channel, username });
+        true
+    }
+
+    /// Ban a username from a channel on behalf of the server operator, see the `control` module.
+    ///
+    /// The account with the name is banned, and the guests using it while the channel is open.
+    /// The ban of the account also applies if the channel is opened later.
+    #[handle_message]
+    fn force_ban(&mut self, channel: String, username: String) {
+        if let Some(channel_proc) = self.channels.get(&channel) {
+            channel_proc.force_ban(username.clone());
+        }
+        self.storage.record_moderation(ModerationAction::Ban {
+            channel: channel.clone(),
+            username: username.clone(),
+        });
+        self.record(CoordinatorEvent::UserBanned { channel, username });
+    }
+
+    /// Create a new webhook token for the channel, only the channel operator can do this.
+    ///
+    /// Returns `None` if the channel doesn't exist or the client is not the operator.
+    #[handle_request]
+    fn create_hook(&mut self, client: SessionRef, channel: String) -> Option<String> {
+        let _timer = self.time("directory.create_hook");
+        if !self.is_operator(client, &channel) {
+            return None;
+        }
+        let token = new_token();
+        self.storage.save_hook(channel.clone(), token.clone());
+        self.record(CoordinatorEvent::HookCreated {
+            channel,
+            token: token.clone(),
+        });
+        Some(token)
+    }
+
+    /// Revoke a webhook token of the channel, only the channel operator can do this.
+    ///
+    /// Returns `false` if the client is not the operator or the token doesn't exist.
+    #[handle_request]
+    fn revoke_hook(&mut self, client: SessionRef, channel: String, token: String) -> bool {
+        let _timer = self.time("directory.revoke_hook");
+        let exists = self
+            .hooks
+            .get(&channel)
+            .map_or(false, |tokens| tokens.contains(&token));
+        if !exists || !self.is_operator(client, &channel) {
+            return false;
+        }
+        self.storage.remove_hook(channel.clone(), token.clone());
+        self.record(CoordinatorEvent::HookRevoked { channel, token });
+        true
+    }
+
+    /// Returns the webhook tokens of the channel, or `None` if the client is not the operator.
+    #[handle_request]
+    fn list_hooks(&mut self, client: SessionRef, channel: String) -> Option<Vec<String>> {
+        let _timer = self.time("directory.list_hooks");
+        if !self.is_operator(client, &channel) {
+            return None;
+        }
+        let tokens = self.hooks.get(&channel).cloned().unwrap_or_default();
+        Some(tokens.into_iter().collect())
+    }
+
+    /// Send a message to a channel on behalf of the coordinator, e.g. an administrator broadcast.
+    ///
+    /// Does nothing if the channel doesn't exist at the moment.
+    #[handle_message]
+    fn broadcast(
+        &mut self,
+        channel: String,
+        timestamp: String,
+        name: String,
+        sender: Option<SessionRef>,
+        message: String,
+        trace: Option<String>,
+    ) {
+        trace::log(&trace, "directory");
+        if let Some(channel_proc) = self.channels.get(&channel) {
+            channel_proc.broadcast_message(channel, timestamp, name, sender, message, trace);
+        }
+    }
+
+    /// Shut down channels without members and temporary channels whose time is up.
+    #[handle_message]
+    fn collect_garbage(&mut self) {
+        self.expire_channels();
+        let empty: Vec<String> = self
+            .channels
+            .iter()
+            .filter(|(name, channel)| !self.pinned.contains(*name) && channel.member_count() == 0)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in empty {
+            self.channels[&name].close();
+            self.channels[&name].shutdown();
+            self.record(CoordinatorEvent::ChannelClosed { name });
+        }
+    }
+}
+
+impl DirectoryProcess {
+    // Start measuring a request, it's reported to the metrics process at the end of the handler.
+    fn time(&self, request: &'static str) -> RequestTimer {
+        RequestTimer::start(self.metrics, request)
+    }
+
+    // Apply the event to the state and save it in the event log.
+    fn record(&mut self, event: CoordinatorEvent) {
+        self.apply(&event);
+        self.event_log.append(event);
+    }
+
+    // Close the temporary channels whose time is up and warn the members of those closing soon.
+    fn expire_channels(&mut self) {
+        let now = Utc::now().timestamp();
+        let expiring: Vec<(String, i64)> = self
+            .expiring
+            .iter()
+            .map(|(name, at)| (name.clone(), at - now))
+            .collect();
+        for (name, remaining) in expiring {
+            let channel_proc = match self.channels.get(&name) {
+                Some(channel_proc) => *channel_proc,
+                None => continue,
+            };
+            if remaining <= 0 {
+                channel_proc.close_expired();
+                channel_proc.shutdown();
+                self.record(CoordinatorEvent::ChannelClosed { name });
+            } else if remaining <= EXPIRY_WARNING.as_secs() as i64
+                && self.warned.insert(name.clone())
+            {
+                let minutes = (remaining + 59) / 60;
+                channel_proc.warn_expiry(minutes as u64);
+            }
+        }
+    }
+
+    // Returns the channel, starting a new channel process if it doesn't exist yet.
+    fn open_channel(&mut self, channel: String) -> ProcessRef<ChannelProcess> {
+        if let Some(exists) = self.channels.get(&channel) {
+            return *exists;
+        }
+        // Start a new channel process. It's not linked, so that it survives a restart of the
+        // directory.
+        let bans = self.bans.get(&channel).cloned().unwrap_or_default();
+        let retention = self.retention.get(&channel).copied();
+        let channel_proc =
+            ChannelProcess::start((channel.clone(), bans, self.storage, retention, self.feeds))
+                .unwrap();
+        // Registering overwrites the process left behind by a closed channel with the same name
+        channel_proc.register(&channel::registry_name(&self.network, &channel));
+        self.record(CoordinatorEvent::ChannelCreated {
+            name: channel.clone(),
+            channel: channel_proc,
+        });
+        // The coordinator announces new channels to clients subscribed to server events
+        let coordinator = coordinator::registry_name(&self.network);
+        if let Some(coordinator) = ProcessRef::<CoordinatorProcess>::lookup(&coordinator) {
+            coordinator.channel_created(channel);
+        }
+        channel_proc
+    }
+
+    // Returns `true` if the channel exists and the client is its operator.
+    fn is_operator(&self, client: SessionRef, channel: &str) -> bool {
+        self.channels
+            .get(channel)
+            .map_or(false, |channel| channel.is_operator(client))
+    }
+
+    // Apply channel events of this shard, all other events are handled by the coordinator.
+    fn apply(&mut self, event: &CoordinatorEvent) {
+        match event {
+            CoordinatorEvent::ChannelCreated { name, channel } if shard_of(name) == self.shard => {
+                self.channels.insert(name.clone(), *channel);
+                for namespace in namespaces(name) {
+                    self.namespaces
+                        .entry(namespace)
+                        .or_default()
+                        .insert(name.clone());
+                }
+            }
+            CoordinatorEvent::ChannelClosed { name } if shard_of(name) == self.shard => {
+                self.channels.remove(name);
+                self.expiring.remove(name);
+                self.warned.remove(name);
+                for namespace in namespaces(name) {
+                    if let Some(channels) = self.namespaces.get_mut(&namespace) {
+                        channels.remove(name);
+                        if channels.is_empty() {
+                            self.namespaces.remove(&namespace);
+                        }
+                    }
+                }
+            }
+            CoordinatorEvent::UserBanned { channel, username }
+                if shard_of(channel) == self.shard =>
+            {
+                self.bans
+                    .entry(channel.clone())
+                    .or_default()
+                    .insert(username.clone());
+            }
+            CoordinatorEvent::ChannelExpires { name, at } if shard_of(name) == self.shard => {
+                self.expiring.insert(name.clone(), *at);
+            }
+            CoordinatorEvent::HookCreated { channel, token } if shard_of(channel) == self.shard => {
+                self.hooks
+                    .entry(channel.clone())
+                    .or_default()
+                    .insert(token.clone());
+            }
+            CoordinatorEvent::HookRevoked { channel, token } if shard_of(channel) == self.shard => {
+                if let Some(tokens) = self.hooks.get_mut(channel) {
+                    tokens.remove(token);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Returns all channels of the shard, the member counts are cached for `LIST_CACHE_TTL`.
+    fn all_channels(&mut self) -> Vec<(String, usize, ChannelMeta)> {
+        if let Some((created, list)) = &self.list_cache {
+            if created.elapsed() < LIST_CACHE_TTL {
+                return list.clone();
+            }
+        }
+        let list: Vec<(String, usize, ChannelMeta)> = self
+            .channels
+            .iter()
+            .map(|(channel_name, channel)| {
+                (
+                    channel_name.clone(),
+                    channel.member_count(),
+                    channel.get_meta(),
+                )
+            })
+            .collect();
+        self.list_cache = Some((Instant::now(), list.clone()));
+        list
+    }
+}
+
+// Returns a new random webhook token.
+fn new_token() -> String {
+    random::hex(16)
+}
+
+/// Returns `true` if the channel is inside the `namespace`, an empty namespace contains all
+/// channels.
+pub fn in_namespace(channel: &str, namespace: &str) -> bool {
+    namespace.is_empty() || namespaces(channel).contains(&namespace_key(namespace))
+}
+
+// "#games" and "games/" both refer to the "games/" namespace.
+fn namespace_key(namespace: &str) -> String {
+    format!(
+        "{}/",
+        namespace.trim_start_matches('#').trim_end_matches('/')
+    )
+}
+
+// Returns all namespaces the channel is part of, e.g. `#games/board/chess` is part of `games/`
+// and `games/board/`.
+fn namespaces(channel: &str) -> Vec<String> {
+    let name = channel.trim_start_matches('#');
+    name.match_indices('/')
+        .map(|(i, _)| name[..=i].to_owned())
+        .collect()
+}
@@ -0,0 +1,261 @@
+use std::net::SocketAddr;
+
+use lunatic::{
+    abstract_process,
+    ap::{Config, ProcessRef},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::ChannelProcess, coordinator::AccountSettings, geoip::Location, password::PasswordHash,
+    protocol::SessionRef,
+};
+
+/// A change of the coordinator's state.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum CoordinatorEvent {
+    ClientJoined {
+        client: SessionRef,
+        username: String,
+        address: SocketAddr,
+        location: Option<Location>,
+        // Unix timestamp
+        connected: i64,
+    },
+    ClientLeft {
+        client: u64,
+    },
+    NameChanged {
+        client: u64,
+        username: String,
+    },
+    AdminGranted {
+        client: u64,
+    },
+    // Not removed when the client leaves, accounts outlive connections.
+    AccountRegistered {
+        // Lowercase username
+        username: String,
+        password: PasswordHash,
+    },
+    Identified {
+        client: u64,
+    },
+    KeyPublished {
+        client: u64,
+        key: [u8; 32],
+    },
+    // Block lists belong to accounts, so they are kept after the client leaves.
+    UserBlocked {
+        // Lowercase usernames
+        account: String,
+        username: String,
+    },
+    UserUnblocked {
+        account: String,
+        username: String,
+    },
+    // Like block lists, settings belong to accounts.
+    SettingsSaved {
+        // Lowercase username
+        account: String,
+        settings: AccountSettings,
+    },
+    ChannelCreated {
+        name: String,
+        channel: ProcessRef<ChannelProcess>,
+    },
+    ChannelClosed {
+        name: String,
+    },
+    ChannelJoined {
+        client: u64,
+        channel: String,
+    },
+    ChannelLeft {
+        client: u64,
+        channel: String,
+    },
+    // Temporary channels are closed at the unix timestamp `at`, even if they have members.
+    ChannelExpires {
+        name: String,
+        at: i64,
+    },
+    // Like bans, webhook tokens outlive the channel.
+    HookCreated {
+        channel: String,
+        token: String,
+    },
+    HookRevoked {
+        channel: String,
+        token: String,
+    },
+    // Not removed when the channel is closed, bans apply to all future channels with this name.
+    UserBanned {
+        channel: String,
+        username: String,
+    },
+    // The server stops accepting clients, a restarted coordinator keeps refusing them.
+    Draining,
+}
+
+impl CoordinatorEvent {
+    fn client(&self) -> Option<u64> {
+        match self {
+            CoordinatorEvent::ClientJoined { client, .. } => Some(client.id()),
+            CoordinatorEvent::ClientLeft { client }
+            | CoordinatorEvent::NameChanged { client, .. }
+            | CoordinatorEvent::AdminGranted { client }
+            | CoordinatorEvent::Identified { client }
+            | CoordinatorEvent::KeyPublished { client, .. }
+            | CoordinatorEvent::ChannelJoined { client, .. }
+            | CoordinatorEvent::ChannelLeft { client, .. } => Some(*client),
+            _ => None,
+        }
+    }
+
+    fn channel(&self) -> Option<&str> {
+        match self {
+            CoordinatorEvent::ChannelCreated { name, .. }
+            | CoordinatorEvent::ChannelClosed { name }
+            | CoordinatorEvent::ChannelExpires { name, .. }
+            | CoordinatorEvent::ChannelJoined { channel: name, .. }
+            | CoordinatorEvent::ChannelLeft { channel: name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// The event log keeps all state changes of the `CoordinatorProcess` and the `DirectoryProcess`
+/// shards.
+///
+/// It runs next to them under the `CoordinatorSup`, so that a restarted coordinator can rebuild
+/// its client map and a restarted shard its channel map by replaying the events.
+///
+/// Events that are cancelled out by later ones (e.g. a join followed by a leave) are removed, so
+/// the log only grows with the live state of the server.
+pub struct EventLogProcess {
+    events: Vec<CoordinatorEvent>,
+}
+
+#[abstract_process(visibility = pub)]
+impl EventLogProcess {
+    #[init]
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(EventLogProcess { events: Vec::new() })
+    }
+
+    /// Add an event to the log.
+    #[handle_message]
+    fn append(&mut self, event: CoordinatorEvent) {
+        append(&mut self.events, event);
+    }
+
+    /// Returns all events in the order they happened.
+    #[handle_request]
+    fn events(&mut self) -> Vec<CoordinatorEvent> {
+        self.events.clone()
+    }
+}
+
+/// Adds `event` to `events` and removes the events it cancels out.
+fn append(events: &mut Vec<CoordinatorEvent>, event: CoordinatorEvent) {
+    match &event {
+        CoordinatorEvent::ClientLeft { client } => {
+            events.retain(|event| event.client() != Some(*client));
+        }
+        CoordinatorEvent::ChannelClosed { name } => {
+            events.retain(|event| event.channel() != Some(name.as_str()));
+        }
+        CoordinatorEvent::ChannelLeft { client, channel } => {
+            events.retain(|event| {
+                !matches!(event, CoordinatorEvent::ChannelJoined { client: c, channel: ch }
+                    if c == client && ch == channel)
+            });
+        }
+        // Only the latest expiry of a channel counts
+        CoordinatorEvent::ChannelExpires { name, .. } => {
+            events.retain(|event| {
+                !matches!(event, CoordinatorEvent::ChannelExpires { name: n, .. } if n == name)
+            });
+            events.push(event);
+        }
+        // A revoked token is forgotten completely
+        CoordinatorEvent::HookRevoked { channel, token } => {
+            events.retain(|event| {
+                !matches!(event, CoordinatorEvent::HookCreated { channel: c, token: t }
+                    if c == channel && t == token)
+            });
+        }
+        // An unblock cancels out the block, neither is kept
+        CoordinatorEvent::UserUnblocked { account, username } => {
+            events.retain(|event| {
+                !matches!(event, CoordinatorEvent::UserBlocked { account: a, username: u }
+                    if a == account && u == username)
+            });
+        }
+        // Only the latest settings of an account count
+        CoordinatorEvent::SettingsSaved { account, .. } => {
+            events.retain(|event| {
+                !matches!(event, CoordinatorEvent::SettingsSaved { account: a, .. } if a == account)
+            });
+            events.push(event);
+        }
+        CoordinatorEvent::NameChanged { client, .. } => {
+            events.retain(|event| {
+                !matches!(event, CoordinatorEvent::NameChanged { client: c, .. } if c == client)
+            });
+            events.push(event);
+        }
+        _ => events.push(event),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expiries(events: &[CoordinatorEvent]) -> Vec<(&str, i64)> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                CoordinatorEvent::ChannelExpires { name, at } => Some((name.as_str(), *at)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn latest_expiry_is_kept() {
+        let mut events = Vec::new();
+        for (name, at) in [("#tmp", 100), ("#other", 150), ("#tmp", 200)] {
+            append(
+                &mut events,
+                CoordinatorEvent::ChannelExpires {
+                    name: name.to_owned(),
+                    at,
+                },
+            );
+        }
+        assert_eq!(expiries(&events), vec![("#other", 150), ("#tmp", 200)]);
+    }
+
+    #[test]
+    fn closed_channel_forgets_its_expiry() {
+        let mut events = Vec::new();
+        append(
+            &mut events,
+            CoordinatorEvent::ChannelExpires {
+                name: "#tmp".to_owned(),
+                at: 100,
+            },
+        );
+        append(
+            &mut events,
+            CoordinatorEvent::ChannelClosed {
+                name: "#tmp".to_owned(),
+            },
+        );
+        assert!(expiries(&events).is_empty());
+    }
+}
@@ -0,0 +1,369 @@
+//! RSS and Atom feeds posted to a channel, added by the operator with `/feed add <url> <interval>`.
+//!
+//! Each feed has a poller process linked to the channel. It fetches the feed after every interval
+//! and hands the entries it didn't see before to `ChannelProcess::post_feed_entries`, which sends
+//! them under the name of the channel. Entries that are already in the feed when polling starts
+//! are not posted, so that adding a feed or reopening the channel doesn't flood it.
+//!
+//! Only what's needed to read common feeds is implemented: HTTP/1.0 requests over plain TCP or
+//! TLS with a few redirects, and the title, link and id of `<item>` and `<entry>` elements.
+//!
+//! Any client that creates a channel becomes its operator, so feeds are off unless the server is
+//! started with `--feeds`. Even then only public addresses are fetched, the host of each request,
+//! redirects included, is resolved first and refused if it points to a loopback, private,
+//! link-local or unspecified address.
+
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use lunatic::net::{self, TcpStream, TlsStream};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::channel;
+
+/// Most feeds of a channel.
+pub const MAX_FEEDS: usize = 5;
+/// Shortest interval between two polls of a feed.
+pub const MIN_FEED_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Most entries posted after one poll, a feed that changed completely doesn't flood the channel.
+pub const MAX_ENTRIES_PER_POLL: usize = 5;
+
+// Longest accepted URL.
+const MAX_URL_LENGTH: usize = 500;
+// Larger responses are cut, the newest entries come first in feeds.
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+const MAX_REDIRECTS: usize = 3;
+// Longer titles are cut.
+const MAX_TITLE: usize = 200;
+// Longest wait for resolving, connecting and each read or write, a slow server can't hang the
+// poller.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A feed polled by the channel.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Feed {
+    /// Identifies the feed when it's removed.
+    pub id: u64,
+    pub url: String,
+    pub interval: Duration,
+    /// Username of the operator that added it.
+    pub by: String,
+}
+
+impl Feed {
+    /// Formats the interval the way `Announcement::parse_interval` reads it.
+    pub fn interval_text(&self) -> String {
+        channel::interval_text(self.interval)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum FeedError {
+    /// Only the channel operator can add and remove feeds.
+    NotOperator,
+    /// The channel already has `MAX_FEEDS`.
+    TooMany,
+    /// The interval is shorter than `MIN_FEED_INTERVAL`.
+    TooFrequent,
+    /// Only `http://` and `https://` URLs can be polled.
+    InvalidUrl,
+    UnknownId,
+    /// The server wasn't started with `--feeds`.
+    Disabled,
+}
+
+/// An item of an RSS feed or an entry of an Atom feed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Entry {
+    /// The guid or id of the entry, its link if it has none.
+    pub id: String,
+    pub title: String,
+    pub link: String,
+}
+
+/// Returns `true` if the URL can be polled.
+pub fn is_valid_url(url: &str) -> bool {
+    let rest = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"));
+    match rest {
+        Some(rest) => {
+            !rest.is_empty()
+                && url.len() <= MAX_URL_LENGTH
+                && !url.chars().any(|ch| ch.is_whitespace() || ch.is_control())
+        }
+        None => false,
+    }
+}
+
+/// Fetch the feed and return its entries in the order of the feed, usually the newest first.
+pub fn fetch(url: &str) -> Result<Vec<Entry>, String> {
+    let mut url = url.to_owned();
+    for _ in 0..=MAX_REDIRECTS {
+        match get(&url)? {
+            Response::Body(body) => return Ok(parse(&body)),
+            Response::Redirect(location) => url = location,
+        }
+    }
+    Err("too many redirects".to_owned())
+}
+
+/// Returns the entries of an RSS or Atom document.
+pub fn parse(xml: &str) -> Vec<Entry> {
+    let entries = Regex::new(r"(?s)<item[\s>].*?</item>|<entry[\s>].*?</entry>").unwrap();
+    let title = Regex::new(r"(?s)<title[^>]*>(.*?)</title>").unwrap();
+    let id = Regex::new(r"(?s)<guid[^>]*>(.*?)</guid>|<id>(.*?)</id>").unwrap();
+    // RSS has the URL as content, Atom in the `href` attribute
+    let link = Regex::new(r#"(?s)<link>(.*?)</link>|<link[^>]*href="([^"]*)""#).unwrap();
+    let first = |regex: &Regex, text: &str| {
+        regex.captures(text).and_then(|captures| {
+            captures
+                .iter()
+                .skip(1)
+                .flatten()
+                .next()
+                .map(|value| text_of(value.as_str()))
+        })
+    };
+    entries
+        .find_iter(xml)
+        .filter_map(|entry| {
+            let entry = entry.as_str();
+            let link = first(&link, entry).unwrap_or_default();
+            let title = first(&title, entry).unwrap_or_else(|| link.clone());
+            let id = first(&id, entry).unwrap_or_else(|| link.clone());
+            if id.is_empty() {
+                return None;
+            }
+            let title = title.chars().take(MAX_TITLE).collect();
+            Some(Entry { id, title, link })
+        })
+        .collect()
+}
+
+enum Response {
+    Body(String),
+    Redirect(String),
+}
+
+// Send a GET request. HTTP/1.0 is used, so that servers don't answer with chunked encoding.
+fn get(url: &str) -> Result<Response, String> {
+    let (tls, rest) = match url.strip_prefix("https://") {
+        Some(rest) => (true, rest),
+        None => (false, url.strip_prefix("http://").ok_or("invalid url")?),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| "invalid port")?),
+        None => (authority, if tls { 443 } else { 80 }),
+    };
+    let address = resolve(host, port)?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: telnet-chat\r\nConnection: close\r\n\r\n",
+        path, authority
+    );
+    let mut response = Vec::new();
+    if tls {
+        // The TLS stream needs the host name to verify the certificate and resolves it again
+        let mut stream = TlsStream::connect_timeout(host, FETCH_TIMEOUT, port, Vec::new())
+            .map_err(|err| err.to_string())?;
+        stream
+            .set_read_timeout(Some(FETCH_TIMEOUT))
+            .map_err(|err| err.to_string())?;
+        stream
+            .set_write_timeout(Some(FETCH_TIMEOUT))
+            .map_err(|err| err.to_string())?;
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|err| err.to_string())?;
+        stream
+            .take(MAX_RESPONSE_SIZE)
+            .read_to_end(&mut response)
+            .map_err(|err| err.to_string())?;
+    } else {
+        let mut stream =
+            TcpStream::connect_timeout(address, FETCH_TIMEOUT).map_err(|err| err.to_string())?;
+        stream
+            .set_read_timeout(Some(FETCH_TIMEOUT))
+            .map_err(|err| err.to_string())?;
+        stream
+            .set_write_timeout(Some(FETCH_TIMEOUT))
+            .map_err(|err| err.to_string())?;
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|err| err.to_string())?;
+        stream
+            .take(MAX_RESPONSE_SIZE)
+            .read_to_end(&mut response)
+            .map_err(|err| err.to_string())?;
+    }
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("incomplete response")?;
+    let mut lines = head.lines();
+    let status: u16 = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or("invalid status line")?;
+    match status {
+        200 => Ok(Response::Body(body.to_owned())),
+        301 | 302 | 303 | 307 | 308 => {
+            let location = lines
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.eq_ignore_ascii_case("location"))
+                .map(|(_, value)| value.trim())
+                .ok_or("redirect without location")?;
+            // Relative redirects stay on the same server
+            let location = if location.starts_with('/') {
+                let scheme = if tls { "https" } else { "http" };
+                format!("{}://{}{}", scheme, authority, location)
+            } else {
+                location.to_owned()
+            };
+            if !is_valid_url(&location) {
+                return Err("invalid redirect".to_owned());
+            }
+            Ok(Response::Redirect(location))
+        }
+        status => Err(format!("status {}", status)),
+    }
+}
+
+// Resolve the host and return its first address, if all of its addresses are public.
+fn resolve(host: &str, port: u32) -> Result<SocketAddr, String> {
+    let addresses: Vec<SocketAddr> =
+        net::resolve_timeout(&format!("{}:{}", host, port), FETCH_TIMEOUT)
+            .map_err(|err| err.to_string())?
+            .collect();
+    if addresses.iter().any(|address| !is_public(address.ip())) {
+        return Err("address not allowed".to_owned());
+    }
+    addresses
+        .into_iter()
+        .next()
+        .ok_or_else(|| "host not found".to_owned())
+}
+
+/// Returns `false` for loopback, private, link-local, unspecified and other addresses that
+/// aren't reachable on the internet.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // Shared address space of carrier-grade NATs, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local, fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // Link-local, fe80::/10
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+// Returns the text of an element, without CDATA markers, tags and whitespace around it.
+fn text_of(content: &str) -> String {
+    let content = content.trim();
+    let content = content
+        .strip_prefix("<![CDATA[")
+        .and_then(|content| content.strip_suffix("]]>"))
+        .map(str::to_owned)
+        .unwrap_or_else(|| unescape(content));
+    // Titles can contain markup, only the text is shown. A `<` that doesn't start a tag is text,
+    // e.g. in `a < b`.
+    let starts_tag = |next: Option<&char>| {
+        next.map_or(false, |next| {
+            next.is_ascii_alphabetic() || *next == '/' || *next == '!'
+        })
+    };
+    let mut text = String::with_capacity(content.len());
+    let mut in_tag = false;
+    let mut chars = content.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '<' if starts_tag(chars.peek()) => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            ch if !in_tag => text.push(if ch.is_control() { ' ' } else { ch }),
+            _ => {}
+        }
+    }
+    text.trim().to_owned()
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rss_with_cdata() {
+        let xml = r#"<rss><channel><title>Blog</title>
+            <item>
+                <title><![CDATA[Rust & <b>WASM</b>]]></title>
+                <link>https://example.com/1</link>
+                <guid isPermaLink="false">post-1</guid>
+            </item>
+            <item><title>a &lt; b</title><link>https://example.com/2</link></item>
+        </channel></rss>"#;
+        let entries = parse(xml);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title, "Rust & WASM");
+        assert_eq!(entries[0].link, "https://example.com/1");
+        assert_eq!(entries[0].id, "post-1");
+        // Without a guid the link identifies the entry
+        assert_eq!(entries[1].title, "a < b");
+        assert_eq!(entries[1].id, "https://example.com/2");
+    }
+
+    #[test]
+    fn atom_with_href() {
+        let xml = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+            <entry>
+                <title type="html">Tom &amp; Jerry</title>
+                <link rel="alternate" href="https://example.com/tom"/>
+                <id>urn:uuid:1</id>
+            </entry>
+        </feed>"#;
+        let entries = parse(xml);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title, "Tom & Jerry");
+        assert_eq!(entries[0].link, "https://example.com/tom");
+        assert_eq!(entries[0].id, "urn:uuid:1");
+    }
+
+    #[test]
+    fn entries_without_id_or_link_are_skipped() {
+        assert!(parse("<rss><item><title>Nothing</title></item></rss>").is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+//! Word filters configured by the server operator.
+//!
+//! Rules are read from a file (`--filter-file`), one rule per line in the form `ACTION REGEX`:
+//!
+//! ```text
+//! # Lines starting with `#` are comments
+//! censor (?i)\bdarn\b
+//! warn   (?i)buy now
+//! drop   (?i)free crypto
+//! mute   (?i)\bslur\b
+//! ```
+//!
+//! Every message a client sends is checked against all rules before it reaches a channel.
+//! Administrators can reload the file without restarting the server with `/rehash`.
+
+use std::time::Duration;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// How long a client can't send messages after triggering a `mute` rule.
+pub const MUTE_DURATION: Duration = Duration::from_secs(60);
+
+/// What happens to a message that matches a rule.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FilterAction {
+    /// Replace the matched text with `*`.
+    Censor,
+    /// Don't send the message.
+    Drop,
+    /// Send the message, but warn the sender.
+    Warn,
+    /// Don't send the message and mute the sender for `MUTE_DURATION`.
+    Mute,
+}
+
+/// A single line of the filter file.
+///
+/// The pattern is kept as text, so that rules can be sent to other processes. Each process
+/// compiles them into `Filters`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FilterRule {
+    pub action: FilterAction,
+    pub pattern: String,
+}
+
+/// Reads and validates the rules of a filter file.
+pub fn load(path: &str) -> Result<Vec<FilterRule>, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|err| format!("can't read {}: {}", path, err))?;
+    let mut rules = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (action, pattern) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("line {}: expected ACTION REGEX", number + 1))?;
+        let action = match action {
+            "censor" => FilterAction::Censor,
+            "drop" => FilterAction::Drop,
+            "warn" => FilterAction::Warn,
+            "mute" => FilterAction::Mute,
+            other => return Err(format!("line {}: unknown action {}", number + 1, other)),
+        };
+        let pattern = pattern.trim().to_owned();
+        Regex::new(&pattern).map_err(|err| format!("line {}: {}", number + 1, err))?;
+        rules.push(FilterRule { action, pattern });
+    }
+    Ok(rules)
+}
+
+/// The result of filtering a message.
+pub struct Verdict {
+    /// The message to send, `None` if it was dropped.
+    pub message: Option<String>,
+    /// The message matched a `warn` rule.
+    pub warn: bool,
+    /// The message matched a `mute` rule.
+    pub mute: bool,
+}
+
+/// Compiled filter rules.
+#[derive(Default)]
+pub struct Filters {
+    rules: Vec<(FilterAction, Regex)>,
+}
+
+impl Filters {
+    /// Compiles the rules, rules that don't compile are skipped.
+    pub fn new(rules: &[FilterRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| Some((rule.action, Regex::new(&rule.pattern).ok()?)))
+            .collect();
+        Filters { rules }
+    }
+
+    /// Applies all matching rules to the message.
+    pub fn apply(&self, message: &str) -> Verdict {
+        let mut verdict = Verdict {
+            message: Some(message.to_owned()),
+            warn: false,
+            mute: false,
+        };
+        for (action, regex) in &self.rules {
+            let text = match &verdict.message {
+                Some(text) if regex.is_match(text) => text,
+                _ => continue,
+            };
+            match action {
+                FilterAction::Censor => {
+                    let censored = regex.replace_all(text, |captures: &regex::Captures| {
+                        "*".repeat(captures[0].chars().count())
+                    });
+                    verdict.message = Some(censored.into_owned());
+                }
+                FilterAction::Drop => verdict.message = None,
+                FilterAction::Warn => verdict.warn = true,
+                FilterAction::Mute => {
+                    verdict.message = None;
+                    verdict.mute = true;
+                }
+            }
+        }
+        verdict
+    }
+}
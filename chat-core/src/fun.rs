@@ -0,0 +1,152 @@
+//! Games played in a channel: `/roll`, `/flip` and `/8ball`.
+//!
+//! Clients only pick the game, the channel plays it with `ChannelProcess::play` and posts the
+//! result from the channel itself, like an announcement. A typed message can't pass for a roll,
+//! because results never come from a member:
+//!
+//! ```text
+//! [12:00 UTC] #general: alice rolled 2d6: 4 + 1 = 5
+//! [12:01 UTC] #general: bob flipped a coin: heads
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::random;
+
+/// Most dice rolled at once.
+pub const MAX_DICE: u32 = 20;
+/// Most sides of a die.
+pub const MAX_SIDES: u32 = 1000;
+/// Longest question asked to the 8-ball, in characters.
+pub const MAX_QUESTION: usize = 200;
+
+const EIGHT_BALL: [&str; 20] = [
+    "It is certain.",
+    "It is decidedly so.",
+    "Without a doubt.",
+    "Yes, definitely.",
+    "You may rely on it.",
+    "As I see it, yes.",
+    "Most likely.",
+    "Outlook good.",
+    "Yes.",
+    "Signs point to yes.",
+    "Reply hazy, try again.",
+    "Ask again later.",
+    "Better not tell you now.",
+    "Cannot predict now.",
+    "Concentrate and ask again.",
+    "Don't count on it.",
+    "My reply is no.",
+    "My sources say no.",
+    "Outlook not so good.",
+    "Very doubtful.",
+];
+
+/// A game the channel plays for a member.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Game {
+    Roll { dice: u32, sides: u32 },
+    Flip,
+    EightBall(String),
+}
+
+impl Game {
+    /// Parses the dice of `/roll`, e.g. `2d6` or `d20`. Without dice, one six-sided die is rolled.
+    pub fn roll(dice: Option<&str>) -> Option<Game> {
+        let (count, sides) = match dice {
+            Some(dice) => dice
+                .to_lowercase()
+                .split_once('d')
+                .and_then(|(count, sides)| {
+                    let count = if count.is_empty() {
+                        Ok(1)
+                    } else {
+                        count.parse()
+                    };
+                    Some((count.ok()?, sides.parse().ok()?))
+                })?,
+            None => (1, 6),
+        };
+        let game = Game::Roll { dice: count, sides };
+        game.is_valid().then_some(game)
+    }
+
+    /// Returns if the game can be played, the channel doesn't trust its clients.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Game::Roll { dice, sides } => {
+                (1..=MAX_DICE).contains(dice) && *sides >= 2 && *sides <= MAX_SIDES
+            }
+            Game::Flip => true,
+            Game::EightBall(question) => {
+                !question.trim().is_empty() && question.chars().count() <= MAX_QUESTION
+            }
+        }
+    }
+
+    /// Plays the game, returns the message with the result.
+    pub fn play(&self) -> String {
+        match self {
+            Game::Roll { dice, sides } => {
+                let rolls: Vec<u32> = (0..*dice).map(|_| random::below(*sides) + 1).collect();
+                let total: u32 = rolls.iter().sum();
+                if rolls.len() == 1 {
+                    format!("rolled {}d{}: {}", dice, sides, total)
+                } else {
+                    let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+                    format!(
+                        "rolled {}d{}: {} = {}",
+                        dice,
+                        sides,
+                        rolls.join(" + "),
+                        total
+                    )
+                }
+            }
+            Game::Flip => {
+                let side = if random::below(2) == 0 {
+                    "heads"
+                } else {
+                    "tails"
+                };
+                format!("flipped a coin: {}", side)
+            }
+            Game::EightBall(question) => {
+                let answer = EIGHT_BALL[random::below(EIGHT_BALL.len() as u32) as usize];
+                format!("asked the magic 8-ball \"{}\": {}", question.trim(), answer)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dice(game: Option<Game>) -> Option<(u32, u32)> {
+        match game? {
+            Game::Roll { dice, sides } => Some((dice, sides)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn roll_defaults_to_one_six_sided_die() {
+        assert_eq!(dice(Game::roll(None)), Some((1, 6)));
+    }
+
+    #[test]
+    fn roll_parses_dice() {
+        assert_eq!(dice(Game::roll(Some("2d6"))), Some((2, 6)));
+        assert_eq!(dice(Game::roll(Some("d20"))), Some((1, 20)));
+        assert_eq!(dice(Game::roll(Some("3D8"))), Some((3, 8)));
+    }
+
+    #[test]
+    fn roll_rejects_invalid_dice() {
+        for invalid in ["0d6", "2d1", "21d6", "2d1001", "2x6", "-1d6", "2d", ""] {
+            assert!(Game::roll(Some(invalid)).is_none(), "{}", invalid);
+        }
+    }
+}
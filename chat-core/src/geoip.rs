@@ -0,0 +1,97 @@
+//! Country lookup of client addresses in a local MaxMind database (`--geoip`, geoip feature).
+//!
+//! The address of each client is looked up once when it connects. Administrators see the
+//! country with `/whois`, which helps to moderate abuse coming from specific networks. Both the
+//! GeoLite2 Country and City databases work.
+
+use std::net::IpAddr;
+
+use lunatic::{abstract_process, ap::Config};
+use serde::{Deserialize, Serialize};
+
+/// Returns the name under which the GeoIP process of a network is registered.
+pub fn registry_name(network: &str) -> String {
+    format!("geoip:{}", network)
+}
+
+/// Where an address is located.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Location {
+    /// ISO 3166-1 code of the country, e.g. `DE`.
+    pub country_code: String,
+    /// English name of the country.
+    pub country: String,
+}
+
+/// An opened MMDB file.
+#[cfg(feature = "geoip")]
+pub struct Database(maxminddb::Reader<Vec<u8>>);
+
+#[cfg(feature = "geoip")]
+impl Database {
+    pub fn open(path: &str) -> Result<Self, String> {
+        maxminddb::Reader::open_readfile(path)
+            .map(Database)
+            .map_err(|err| format!("can't open {}: {}", path, err))
+    }
+
+    fn locate(&self, ip: IpAddr) -> Option<Location> {
+        let country = self
+            .0
+            .lookup::<maxminddb::geoip2::Country>(ip)
+            .ok()?
+            .country?;
+        let country_code = country.iso_code?.to_owned();
+        let name = country
+            .names
+            .and_then(|names| names.get("en").map(|name| name.to_string()))
+            .unwrap_or_else(|| country_code.clone());
+        Some(Location {
+            country_code,
+            country: name,
+        })
+    }
+}
+
+/// An opened MMDB file.
+#[cfg(not(feature = "geoip"))]
+pub struct Database;
+
+#[cfg(not(feature = "geoip"))]
+impl Database {
+    pub fn open(_: &str) -> Result<Self, String> {
+        Err("the server was built without the geoip feature".to_owned())
+    }
+
+    fn locate(&self, _: IpAddr) -> Option<Location> {
+        None
+    }
+}
+
+/// The GeoIP process keeps the database of a network in memory.
+///
+/// It runs next to the coordinator, clients ask it for their location while connecting. Without
+/// a database it answers every lookup with `None`.
+pub struct GeoIpProcess {
+    database: Option<Database>,
+}
+
+#[abstract_process(visibility = pub)]
+impl GeoIpProcess {
+    #[init]
+    fn init(_: Config<Self>, path: Option<String>) -> Result<Self, ()> {
+        // The file was checked on startup, but may have changed since
+        let database = path.and_then(|path| {
+            Database::open(&path)
+                .map_err(|err| eprintln!("GeoIP database unavailable: {}", err))
+                .ok()
+        });
+        Ok(GeoIpProcess { database })
+    }
+
+    /// Returns the country of `ip`, `None` if it's unknown or no database is loaded.
+    #[handle_request]
+    fn locate(&mut self, ip: IpAddr) -> Option<Location> {
+        self.database.as_ref()?.locate(ip)
+    }
+}
@@ -0,0 +1,32 @@
+//! The chat backend: networks, channels, accounts and their storage.
+//!
+//! Every network is run by a `coordinator::CoordinatorSup`, it starts the coordinator, the
+//! directory shards that own the channels and the processes they rely on. The backend has no user
+//! interface, frontends attach a session for every connected user and talk to it through the
+//! `protocol`. The telnet server is one such frontend, other lunatic applications can embed the
+//! backend and bring their own:
+//!
+//! ```ignore
+//! let name = coordinator::registry_name(&coordinator_config.network);
+//! CoordinatorSup::link().start(coordinator_config).unwrap();
+//! let coordinator = ProcessRef::<CoordinatorProcess>::lookup(&name).unwrap();
+//! ```
+
+pub mod channel;
+pub mod coordinator;
+pub mod directory;
+pub mod event_log;
+pub mod feed;
+pub mod filter;
+pub mod fun;
+pub mod geoip;
+pub mod mailbox;
+pub mod metrics;
+pub mod motd;
+pub mod password;
+pub mod paste;
+pub mod protocol;
+pub mod random;
+pub mod storage;
+pub mod templates;
+pub mod trace;
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use lunatic::{abstract_process, ap::Config};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{SessionEvent, SessionRef};
+
+// How many messages are kept for an account, the oldest are dropped first.
+const MAILBOX_LIMIT: usize = 100;
+
+/// Returns the name under which the mailbox of a network is registered.
+pub fn registry_name(network: &str) -> String {
+    format!("mailbox:{}", network)
+}
+
+/// A message sent from one user to another.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DirectMessage {
+    pub from: String,
+    /// If the sender identified with the account of its name.
+    pub identified: bool,
+    pub timestamp: String,
+    /// Hex encoded ciphertext if `sender_key` is set.
+    pub message: String,
+    /// The public key of the sender if the message is encrypted, see `/secure`.
+    pub sender_key: Option<[u8; 32]>,
+}
+
+/// The mailbox keeps direct messages for registered users that are offline.
+///
+/// The coordinator stores messages here if the recipient is not connected and identified. Once
+/// the recipient identifies, the coordinator asks the mailbox to deliver them. The mailbox runs
+/// next to the coordinator, so the messages survive a restart of the coordinator.
+pub struct MailboxProcess {
+    // Waiting messages of each lowercase account name, the oldest first.
+    messages: HashMap<String, Vec<DirectMessage>>,
+}
+
+#[abstract_process(visibility = pub)]
+impl MailboxProcess {
+    #[init]
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(MailboxProcess {
+            messages: HashMap::new(),
+        })
+    }
+
+    /// Keep a message until the account's owner identifies.
+    #[handle_message]
+    fn store(&mut self, account: String, message: DirectMessage) {
+        let messages = self.messages.entry(account).or_default();
+        messages.push(message);
+        if messages.len() > MAILBOX_LIMIT {
+            messages.remove(0);
+        }
+    }
+
+    /// Send all waiting messages of the account to the client and forget them.
+    #[handle_message]
+    fn deliver(&mut self, account: String, client: SessionRef) {
+        for message in self.messages.remove(&account).unwrap_or_default() {
+            client.send(SessionEvent::Direct(message));
+        }
+    }
+}
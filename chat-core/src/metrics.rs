@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use lunatic::{
+    abstract_process,
+    ap::{Config, ProcessRef},
+};
+use serde::{Deserialize, Serialize};
+
+// How many of the latest samples are kept for each request.
+const MAX_SAMPLES: usize = 1000;
+
+/// Returns the name under which the metrics process of a network is registered.
+pub fn registry_name(network: &str) -> String {
+    format!("metrics:{}", network)
+}
+
+/// Timing of a single request type.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RequestTiming {
+    pub request: String,
+    /// How many requests were handled in total.
+    pub count: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+}
+
+/// The metrics process collects how long the coordinator takes to handle each request.
+///
+/// It runs next to the coordinator, so that reporting a timing is a cheap message and reading
+/// the metrics doesn't wait on a busy coordinator.
+pub struct MetricsProcess {
+    // Total count and the latest samples of each request.
+    requests: HashMap<String, (u64, Vec<Duration>)>,
+}
+
+#[abstract_process(visibility = pub)]
+impl MetricsProcess {
+    #[init]
+    fn init(_: Config<Self>, _: ()) -> Result<Self, ()> {
+        Ok(MetricsProcess {
+            requests: HashMap::new(),
+        })
+    }
+
+    /// Record how long handling a request took.
+    #[handle_message]
+    fn record(&mut self, request: String, duration: Duration) {
+        let (count, samples) = self.requests.entry(request).or_default();
+        *count += 1;
+        samples.push(duration);
+        // Drop the older half of the samples, so that the percentiles follow recent load.
+        if samples.len() > MAX_SAMPLES {
+            samples.drain(0..MAX_SAMPLES / 2);
+        }
+    }
+
+    /// Returns the timing of all requests, sorted by name.
+    #[handle_request]
+    fn timings(&mut self) -> Vec<RequestTiming> {
+        let mut timings: Vec<RequestTiming> = self
+            .requests
+            .iter()
+            .map(|(request, (count, samples))| {
+                let mut sorted = samples.clone();
+                sorted.sort();
+                let percentile = |p: usize| sorted[(sorted.len() * p / 100).min(sorted.len() - 1)];
+                RequestTiming {
+                    request: request.clone(),
+                    count: *count,
+                    p50: percentile(50),
+                    p95: percentile(95),
+                }
+            })
+            .collect();
+        timings.sort_by(|a, b| a.request.cmp(&b.request));
+        timings
+    }
+}
+
+/// Measures a request and reports it to the metrics process when dropped.
+pub struct RequestTimer {
+    metrics: ProcessRef<MetricsProcess>,
+    request: &'static str,
+    start: Instant,
+}
+
+impl RequestTimer {
+    pub fn start(metrics: ProcessRef<MetricsProcess>, request: &'static str) -> Self {
+        RequestTimer {
+            metrics,
+            request,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for RequestTimer {
+    fn drop(&mut self) {
+        self.metrics
+            .record(self.request.to_owned(), self.start.elapsed());
+    }
+}
@@ -0,0 +1,36 @@
+//! Message of the day plugins.
+//!
+//! A plugin is a separate WASM module that exports a `motd` function. The function is spawned as
+//! a new process that receives a reference to the requesting process as its first message and
+//! replies with the text that should be shown on the welcome screen, e.g. the weather or the
+//! latest release notes.
+
+use std::time::Duration;
+
+use lunatic::{Mailbox, MailboxResult, Process, WasmModule};
+
+// How long a plugin can take to reply before it's killed.
+const PLUGIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Loads the plugin module from the file system.
+///
+/// The directory containing the plugin needs to be accessible to the server (`lunatic --dir`).
+pub fn load(path: &str) -> Option<WasmModule> {
+    let module = std::fs::read(path).ok()?;
+    WasmModule::new(&module).ok()
+}
+
+/// Spawns the plugin and waits for it to reply with the message of the day.
+///
+/// Returns `None` if the plugin fails or doesn't reply in time.
+pub fn generate(plugin: &WasmModule, mailbox: &Mailbox<String>) -> Option<String> {
+    let process: Process<Process<String>> = plugin.spawn("motd", &[]).ok()?;
+    process.send(mailbox.this());
+    match mailbox.receive_timeout(PLUGIN_TIMEOUT) {
+        MailboxResult::Message(motd) => Some(motd),
+        _ => {
+            process.kill();
+            None
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Password hashes of accounts.
+//!
+//! Passwords are hashed with PBKDF2-HMAC-SHA256 and a random salt per account. Hashes end up in
+//! the event log and the storage, the salt and the number of rounds are kept with them, so that
+//! the rounds can be raised later without breaking existing accounts.
+
+use pbkdf2::pbkdf2_hmac;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+// Rounds of new hashes. Hashing runs in the client process of the user, so this is a trade-off
+// between the cost of guessing and how long `/register` and `/identify` take.
+const ROUNDS: u32 = 100_000;
+const SALT_LENGTH: usize = 16;
+
+/// The salted hash of an account's password.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct PasswordHash {
+    pub rounds: u32,
+    pub salt: Vec<u8>,
+    pub hash: [u8; 32],
+}
+
+impl PasswordHash {
+    /// Hash the password with a new random salt.
+    pub fn new(password: &str) -> Self {
+        let mut salt = vec![0; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        Self::with_salt(password, salt, ROUNDS)
+    }
+
+    /// Returns `true` if the password matches the hash.
+    pub fn verify(&self, password: &str) -> bool {
+        let other = Self::with_salt(password, self.salt.clone(), self.rounds);
+        // Compare all bytes, so that the time doesn't tell how much of the hash matched
+        let difference = self
+            .hash
+            .iter()
+            .zip(other.hash.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        difference == 0
+    }
+
+    fn with_salt(password: &str, salt: Vec<u8>, rounds: u32) -> Self {
+        let mut hash = [0; 32];
+        pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, rounds, &mut hash);
+        PasswordHash { rounds, salt, hash }
+    }
+}
@@ -0,0 +1,28 @@
+//! Longer texts shared in a channel without flooding it, e.g. logs or code (`/paste`).
+//!
+//! The client collects the lines of a paste and saves it to the storage of the network. The
+//! channel only gets a short notice with the id, users open the paste in a tab with `/show <id>`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::random;
+
+/// Most lines of a paste.
+pub const MAX_LINES: usize = 500;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Paste {
+    /// Random, so that pastes of other channels can't be listed by counting.
+    pub id: String,
+    pub author: String,
+    /// The channel it was shared in.
+    pub channel: String,
+    /// Unix timestamp of when it was shared.
+    pub created: i64,
+    pub lines: Vec<String>,
+}
+
+/// Returns a new paste id, 16 lowercase hex digits.
+pub fn new_id() -> String {
+    random::hex(8)
+}
@@ -0,0 +1,105 @@
+//! Messages between the chat backend and the sessions of connected users.
+//!
+//! A session is whatever connects a user to the backend, e.g. the telnet client of the server
+//! binary. It calls the coordinator, directory and channel processes directly and hands them its
+//! `SessionRef`. The backend identifies the user by it and pushes everything that happens on its
+//! own as a `SessionEvent`.
+//!
+//! Sessions receive the events with a process of their own, usually linked to the session so
+//! that both die together:
+//!
+//! ```ignore
+//! let session = SessionRef::new(Process::spawn_link(client, |client, events: Mailbox<SessionEvent>| {
+//!     loop {
+//!         client.session_event(events.receive());
+//!     }
+//! }));
+//! coordinator.join_server(session, address, location, None);
+//! ```
+
+use lunatic::Process;
+use serde::{Deserialize, Serialize};
+
+pub use crate::channel::{ChannelMeta, ChatMessage};
+pub use crate::coordinator::ServerEvent;
+pub use crate::filter::FilterRule;
+pub use crate::mailbox::DirectMessage;
+pub use crate::templates::CustomTemplates;
+
+/// Something the backend tells a session without being asked.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum SessionEvent {
+    /// A batch of messages of a joined or followed channel, it has to be acknowledged with
+    /// `ChannelProcess::ack` once it's shown.
+    Messages(Vec<ChatMessage>),
+    /// The session fell too far behind acknowledging batches of the channel, the following ones
+    /// are skipped until it caught up.
+    MessagesSkipped(String),
+    /// The reply of a channel to `ChannelProcess::ping`.
+    Pong(String),
+    /// The current channel list of a namespace, after `CoordinatorProcess::subscribe_list`.
+    ChannelList {
+        namespace: String,
+        list: Vec<(String, usize, ChannelMeta)>,
+    },
+    /// Pushed after `CoordinatorProcess::subscribe_events`.
+    Server(ServerEvent),
+    Direct(DirectMessage),
+    Invitation {
+        from: String,
+        channel: String,
+    },
+    /// Another session of the same account joined a channel.
+    SessionJoined(String),
+    /// Another session of the same account left a channel.
+    SessionLeft(String),
+    /// A temporary channel the session joined or followed is closed in the given number of
+    /// minutes.
+    ChannelExpiring {
+        channel: String,
+        minutes: u64,
+    },
+    /// A temporary channel the session joined or followed was closed, see
+    /// `DirectoryProcess::expire_channel`.
+    ChannelExpired(String),
+    /// The session was banned from a channel it joined or followed, it's no longer part of it.
+    Banned(String),
+    /// A followed channel was shut down because its last member left.
+    ChannelClosed(String),
+    /// An administrator reloaded the word filters.
+    Filters(Vec<FilterRule>),
+    /// An administrator reloaded the custom screens.
+    Templates(CustomTemplates),
+    /// The server stops in the given number of seconds.
+    Draining(u64),
+    /// The session should disconnect, e.g. it was kicked.
+    Exit,
+    /// The session should panic, used to test recovery.
+    Crash,
+}
+
+/// A connected user as seen by the backend.
+///
+/// The id is unique across all sessions and stays the same for the whole connection.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct SessionRef(Process<SessionEvent>);
+
+impl SessionRef {
+    /// Wrap the process that receives the events of a session.
+    pub fn new(events: Process<SessionEvent>) -> Self {
+        SessionRef(events)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.0.id()
+    }
+
+    /// Stop the process that receives the events, once the session is gone.
+    pub fn kill(&self) {
+        self.0.kill();
+    }
+
+    pub fn send(&self, event: SessionEvent) {
+        self.0.send(event);
+    }
+}
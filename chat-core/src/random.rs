@@ -0,0 +1,22 @@
+//! Random values drawn from the random source of the host, for ids, tokens and games.
+
+use rand_core::{OsRng, RngCore};
+
+/// Returns `bytes` random bytes as lowercase hex digits.
+pub fn hex(bytes: usize) -> String {
+    let mut buffer = vec![0; bytes];
+    OsRng.fill_bytes(&mut buffer);
+    buffer.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Returns a random number below `bound`, each of them equally likely.
+pub fn below(bound: u32) -> u32 {
+    // Values from the incomplete last range of `bound` numbers would make low numbers more likely
+    let limit = u32::MAX - u32::MAX % bound;
+    loop {
+        let value = OsRng.next_u32();
+        if value < limit {
+            return value % bound;
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! File backend, every change is appended to a file as a JSON line.
+//!
+//! The file is read into a `MemoryStorage` when it's opened and all queries are answered from
+//! memory, so the same limits apply to how much history can be loaded.
+//!
+//! A crash while a line is appended can leave it incomplete. Such a last line is cut off when the
+//! file is opened, invalid lines before it are reported as a corrupt file.
+
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    memory::MemoryStorage, ChannelCheckpoint, ConnectionRecord, HistoryEntry, ModerationAction,
+    Storage,
+};
+use crate::channel::ChannelMeta;
+use crate::coordinator::AccountSettings;
+use crate::password::PasswordHash;
+use crate::paste::Paste;
+
+#[derive(Serialize, Deserialize)]
+enum Record {
+    Message {
+        channel: String,
+        entry: HistoryEntry,
+    },
+    ChannelMeta {
+        channel: String,
+        meta: ChannelMeta,
+    },
+    Checkpoint {
+        channel: String,
+        checkpoint: ChannelCheckpoint,
+    },
+    Account {
+        username: String,
+        password: PasswordHash,
+    },
+    Settings {
+        account: String,
+        settings: AccountSettings,
+    },
+    Moderation(ModerationAction),
+    Hook {
+        channel: String,
+        token: String,
+    },
+    HookRemoved {
+        channel: String,
+        token: String,
+    },
+    Connection(ConnectionRecord),
+    Paste(Paste),
+}
+
+// A line of the file, networks sharing the file only read their own records.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    network: String,
+    record: Record,
+}
+
+pub struct FileStorage {
+    path: String,
+    file: File,
+    network: String,
+    memory: MemoryStorage,
+}
+
+impl FileStorage {
+    pub fn open(path: &str, network: &str) -> Result<Self, String> {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(format!("can't read {}: {}", path, err)),
+        };
+        let mut memory = MemoryStorage::default();
+        // Bytes of the lines that were read, everything after them is cut off
+        let mut valid = 0;
+        let lines: Vec<&[u8]> = content.split_inclusive(|byte| *byte == b'\n').collect();
+        for (number, raw) in lines.iter().enumerate() {
+            let line = std::str::from_utf8(raw)
+                .map_err(|err| err.to_string())
+                .and_then(|line| {
+                    serde_json::from_str::<Line>(line.trim_end()).map_err(|err| err.to_string())
+                });
+            let line = match line {
+                Ok(line) => line,
+                Err(_) if number + 1 == lines.len() => {
+                    eprintln!("Dropped the incomplete last line of {}", path);
+                    break;
+                }
+                Err(err) => return Err(format!("{} line {}: {}", path, number + 1, err)),
+            };
+            if line.network == network {
+                apply(&mut memory, &line.record)?;
+            }
+            valid += raw.len();
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("can't open {}: {}", path, err))?;
+        if valid < content.len() {
+            file.set_len(valid as u64)
+                .map_err(|err| format!("can't repair {}: {}", path, err))?;
+        }
+        // A complete last line may still miss its line break
+        if valid > 0 && content[valid - 1] != b'\n' {
+            file.write_all(b"\n")
+                .map_err(|err| format!("can't repair {}: {}", path, err))?;
+        }
+        Ok(FileStorage {
+            path: path.to_owned(),
+            file,
+            network: network.to_owned(),
+            memory,
+        })
+    }
+
+    // Applies the record to the memory and appends it to the file, rejected records are not
+    // written.
+    fn write(&mut self, record: Record) -> Result<(), String> {
+        apply(&mut self.memory, &record)?;
+        let line = Line {
+            network: self.network.clone(),
+            record,
+        };
+        let mut json = serde_json::to_string(&line).map_err(|err| err.to_string())?;
+        json.push('\n');
+        // One write per line, so that lines of networks sharing the file don't interleave
+        self.file
+            .write_all(json.as_bytes())
+            .map_err(|err| format!("can't write {}: {}", self.path, err))
+    }
+}
+
+fn apply(memory: &mut MemoryStorage, record: &Record) -> Result<(), String> {
+    match record {
+        Record::Message { channel, entry } => memory.append_message(channel, entry),
+        Record::ChannelMeta { channel, meta } => memory.save_channel_meta(channel, meta),
+        Record::Checkpoint {
+            channel,
+            checkpoint,
+        } => memory.save_checkpoint(channel, checkpoint),
+        Record::Account { username, password } => memory.save_account(username, password),
+        Record::Settings { account, settings } => memory.save_settings(account, settings),
+        Record::Moderation(action) => memory.record_moderation(action),
+        Record::Hook { channel, token } => memory.save_hook(channel, token),
+        Record::HookRemoved { channel, token } => memory.remove_hook(channel, token),
+        Record::Connection(connection) => memory.record_connection(connection),
+        // A replayed paste whose id was taken before is left out
+        Record::Paste(paste) => memory.save_paste(paste).map(|_| ()),
+    }
+}
+
+impl Storage for FileStorage {
+    fn append_message(&mut self, channel: &str, entry: &HistoryEntry) -> Result<(), String> {
+        self.write(Record::Message {
+            channel: channel.to_owned(),
+            entry: entry.clone(),
+        })
+    }
+
+    fn load_recent(&mut self, channel: &str, limit: usize) -> Result<Vec<HistoryEntry>, String> {
+        self.memory.load_recent(channel, limit)
+    }
+
+    fn load_range(
+        &mut self,
+        channel: &str,
+        before: u64,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, String> {
+        self.memory.load_range(channel, before, limit)
+    }
+
+    fn load_since(
+        &mut self,
+        channel: &str,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, String> {
+        self.memory.load_since(channel, since, limit)
+    }
+
+    // Later checkpoints replace earlier ones when the file is read again
+    fn save_checkpoint(
+        &mut self,
+        channel: &str,
+        checkpoint: &ChannelCheckpoint,
+    ) -> Result<(), String> {
+        self.write(Record::Checkpoint {
+            channel: channel.to_owned(),
+            checkpoint: checkpoint.clone(),
+        })
+    }
+
+    fn load_checkpoint(&mut self, channel: &str) -> Result<Option<ChannelCheckpoint>, String> {
+        self.memory.load_checkpoint(channel)
+    }
+
+    fn save_channel_meta(&mut self, channel: &str, meta: &ChannelMeta) -> Result<(), String> {
+        self.write(Record::ChannelMeta {
+            channel: channel.to_owned(),
+            meta: meta.clone(),
+        })
+    }
+
+    fn load_channel_meta(&mut self, channel: &str) -> Result<Option<ChannelMeta>, String> {
+        self.memory.load_channel_meta(channel)
+    }
+
+    fn save_account(&mut self, username: &str, password: &PasswordHash) -> Result<(), String> {
+        self.write(Record::Account {
+            username: username.to_owned(),
+            password: password.clone(),
+        })
+    }
+
+    fn load_accounts(&mut self) -> Result<Vec<(String, PasswordHash)>, String> {
+        self.memory.load_accounts()
+    }
+
+    // Later settings replace earlier ones when the file is read again
+    fn save_settings(&mut self, account: &str, settings: &AccountSettings) -> Result<(), String> {
+        self.write(Record::Settings {
+            account: account.to_owned(),
+            settings: settings.clone(),
+        })
+    }
+
+    fn load_settings(&mut self) -> Result<Vec<(String, AccountSettings)>, String> {
+        self.memory.load_settings()
+    }
+
+    fn record_moderation(&mut self, action: &ModerationAction) -> Result<(), String> {
+        self.write(Record::Moderation(action.clone()))
+    }
+
+    fn load_blocks(&mut self) -> Result<Vec<(String, String)>, String> {
+        self.memory.load_blocks()
+    }
+
+    fn load_bans(&mut self) -> Result<Vec<(String, String)>, String> {
+        self.memory.load_bans()
+    }
+
+    fn save_hook(&mut self, channel: &str, token: &str) -> Result<(), String> {
+        self.write(Record::Hook {
+            channel: channel.to_owned(),
+            token: token.to_owned(),
+        })
+    }
+
+    fn remove_hook(&mut self, channel: &str, token: &str) -> Result<(), String> {
+        self.write(Record::HookRemoved {
+            channel: channel.to_owned(),
+            token: token.to_owned(),
+        })
+    }
+
+    fn load_hooks(&mut self) -> Result<Vec<(String, String)>, String> {
+        self.memory.load_hooks()
+    }
+
+    fn record_connection(&mut self, connection: &ConnectionRecord) -> Result<(), String> {
+        self.write(Record::Connection(connection.clone()))
+    }
+
+    fn load_connections(
+        &mut self,
+        user: &str,
+        limit: usize,
+    ) -> Result<Vec<ConnectionRecord>, String> {
+        self.memory.load_connections(user, limit)
+    }
+
+    fn save_paste(&mut self, paste: &Paste) -> Result<bool, String> {
+        if self.memory.load_paste(&paste.id)?.is_some() {
+            return Ok(false);
+        }
+        self.write(Record::Paste(paste.clone()))?;
+        Ok(true)
+    }
+
+    fn load_paste(&mut self, id: &str) -> Result<Option<Paste>, String> {
+        self.memory.load_paste(id)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.file
+            .sync_all()
+            .map_err(|err| format!("can't sync {}: {}", self.path, err))
+    }
+}
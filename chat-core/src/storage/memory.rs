@@ -0,0 +1,273 @@
+//! In-memory backend, the data is lost when the server stops.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use super::{ChannelCheckpoint, ConnectionRecord, HistoryEntry, ModerationAction, Storage};
+use crate::channel::ChannelMeta;
+use crate::coordinator::AccountSettings;
+use crate::password::PasswordHash;
+use crate::paste::Paste;
+
+// How many messages are kept for each channel, the oldest half is dropped when it's reached.
+const MESSAGE_LIMIT: usize = 10_000;
+// Estimated bytes of the messages of all channels together. Once it's reached, the oldest half of
+// the channel that got no message for the longest time is dropped.
+const TOTAL_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+// How many moderation actions are kept, the oldest first.
+const MODERATION_LIMIT: usize = 1000;
+// How many connections are kept, the oldest half is dropped when it's reached.
+const CONNECTION_LIMIT: usize = 10_000;
+// How many pastes are kept, the oldest first.
+const PASTE_LIMIT: usize = 1000;
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    // Messages of each channel with increasing ids, the oldest first.
+    messages: HashMap<String, Vec<HistoryEntry>>,
+    // Estimated bytes of all kept messages, see `entry_size`.
+    message_size: usize,
+    // When each channel got its last message, counted in appended messages.
+    last_append: HashMap<String, u64>,
+    appended: u64,
+    meta: HashMap<String, ChannelMeta>,
+    checkpoints: HashMap<String, ChannelCheckpoint>,
+    accounts: HashMap<String, PasswordHash>,
+    settings: HashMap<String, AccountSettings>,
+    moderation: Vec<ModerationAction>,
+    // What the moderation actions left behind, they are kept even after the actions are dropped.
+    blocks: BTreeSet<(String, String)>,
+    bans: BTreeSet<(String, String)>,
+    hooks: BTreeSet<(String, String)>,
+    // The oldest first.
+    connections: Vec<ConnectionRecord>,
+    pastes: HashMap<String, Paste>,
+    // Ids of the pastes, the oldest first.
+    paste_order: VecDeque<String>,
+}
+
+impl Storage for MemoryStorage {
+    fn append_message(&mut self, channel: &str, entry: &HistoryEntry) -> Result<(), String> {
+        let messages = self.messages.entry(channel.to_owned()).or_default();
+        // Ids continue from the stored history, a reopened channel doesn't reuse them
+        if messages.last().map_or(false, |(id, _)| *id >= entry.0) {
+            return Err(format!(
+                "message {} of {} is already stored",
+                entry.0, channel
+            ));
+        }
+        messages.push(entry.clone());
+        self.message_size += entry_size(entry);
+        if messages.len() > MESSAGE_LIMIT {
+            let dropped: usize = messages
+                .drain(0..MESSAGE_LIMIT / 2)
+                .map(|e| entry_size(&e))
+                .sum();
+            self.message_size -= dropped;
+        }
+        self.appended += 1;
+        self.last_append.insert(channel.to_owned(), self.appended);
+        while self.message_size > TOTAL_MESSAGE_SIZE {
+            self.drop_least_active();
+        }
+        Ok(())
+    }
+
+    fn load_recent(&mut self, channel: &str, limit: usize) -> Result<Vec<HistoryEntry>, String> {
+        self.load_range(channel, u64::MAX, limit)
+    }
+
+    fn load_range(
+        &mut self,
+        channel: &str,
+        before: u64,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, String> {
+        let messages = match self.messages.get(channel) {
+            Some(messages) => messages,
+            None => return Ok(Vec::new()),
+        };
+        let end = messages.partition_point(|(id, _)| *id < before);
+        let start = end.saturating_sub(limit);
+        Ok(messages[start..end].to_vec())
+    }
+
+    fn load_since(
+        &mut self,
+        channel: &str,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, String> {
+        let messages = match self.messages.get(channel) {
+            Some(messages) => messages,
+            None => return Ok(Vec::new()),
+        };
+        let start = messages.partition_point(|(id, _)| *id < since);
+        let start = start.max(messages.len().saturating_sub(limit));
+        Ok(messages[start..].to_vec())
+    }
+
+    fn save_checkpoint(
+        &mut self,
+        channel: &str,
+        checkpoint: &ChannelCheckpoint,
+    ) -> Result<(), String> {
+        self.checkpoints
+            .insert(channel.to_owned(), checkpoint.clone());
+        Ok(())
+    }
+
+    fn load_checkpoint(&mut self, channel: &str) -> Result<Option<ChannelCheckpoint>, String> {
+        Ok(self.checkpoints.get(channel).cloned())
+    }
+
+    fn save_channel_meta(&mut self, channel: &str, meta: &ChannelMeta) -> Result<(), String> {
+        self.meta.insert(channel.to_owned(), meta.clone());
+        Ok(())
+    }
+
+    fn load_channel_meta(&mut self, channel: &str) -> Result<Option<ChannelMeta>, String> {
+        Ok(self.meta.get(channel).cloned())
+    }
+
+    fn save_account(&mut self, username: &str, password: &PasswordHash) -> Result<(), String> {
+        self.accounts.insert(username.to_owned(), password.clone());
+        Ok(())
+    }
+
+    fn load_accounts(&mut self) -> Result<Vec<(String, PasswordHash)>, String> {
+        Ok(self
+            .accounts
+            .iter()
+            .map(|(username, password)| (username.clone(), password.clone()))
+            .collect())
+    }
+
+    fn save_settings(&mut self, account: &str, settings: &AccountSettings) -> Result<(), String> {
+        self.settings.insert(account.to_owned(), settings.clone());
+        Ok(())
+    }
+
+    fn load_settings(&mut self) -> Result<Vec<(String, AccountSettings)>, String> {
+        Ok(self
+            .settings
+            .iter()
+            .map(|(account, settings)| (account.clone(), settings.clone()))
+            .collect())
+    }
+
+    fn record_moderation(&mut self, action: &ModerationAction) -> Result<(), String> {
+        match action {
+            ModerationAction::Ban { channel, username } => {
+                self.bans.insert((channel.clone(), username.clone()));
+            }
+            ModerationAction::Block { account, username } => {
+                self.blocks.insert((account.clone(), username.clone()));
+            }
+            ModerationAction::Unblock { account, username } => {
+                self.blocks.remove(&(account.clone(), username.clone()));
+            }
+        }
+        self.moderation.push(action.clone());
+        if self.moderation.len() > MODERATION_LIMIT {
+            self.moderation.remove(0);
+        }
+        Ok(())
+    }
+
+    fn load_blocks(&mut self) -> Result<Vec<(String, String)>, String> {
+        Ok(self.blocks.iter().cloned().collect())
+    }
+
+    fn load_bans(&mut self) -> Result<Vec<(String, String)>, String> {
+        Ok(self.bans.iter().cloned().collect())
+    }
+
+    fn save_hook(&mut self, channel: &str, token: &str) -> Result<(), String> {
+        self.hooks.insert((channel.to_owned(), token.to_owned()));
+        Ok(())
+    }
+
+    fn remove_hook(&mut self, channel: &str, token: &str) -> Result<(), String> {
+        self.hooks.remove(&(channel.to_owned(), token.to_owned()));
+        Ok(())
+    }
+
+    fn load_hooks(&mut self) -> Result<Vec<(String, String)>, String> {
+        Ok(self.hooks.iter().cloned().collect())
+    }
+
+    fn record_connection(&mut self, connection: &ConnectionRecord) -> Result<(), String> {
+        self.connections.push(connection.clone());
+        if self.connections.len() > CONNECTION_LIMIT {
+            self.connections.drain(0..CONNECTION_LIMIT / 2);
+        }
+        Ok(())
+    }
+
+    fn load_connections(
+        &mut self,
+        user: &str,
+        limit: usize,
+    ) -> Result<Vec<ConnectionRecord>, String> {
+        Ok(self
+            .connections
+            .iter()
+            .rev()
+            .filter(|connection| connection.matches(user))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn save_paste(&mut self, paste: &Paste) -> Result<bool, String> {
+        if self.pastes.contains_key(&paste.id) {
+            return Ok(false);
+        }
+        self.pastes.insert(paste.id.clone(), paste.clone());
+        self.paste_order.push_back(paste.id.clone());
+        if self.paste_order.len() > PASTE_LIMIT {
+            if let Some(oldest) = self.paste_order.pop_front() {
+                self.pastes.remove(&oldest);
+            }
+        }
+        Ok(true)
+    }
+
+    fn load_paste(&mut self, id: &str) -> Result<Option<Paste>, String> {
+        Ok(self.pastes.get(id).cloned())
+    }
+}
+
+impl MemoryStorage {
+    // Drop the oldest half of the messages of the channel that got no message for the longest
+    // time, all of them if only one is left.
+    fn drop_least_active(&mut self) {
+        let channel = match self
+            .last_append
+            .iter()
+            .min_by_key(|(_, appended)| **appended)
+        {
+            Some((channel, _)) => channel.clone(),
+            None => return,
+        };
+        let messages = match self.messages.get_mut(&channel) {
+            Some(messages) => messages,
+            None => {
+                self.last_append.remove(&channel);
+                return;
+            }
+        };
+        let end = (messages.len() / 2).max(1);
+        let dropped: usize = messages.drain(0..end).map(|e| entry_size(&e)).sum();
+        self.message_size -= dropped;
+        if messages.is_empty() {
+            self.messages.remove(&channel);
+            self.last_append.remove(&channel);
+        }
+    }
+}
+
+// Estimated bytes a stored message takes up.
+fn entry_size((_, (timestamp, name, text, _)): &HistoryEntry) -> usize {
+    std::mem::size_of::<HistoryEntry>() + timestamp.len() + name.len() + text.len()
+}
@@ -0,0 +1,376 @@
+//! Persistence of messages, channel descriptions and checkpoints, accounts, moderation actions,
+//! connections and pastes (`--storage`).
+//!
+//! The event log only keeps state while the server is running. The storage backend keeps channel
+//! messages and descriptions, registered accounts with their settings and block lists, channel
+//! bans, webhook tokens, moderation actions, the connection history of users and pastes, so that
+//! channels can load their history when they are opened again and the coordinator and directory
+//! know the accounts and bans of previous runs. Backends are selected with `--storage`:
+//!
+//! ```text
+//! memory          kept until the server stops (default)
+//! file:PATH       appended as JSON lines to a file
+//! sqlite:PATH     written to a SQLite database (sqlite feature)
+//! ```
+//!
+//! Backends implement the `Storage` trait. They are owned by the `StorageProcess` of a network, so
+//! that channels and the coordinator never block on the backend directly.
+
+mod file;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::net::SocketAddr;
+
+use lunatic::{abstract_process, ap::Config};
+use serde::{Deserialize, Serialize};
+
+use crate::channel::{Announcement, ChannelMeta};
+use crate::coordinator::AccountSettings;
+use crate::feed::Feed;
+use crate::password::PasswordHash;
+use crate::paste::Paste;
+
+/// Returns the name under which the storage process of a network is registered.
+pub fn registry_name(network: &str) -> String {
+    format!("storage:{}", network)
+}
+
+/// A message of a channel's history with its id: timestamp, username, text and if the sender was
+/// identified with the account of the username.
+pub type HistoryEntry = (u64, (String, String, String, bool));
+
+/// An action taken against a user, kept for later review.
+///
+/// The backends also keep the block lists and bans the actions leave behind, see
+/// `Storage::load_blocks` and `Storage::load_bans`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ModerationAction {
+    /// The channel operator banned a username from the channel.
+    Ban { channel: String, username: String },
+    /// An account blocked a username.
+    Block { account: String, username: String },
+    /// An account unblocked a username.
+    Unblock { account: String, username: String },
+}
+
+/// A connection of a client, listed by `/history`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ConnectionRecord {
+    /// All usernames used during the connection, the first one was generated.
+    pub usernames: Vec<String>,
+    /// Lowercase username of the account the client identified with.
+    pub account: Option<String>,
+    pub address: SocketAddr,
+    /// Unix timestamp of when the client connected.
+    pub connected: i64,
+    /// How many seconds the client stayed, `None` while it's still connected.
+    pub duration: Option<u64>,
+}
+
+impl ConnectionRecord {
+    /// If the connection belongs to `user`, which is a username, an account or an IP address.
+    pub fn matches(&self, user: &str) -> bool {
+        self.usernames
+            .iter()
+            .any(|username| username.eq_ignore_ascii_case(user))
+            || self.account.as_deref() == Some(&user.to_lowercase())
+            || self.address.ip().to_string() == user
+    }
+}
+
+/// The state of a channel saved every few minutes, see `ChannelProcess::checkpoint`.
+///
+/// A reopened channel starts from its checkpoint and only loads the messages that were stored
+/// after it. The description is not part of it, it's saved on every change already.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChannelCheckpoint {
+    /// Id of the first message that is not part of the checkpoint.
+    pub next_message_id: u64,
+    /// Lowercase account of the operator, if it was identified.
+    pub operator: Option<String>,
+    /// The newest messages, the oldest first.
+    pub history: Vec<HistoryEntry>,
+    /// Scheduled announcements, missing in checkpoints of older versions.
+    #[serde(default)]
+    pub announcements: Vec<Announcement>,
+    /// Polled feeds, missing in checkpoints of older versions.
+    #[serde(default)]
+    pub feeds: Vec<Feed>,
+    /// Messages with a lower id expired, missing in checkpoints of older versions.
+    #[serde(default)]
+    pub expired_before: u64,
+    /// When messages arrived as ids with Unix timestamps in seconds, see `ChannelProcess`.
+    /// Missing in checkpoints of older versions.
+    #[serde(default)]
+    pub arrivals: Vec<(u64, u64)>,
+}
+
+/// Which backend stores the data of the networks.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum StorageConfig {
+    Memory,
+    /// Path of the JSON lines file, all networks can share the same file.
+    File(String),
+    /// Path of the SQLite database, all networks can share the same file.
+    Sqlite(String),
+}
+
+/// A persistence backend of a network.
+pub trait Storage {
+    /// Add a message to the history of a channel.
+    fn append_message(&mut self, channel: &str, entry: &HistoryEntry) -> Result<(), String>;
+    /// Returns up to `limit` of the newest messages of a channel, the oldest first.
+    fn load_recent(&mut self, channel: &str, limit: usize) -> Result<Vec<HistoryEntry>, String>;
+    /// Returns up to `limit` messages of a channel older than the message with id `before`, the
+    /// oldest first.
+    fn load_range(
+        &mut self,
+        channel: &str,
+        before: u64,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, String>;
+    /// Returns up to `limit` of the newest messages of a channel with an id of at least `since`,
+    /// the oldest first.
+    fn load_since(
+        &mut self,
+        channel: &str,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, String>;
+    /// Replace the checkpoint of a channel.
+    fn save_checkpoint(
+        &mut self,
+        channel: &str,
+        checkpoint: &ChannelCheckpoint,
+    ) -> Result<(), String>;
+    fn load_checkpoint(&mut self, channel: &str) -> Result<Option<ChannelCheckpoint>, String>;
+    fn save_channel_meta(&mut self, channel: &str, meta: &ChannelMeta) -> Result<(), String>;
+    fn load_channel_meta(&mut self, channel: &str) -> Result<Option<ChannelMeta>, String>;
+    /// Save a registered lowercase username and its password hash.
+    fn save_account(&mut self, username: &str, password: &PasswordHash) -> Result<(), String>;
+    /// Returns all saved accounts.
+    fn load_accounts(&mut self) -> Result<Vec<(String, PasswordHash)>, String>;
+    /// Save the settings of a lowercase account, they replace the saved ones.
+    fn save_settings(&mut self, account: &str, settings: &AccountSettings) -> Result<(), String>;
+    /// Returns the saved settings of all accounts.
+    fn load_settings(&mut self) -> Result<Vec<(String, AccountSettings)>, String>;
+    fn record_moderation(&mut self, action: &ModerationAction) -> Result<(), String>;
+    /// Returns the accounts and the lowercase usernames they blocked and didn't unblock since.
+    fn load_blocks(&mut self) -> Result<Vec<(String, String)>, String>;
+    /// Returns the channels and the usernames banned from them.
+    fn load_bans(&mut self) -> Result<Vec<(String, String)>, String>;
+    /// Save a webhook token of a channel.
+    fn save_hook(&mut self, channel: &str, token: &str) -> Result<(), String>;
+    /// Forget a revoked webhook token of a channel.
+    fn remove_hook(&mut self, channel: &str, token: &str) -> Result<(), String>;
+    /// Returns the channels and their webhook tokens.
+    fn load_hooks(&mut self) -> Result<Vec<(String, String)>, String>;
+    /// Add a finished connection to the history.
+    fn record_connection(&mut self, connection: &ConnectionRecord) -> Result<(), String>;
+    /// Returns up to `limit` of the newest connections that match `user`, the newest first.
+    fn load_connections(
+        &mut self,
+        user: &str,
+        limit: usize,
+    ) -> Result<Vec<ConnectionRecord>, String>;
+    /// Returns `false` if a paste with the same id exists already, it's not replaced.
+    fn save_paste(&mut self, paste: &Paste) -> Result<bool, String>;
+    fn load_paste(&mut self, id: &str) -> Result<Option<Paste>, String>;
+    /// Make sure all writes reached the disk, called before the server stops.
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Opens the configured backend for a network.
+pub fn open(config: &StorageConfig, network: &str) -> Result<Box<dyn Storage>, String> {
+    match config {
+        StorageConfig::Memory => Ok(Box::new(memory::MemoryStorage::default())),
+        StorageConfig::File(path) => Ok(Box::new(file::FileStorage::open(path, network)?)),
+        StorageConfig::Sqlite(path) => open_sqlite(path, network),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite(path: &str, network: &str) -> Result<Box<dyn Storage>, String> {
+    Ok(Box::new(sqlite::SqliteStorage::open(path, network)?))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_sqlite(_: &str, _: &str) -> Result<Box<dyn Storage>, String> {
+    Err("the server was built without the sqlite feature".to_owned())
+}
+
+/// The storage process owns the storage backend of a network.
+///
+/// It runs next to the coordinator. Writes are messages, so that a slow backend doesn't slow down
+/// channels.
+pub struct StorageProcess {
+    storage: Box<dyn Storage>,
+}
+
+#[abstract_process(visibility = pub)]
+impl StorageProcess {
+    #[init]
+    fn init(_: Config<Self>, args: (String, StorageConfig)) -> Result<Self, ()> {
+        let (network, config) = args;
+        // The backend was checked on startup, but may have become unavailable since
+        let storage = open(&config, &network).unwrap_or_else(|err| {
+            eprintln!("Storage of network {} unavailable: {}", network, err);
+            Box::new(memory::MemoryStorage::default())
+        });
+        Ok(StorageProcess { storage })
+    }
+
+    #[handle_message]
+    fn append_message(&mut self, channel: String, entry: HistoryEntry) {
+        self.run(|storage| storage.append_message(&channel, &entry));
+    }
+
+    /// Returns up to `limit` of the newest stored messages of a channel, the oldest first.
+    #[handle_request]
+    fn load_recent(&mut self, channel: String, limit: usize) -> Vec<HistoryEntry> {
+        self.run(|storage| storage.load_recent(&channel, limit))
+            .unwrap_or_default()
+    }
+
+    /// Returns up to `limit` stored messages of a channel older than `before`, the oldest first.
+    #[handle_request]
+    fn load_range(&mut self, channel: String, before: u64, limit: usize) -> Vec<HistoryEntry> {
+        self.run(|storage| storage.load_range(&channel, before, limit))
+            .unwrap_or_default()
+    }
+
+    /// Returns up to `limit` of the newest stored messages of a channel with an id of at least
+    /// `since`, the oldest first.
+    #[handle_request]
+    fn load_since(&mut self, channel: String, since: u64, limit: usize) -> Vec<HistoryEntry> {
+        self.run(|storage| storage.load_since(&channel, since, limit))
+            .unwrap_or_default()
+    }
+
+    #[handle_message]
+    fn save_checkpoint(&mut self, channel: String, checkpoint: ChannelCheckpoint) {
+        self.run(|storage| storage.save_checkpoint(&channel, &checkpoint));
+    }
+
+    #[handle_request]
+    fn checkpoint(&mut self, channel: String) -> Option<ChannelCheckpoint> {
+        self.run(|storage| storage.load_checkpoint(&channel))
+            .flatten()
+    }
+
+    #[handle_message]
+    fn save_channel_meta(&mut self, channel: String, meta: ChannelMeta) {
+        self.run(|storage| storage.save_channel_meta(&channel, &meta));
+    }
+
+    #[handle_request]
+    fn channel_meta(&mut self, channel: String) -> Option<ChannelMeta> {
+        self.run(|storage| storage.load_channel_meta(&channel))
+            .flatten()
+    }
+
+    #[handle_message]
+    fn save_account(&mut self, username: String, password: PasswordHash) {
+        self.run(|storage| storage.save_account(&username, &password));
+    }
+
+    /// Returns the lowercase usernames and password hashes of all stored accounts.
+    #[handle_request]
+    fn accounts(&mut self) -> Vec<(String, PasswordHash)> {
+        self.run(|storage| storage.load_accounts())
+            .unwrap_or_default()
+    }
+
+    #[handle_message]
+    fn save_settings(&mut self, account: String, settings: AccountSettings) {
+        self.run(|storage| storage.save_settings(&account, &settings));
+    }
+
+    /// Returns the lowercase accounts and settings of all stored accounts.
+    #[handle_request]
+    fn settings(&mut self) -> Vec<(String, AccountSettings)> {
+        self.run(|storage| storage.load_settings())
+            .unwrap_or_default()
+    }
+
+    #[handle_message]
+    fn record_moderation(&mut self, action: ModerationAction) {
+        self.run(|storage| storage.record_moderation(&action));
+    }
+
+    /// Returns the accounts and the usernames they blocked.
+    #[handle_request]
+    fn blocks(&mut self) -> Vec<(String, String)> {
+        self.run(|storage| storage.load_blocks())
+            .unwrap_or_default()
+    }
+
+    /// Returns the channels and the usernames banned from them.
+    #[handle_request]
+    fn bans(&mut self) -> Vec<(String, String)> {
+        self.run(|storage| storage.load_bans()).unwrap_or_default()
+    }
+
+    #[handle_message]
+    fn save_hook(&mut self, channel: String, token: String) {
+        self.run(|storage| storage.save_hook(&channel, &token));
+    }
+
+    #[handle_message]
+    fn remove_hook(&mut self, channel: String, token: String) {
+        self.run(|storage| storage.remove_hook(&channel, &token));
+    }
+
+    /// Returns the channels and their webhook tokens.
+    #[handle_request]
+    fn hooks(&mut self) -> Vec<(String, String)> {
+        self.run(|storage| storage.load_hooks()).unwrap_or_default()
+    }
+
+    #[handle_message]
+    fn record_connection(&mut self, connection: ConnectionRecord) {
+        self.run(|storage| storage.record_connection(&connection));
+    }
+
+    /// Returns up to `limit` of the newest stored connections of a username, account or IP
+    /// address, the newest first.
+    #[handle_request]
+    fn connections(&mut self, user: String, limit: usize) -> Vec<ConnectionRecord> {
+        self.run(|storage| storage.load_connections(&user, limit))
+            .unwrap_or_default()
+    }
+
+    /// Returns `false` if the paste wasn't saved, e.g. because its id is taken.
+    #[handle_request]
+    fn save_paste(&mut self, paste: Paste) -> bool {
+        self.run(|storage| storage.save_paste(&paste))
+            .unwrap_or(false)
+    }
+
+    #[handle_request]
+    fn paste(&mut self, id: String) -> Option<Paste> {
+        self.run(|storage| storage.load_paste(&id)).flatten()
+    }
+
+    /// Returns after all previous writes were flushed by the backend.
+    #[handle_request]
+    fn flush(&mut self) {
+        self.run(|storage| storage.flush());
+    }
+}
+
+impl StorageProcess {
+    // Runs an operation on the backend, failures are logged and don't stop the server.
+    fn run<T>(
+        &mut self,
+        operation: impl FnOnce(&mut dyn Storage) -> Result<T, String>,
+    ) -> Option<T> {
+        operation(self.storage.as_mut())
+            .map_err(|err| eprintln!("Storage error: {}", err))
+            .ok()
+    }
+}
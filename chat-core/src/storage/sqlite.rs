@@ -0,0 +1,512 @@
+//! SQLite backend, using the database support of the lunatic runtime.
+
+use chrono::Utc;
+use lunatic::sqlite::{BindValue, Query, SqliteClient, SqliteRow, SqliteValue};
+
+use super::{ChannelCheckpoint, ConnectionRecord, HistoryEntry, ModerationAction, Storage};
+use crate::channel::ChannelMeta;
+use crate::coordinator::AccountSettings;
+use crate::password::PasswordHash;
+use crate::paste::Paste;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS messages (
+        network   TEXT NOT NULL,
+        channel   TEXT NOT NULL,
+        id        INTEGER NOT NULL,
+        timestamp TEXT NOT NULL,
+        name      TEXT NOT NULL,
+        message   TEXT NOT NULL,
+        identified INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (network, channel, id)
+    );
+    CREATE TABLE IF NOT EXISTS channels (
+        network     TEXT NOT NULL,
+        channel     TEXT NOT NULL,
+        language    TEXT NOT NULL,
+        description TEXT NOT NULL,
+        PRIMARY KEY (network, channel)
+    );
+    CREATE TABLE IF NOT EXISTS checkpoints (
+        network    TEXT NOT NULL,
+        channel    TEXT NOT NULL,
+        checkpoint TEXT NOT NULL,
+        PRIMARY KEY (network, channel)
+    );
+    CREATE TABLE IF NOT EXISTS accounts (
+        network  TEXT NOT NULL,
+        username TEXT NOT NULL,
+        password TEXT NOT NULL,
+        PRIMARY KEY (network, username)
+    );
+    CREATE TABLE IF NOT EXISTS settings (
+        network  TEXT NOT NULL,
+        account  TEXT NOT NULL,
+        settings TEXT NOT NULL,
+        PRIMARY KEY (network, account)
+    );
+    CREATE TABLE IF NOT EXISTS moderation (
+        network  TEXT NOT NULL,
+        time     TEXT NOT NULL,
+        action   TEXT NOT NULL,
+        target   TEXT NOT NULL,
+        username TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS blocks (
+        network  TEXT NOT NULL,
+        account  TEXT NOT NULL,
+        username TEXT NOT NULL,
+        PRIMARY KEY (network, account, username)
+    );
+    CREATE TABLE IF NOT EXISTS bans (
+        network  TEXT NOT NULL,
+        channel  TEXT NOT NULL,
+        username TEXT NOT NULL,
+        PRIMARY KEY (network, channel, username)
+    );
+    CREATE TABLE IF NOT EXISTS hooks (
+        network TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        token   TEXT NOT NULL,
+        PRIMARY KEY (network, channel, token)
+    );
+    CREATE TABLE IF NOT EXISTS connections (
+        network   TEXT NOT NULL,
+        connected INTEGER NOT NULL,
+        duration  INTEGER NOT NULL,
+        ip        TEXT NOT NULL,
+        address   TEXT NOT NULL,
+        account   TEXT NOT NULL,
+        usernames TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS pastes (
+        network TEXT NOT NULL,
+        id      TEXT NOT NULL,
+        author  TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        created INTEGER NOT NULL,
+        content TEXT NOT NULL,
+        PRIMARY KEY (network, id)
+    );
+";
+
+/// Keeps the data of one network in a SQLite database, rows are tagged with the network's name.
+pub struct SqliteStorage {
+    client: SqliteClient,
+    network: String,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str, network: &str) -> Result<Self, String> {
+        let client =
+            SqliteClient::connect(path).map_err(|err| format!("can't open {}: {:?}", path, err))?;
+        client
+            .execute(SCHEMA)
+            .map_err(|err| format!("can't create tables in {}: {:?}", path, err))?;
+        Ok(SqliteStorage {
+            client,
+            network: network.to_owned(),
+        })
+    }
+
+    // Prepares a query with the network's name bound to the first parameter.
+    fn query(&self, sql: &str) -> Query {
+        self.client
+            .prepare_query(sql)
+            .bind(BindValue::Text(self.network.clone()))
+    }
+
+    // Runs a query of the network that selects two text columns.
+    fn pairs(&self, sql: &str) -> Result<Vec<(String, String)>, String> {
+        let rows = self
+            .query(sql)
+            .execute()
+            .map_err(|err| format!("can't load rows: {:?}", err))?;
+        rows.iter()
+            .map(|row| Ok((text(row, 0)?, text(row, 1)?)))
+            .collect()
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn append_message(&mut self, channel: &str, entry: &HistoryEntry) -> Result<(), String> {
+        let (id, (timestamp, name, message, identified)) = entry;
+        self.query(
+            "INSERT OR REPLACE INTO messages
+             (network, channel, id, timestamp, name, message, identified)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(BindValue::Text(channel.to_owned()))
+        .bind(BindValue::Int64(*id as i64))
+        .bind(BindValue::Text(timestamp.clone()))
+        .bind(BindValue::Text(name.clone()))
+        .bind(BindValue::Text(message.clone()))
+        .bind(BindValue::Int64(*identified as i64))
+        .execute()
+        .map(|_| ())
+        .map_err(|err| format!("can't store message: {:?}", err))
+    }
+
+    fn load_recent(&mut self, channel: &str, limit: usize) -> Result<Vec<HistoryEntry>, String> {
+        self.load_range(channel, i64::MAX as u64, limit)
+    }
+
+    fn load_range(
+        &mut self,
+        channel: &str,
+        before: u64,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, String> {
+        let rows = self
+            .query(
+                "SELECT id, timestamp, name, message, identified FROM messages
+                 WHERE network = ? AND channel = ? AND id < ? ORDER BY id DESC LIMIT ?",
+            )
+            .bind(BindValue::Text(channel.to_owned()))
+            .bind(BindValue::Int64(before.min(i64::MAX as u64) as i64))
+            .bind(BindValue::Int64(limit as i64))
+            .execute()
+            .map_err(|err| format!("can't load messages: {:?}", err))?;
+        messages(rows)
+    }
+
+    fn load_since(
+        &mut self,
+        channel: &str,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<HistoryEntry>, String> {
+        let rows = self
+            .query(
+                "SELECT id, timestamp, name, message, identified FROM messages
+                 WHERE network = ? AND channel = ? AND id >= ? ORDER BY id DESC LIMIT ?",
+            )
+            .bind(BindValue::Text(channel.to_owned()))
+            .bind(BindValue::Int64(since.min(i64::MAX as u64) as i64))
+            .bind(BindValue::Int64(limit as i64))
+            .execute()
+            .map_err(|err| format!("can't load messages: {:?}", err))?;
+        messages(rows)
+    }
+
+    // The checkpoint is stored as JSON, it's only ever read as a whole
+    fn save_checkpoint(
+        &mut self,
+        channel: &str,
+        checkpoint: &ChannelCheckpoint,
+    ) -> Result<(), String> {
+        let json = serde_json::to_string(checkpoint).map_err(|err| err.to_string())?;
+        self.query(
+            "INSERT OR REPLACE INTO checkpoints (network, channel, checkpoint) VALUES (?, ?, ?)",
+        )
+        .bind(BindValue::Text(channel.to_owned()))
+        .bind(BindValue::Text(json))
+        .execute()
+        .map(|_| ())
+        .map_err(|err| format!("can't save checkpoint: {:?}", err))
+    }
+
+    fn load_checkpoint(&mut self, channel: &str) -> Result<Option<ChannelCheckpoint>, String> {
+        let rows = self
+            .query("SELECT checkpoint FROM checkpoints WHERE network = ? AND channel = ?")
+            .bind(BindValue::Text(channel.to_owned()))
+            .execute()
+            .map_err(|err| format!("can't load checkpoint: {:?}", err))?;
+        rows.first()
+            .map(|row| {
+                serde_json::from_str(&text(row, 0)?)
+                    .map_err(|err| format!("invalid checkpoint of {}: {}", channel, err))
+            })
+            .transpose()
+    }
+
+    fn save_channel_meta(&mut self, channel: &str, meta: &ChannelMeta) -> Result<(), String> {
+        self.query(
+            "INSERT OR REPLACE INTO channels (network, channel, language, description)
+             VALUES (?, ?, ?, ?)",
+        )
+        .bind(BindValue::Text(channel.to_owned()))
+        .bind(BindValue::Text(meta.language.clone()))
+        .bind(BindValue::Text(meta.description.clone()))
+        .execute()
+        .map(|_| ())
+        .map_err(|err| format!("can't save channel description: {:?}", err))
+    }
+
+    fn load_channel_meta(&mut self, channel: &str) -> Result<Option<ChannelMeta>, String> {
+        let rows = self
+            .query("SELECT language, description FROM channels WHERE network = ? AND channel = ?")
+            .bind(BindValue::Text(channel.to_owned()))
+            .execute()
+            .map_err(|err| format!("can't load channel description: {:?}", err))?;
+        rows.first()
+            .map(|row| {
+                Ok(ChannelMeta {
+                    language: text(row, 0)?,
+                    description: text(row, 1)?,
+                })
+            })
+            .transpose()
+    }
+
+    fn save_account(&mut self, username: &str, password: &PasswordHash) -> Result<(), String> {
+        // The hash is stored as JSON, together with its salt and rounds
+        let password = serde_json::to_string(password).map_err(|err| err.to_string())?;
+        self.query("INSERT OR REPLACE INTO accounts (network, username, password) VALUES (?, ?, ?)")
+            .bind(BindValue::Text(username.to_owned()))
+            .bind(BindValue::Text(password))
+            .execute()
+            .map(|_| ())
+            .map_err(|err| format!("can't store account: {:?}", err))
+    }
+
+    fn load_accounts(&mut self) -> Result<Vec<(String, PasswordHash)>, String> {
+        let rows = self
+            .query("SELECT username, password FROM accounts WHERE network = ?")
+            .execute()
+            .map_err(|err| format!("can't load accounts: {:?}", err))?;
+        rows.iter()
+            .map(|row| {
+                let password =
+                    serde_json::from_str(&text(row, 1)?).map_err(|err| err.to_string())?;
+                Ok((text(row, 0)?, password))
+            })
+            .collect()
+    }
+
+    // The settings are stored as JSON, they are only ever read as a whole
+    fn save_settings(&mut self, account: &str, settings: &AccountSettings) -> Result<(), String> {
+        let json = serde_json::to_string(settings).map_err(|err| err.to_string())?;
+        self.query("INSERT OR REPLACE INTO settings (network, account, settings) VALUES (?, ?, ?)")
+            .bind(BindValue::Text(account.to_owned()))
+            .bind(BindValue::Text(json))
+            .execute()
+            .map(|_| ())
+            .map_err(|err| format!("can't save settings: {:?}", err))
+    }
+
+    fn load_settings(&mut self) -> Result<Vec<(String, AccountSettings)>, String> {
+        let rows = self
+            .query("SELECT account, settings FROM settings WHERE network = ?")
+            .execute()
+            .map_err(|err| format!("can't load settings: {:?}", err))?;
+        rows.iter()
+            .map(|row| {
+                let settings =
+                    serde_json::from_str(&text(row, 1)?).map_err(|err| err.to_string())?;
+                Ok((text(row, 0)?, settings))
+            })
+            .collect()
+    }
+
+    // The action is logged, and the block list or bans it changes are updated
+    fn record_moderation(&mut self, action: &ModerationAction) -> Result<(), String> {
+        let (kind, target, username, sql) = match action {
+            ModerationAction::Ban { channel, username } => (
+                "ban",
+                channel,
+                username,
+                "INSERT OR IGNORE INTO bans (network, channel, username) VALUES (?, ?, ?)",
+            ),
+            ModerationAction::Block { account, username } => (
+                "block",
+                account,
+                username,
+                "INSERT OR IGNORE INTO blocks (network, account, username) VALUES (?, ?, ?)",
+            ),
+            ModerationAction::Unblock { account, username } => (
+                "unblock",
+                account,
+                username,
+                "DELETE FROM blocks WHERE network = ? AND account = ? AND username = ?",
+            ),
+        };
+        self.query(
+            "INSERT INTO moderation (network, time, action, target, username)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(BindValue::Text(Utc::now().to_rfc3339()))
+        .bind(BindValue::Text(kind.to_owned()))
+        .bind(BindValue::Text(target.clone()))
+        .bind(BindValue::Text(username.clone()))
+        .execute()
+        .map_err(|err| format!("can't store moderation action: {:?}", err))?;
+        self.query(sql)
+            .bind(BindValue::Text(target.clone()))
+            .bind(BindValue::Text(username.clone()))
+            .execute()
+            .map(|_| ())
+            .map_err(|err| format!("can't store moderation action: {:?}", err))
+    }
+
+    fn load_blocks(&mut self) -> Result<Vec<(String, String)>, String> {
+        self.pairs("SELECT account, username FROM blocks WHERE network = ?")
+    }
+
+    fn load_bans(&mut self) -> Result<Vec<(String, String)>, String> {
+        self.pairs("SELECT channel, username FROM bans WHERE network = ?")
+    }
+
+    fn save_hook(&mut self, channel: &str, token: &str) -> Result<(), String> {
+        self.query("INSERT OR IGNORE INTO hooks (network, channel, token) VALUES (?, ?, ?)")
+            .bind(BindValue::Text(channel.to_owned()))
+            .bind(BindValue::Text(token.to_owned()))
+            .execute()
+            .map(|_| ())
+            .map_err(|err| format!("can't store webhook token: {:?}", err))
+    }
+
+    fn remove_hook(&mut self, channel: &str, token: &str) -> Result<(), String> {
+        self.query("DELETE FROM hooks WHERE network = ? AND channel = ? AND token = ?")
+            .bind(BindValue::Text(channel.to_owned()))
+            .bind(BindValue::Text(token.to_owned()))
+            .execute()
+            .map(|_| ())
+            .map_err(|err| format!("can't remove webhook token: {:?}", err))
+    }
+
+    fn load_hooks(&mut self) -> Result<Vec<(String, String)>, String> {
+        self.pairs("SELECT channel, token FROM hooks WHERE network = ?")
+    }
+
+    fn record_connection(&mut self, connection: &ConnectionRecord) -> Result<(), String> {
+        // Usernames are stored as `,alice,Bob,`, so that a name can be matched with its commas.
+        // An empty account means that the client never identified.
+        let usernames = format!(",{},", connection.usernames.join(","));
+        self.query(
+            "INSERT INTO connections (network, connected, duration, ip, address, account, usernames)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(BindValue::Int64(connection.connected))
+        .bind(BindValue::Int64(connection.duration.unwrap_or(0) as i64))
+        .bind(BindValue::Text(connection.address.ip().to_string()))
+        .bind(BindValue::Text(connection.address.to_string()))
+        .bind(BindValue::Text(connection.account.clone().unwrap_or_default()))
+        .bind(BindValue::Text(usernames))
+        .execute()
+        .map(|_| ())
+        .map_err(|err| format!("can't store connection: {:?}", err))
+    }
+
+    fn load_connections(
+        &mut self,
+        user: &str,
+        limit: usize,
+    ) -> Result<Vec<ConnectionRecord>, String> {
+        let lowercase = user.to_lowercase();
+        let rows = self
+            .query(
+                "SELECT connected, duration, address, account, usernames FROM connections
+                 WHERE network = ? AND (account = ? OR ip = ? OR instr(lower(usernames), ?) > 0)
+                 ORDER BY connected DESC LIMIT ?",
+            )
+            .bind(BindValue::Text(lowercase.clone()))
+            .bind(BindValue::Text(user.to_owned()))
+            .bind(BindValue::Text(format!(",{},", lowercase)))
+            .bind(BindValue::Int64(limit as i64))
+            .execute()
+            .map_err(|err| format!("can't load connections: {:?}", err))?;
+        rows.iter()
+            .map(|row| {
+                let address = text(row, 2)?;
+                let account = text(row, 3)?;
+                let usernames = text(row, 4)?;
+                Ok(ConnectionRecord {
+                    connected: integer(row, 0)?,
+                    duration: Some(integer(row, 1)? as u64),
+                    address: address
+                        .parse()
+                        .map_err(|_| format!("invalid address {}", address))?,
+                    account: (!account.is_empty()).then_some(account),
+                    usernames: usernames
+                        .trim_matches(',')
+                        .split(',')
+                        .map(str::to_owned)
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+
+    // The lines are stored as one text, separated by newlines
+    fn save_paste(&mut self, paste: &Paste) -> Result<bool, String> {
+        if self.load_paste(&paste.id)?.is_some() {
+            return Ok(false);
+        }
+        self.query(
+            "INSERT INTO pastes (network, id, author, channel, created, content)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(BindValue::Text(paste.id.clone()))
+        .bind(BindValue::Text(paste.author.clone()))
+        .bind(BindValue::Text(paste.channel.clone()))
+        .bind(BindValue::Int64(paste.created))
+        .bind(BindValue::Text(paste.lines.join("\n")))
+        .execute()
+        .map(|_| true)
+        .map_err(|err| format!("can't store paste: {:?}", err))
+    }
+
+    fn load_paste(&mut self, id: &str) -> Result<Option<Paste>, String> {
+        let rows = self
+            .query(
+                "SELECT author, channel, created, content FROM pastes WHERE network = ? AND id = ?",
+            )
+            .bind(BindValue::Text(id.to_owned()))
+            .execute()
+            .map_err(|err| format!("can't load paste: {:?}", err))?;
+        rows.first()
+            .map(|row| {
+                Ok(Paste {
+                    id: id.to_owned(),
+                    author: text(row, 0)?,
+                    channel: text(row, 1)?,
+                    created: integer(row, 2)?,
+                    lines: text(row, 3)?.split('\n').map(str::to_owned).collect(),
+                })
+            })
+            .transpose()
+    }
+}
+
+// Converts rows of `id, timestamp, name, message, identified` sorted by descending id to history
+// entries.
+fn messages(rows: Vec<SqliteRow>) -> Result<Vec<HistoryEntry>, String> {
+    let mut entries = rows
+        .iter()
+        .map(|row| {
+            Ok((
+                integer(row, 0)? as u64,
+                (
+                    text(row, 1)?,
+                    text(row, 2)?,
+                    text(row, 3)?,
+                    integer(row, 4)? != 0,
+                ),
+            ))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    entries.reverse();
+    Ok(entries)
+}
+
+fn text(row: &SqliteRow, column: usize) -> Result<String, String> {
+    match row.get_column(column) {
+        Some(SqliteValue::Text(text)) => Ok(text.clone()),
+        other => Err(format!(
+            "expected text in column {}, got {:?}",
+            column, other
+        )),
+    }
+}
+
+fn integer(row: &SqliteRow, column: usize) -> Result<i64, String> {
+    match row.get_column(column) {
+        Some(SqliteValue::I64(value)) => Ok(*value),
+        Some(SqliteValue::Integer(value)) => Ok(*value as i64),
+        other => Err(format!(
+            "expected an integer in column {}, got {:?}",
+            column, other
+        )),
+    }
+}
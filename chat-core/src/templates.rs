@@ -0,0 +1,108 @@
+//! Screens customized by the server operator (`--template-dir`).
+//!
+//! The built-in screens are askama templates compiled into the server. With the
+//! `runtime-templates` feature, the welcome, instructions and channel list screens can be replaced
+//! by [minijinja](https://docs.rs/minijinja) templates read from a directory, one subdirectory per
+//! language:
+//!
+//! ```text
+//! templates/en/welcome.txt       username, clients, channels, motd
+//! templates/en/instructions.txt  commands
+//! templates/es/list.txt          list: [{ name, users, language, description }]
+//! ```
+//!
+//! The `username` of the welcome screen is empty while the client chooses one (`--nick-prompt`).
+//! Missing files fall back to the built-in screen. Administrators can reload the directory with
+//! `/rehash`.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Language of a client's screens and messages.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Locale {
+    En,
+    Es,
+}
+
+impl Locale {
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "en" => Some(Locale::En),
+            "es" => Some(Locale::Es),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Es => "es",
+        }
+    }
+}
+
+/// Screens that can be replaced.
+pub const NAMES: [&str; 3] = ["welcome.txt", "instructions.txt", "list.txt"];
+
+/// Template sources loaded from the template directory, keyed by `<language>/<name>`.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct CustomTemplates {
+    sources: HashMap<String, String>,
+}
+
+impl CustomTemplates {
+    /// Reads and validates all templates of the directory.
+    pub fn load(dir: &str) -> Result<Self, String> {
+        if !cfg!(feature = "runtime-templates") {
+            return Err("the server was built without the runtime-templates feature".to_owned());
+        }
+        let mut sources = HashMap::new();
+        for locale in [Locale::En, Locale::Es] {
+            for name in NAMES {
+                let key = format!("{}/{}", locale.code(), name);
+                let path = format!("{}/{}", dir, key);
+                let source = match std::fs::read_to_string(&path) {
+                    Ok(source) => source,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(format!("can't read {}: {}", path, err)),
+                };
+                check(&source).map_err(|err| format!("{}: {}", path, err))?;
+                sources.insert(key, source);
+            }
+        }
+        Ok(CustomTemplates { sources })
+    }
+
+    /// Renders the operator's template of a screen, `None` if there is none or it fails.
+    pub fn render<S: Serialize>(&self, locale: Locale, name: &str, context: S) -> Option<String> {
+        let source = self.sources.get(&format!("{}/{}", locale.code(), name))?;
+        render(source, context)
+    }
+}
+
+#[cfg(feature = "runtime-templates")]
+fn check(source: &str) -> Result<(), String> {
+    let env = minijinja::Environment::new();
+    env.template_from_str(source)
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "runtime-templates"))]
+fn check(_: &str) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(feature = "runtime-templates")]
+fn render<S: Serialize>(source: &str, context: S) -> Option<String> {
+    minijinja::Environment::new()
+        .render_str(source, context)
+        .ok()
+}
+
+#[cfg(not(feature = "runtime-templates"))]
+fn render<S: Serialize>(_: &str, _: S) -> Option<String> {
+    None
+}
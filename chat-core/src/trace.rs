@@ -0,0 +1,26 @@
+//! Tracing of messages through the broadcast pipeline.
+//!
+//! If tracing is enabled for a network (`--trace`), each chat message gets an id when it enters
+//! the system. Every process that handles the message logs the id together with a timestamp, so
+//! that the latency of each hop can be reconstructed from the server output.
+
+use chrono::Utc;
+
+/// Returns a new trace id for the `n`th message sent by a process.
+pub fn id(process: u64, n: u64) -> String {
+    format!("{}-{}", process, n)
+}
+
+/// Log that the traced message reached a `stage` of the pipeline, e.g. `channel`.
+///
+/// Messages without a trace id are ignored.
+pub fn log(trace: &Option<String>, stage: &str) {
+    if let Some(id) = trace {
+        println!(
+            "[trace] {} {} {}",
+            Utc::now().format("%H:%M:%S%.6f"),
+            id,
+            stage
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Large letters for `/banner`, drawn with `#` in a built-in figlet-style font.
+//!
+//! Each glyph is 5 columns wide and 5 rows high. Lowercase letters are drawn as uppercase and
+//! characters without a glyph as `?`:
+//!
+//! ```text
+//!  ###  #   # #####
+//! #   # #   #   #
+//! #   # #   #   #
+//! #   # #   #   #
+//!  ###   ###    #
+//! ```
+
+/// Characters of a banner, longer texts are cut so that it fits narrow terminals.
+pub const MAX_LENGTH: usize = 8;
+
+const HEIGHT: usize = 5;
+
+type Glyph = [&'static str; HEIGHT];
+
+/// Returns the rows of the banner, without trailing spaces.
+pub fn render(text: &str) -> Vec<String> {
+    let glyphs: Vec<Glyph> = text
+        .chars()
+        .take(MAX_LENGTH)
+        .map(|ch| glyph(ch.to_ascii_uppercase()))
+        .collect();
+    (0..HEIGHT)
+        .map(|row| {
+            let line: Vec<&str> = glyphs.iter().map(|glyph| glyph[row]).collect();
+            line.join(" ").trim_end().to_owned()
+        })
+        .collect()
+}
+
+fn glyph(ch: char) -> Glyph {
+    match ch {
+        'A' => [" ### ", "#   #", "#####", "#   #", "#   #"],
+        'B' => ["#### ", "#   #", "#### ", "#   #", "#### "],
+        'C' => [" ####", "#    ", "#    ", "#    ", " ####"],
+        'D' => ["#### ", "#   #", "#   #", "#   #", "#### "],
+        'E' => ["#####", "#    ", "#### ", "#    ", "#####"],
+        'F' => ["#####", "#    ", "#### ", "#    ", "#    "],
+        'G' => [" ####", "#    ", "#  ##", "#   #", " ####"],
+        'H' => ["#   #", "#   #", "#####", "#   #", "#   #"],
+        'I' => ["#####", "  #  ", "  #  ", "  #  ", "#####"],
+        'J' => ["#####", "   # ", "   # ", "#  # ", " ##  "],
+        'K' => ["#   #", "#  # ", "###  ", "#  # ", "#   #"],
+        'L' => ["#    ", "#    ", "#    ", "#    ", "#####"],
+        'M' => ["#   #", "## ##", "# # #", "#   #", "#   #"],
+        'N' => ["#   #", "##  #", "# # #", "#  ##", "#   #"],
+        'O' => [" ### ", "#   #", "#   #", "#   #", " ### "],
+        'P' => ["#### ", "#   #", "#### ", "#    ", "#    "],
+        'Q' => [" ### ", "#   #", "# # #", "#  # ", " ## #"],
+        'R' => ["#### ", "#   #", "#### ", "#  # ", "#   #"],
+        'S' => [" ####", "#    ", " ### ", "    #", "#### "],
+        'T' => ["#####", "  #  ", "  #  ", "  #  ", "  #  "],
+        'U' => ["#   #", "#   #", "#   #", "#   #", " ### "],
+        'V' => ["#   #", "#   #", "#   #", " # # ", "  #  "],
+        'W' => ["#   #", "#   #", "# # #", "## ##", "#   #"],
+        'X' => ["#   #", " # # ", "  #  ", " # # ", "#   #"],
+        'Y' => ["#   #", " # # ", "  #  ", "  #  ", "  #  "],
+        'Z' => ["#####", "   # ", "  #  ", " #   ", "#####"],
+        '0' => [" ### ", "#  ##", "# # #", "##  #", " ### "],
+        '1' => ["  #  ", " ##  ", "  #  ", "  #  ", " ### "],
+        '2' => [" ### ", "#   #", "  ## ", " #   ", "#####"],
+        '3' => ["#### ", "    #", " ### ", "    #", "#### "],
+        '4' => ["#   #", "#   #", "#####", "    #", "    #"],
+        '5' => ["#####", "#    ", "#### ", "    #", "#### "],
+        '6' => [" ### ", "#    ", "#### ", "#   #", " ### "],
+        '7' => ["#####", "    #", "   # ", "  #  ", "  #  "],
+        '8' => [" ### ", "#   #", " ### ", "#   #", " ### "],
+        '9' => [" ### ", "#   #", " ####", "    #", " ### "],
+        ' ' => ["     ", "     ", "     ", "     ", "     "],
+        '!' => ["  #  ", "  #  ", "  #  ", "     ", "  #  "],
+        '.' => ["     ", "     ", "     ", "     ", "  #  "],
+        ',' => ["     ", "     ", "     ", "  #  ", " #   "],
+        ':' => ["     ", "  #  ", "     ", "  #  ", "     "],
+        '-' => ["     ", "     ", " ### ", "     ", "     "],
+        '#' => [" # # ", "#####", " # # ", "#####", " # # "],
+        _ => [" ### ", "#   #", "  ## ", "     ", "  #  "],
+    }
+}
@@ -0,0 +1,220 @@
+//! Benchmarks of the hot paths of a connection (`--bench`).
+//!
+//! Every keystroke of every client goes through the telnet parser and every render through the
+//! backend's escape sequence encoder. Both are measured over fixed inputs, so that numbers of two
+//! builds can be compared. The benchmarks run in the same lunatic runtime as the server:
+//!
+//! ```bash
+//! > cargo build --release --target=wasm32-wasi
+//! > lunatic target/wasm32-wasi/release/telnet-chat.wasm --bench
+//! ```
+
+use std::hint::black_box;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use tui::{
+    buffer::{Buffer, Cell},
+    layout::Rect,
+    style::{Color, Modifier, Style},
+};
+
+use crate::telnet::Telnet;
+use crate::ui::telnet_backend::draw_cells;
+
+// Each benchmark repeats its input until it ran at least this long.
+const MIN_DURATION: Duration = Duration::from_secs(2);
+// Largest sequence the parser buffers, the default of `--max-input`.
+const MAX_INPUT: usize = 16 * 1024;
+// Payload of a full TCP segment on an ethernet link.
+const SEGMENT: usize = 1460;
+// Size of the synthetic frames, a common terminal window.
+const WIDTH: u16 = 120;
+const HEIGHT: u16 = 40;
+
+/// Runs all benchmarks and prints one line per benchmark.
+pub fn run() {
+    println!(
+        "{:<32} {:>12} {:>14} {:>12}",
+        "benchmark", "runs", "time/run", "MB/s"
+    );
+    bench_telnet("telnet typing", &typing(), 1);
+    bench_telnet("telnet paste", &paste(), SEGMENT);
+    bench_telnet("telnet utf-8", &utf8(), SEGMENT);
+    bench_telnet("telnet escape sequences", &escapes(), 64);
+    bench_telnet("telnet negotiation", &negotiation(), SEGMENT);
+
+    let empty = Buffer::empty(Rect::new(0, 0, WIDTH, HEIGHT));
+    let chat = chat_frame("Hello there, how is everyone doing today?");
+    bench_draw("draw full chat frame", &empty, &chat);
+    bench_draw(
+        "draw wide characters",
+        &empty,
+        &chat_frame("こんにちは、元気ですか？ 🙂"),
+    );
+    let typed = chat_frame("Hello there, how is everyone doing today!");
+    bench_draw("draw keystroke diff", &chat, &typed);
+}
+
+// Parses `input` delivered in reads of at most `chunk` bytes, like packets of a connection.
+fn bench_telnet(name: &str, input: &[u8], chunk: usize) {
+    measure(name, input.len(), || {
+        let mut telnet = Telnet::new(Replay { input, chunk }, MAX_INPUT);
+        // The parser fails once the input is used up, like on a closed connection
+        let mut messages = 0;
+        while let Ok(message) = telnet.next() {
+            black_box(message);
+            messages += 1;
+        }
+        messages
+    });
+}
+
+// Encodes the cells that differ between two frames, like a render of the UI.
+fn bench_draw(name: &str, previous: &Buffer, next: &Buffer) {
+    let diff: Vec<(u16, u16, &Cell)> = previous.diff(next);
+    let mut buffer = Vec::new();
+    let mut bytes = 0;
+    measure(name, 0, || {
+        buffer.clear();
+        draw_cells(&mut buffer, diff.iter().copied());
+        bytes = buffer.len();
+        black_box(&buffer);
+        bytes
+    });
+    println!("{:<32} {:>12} bytes per frame", "", bytes);
+}
+
+// Runs `f` repeatedly and prints the time per run. `bytes` is the input size of one run, it's
+// used for the throughput.
+fn measure(name: &str, bytes: usize, mut f: impl FnMut() -> usize) {
+    // Warm up allocations and caches
+    black_box(f());
+    let start = Instant::now();
+    let mut runs = 0u32;
+    while start.elapsed() < MIN_DURATION {
+        black_box(f());
+        runs += 1;
+    }
+    let per_run = start.elapsed() / runs;
+    let throughput = if bytes > 0 {
+        format!("{:.1}", bytes as f64 / per_run.as_secs_f64() / 1_000_000.0)
+    } else {
+        "-".to_owned()
+    };
+    println!(
+        "{:<32} {:>12} {:>14?} {:>12}",
+        name, runs, per_run, throughput
+    );
+}
+
+// A client connection that sends the input in chunks and discards everything written to it.
+struct Replay<'a> {
+    input: &'a [u8],
+    chunk: usize,
+}
+
+impl<'a> Read for Replay<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.chunk.min(buf.len()).min(self.input.len());
+        buf[..len].copy_from_slice(&self.input[..len]);
+        self.input = &self.input[len..];
+        Ok(len)
+    }
+}
+
+impl<'a> Write for Replay<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Chat messages typed one key at a time, with an occasional correction.
+fn typing() -> Vec<u8> {
+    let mut input = Vec::new();
+    for _ in 0..100 {
+        input.extend_from_slice(b"hey, did anyone try the new release yet?");
+        input.extend_from_slice(&[127, 127, b'?']);
+        input.extend_from_slice(b"\r\0");
+    }
+    input
+}
+
+// Long lines pasted at once.
+fn paste() -> Vec<u8> {
+    let line = "The quick brown fox jumps over the lazy dog. ".repeat(40);
+    let mut input = Vec::new();
+    for _ in 0..50 {
+        input.extend_from_slice(line.as_bytes());
+        input.extend_from_slice(b"\r\n");
+    }
+    input
+}
+
+// Multi-byte characters, they are parsed one byte at a time.
+fn utf8() -> Vec<u8> {
+    let mut input = Vec::new();
+    for _ in 0..200 {
+        input.extend_from_slice("Grüße aus Zürich! 你好，世界 🦀🦀🦀".as_bytes());
+        input.extend_from_slice(b"\r\n");
+    }
+    input
+}
+
+// Navigation keys, e.g. scrolling through the history.
+fn escapes() -> Vec<u8> {
+    let keys: [&[u8]; 8] = [
+        b"\x1b[A",
+        b"\x1b[B",
+        b"\x1b[5~",
+        b"\x1b[6~",
+        b"\x1bOP",
+        b"\x1b[15~",
+        b"\x1b1",
+        b"\x1b",
+    ];
+    let mut input = Vec::new();
+    for _ in 0..500 {
+        for key in keys {
+            input.extend_from_slice(key);
+        }
+    }
+    input
+}
+
+// Window size reports while a window is resized, with escaped 255 bytes, and other commands.
+fn negotiation() -> Vec<u8> {
+    let mut input = Vec::new();
+    for width in 0..2000u16 {
+        input.extend_from_slice(&[255, 250, 31]);
+        for byte in width.to_be_bytes().iter().chain(&[0, 255]) {
+            input.push(*byte);
+            if *byte == 255 {
+                input.push(255);
+            }
+        }
+        input.extend_from_slice(&[255, 240]);
+        input.extend_from_slice(&[255, 241, 255, 253, 99]);
+    }
+    input
+}
+
+// A channel tab full of messages with colored timestamps and bold usernames.
+fn chat_frame(text: &str) -> Buffer {
+    let mut buffer = Buffer::empty(Rect::new(0, 0, WIDTH, HEIGHT));
+    let timestamp = Style::default().fg(Color::Yellow);
+    let username = Style::default().add_modifier(Modifier::BOLD);
+    for y in 0..HEIGHT {
+        let width = WIDTH as usize;
+        let x = buffer.set_stringn(0, y, "[12:34:56] ", width, timestamp).0;
+        let x = buffer
+            .set_stringn(x, y, format!("user{}: ", y % 7), width, username)
+            .0;
+        buffer.set_stringn(x, y, text, width, Style::default());
+    }
+    buffer
+}
@@ -1,250 +1,2581 @@
-use std::process::exit;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
-use crate::channel::ChannelProcessRequests;
-use crate::coordinator::{
-    CoordinatorProcess, CoordinatorProcessMessages, CoordinatorProcessRequests,
+use chat_core::channel::{
+    self, Announcement, AnnouncementError, ChannelMeta, ChannelProcess, ChannelProcessMessages,
+    ChannelProcessRequests, ChatMessage,
 };
-use crate::telnet::Telnet;
-use crate::ui::telnet_backend::WindowSize;
-use crate::ui::{Tab, TabType, Ui, UiTabs};
-use crate::{
-    telnet::TelnetMessage::{self, *},
-    ui::telnet_backend,
+use chat_core::coordinator::{
+    self, AccountSettings, BlockError, BlockList, BroadcastError, ConnectionsError,
+    CoordinatorProcess, CoordinatorProcessMessages, CoordinatorProcessRequests, Delivery,
+    DirectMessageError, DumpError, JoinError, PasswordError, RehashError, ServerEvent,
+    UsernameError, WhoisError, HEARTBEAT_INTERVAL, LIST_PUSH_INTERVAL,
 };
-use askama::Template;
-use chrono::{DateTime, Local};
+use chat_core::directory::{
+    self, Directory, DirectoryProcess, DirectoryProcessMessages, DirectoryProcessRequests,
+    FollowError,
+};
+use chat_core::feed::FeedError;
+use chat_core::filter::{self, FilterRule, Filters};
+use chat_core::geoip::{self, GeoIpProcess, GeoIpProcessRequests, Location};
+use chat_core::mailbox::DirectMessage;
+use chat_core::password::PasswordHash;
+use chat_core::paste::{self, Paste};
+use chat_core::protocol::{SessionEvent, SessionRef};
+use chat_core::storage::{
+    self, HistoryEntry, StorageProcess, StorageProcessMessages, StorageProcessRequests,
+};
+use chat_core::templates::CustomTemplates;
+use chat_core::trace;
+
+use crate::banner;
+use crate::commands::{self, Permissions};
+use crate::frontend::{
+    Connection, Frontend,
+    Input::{self, *},
+    Key, TelnetFrontend,
+};
+use crate::i18n::{self, Locale, Text};
+use crate::recording;
+use crate::secure::KeyPair;
+use crate::telnet::Capabilities;
+use crate::ui::telnet_backend::{WindowSize, FALLBACK_WINDOW_SIZE};
+use crate::ui::{history::History, CursorMove, Tab, TabType, UiConfig, UiTabs};
+use chrono::{DateTime, Local, Utc};
 use lunatic::ap::{Config, ProcessRef};
-use lunatic::{abstract_process, Process};
-use lunatic::{net::TcpStream, Mailbox};
-use serde::{Deserialize, Serialize};
-
-// The template for the welcome screen.
-#[derive(Template)]
-#[template(path = "welcome.txt", escape = "none")]
-#[derive(Serialize, Deserialize, Clone)]
-struct Welcome {
-    username: String,
-    clients: usize,
+use lunatic::Mailbox;
+use lunatic::{abstract_process, AbstractProcess, Process};
+
+// How many decoded messages are kept in the "Keys" tab.
+const KEYS_DEBUG_LINES: usize = 20;
+// How many messages are fetched from a channel's history at once.
+const HISTORY_PAGE: usize = 20;
+// How many missing messages of a channel are fetched at once after a gap in the ids.
+const RESYNC_LIMIT: usize = 100;
+// How many server events are kept in the "Server" tab.
+const SERVER_EVENTS_LIMIT: usize = 50;
+// How long a client waits between two banners.
+const BANNER_INTERVAL: Duration = Duration::from_secs(60);
+// How many ids are tried before a paste isn't shared.
+const PASTE_ATTEMPTS: u32 = 3;
+// Ctrl+C only quits if it's pressed again within this time.
+const QUIT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(3);
+
+// How many times a named process is looked up before giving up.
+const LOOKUP_ATTEMPTS: u32 = 5;
+// The delay before the second lookup attempt, doubled after each failed attempt.
+const LOOKUP_BACKOFF: Duration = Duration::from_millis(50);
+
+// Look up a named process, retrying with an exponential backoff if it's not registered yet.
+fn lookup_with_retry<T: AbstractProcess>(name: &str) -> Option<ProcessRef<T>> {
+    let mut delay = LOOKUP_BACKOFF;
+    for attempt in 1..=LOOKUP_ATTEMPTS {
+        if let Some(process) = ProcessRef::<T>::lookup(name) {
+            return Some(process);
+        }
+        if attempt < LOOKUP_ATTEMPTS {
+            lunatic::sleep(delay);
+            delay *= 2;
+        }
+    }
+    None
+}
+
+// Which messages of a channel count as unread and can ring the bell, set with `/notify-level`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NotifyLevel {
+    All,
+    Mentions,
+    Silent,
+}
+
+// Returns the stable reference to a channel message, e.g. `#general/1234`.
+fn permalink(channel: &str, id: u64) -> String {
+    format!("{}/{}", channel, id)
+}
+
+// Parses a reference created by `permalink`. Channel names can contain `/` themselves.
+fn parse_permalink(link: &str) -> Option<(String, u64)> {
+    let (channel, id) = link.rsplit_once('/')?;
+    Some((channel.to_owned(), id.parse().ok()?))
+}
+
+// Turn a page of channel history into lines of a tab, identified senders are marked.
+fn history_lines(page: Vec<HistoryEntry>) -> Vec<(u64, (String, String, String))> {
+    page.into_iter()
+        .map(|(id, (timestamp, name, message, identified))| {
+            (
+                id,
+                (timestamp, channel::display_name(&name, identified), message),
+            )
+        })
+        .collect()
+}
+
+// A line shown in a channel tab that doesn't come from a user.
+fn system_line(text: String) -> (String, String, String) {
+    (String::new(), "*".to_string(), text)
+}
+
+// Returns if a page of channel history starts at the oldest message that didn't expire yet, or
+// no message is visible at all.
+fn reaches_expired(page: &[HistoryEntry], expired_before: Option<u64>) -> bool {
+    match (expired_before, page.first()) {
+        (Some(boundary), Some((id, _))) => *id <= boundary,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
 }
 
-// The template for the list of all channels screen.
-#[derive(Template)]
-#[template(path = "list.txt", escape = "none")]
-struct ChannelList {
-    list: Vec<(String, usize)>,
+// Returns the text explaining why an announcement couldn't be scheduled or cancelled.
+fn announcement_error(err: AnnouncementError) -> Text {
+    match err {
+        AnnouncementError::NotOperator => Text::NotOperator,
+        AnnouncementError::TooMany => Text::TooManyAnnouncements,
+        AnnouncementError::TooFrequent => Text::AnnouncementTooFrequent,
+        AnnouncementError::UnknownId => Text::UnknownAnnouncement,
+    }
 }
 
-// The template for the instructions screen
-#[derive(Template)]
-#[template(path = "instructions.txt", escape = "none")]
-struct Instructions {}
+fn feed_error(err: FeedError) -> Text {
+    match err {
+        FeedError::NotOperator => Text::NotOperator,
+        FeedError::TooMany => Text::TooManyFeeds,
+        FeedError::TooFrequent => Text::FeedTooFrequent,
+        FeedError::InvalidUrl => Text::InvalidFeedUrl,
+        FeedError::UnknownId => Text::UnknownFeed,
+        FeedError::Disabled => Text::FeedsDisabled,
+    }
+}
 
 /// The client process is spawned for each new telnet connection to the server.
 ///
-/// It receives the `TcpStream` of the connection as argument. Then the client will spawn a
-/// sub-process that uses the `TcpStream` to create higher level commands from low-level telnet
-/// stream and forward the commands to the client.
+/// It receives the `Connection`, its peer address, whether the listener expects a PROXY protocol
+/// header, the UI configuration and the name of the network it connected to as arguments. Then
+/// the client will start the frontend of the connection, it forwards the decoded input to the
+/// client and shows the rendered UI.
 ///
-/// The client will re-render the UI based on messages it receives from the coordinator, channels
-/// or telnet sub-process.
+/// The client will re-render the UI based on messages it receives from the backend or the
+/// frontend. Only the frontend knows how the user is connected, see `frontend`.
 pub struct ClientProcess {
     this: ProcessRef<ClientProcess>,
+    // How the backend knows this client, it forwards the events of the backend to us.
+    session: SessionRef,
     coordinator: ProcessRef<CoordinatorProcess>,
+    directory: Directory,
+    // Name of the network, the storage is looked up by it.
+    network: String,
     username: String,
+    // Source address of the connection, the real client's behind a proxy.
+    address: SocketAddr,
+    // Country of the address, looked up once when connecting.
+    location: Option<Location>,
+    // Identified with the account registered under `username`.
+    identified: bool,
+    // Lowercase accounts whose messages are hidden, loaded from the account when identifying.
+    blocked: HashSet<String>,
+    // Sessions of guests whose messages are hidden.
+    blocked_sessions: HashSet<u64>,
+    // Direct messages received during this session, the oldest first. Encrypted ones are kept
+    // decrypted.
+    inbox: Vec<DirectMessage>,
+    // Generated for each connection, the public key is published after identifying.
+    keys: KeyPair,
+    // Public keys of the lowercase usernames that direct messages are encrypted for.
+    secure: HashMap<String, [u8; 32]>,
+    // Channels followed with `/follow`, their tabs are read-only.
+    following: HashSet<String>,
+    // Channel of the last invitation, joined with `/accept`.
+    invitation: Option<String>,
+    // Word filters applied to sent messages, replaced by the coordinator on `/rehash`.
+    filters: Filters,
+    // Screens customized by the server operator, replaced by the coordinator on `/rehash`.
+    templates: CustomTemplates,
+    // The client can't send messages until this time after triggering a `mute` filter.
+    muted_until: Option<Instant>,
     tabs: UiTabs,
-    ui: Ui,
-    window_size: WindowSize,
+    // Shows the tabs to the user and delivers the input, see `frontend`.
+    frontend: Box<dyn Frontend>,
+    // What was negotiated with the telnet client, except for the window size.
+    caps: Capabilities,
+    // The welcome screen without the hint for the telnet client, it's added once the client is
+    // recognized.
+    welcome: String,
+    // Ctrl+C was pressed once, pressing it again quits until `quit_expired` arrives.
+    quit_pending: bool,
+    // Show all decoded telnet messages in the "Keys" tab.
+    keys_debug: bool,
+    // Ring the terminal bell on mentions in background tabs.
+    bell: bool,
+    // How much attention each channel asks for, channels not in here use `NotifyLevel::All`.
+    notify_levels: HashMap<String, NotifyLevel>,
+    // Commands defined with `/alias`, without the leading `/`, and what they expand to.
+    aliases: BTreeMap<String, String>,
+    // Messages are shown without formatting, set with `/format off`.
+    plain: bool,
+    locale: Locale,
+    // When the last `/ping` was sent to a channel.
+    ping_sent: Option<Instant>,
+    // Attach trace ids to sent messages.
+    trace: bool,
+    // Number of messages sent, used to create unique trace ids.
+    sent: u64,
+    // The namespace of the open channel list, its last received content and when it arrived.
+    channel_list: Option<(String, Instant, Vec<(String, usize, ChannelMeta)>)>,
+    // The latest server events with the time they arrived, the newest first. `None` if not
+    // subscribed.
+    server_events: Option<VecDeque<(String, ServerEvent)>>,
+    // Id of the next expected message of each channel tab, used to notice lost or repeated
+    // messages. Channels without an entry accept any id.
+    sequences: HashMap<String, u64>,
+    // Asking for a nickname on the welcome screen (`--nick-prompt`), the client didn't join the
+    // server yet.
+    choosing_nickname: bool,
+    // Where sessions are recorded (`--record-dir`), `None` if recording is disabled.
+    record_dir: Option<String>,
+    // Path of the running recording.
+    recording: Option<String>,
+    // Recordings the session started, see `recording::MAX_PER_SESSION`.
+    recordings: u32,
+    // Everyone can use `/banner`, not only channel operators.
+    banner_everyone: bool,
+    // When the last banner was sent.
+    last_banner: Option<Instant>,
+    // The channel and the lines collected so far while pasting, input isn't sent until the paste
+    // is complete.
+    pasting: Option<(String, Vec<String>)>,
+    // Registers us with the coordinator, stopped once the client is gone.
+    heartbeat: Process<()>,
+    // `exit` ran, the process ends after the shutdown.
+    exiting: bool,
 }
 
 #[abstract_process(visibility = pub)]
 impl ClientProcess {
     #[init]
-    fn init(config: Config<Self>, stream: TcpStream) -> Result<Self, ()> {
-        // Look up the coordinator or fail if it doesn't exist.
-        let coordinator = ProcessRef::<CoordinatorProcess>::lookup("coordinator").unwrap();
-        // Link coordinator to child. The coordinator sets `die_when_link_dies` to `0` and will not fail if child fails.
-        coordinator.link();
+    fn init(
+        config: Config<Self>,
+        args: (Connection, SocketAddr, UiConfig, String, Option<String>),
+    ) -> Result<Self, ()> {
+        let (mut connection, address, ui_config, network, record_dir) = args;
+        // Look up the coordinator of the network. It may not be registered yet if the server is
+        // still starting.
+        let coordinator_name = coordinator::registry_name(&network);
+        let coordinator = match lookup_with_retry::<CoordinatorProcess>(&coordinator_name) {
+            Some(coordinator) => coordinator,
+            None => {
+                connection.refuse("Server is starting, please try again in a moment.");
+                return Err(());
+            }
+        };
+        if !coordinator.accepts_clients() {
+            connection.refuse("Server is restarting, please try again in a moment.");
+            return Err(());
+        }
+        // The client is not linked to the coordinator. If the coordinator dies, the supervisor
+        // will restart it and the heartbeat process below lets us register with the new one.
         // Let the coordinator know that we joined.
-        let client_info = coordinator.join_server(config.self_ref());
-
-        // This process is in charge of turning the raw tcp stream into higher level messages that are
-        // sent back to the client. It's linked to the client and if one of them fails the other will too.
-        Process::spawn_link(
-            (config.self_ref(), stream.clone()),
-            |(client, stream), _: Mailbox<()>| {
-                let mut telnet = Telnet::new(stream);
-                telnet.iac_do_linemode().unwrap();
-                telnet.iac_linemode_zero();
-                telnet.iac_will_echo().unwrap();
-                telnet.iac_do_naws().unwrap();
-
-                loop {
-                    match telnet.next() {
-                        Ok(message) => client.process(message),
-                        Err(err) => panic!("A telnet error ocurred: {:?}", err),
-                    };
+        let location = ProcessRef::<GeoIpProcess>::lookup(&geoip::registry_name(&network))
+            .and_then(|geoip| geoip.locate(address.ip()));
+        // Everything the backend pushes to us arrives through the session. The forwarding process
+        // is linked, so the backend sees the session die together with the client.
+        let session = SessionRef::new(Process::spawn_link(
+            config.self_ref(),
+            |client, events: Mailbox<SessionEvent>| loop {
+                client.session_event(events.receive());
+            },
+        ));
+        // With the prompt, the client joins once the user picked an available name
+        let client_info = if ui_config.nick_prompt {
+            coordinator.server_info()
+        } else {
+            coordinator
+                .join_server(session, address, location.clone(), None)
+                .map_err(|_| ())?
+        };
+        let directory = Directory::new(&network);
+
+        // Regularly let the coordinator know that we are alive. If the client dies, the linked
+        // heartbeat process dies too and the coordinator will clean up after us.
+        //
+        // The heartbeat process also notices when the coordinator was restarted, because a
+        // different process is registered under the coordinator's name.
+        let heartbeat = Process::spawn_link(
+            (coordinator, coordinator_name, config.self_ref(), session),
+            |(mut coordinator, coordinator_name, client, session), _: Mailbox<()>| loop {
+                lunatic::sleep(HEARTBEAT_INTERVAL);
+                if let Some(current) = ProcessRef::<CoordinatorProcess>::lookup(&coordinator_name) {
+                    if current != coordinator {
+                        coordinator = current;
+                        client.coordinator_restarted(coordinator);
+                    }
                 }
+                coordinator.heartbeat(session);
+                // Measure the connection health shown in the tab bar
+                let start = Instant::now();
+                coordinator.ping();
+                client.set_latency(start.elapsed());
             },
         );
 
-        let window_size = telnet_backend::WindowSize::new();
-        let welcome = Welcome {
-            username: client_info.username.clone(),
-            clients: client_info.total_clients,
-        };
-        let tab = Tab::new(
-            "Welcome".to_string(),
-            None,
-            TabType::Info(welcome.render().unwrap()),
+        let welcome = i18n::welcome(
+            ui_config.locale,
+            &client_info.templates,
+            &client_info.username,
+            client_info.total_clients,
+            directory.channel_count(),
+            &client_info.motd,
         );
+        let tab = Tab::new("Welcome".to_string(), None, TabType::Info(welcome.clone()));
         let tabs = UiTabs::new(tab);
-        let ui = Ui::new(stream, window_size.clone(), tabs.clone());
+        let frontend = connection.start(config.self_ref(), tabs.clone(), ui_config)?;
 
-        Ok(ClientProcess {
+        let mut client = ClientProcess {
             this: config.self_ref(),
+            session,
             coordinator,
+            directory,
+            network,
             username: client_info.username,
+            address,
+            location,
+            identified: false,
+            blocked: HashSet::new(),
+            blocked_sessions: HashSet::new(),
+            inbox: Vec::new(),
+            keys: KeyPair::generate(),
+            secure: HashMap::new(),
+            following: HashSet::new(),
+            invitation: None,
+            filters: Filters::new(&client_info.filters),
+            templates: client_info.templates,
+            muted_until: None,
             tabs,
-            ui,
-            window_size,
-        })
+            frontend,
+            caps: Capabilities::default(),
+            welcome,
+            quit_pending: false,
+            keys_debug: false,
+            bell: true,
+            notify_levels: HashMap::new(),
+            aliases: BTreeMap::new(),
+            plain: false,
+            locale: ui_config.locale,
+            ping_sent: None,
+            trace: client_info.trace,
+            sent: 0,
+            channel_list: None,
+            server_events: None,
+            sequences: HashMap::new(),
+            choosing_nickname: ui_config.nick_prompt,
+            record_dir,
+            recording: None,
+            recordings: 0,
+            heartbeat,
+            exiting: false,
+            banner_everyone: ui_config.banner_everyone,
+            last_banner: None,
+            pasting: None,
+        };
+        if client.choosing_nickname {
+            let text = i18n::text(client.locale, Text::ChooseNickname);
+            client.tabs.set_status(text.to_owned());
+        } else if let Some(channel) = client_info.auto_join {
+            // Land in the default channel instead of the welcome screen, which stays open as a tab
+            client.open_channel(&channel);
+        }
+        Ok(client)
     }
 
-    /// Handle data coming in over TCP from telnet.
-    #[handle_message]
-    fn process(&mut self, command: TelnetMessage) {
+    /// Handle input coming in through the frontend, the reply tells the frontend that it can
+    /// deliver the next one.
+    #[handle_request]
+    fn process(&mut self, command: Input) {
+        if self.exiting {
+            return;
+        }
+        if self.keys_debug {
+            self.tabs
+                .append_info("Keys", format!("  {:?}", command), KEYS_DEBUG_LINES);
+        }
         match command {
-            CtrlC | Error => {
+            CtrlC if self.quit_pending => {
+                self.this.exit();
+            }
+            // It's easy to hit by accident, so the first press only asks for a second one
+            CtrlC => {
+                self.quit_pending = true;
+                let text = i18n::text(self.locale, Text::PressAgainToQuit);
+                self.tabs.set_status(text.to_string());
+                Process::spawn_link(self.this, |client, _: Mailbox<()>| {
+                    lunatic::sleep(QUIT_CONFIRM_TIMEOUT);
+                    client.quit_expired();
+                });
+                self.frontend.render();
+            }
+            Closed => {
                 self.this.exit();
             }
             Tab => {
                 self.tabs.next();
-                self.ui.render();
+                self.frontend.render();
             }
             Backspace => {
                 self.tabs.input_del_char();
-                self.ui.render();
+                self.frontend.render();
             }
             Char(ch) => {
-                self.tabs.input_add_char(ch.into());
-                self.ui.render();
+                self.tabs.input_add_char(ch);
+                self.frontend.render();
+            }
+            CtrlW => {
+                self.tabs.input_del_word();
+                self.frontend.render();
+            }
+            CtrlU => {
+                self.tabs.clear();
+                self.frontend.render();
+            }
+            CtrlA | Key(Key::Home) => {
+                self.tabs.input_move_cursor(CursorMove::Start);
+                self.frontend.render();
+            }
+            CtrlE | Key(Key::End) => {
+                self.tabs.input_move_cursor(CursorMove::End);
+                self.frontend.render();
+            }
+            Key(Key::Esc) => {
+                self.tabs.toggle_copy_mode();
+                self.frontend.render();
+            }
+            Key(Key::Up) => {
+                // Fetch older messages when scrolling past the top of a channel
+                if self.tabs.get_selected().get_selection() == Some(0) {
+                    self.load_history();
+                }
+                self.tabs.move_selection(true);
+                self.frontend.render();
+            }
+            Key(Key::Down) => {
+                self.tabs.move_selection(false);
+                self.frontend.render();
+            }
+            Key(Key::Left) => {
+                self.tabs.input_move_cursor(CursorMove::Left);
+                self.frontend.render();
+            }
+            Key(Key::Right) => {
+                self.tabs.input_move_cursor(CursorMove::Right);
+                self.frontend.render();
             }
             Enter => {
+                self.tabs.clear_status();
                 let input = self.tabs.clear();
+                // Nothing else is possible before joining the server
+                if self.choosing_nickname {
+                    self.choose_nickname(input.trim());
+                    self.frontend.render();
+                    return;
+                }
+                // Lines of a paste are collected as they are, with their indentation
+                if self.pasting.is_some() {
+                    self.paste_line(input);
+                    self.frontend.render();
+                    return;
+                }
+                let input = self.expand_alias(input.trim());
                 let input = input.trim();
                 if input.starts_with('/') {
                     // Command
                     let mut split = input.split(' ');
-                    match split.next().unwrap() {
+                    let command = split.next().unwrap();
+                    match command {
                         "/help" => {
-                            let instructions = Instructions {};
+                            self.show_help();
+                            self.frontend.render();
+                        }
+                        "/copy" => {
+                            self.tabs.toggle_copy_mode();
+                            self.frontend.render();
+                        }
+                        "/alias" => {
+                            if let Some(name) = split.next() {
+                                let name = name.trim_start_matches('/').to_owned();
+                                let expansion = split.collect::<Vec<_>>().join(" ");
+                                let expansion = expansion.trim_matches('"').trim().to_owned();
+                                // `/alias` itself can't be redefined, so aliases can always be fixed
+                                let reserved = name == "alias" || name == "aliases";
+                                if name.is_empty() || reserved {
+                                    // Nothing to define
+                                } else if expansion.is_empty() {
+                                    self.aliases.remove(&name);
+                                    self.save_settings();
+                                } else {
+                                    self.aliases.insert(name, expansion);
+                                    self.save_settings();
+                                }
+                            }
+                            self.frontend.render();
+                        }
+                        "/aliases" => {
+                            let content = i18n::aliases(self.locale, &self.aliases);
                             let tab = Tab::new(
-                                "Help".to_string(),
+                                "Aliases".to_string(),
                                 None,
-                                TabType::Info(instructions.render().unwrap()),
+                                TabType::Info(content.clone()),
                             );
                             self.tabs.add_or_switch(tab);
-                            self.ui.render();
+                            self.tabs.set_info("Aliases", content);
+                            self.frontend.render();
+                        }
+                        "/notify-level" => {
+                            let channel = split.next().unwrap_or_default().to_owned();
+                            let word = split.next().unwrap_or_default();
+                            let level = match word {
+                                "all" => Some(NotifyLevel::All),
+                                "mentions" => Some(NotifyLevel::Mentions),
+                                "none" => Some(NotifyLevel::Silent),
+                                _ => None,
+                            };
+                            if let (true, Some(level)) = (channel.starts_with('#'), level) {
+                                let text = i18n::text(self.locale, Text::NotifyLevel);
+                                self.tabs
+                                    .set_status(format!("{} {}: {}", text, channel, word));
+                                if level == NotifyLevel::All {
+                                    self.notify_levels.remove(&channel);
+                                } else {
+                                    self.notify_levels.insert(channel, level);
+                                }
+                            }
+                            self.frontend.render();
+                        }
+                        "/bell" => {
+                            match split.next() {
+                                Some("on") => self.bell = true,
+                                Some("off") => self.bell = false,
+                                _ => {}
+                            }
+                            self.frontend.render();
+                        }
+                        "/format" => {
+                            let plain = match split.next() {
+                                Some("on") => Some(false),
+                                Some("off") => Some(true),
+                                _ => None,
+                            };
+                            if let Some(plain) = plain {
+                                self.plain = plain;
+                                self.frontend.set_formatting(!plain);
+                                self.save_settings();
+                            }
+                            self.frontend.render();
+                        }
+                        "/lang" => {
+                            if let Some(locale) = split.next().and_then(Locale::from_code) {
+                                self.locale = locale;
+                                self.frontend.set_locale(locale);
+                            }
+                            self.frontend.render();
+                        }
+                        "/caps" => {
+                            let (width, height) = self.frontend.size();
+                            let content =
+                                i18n::capabilities(self.locale, width, height, &self.caps);
+                            let tab =
+                                Tab::new("Caps".to_string(), None, TabType::Info(content.clone()));
+                            self.tabs.add_or_switch(tab);
+                            self.tabs.set_info("Caps", content);
+                            self.frontend.render();
+                        }
+                        "/paste" => {
+                            let selected = self.tabs.get_selected();
+                            let name = selected.get_name();
+                            if selected.get_channel().is_none() {
+                                // Only channels can get a paste
+                            } else if self.following.contains(&name) {
+                                let text = i18n::text(self.locale, Text::ReadOnly);
+                                self.tabs.set_status(text.to_string());
+                            } else {
+                                self.pasting = Some((name, Vec::new()));
+                                let text = i18n::text(self.locale, Text::Pasting);
+                                self.tabs.set_status(format!("{} 0", text));
+                            }
+                            self.frontend.render();
+                        }
+                        "/show" => {
+                            if let Some(id) = split.next() {
+                                self.show_paste(id);
+                            }
+                            self.frontend.render();
+                        }
+                        name if commands::find(name).and_then(|c| c.game).is_some() => {
+                            let args = split.collect::<Vec<_>>().join(" ");
+                            if let Some(args) = self.filter(args.trim()) {
+                                self.play(name, &args);
+                            }
+                            self.frontend.render();
+                        }
+                        "/announce-every" => {
+                            let interval = split.next().and_then(Announcement::parse_interval);
+                            let message = split.collect::<Vec<_>>().join(" ");
+                            let message = message.trim().trim_matches('"').trim();
+                            self.schedule_announcement(interval, message);
+                            self.frontend.render();
+                        }
+                        "/announcements" => {
+                            let cancel = match (split.next(), split.next()) {
+                                (Some("cancel"), Some(id)) => id.parse().ok(),
+                                _ => None,
+                            };
+                            if let Some(id) = cancel {
+                                self.cancel_announcement(id);
+                            }
+                            self.show_announcements();
+                            self.frontend.render();
+                        }
+                        "/feed" => {
+                            match (split.next(), split.next(), split.next()) {
+                                (Some("add"), Some(url), interval) => {
+                                    let interval = interval.and_then(Announcement::parse_interval);
+                                    self.add_feed(url, interval);
+                                }
+                                (Some("remove"), Some(id), _) => {
+                                    if let Ok(id) = id.parse() {
+                                        self.remove_feed(id);
+                                    }
+                                    self.show_feeds();
+                                }
+                                _ => self.show_feeds(),
+                            }
+                            self.frontend.render();
+                        }
+                        "/banner" => {
+                            let text = split.collect::<Vec<_>>().join(" ");
+                            self.send_banner(text.trim());
+                            self.frontend.render();
+                        }
+                        "/record" => {
+                            self.toggle_recording();
+                            self.frontend.render();
+                        }
+                        "/keys" => {
+                            self.keys_debug = !self.keys_debug;
+                            if self.keys_debug {
+                                let tab = Tab::new(
+                                    "Keys".to_string(),
+                                    None,
+                                    TabType::Info(
+                                        i18n::text(self.locale, Text::KeysPlaceholder).to_string(),
+                                    ),
+                                );
+                                self.tabs.add_or_switch(tab);
+                            }
+                            self.frontend.render();
                         }
                         "/nick" => {
                             if let Some(nick) = split.next() {
-                                self.username =
-                                    self.coordinator.change_name(self.this, nick.to_owned());
+                                match self.coordinator.change_name(self.session, nick.to_owned()) {
+                                    Ok(username) => {
+                                        self.username = username;
+                                        // The new name may belong to another account
+                                        self.identified = false;
+                                        self.blocked.clear();
+                                        self.blocked_sessions.clear();
+                                        self.secure.clear();
+                                    }
+                                    Err(err) => {
+                                        let text = match err {
+                                            UsernameError::Taken => Text::NameTaken,
+                                            UsernameError::Invalid => Text::NameInvalid,
+                                        };
+                                        let text = i18n::text(self.locale, text);
+                                        self.tabs.set_status(format!("{} {}", text, nick));
+                                    }
+                                }
                             };
-                            self.ui.render();
+                            self.frontend.render();
+                        }
+                        "/stats" => {
+                            // Only channel statistics are available for now
+                            if split.next() == Some("channels") {
+                                let stats = self.directory.channel_stats();
+                                let tab = Tab::new(
+                                    "Stats".to_string(),
+                                    None,
+                                    TabType::Info(i18n::channel_stats(self.locale, stats)),
+                                );
+                                self.tabs.add_or_switch(tab);
+                            }
+                            self.frontend.render();
                         }
                         "/list" => {
-                            let list = self.coordinator.list_channels();
-                            let list = ChannelList { list };
+                            let namespace = split.next().unwrap_or_default().to_owned();
+                            let list = self.list_channels(namespace);
+                            let content = i18n::channel_list(self.locale, &self.templates, list);
                             let tab = Tab::new(
                                 "Channels".to_string(),
                                 None,
-                                TabType::Info(list.render().unwrap()),
+                                TabType::Info(content.clone()),
                             );
                             self.tabs.add_or_switch(tab);
-                            self.ui.render();
+                            self.tabs.set_info("Channels", content);
+                            self.frontend.render();
                         }
-                        "/drop" => {
-                            let current_channel = self.tabs.get_selected().get_name();
-                            // If the tab is a channel notify coordinator that we are leaving.
-                            if current_channel.starts_with('#') {
-                                self.coordinator.leave_channel(self.this, current_channel);
+                        "/describe" => {
+                            let selected = self.tabs.get_selected();
+                            if let Some(channel) = selected.get_channel() {
+                                let meta = ChannelMeta {
+                                    language: split.next().unwrap_or_default().to_owned(),
+                                    description: split.collect::<Vec<_>>().join(" "),
+                                };
+                                if !channel.describe(self.session, meta) {
+                                    let text = i18n::text(self.locale, Text::NotOperator);
+                                    self.tabs.add_system_message(
+                                        selected.get_name(),
+                                        system_line(text.to_string()),
+                                    );
+                                }
                             }
-                            self.tabs.drop();
-                            self.ui.render();
+                            self.frontend.render();
                         }
-                        "/join" => {
-                            let channel_name = if let Some(channel_name) = split.next() {
-                                channel_name
-                            } else {
-                                return;
+                        "/ban" => {
+                            let selected = self.tabs.get_selected();
+                            let username = split.next().unwrap_or_default();
+                            if selected.get_channel().is_some() && !username.is_empty() {
+                                let channel = selected.get_name();
+                                if let Some(shard) = self.shard(&channel) {
+                                    let text = if shard.ban_user(
+                                        self.session,
+                                        channel.clone(),
+                                        username.to_owned(),
+                                    ) {
+                                        format!(
+                                            "{} {}",
+                                            i18n::text(self.locale, Text::UserBanned),
+                                            username
+                                        )
+                                    } else {
+                                        i18n::text(self.locale, Text::NotOperator).to_string()
+                                    };
+                                    self.tabs.add_system_message(channel, system_line(text));
+                                }
+                            }
+                            self.frontend.render();
+                        }
+                        "/register" | "/identify" => {
+                            // `/identify <username> <password>` adds a session to an account
+                            let mut args: Vec<String> = split.map(|arg| arg.to_owned()).collect();
+                            let password = args.pop().unwrap_or_default();
+                            let account = args.pop().filter(|_| command == "/identify");
+                            if !password.is_empty() {
+                                // Passwords are hashed and checked here, it would block the
+                                // coordinator
+                                let text = if command == "/register" {
+                                    let password = PasswordHash::new(&password);
+                                    if self.coordinator.register(self.session, password) {
+                                        self.identified = true;
+                                        self.blocked.clear();
+                                        self.blocked_sessions.clear();
+                                        self.coordinator
+                                            .publish_key(self.session, self.keys.public());
+                                        self.save_settings();
+                                        Text::Registered
+                                    } else {
+                                        Text::RegisterFailed
+                                    }
+                                } else {
+                                    let name =
+                                        account.clone().unwrap_or_else(|| self.username.clone());
+                                    match self.coordinator.password(self.session, name) {
+                                        Ok(hash) if hash.verify(&password) => {
+                                            self.identify(account, hash)
+                                        }
+                                        Err(PasswordError::TooManyAttempts) => {
+                                            Text::TooManyAttempts
+                                        }
+                                        _ => Text::IdentifyFailed,
+                                    }
+                                };
+                                self.tabs
+                                    .set_status(i18n::text(self.locale, text).to_string());
+                            }
+                            self.frontend.render();
+                        }
+                        "/block" | "/unblock" => {
+                            let username = split.next().unwrap_or_default().to_owned();
+                            if !username.is_empty() {
+                                let block = command == "/block";
+                                let text = match self.coordinator.block(
+                                    self.session,
+                                    username.clone(),
+                                    block,
+                                ) {
+                                    Ok(changed) => {
+                                        let BlockList { accounts, sessions } = changed;
+                                        let text = if block {
+                                            self.blocked.extend(accounts);
+                                            self.blocked_sessions.extend(sessions);
+                                            Text::Blocked
+                                        } else {
+                                            for account in &accounts {
+                                                self.blocked.remove(account);
+                                            }
+                                            for session in &sessions {
+                                                self.blocked_sessions.remove(session);
+                                            }
+                                            Text::Unblocked
+                                        };
+                                        format!("{} {}", i18n::text(self.locale, text), username)
+                                    }
+                                    Err(BlockError::NotIdentified) => {
+                                        i18n::text(self.locale, Text::BlockNeedsAccount).to_string()
+                                    }
+                                    Err(BlockError::UnknownUser) => format!(
+                                        "{} {}",
+                                        i18n::text(self.locale, Text::UnknownUser),
+                                        username
+                                    ),
+                                };
+                                self.tabs.set_status(text);
+                            }
+                            self.frontend.render();
+                        }
+                        "/msg" => {
+                            let to = split.next().unwrap_or_default().to_owned();
+                            let message = split.collect::<Vec<_>>().join(" ");
+                            let message = match message.len() {
+                                1..=299 if !to.is_empty() => self.filter(&message),
+                                _ => None,
+                            };
+                            if let Some(message) = message {
+                                let now: DateTime<Local> = Local::now();
+                                let timestamp = format!("[{}] ", now.format("%H:%M UTC"));
+                                let peer = self.secure.get(&to.to_lowercase());
+                                let sealed = peer.and_then(|peer| self.keys.seal(peer, &message));
+                                let result = match (peer, sealed) {
+                                    (Some(_), None) => Err(DirectMessageError::NotSecure),
+                                    (_, sealed) => {
+                                        let secure = sealed.is_some();
+                                        self.coordinator.direct_message(
+                                            self.session,
+                                            to.clone(),
+                                            timestamp,
+                                            sealed.unwrap_or(message),
+                                            secure,
+                                        )
+                                    }
+                                };
+                                let text = match result {
+                                    Ok(Delivery::Delivered) => Text::MessageSent,
+                                    Ok(Delivery::Stored) => Text::MessageStored,
+                                    Err(DirectMessageError::UnknownUser) => Text::UnknownUser,
+                                    Err(DirectMessageError::Blocked) => Text::RecipientBlocked,
+                                    Err(DirectMessageError::NotSecure) => Text::SecureUnavailable,
+                                };
+                                let text = i18n::text(self.locale, text);
+                                self.tabs.set_status(format!("{} {}", text, to));
+                            }
+                            self.frontend.render();
+                        }
+                        "/secure" => {
+                            let username = split.next().unwrap_or_default();
+                            let username = username.trim_start_matches('@').to_owned();
+                            if !username.is_empty() {
+                                let status = if split.next() == Some("off") {
+                                    self.secure.remove(&username.to_lowercase());
+                                    let text = i18n::text(self.locale, Text::SecureEnded);
+                                    format!("{} {}", text, username)
+                                } else {
+                                    match self
+                                        .coordinator
+                                        .public_key(self.session, username.clone())
+                                    {
+                                        Some(key) if self.keys.accepts(&key) => {
+                                            self.secure.insert(username.to_lowercase(), key);
+                                            let text = i18n::text(self.locale, Text::SecureSession);
+                                            let fingerprint =
+                                                i18n::text(self.locale, Text::SecureFingerprint);
+                                            format!(
+                                                "{} {}, {} {}",
+                                                text,
+                                                username,
+                                                fingerprint,
+                                                self.keys.fingerprint(&key)
+                                            )
+                                        }
+                                        _ => {
+                                            let text =
+                                                i18n::text(self.locale, Text::SecureUnavailable);
+                                            format!("{} {}", text, username)
+                                        }
+                                    }
+                                };
+                                self.tabs.set_status(status);
+                            }
+                            self.frontend.render();
+                        }
+                        "/sessions" => {
+                            let sessions = self.coordinator.sessions(self.session);
+                            let content = i18n::sessions(self.locale, sessions);
+                            let tab = Tab::new(
+                                "Sessions".to_string(),
+                                None,
+                                TabType::Info(content.clone()),
+                            );
+                            self.tabs.add_or_switch(tab);
+                            self.tabs.set_info("Sessions", content);
+                            self.frontend.render();
+                        }
+                        "/inbox" => {
+                            let content = self.inbox_content();
+                            let tab =
+                                Tab::new("Inbox".to_string(), None, TabType::Info(content.clone()));
+                            self.tabs.add_or_switch(tab);
+                            self.tabs.set_info("Inbox", content);
+                            self.frontend.render();
+                        }
+                        "/link" => {
+                            let selected = self.tabs.get_selected();
+                            let id = match split.next() {
+                                Some(id) => id.parse().ok(),
+                                None => selected.get_selection_id(),
                             };
-                            if channel_name.starts_with('#') {
-                                let channel = self
+                            if let (Some(_), Some(id)) = (selected.get_channel(), id) {
+                                let text = i18n::text(self.locale, Text::Permalink);
+                                let link = permalink(&selected.get_name(), id);
+                                self.tabs.set_status(format!("{} {}", text, link));
+                            }
+                            self.frontend.render();
+                        }
+                        "/goto" => {
+                            if let Some((channel, id)) = split.next().and_then(parse_permalink) {
+                                self.goto_message(&channel, id);
+                            }
+                            self.frontend.render();
+                        }
+                        "/events" => {
+                            if self.server_events.is_none() {
+                                self.coordinator.subscribe_events(self.session);
+                                self.server_events = Some(VecDeque::new());
+                            }
+                            let content = self.server_events_content();
+                            let tab = Tab::new(
+                                "Server".to_string(),
+                                None,
+                                TabType::Info(content.clone()),
+                            );
+                            self.tabs.add_or_switch(tab);
+                            self.tabs.set_info("Server", content);
+                            self.frontend.render();
+                        }
+                        "/who" => {
+                            let selected = self.tabs.get_selected();
+                            if selected.get_channel().is_some() {
+                                let channel = selected.get_name();
+                                let members = self
                                     .coordinator
-                                    .join_channel(self.this, channel_name.to_owned());
-
-                                // Get last messages from channel
-                                let last_messages = channel.get_last_messages();
-                                // Create new tab bound to channel
+                                    .who(channel.clone())
+                                    .into_iter()
+                                    .map(|(name, identified)| {
+                                        channel::display_name(&name, identified)
+                                    })
+                                    .collect();
+                                let content = i18n::members(self.locale, &channel, members);
                                 let tab = Tab::new(
-                                    channel_name.to_owned(),
-                                    Some(channel),
-                                    TabType::Channel(last_messages),
+                                    "Who".to_string(),
+                                    None,
+                                    TabType::Info(content.clone()),
                                 );
                                 self.tabs.add_or_switch(tab);
+                                self.tabs.set_info("Who", content);
+                            }
+                            self.frontend.render();
+                        }
+                        "/rehash" => {
+                            let text = match self.coordinator.rehash(self.session) {
+                                Ok(rules) => {
+                                    format!("{} {}", i18n::text(self.locale, Text::Rehashed), rules)
+                                }
+                                Err(RehashError::NotAdmin) => {
+                                    i18n::text(self.locale, Text::NotAdmin).to_string()
+                                }
+                                Err(
+                                    RehashError::InvalidFilters(err)
+                                    | RehashError::InvalidTemplates(err),
+                                ) => {
+                                    let text = i18n::text(self.locale, Text::RehashFailed);
+                                    format!("{} {}", text, err)
+                                }
+                            };
+                            self.tabs.set_status(text);
+                            self.frontend.render();
+                        }
+                        "/whois" => {
+                            let username = split.next().unwrap_or_default().to_owned();
+                            if !username.is_empty() {
+                                match self.coordinator.whois(self.session, username.clone()) {
+                                    Ok(connections) => {
+                                        let content =
+                                            i18n::whois(self.locale, &username, connections);
+                                        let tab = Tab::new(
+                                            "Whois".to_string(),
+                                            None,
+                                            TabType::Info(content.clone()),
+                                        );
+                                        self.tabs.add_or_switch(tab);
+                                        self.tabs.set_info("Whois", content);
+                                    }
+                                    Err(WhoisError::NotAdmin) => self.tabs.set_status(
+                                        i18n::text(self.locale, Text::NotAdmin).to_string(),
+                                    ),
+                                    Err(WhoisError::UnknownUser) => {
+                                        let text = i18n::text(self.locale, Text::UnknownUser);
+                                        self.tabs.set_status(format!("{} {}", text, username));
+                                    }
+                                }
+                            }
+                            self.frontend.render();
+                        }
+                        "/history" => {
+                            let user = split.next().unwrap_or_default().to_owned();
+                            if !user.is_empty() {
+                                match self.coordinator.connections(self.session, user.clone()) {
+                                    Ok(connections) => {
+                                        let content =
+                                            i18n::connections(self.locale, &user, connections);
+                                        let tab = Tab::new(
+                                            "History".to_string(),
+                                            None,
+                                            TabType::Info(content.clone()),
+                                        );
+                                        self.tabs.add_or_switch(tab);
+                                        self.tabs.set_info("History", content);
+                                    }
+                                    Err(ConnectionsError::NotAdmin) => self.tabs.set_status(
+                                        i18n::text(self.locale, Text::NotAdmin).to_string(),
+                                    ),
+                                }
+                            }
+                            self.frontend.render();
+                        }
+                        "/dump" => {
+                            let text = match self.coordinator.dump(self.session) {
+                                Ok(path) => {
+                                    format!("{} {}", i18n::text(self.locale, Text::Dumped), path)
+                                }
+                                Err(DumpError::NotAdmin) => {
+                                    i18n::text(self.locale, Text::NotAdmin).to_string()
+                                }
+                                Err(DumpError::Io(err)) => {
+                                    format!("{} {}", i18n::text(self.locale, Text::DumpFailed), err)
+                                }
+                            };
+                            self.tabs.set_status(text);
+                            self.frontend.render();
+                        }
+                        // Chaos testing of the supervision and cleanup paths
+                        #[cfg(debug_assertions)]
+                        "/crash" => {
+                            use chat_core::coordinator::{CrashError, CrashTarget};
+                            let target = match (split.next(), split.next()) {
+                                (Some("channel"), Some(name)) => {
+                                    Some(CrashTarget::Channel(name.to_owned()))
+                                }
+                                (Some("client"), Some(name)) => {
+                                    Some(CrashTarget::Client(name.to_owned()))
+                                }
+                                (Some("coordinator"), _) => Some(CrashTarget::Coordinator),
+                                _ => None,
+                            };
+                            if let Some(target) = target {
+                                let text =
+                                    match self.coordinator.crash_process(self.session, target) {
+                                        Ok(()) => Text::Crashed,
+                                        Err(CrashError::NotAdmin) => Text::NotAdmin,
+                                        Err(CrashError::Disabled) => Text::CrashDisabled,
+                                        Err(CrashError::UnknownTarget) => Text::UnknownTarget,
+                                    };
+                                self.tabs
+                                    .set_status(i18n::text(self.locale, text).to_string());
+                            }
+                            self.frontend.render();
+                        }
+                        "/hook" => {
+                            let selected = self.tabs.get_selected();
+                            if selected.get_channel().is_some() {
+                                let channel = selected.get_name();
+                                if let Some(shard) = self.shard(&channel) {
+                                    let text = match (split.next(), split.next()) {
+                                        (Some("create"), _) => shard
+                                            .create_hook(self.session, channel.clone())
+                                            .map(|token| (Text::HookCreated, token)),
+                                        (Some("revoke"), Some(token)) => shard
+                                            .revoke_hook(
+                                                self.session,
+                                                channel.clone(),
+                                                token.to_owned(),
+                                            )
+                                            .then(|| (Text::HookRevoked, token.to_owned())),
+                                        (Some("list"), _) => shard
+                                            .list_hooks(self.session, channel.clone())
+                                            .map(|tokens| (Text::HookList, tokens.join(", "))),
+                                        _ => None,
+                                    };
+                                    // Tokens are only shown to the operator, never sent to the channel
+                                    let text = match text {
+                                        Some((text, tokens)) => {
+                                            format!("{} {}", i18n::text(self.locale, text), tokens)
+                                        }
+                                        None => {
+                                            i18n::text(self.locale, Text::NotOperator).to_string()
+                                        }
+                                    };
+                                    self.tabs.add_system_message(channel, system_line(text));
+                                }
+                            }
+                            self.frontend.render();
+                        }
+                        "/admin" => {
+                            let password = split.next().unwrap_or_default().to_owned();
+                            let text = if self.coordinator.authenticate(self.session, password) {
+                                Text::AdminGranted
+                            } else {
+                                Text::AdminDenied
+                            };
+                            self.tabs
+                                .set_status(i18n::text(self.locale, text).to_string());
+                            self.frontend.render();
+                        }
+                        "/broadcast" => {
+                            let group = split.next().unwrap_or_default().to_owned();
+                            let message = split.collect::<Vec<_>>().join(" ");
+                            if !message.is_empty() && message.len() < 300 {
+                                let now: DateTime<Local> = Local::now();
+                                let timestamp = format!("[{}] ", now.format("%H:%M UTC"));
+                                let trace = self.next_trace();
+                                let result = self.coordinator.broadcast(
+                                    self.session,
+                                    group.clone(),
+                                    timestamp,
+                                    message,
+                                    trace,
+                                );
+                                match result {
+                                    Ok(()) => {}
+                                    Err(BroadcastError::NotAdmin) => {
+                                        let text = i18n::text(self.locale, Text::NotAdmin);
+                                        self.tabs.set_status(text.to_string());
+                                    }
+                                    Err(BroadcastError::UnknownGroup) => {
+                                        let text = i18n::text(self.locale, Text::UnknownGroup);
+                                        self.tabs.set_status(format!("{} {}", text, group));
+                                    }
+                                }
+                            }
+                            self.frontend.render();
+                        }
+                        "/ping" => {
+                            let start = Instant::now();
+                            self.coordinator.ping();
+                            let text = format!(
+                                "{} {}ms",
+                                i18n::text(self.locale, Text::PingCoordinator),
+                                start.elapsed().as_millis()
+                            );
+                            let selected = self.tabs.get_selected();
+                            if let Some(channel) = selected.get_channel() {
+                                self.tabs
+                                    .add_system_message(selected.get_name(), system_line(text));
+                                // The channel echo is reported once the `pong` arrives
+                                self.ping_sent = Some(Instant::now());
+                                channel.ping(self.session, selected.get_name());
                             } else {
-                                // Incorrect channel name
+                                self.tabs.set_status(text);
+                            }
+                            self.frontend.render();
+                        }
+                        "/all" => {
+                            let tab = Tab::new(
+                                "All".to_string(),
+                                None,
+                                TabType::Aggregate(self.tabs.aggregate()),
+                            );
+                            self.tabs.add_or_switch(tab);
+                            self.frontend.render();
+                        }
+                        "/drafts" => {
+                            let drafts = i18n::drafts(self.locale, self.tabs.drafts());
+                            let tab = Tab::new("Drafts".to_string(), None, TabType::Info(drafts));
+                            self.tabs.add_or_switch(tab);
+                            self.frontend.render();
+                        }
+                        "/drop" => {
+                            let current_channel = self.tabs.get_selected().get_name();
+                            // If the tab is a channel notify coordinator that we are leaving.
+                            // Followed channels are not known to the coordinator.
+                            if self.following.remove(&current_channel) {
+                                if let Some(shard) = self.directory.shard(&current_channel) {
+                                    shard.leave_channel(self.session, current_channel);
+                                }
+                            } else if current_channel.starts_with('#') {
+                                self.coordinator
+                                    .leave_channel(self.session, current_channel);
                             }
-                            self.ui.render();
+                            self.tabs.drop();
+                            self.frontend.render();
+                        }
+                        "/join" => {
+                            if let Some(channel_name) = split.next() {
+                                match (split.next(), split.next()) {
+                                    (Some("--ttl"), Some(ttl)) => {
+                                        self.open_temporary_channel(channel_name, ttl)
+                                    }
+                                    _ => self.open_channel(channel_name),
+                                }
+                            }
+                            self.frontend.render();
+                        }
+                        "/follow" => {
+                            if let Some(channel_name) = split.next() {
+                                self.follow_channel(channel_name);
+                            }
+                            self.frontend.render();
+                        }
+                        "/invite" => {
+                            let selected = self.tabs.get_selected();
+                            let to = split.next().unwrap_or_default().to_owned();
+                            if selected.get_channel().is_some() && !to.is_empty() {
+                                let result = self.coordinator.invite(
+                                    self.session,
+                                    to.clone(),
+                                    selected.get_name(),
+                                );
+                                let text = match result {
+                                    Ok(()) => Text::InvitationSent,
+                                    Err(DirectMessageError::UnknownUser) => Text::UnknownUser,
+                                    Err(DirectMessageError::Blocked) => Text::RecipientBlocked,
+                                    Err(DirectMessageError::NotSecure) => Text::SecureUnavailable,
+                                };
+                                let text = i18n::text(self.locale, text);
+                                self.tabs.set_status(format!("{} {}", text, to));
+                            }
+                            self.frontend.render();
+                        }
+                        "/accept" => {
+                            if let Some(channel_name) = self.invitation.take() {
+                                // Close the invitation tab, the channel tab replaces it
+                                if self.tabs.names().iter().any(|name| name == "Invitation") {
+                                    self.tabs.switch("Invitation");
+                                    self.tabs.drop();
+                                }
+                                self.open_channel(&channel_name);
+                            }
+                            self.frontend.render();
                         }
                         "/exit" => {
                             self.this.exit();
                         }
                         _ => {}
                     }
+                } else if self
+                    .following
+                    .contains(&self.tabs.get_selected().get_name())
+                {
+                    let text = i18n::text(self.locale, Text::ReadOnly);
+                    self.tabs.set_status(text.to_string());
                 } else {
                     // Send to channel
-                    if !input.is_empty() && input.len() < 300 {
+                    let message = match input.len() {
+                        1..=299 => self.filter(input),
+                        _ => None,
+                    };
+                    if let Some(message) = message {
                         let now: DateTime<Local> = Local::now();
                         let timestamp = format!("[{}] ", now.format("%H:%M UTC"));
+                        let trace = self.next_trace();
                         self.tabs.get_selected().message(
                             timestamp,
                             self.username.clone(),
-                            input.to_string(),
+                            self.session,
+                            message,
+                            trace,
                         );
                     }
                 }
-                self.ui.render();
+                self.frontend.render();
+            }
+            // Alt + number switches directly to a tab
+            Key(Key::Alt(digit @ b'1'..=b'9')) => {
+                self.tabs.select((digit - b'1') as usize);
+                self.frontend.render();
+            }
+            Key(Key::F(1)) => {
+                self.show_help();
+                self.frontend.render();
+            }
+            Resize(width, height) => {
+                if WindowSize::is_valid(width, height) {
+                    self.frontend.resize(width, height);
+                } else {
+                    // Scrolling and drawing can't work with a size of 0, so the client gets a
+                    // common size and can still read the notice
+                    let (fallback_width, fallback_height) = FALLBACK_WINDOW_SIZE;
+                    self.tabs.set_status(format!(
+                        "{} ({}x{})",
+                        i18n::text(self.locale, Text::InvalidWindowSize),
+                        width,
+                        height
+                    ));
+                    self.frontend.resize(fallback_width, fallback_height);
+                }
             }
-            Naws(width, height) => {
-                self.window_size.set(width, height);
-                self.ui.render();
+            Terminal(caps) => {
+                self.caps = caps;
+                self.show_client_hint();
+                self.frontend.render();
             }
             _ => {}
         }
     }
 
-    /// Handle messages sent by a channel to us.
+    /// Handle an event the backend pushed to our session.
+    #[handle_message]
+    fn session_event(&mut self, event: SessionEvent) {
+        if self.exiting {
+            return;
+        }
+        match event {
+            SessionEvent::Messages(batch) => self.receive_messages(batch),
+            SessionEvent::MessagesSkipped(channel) => self.messages_skipped(channel),
+            SessionEvent::Pong(channel) => self.pong(channel),
+            SessionEvent::ChannelList { namespace, list } => self.channel_list(namespace, list),
+            SessionEvent::Server(event) => self.server_event(event),
+            SessionEvent::Direct(message) => self.receive_direct(message),
+            SessionEvent::Invitation { from, channel } => self.receive_invitation(from, channel),
+            SessionEvent::SessionJoined(channel) => self.session_joined(channel),
+            SessionEvent::SessionLeft(channel) => self.session_left(channel),
+            SessionEvent::ChannelExpiring { channel, minutes } => {
+                self.channel_expiring(channel, minutes)
+            }
+            SessionEvent::ChannelExpired(channel) => self.channel_expired(channel),
+            SessionEvent::Banned(channel) => self.banned(channel),
+            SessionEvent::ChannelClosed(channel) => self.channel_closed(channel),
+            SessionEvent::Filters(rules) => self.set_filters(rules),
+            SessionEvent::Templates(templates) => self.set_templates(templates),
+            SessionEvent::Draining(seconds) => self.server_draining(seconds),
+            SessionEvent::Exit => self.exit(),
+            SessionEvent::Crash => self.crash(),
+        }
+    }
+
+    /// Update the latency to the coordinator shown in the tab bar.
+    #[handle_message]
+    fn set_latency(&mut self, latency: Duration) {
+        self.tabs.set_latency(latency);
+        self.frontend.render();
+    }
+
+    /// Handle a batch of messages sent by a channel to us.
+    ///
+    /// The UI is rendered once for the whole batch.
+    #[handle_message]
+    fn receive_messages(&mut self, batch: Vec<ChatMessage>) {
+        // All messages of a batch come from the same channel
+        let channel = batch
+            .first()
+            .and_then(|message| self.tabs.get_channel(&message.channel));
+        let mut ring = false;
+        for payload in batch {
+            if self.check_sequence(&payload.channel, payload.id) {
+                ring |= self.add_message(payload);
+            }
+        }
+        self.frontend.render();
+        if ring {
+            self.frontend.bell();
+        }
+        // Let the channel send more once this batch is shown
+        if let Some(channel) = channel {
+            channel.ack(self.session);
+        }
+    }
+
+    /// Update the open channel list, pushed by the coordinator after a `/list`.
+    ///
+    /// Once the "Channels" tab is closed, we unsubscribe from further updates.
+    #[handle_message]
+    fn channel_list(&mut self, namespace: String, list: Vec<(String, usize, ChannelMeta)>) {
+        if !self.tabs.names().iter().any(|name| name == "Channels") {
+            self.coordinator.unsubscribe_list(self.session);
+            self.channel_list = None;
+            return;
+        }
+        // Ignore pushes for a namespace we are not looking at anymore
+        if !matches!(&self.channel_list, Some((current, _, _)) if *current == namespace) {
+            return;
+        }
+        let content = i18n::channel_list(self.locale, &self.templates, list.clone());
+        self.channel_list = Some((namespace, Instant::now(), list));
+        self.tabs.set_info("Channels", content);
+        self.frontend.render();
+    }
+
+    /// Show a server event in the Server tab, unsubscribes if the tab was closed.
+    #[handle_message]
+    fn server_event(&mut self, event: ServerEvent) {
+        if !self.tabs.names().iter().any(|name| name == "Server") {
+            self.coordinator.unsubscribe_events(self.session);
+            self.server_events = None;
+            return;
+        }
+        let events = match &mut self.server_events {
+            Some(events) => events,
+            None => return,
+        };
+        let now: DateTime<Local> = Local::now();
+        events.push_front((format!("[{}] ", now.format("%H:%M UTC")), event));
+        events.truncate(SERVER_EVENTS_LIMIT);
+        let content = self.server_events_content();
+        self.tabs.set_info("Server", content);
+        self.frontend.render();
+    }
+
+    /// Handle a direct message sent to us, either right away or from the mailbox.
+    #[handle_message]
+    fn receive_direct(&mut self, mut message: DirectMessage) {
+        if self.is_blocked(&message.from, message.identified, None) {
+            return;
+        }
+        if let Some(sender_key) = message.sender_key {
+            match self.keys.open(&sender_key, &message.message) {
+                Some(decrypted) => {
+                    message.message = decrypted;
+                    // Answers to an encrypted message are encrypted too.
+                    self.secure.insert(message.from.to_lowercase(), sender_key);
+                }
+                None => {
+                    let text = i18n::text(self.locale, Text::CannotDecrypt);
+                    self.tabs.set_status(format!("{} {}", text, message.from));
+                    self.frontend.render();
+                    return;
+                }
+            }
+        }
+        let text = i18n::text(self.locale, Text::NewMessage);
+        self.tabs.set_status(format!("{} {}", text, message.from));
+        self.inbox.push(message);
+        self.tabs.set_info("Inbox", self.inbox_content());
+        self.frontend.render();
+        if self.bell {
+            self.frontend.bell();
+        }
+    }
+
+    /// Show an invitation to a channel, the user can join it with `/accept`.
+    ///
+    /// A newer invitation replaces the previous one.
+    #[handle_message]
+    fn receive_invitation(&mut self, from: String, channel: String) {
+        let content = i18n::invitation(self.locale, &from, &channel);
+        self.invitation = Some(channel);
+        let tab = Tab::new(
+            "Invitation".to_string(),
+            None,
+            TabType::Info(content.clone()),
+        );
+        self.tabs.add_or_switch(tab);
+        self.tabs.set_info("Invitation", content);
+        self.frontend.render();
+        if self.bell {
+            self.frontend.bell();
+        }
+    }
+
+    /// Replace the word filters after an administrator reloaded them.
+    #[handle_message]
+    fn set_filters(&mut self, rules: Vec<FilterRule>) {
+        self.filters = Filters::new(&rules);
+    }
+
+    /// Replace the custom screens after an administrator reloaded them.
+    #[handle_message]
+    fn set_templates(&mut self, templates: CustomTemplates) {
+        self.templates = templates;
+    }
+
+    /// Handle the channel's reply to a `/ping`.
+    #[handle_message]
+    fn pong(&mut self, channel: String) {
+        if let Some(sent) = self.ping_sent.take() {
+            let text = format!(
+                "{} {}ms",
+                i18n::text(self.locale, Text::PingChannel),
+                sent.elapsed().as_millis()
+            );
+            self.tabs.add_system_message(channel, system_line(text));
+            self.frontend.render();
+        }
+    }
+
+    /// Register with a new coordinator after the old one died.
+    ///
+    /// All joined channels are joined again. If a channel didn't survive the restart, a new one
+    /// is created and the tab is bound to it.
+    #[handle_message]
+    fn coordinator_restarted(&mut self, coordinator: ProcessRef<CoordinatorProcess>) {
+        self.coordinator = coordinator;
+        // The new coordinator is joined once a nickname is chosen
+        if self.choosing_nickname {
+            return;
+        }
+        // The subscription to the channel list didn't survive the restart
+        self.channel_list = None;
+        self.coordinator.rejoin_server(
+            self.session,
+            self.username.clone(),
+            self.address,
+            self.location.clone(),
+        );
+        // Keep the Server tab live
+        if self.server_events.is_some() {
+            self.coordinator.subscribe_events(self.session);
+        }
+        for name in self.tabs.names() {
+            // Followed channels don't go through the coordinator
+            if !name.starts_with('#') || self.following.contains(&name) {
+                continue;
+            }
+            if let Ok(channel) = self.join_channel(name.clone()) {
+                self.tabs.set_channel(&name, channel);
+                // A new channel process may number its messages differently
+                self.sequences.remove(&name);
+            }
+        }
+        self.frontend.render();
+    }
+
+    /// Another session of our account joined a channel, join it in the background too.
+    #[handle_message]
+    fn session_joined(&mut self, channel: String) {
+        let selected = self.tabs.get_selected().get_name();
+        self.open_channel(&channel);
+        self.tabs.switch(&selected);
+        self.frontend.render();
+    }
+
+    /// Another session of our account left a channel, close its tab too.
+    #[handle_message]
+    fn session_left(&mut self, channel: String) {
+        if self.following.contains(&channel) || !self.tabs.names().contains(&channel) {
+            return;
+        }
+        let selected = self.tabs.get_selected().get_name();
+        self.coordinator
+            .leave_channel(self.session, channel.clone());
+        self.tabs.switch(&channel);
+        self.tabs.drop();
+        self.tabs.switch(&selected);
+        self.frontend.render();
+    }
+
+    /// A temporary channel is closed soon, its members and followers are warned in its tab.
+    #[handle_message]
+    fn channel_expiring(&mut self, channel: String, minutes: u64) {
+        let text = i18n::text(self.locale, Text::ChannelClosesIn);
+        let text = format!("{} {} min.", text, minutes);
+        self.tabs.add_system_message(channel, system_line(text));
+        self.frontend.render();
+    }
+
+    /// A temporary channel was closed, its tab goes away.
+    #[handle_message]
+    fn channel_expired(&mut self, channel: String) {
+        self.channel_gone(channel, Text::ChannelExpired);
+    }
+
+    /// The user was banned from a joined or followed channel, its tab goes away.
+    #[handle_message]
+    fn banned(&mut self, channel: String) {
+        self.channel_gone(channel, Text::Banned);
+    }
+
+    /// A followed channel was shut down, its tab goes away.
+    #[handle_message]
+    fn channel_closed(&mut self, channel: String) {
+        self.channel_gone(channel, Text::ChannelClosed);
+    }
+
+    /// The server stops soon, let the user know when to reconnect.
+    #[handle_message]
+    fn server_draining(&mut self, seconds: u64) {
+        let text = i18n::text(self.locale, Text::ServerDraining);
+        self.tabs.set_status(format!("{} {}s", text, seconds));
+        self.frontend.render();
+    }
+
+    /// The telnet writer sent a frame to the terminal.
+    #[handle_message]
+    fn frame_written(&mut self) {
+        if let Some(telnet) = self.frontend.as_any().downcast_mut::<TelnetFrontend>() {
+            telnet.frame_written();
+        }
+    }
+
+    /// Ctrl+C wasn't pressed a second time, the next press asks again.
+    #[handle_message]
+    fn quit_expired(&mut self) {
+        self.quit_pending = false;
+        let prompt = i18n::text(self.locale, Text::PressAgainToQuit);
+        if self.tabs.get_status().as_deref() == Some(prompt) {
+            self.tabs.clear_status();
+            self.frontend.render();
+        }
+    }
+
+    /// Render the frame that was put off to stay within the frame rate limit.
     #[handle_message]
-    fn receive_message(
-        &mut self,
-        channel: String,
-        timestamp: String,
-        name: String,
-        message: String,
-    ) {
-        self.tabs.add_message(channel, timestamp, name, message);
-        self.ui.render();
+    fn render_due(&mut self) {
+        if let Some(telnet) = self.frontend.as_any().downcast_mut::<TelnetFrontend>() {
+            telnet.render_due();
+        }
     }
 
-    /// Clean up on exit.
+    #[handle_message]
+    fn crash(&mut self) {
+        panic!("client crashed on request");
+    }
+
+    /// Clean up on exit and end the client process.
     #[handle_message]
     fn exit(&mut self) {
+        if self.exiting {
+            return;
+        }
+        self.exiting = true;
         // Let the coordinator know that we left
-        self.coordinator.leave_server(self.this);
-        // `exit(1)` is used to kill the linked telnet sub-process, because lunatic doesn't provide a
-        // `kill process` API yet.
-        exit(1);
+        self.coordinator.leave_server(self.session);
+        for channel in &self.following {
+            if let Some(shard) = self.directory.shard(channel) {
+                shard.leave_channel(self.session, channel.clone());
+            }
+        }
+        // Give the user back a clean terminal
+        let frontend = self.frontend.close();
+        // The process returns once it handled the shutdown. Linked processes only die together
+        // with a failed process, so they are stopped after it's gone.
+        Process::spawn(
+            (self.this, self.heartbeat, self.session, frontend),
+            |(client, heartbeat, session, frontend), _: Mailbox<()>| {
+                client.shutdown();
+                heartbeat.kill();
+                session.kill();
+                frontend.kill();
+            },
+        );
+    }
+}
+
+impl ClientProcess {
+    // Add the hint for the recognized telnet client to the welcome screen.
+    fn show_client_hint(&mut self) {
+        if let Some(client) = self.caps.client() {
+            let hint = i18n::client_hint(self.locale, client);
+            self.tabs
+                .set_info("Welcome", format!("{}\n\n  {}", self.welcome, hint));
+        }
+    }
+
+    // Join the server under the nickname entered at the prompt, or ask again if it's refused.
+    fn choose_nickname(&mut self, nickname: &str) {
+        let joined = self.coordinator.join_server(
+            self.session,
+            self.address,
+            self.location.clone(),
+            Some(nickname.to_owned()),
+        );
+        match joined {
+            Ok(info) => {
+                self.choosing_nickname = false;
+                self.username = info.username;
+                let welcome = i18n::welcome(
+                    self.locale,
+                    &self.templates,
+                    &self.username,
+                    info.total_clients,
+                    self.directory.channel_count(),
+                    &info.motd,
+                );
+                self.welcome = welcome;
+                self.tabs.set_info("Welcome", self.welcome.clone());
+                self.show_client_hint();
+                if let Some(channel) = info.auto_join {
+                    self.open_channel(&channel);
+                }
+            }
+            Err(err) => {
+                let text = match err {
+                    UsernameError::Taken => Text::NicknameTaken,
+                    UsernameError::Invalid => Text::NicknameInvalid,
+                };
+                self.tabs
+                    .set_status(i18n::text(self.locale, text).to_owned());
+            }
+        }
+    }
+
+    // Add a message from a channel to the tabs.
+    //
+    // Returns `true` if the bell should ring, because we are mentioned in a channel that is not
+    // currently visible.
+    fn add_message(&mut self, payload: ChatMessage) -> bool {
+        let ChatMessage {
+            channel,
+            id,
+            timestamp,
+            name,
+            identified,
+            session,
+            message,
+            trace,
+        } = payload;
+        trace::log(&trace, "delivered");
+        if self.is_blocked(&name, identified, session) {
+            return false;
+        }
+        let mentioned = name != self.username
+            && message
+                .split(|ch: char| !ch.is_alphanumeric() && ch != '_')
+                .any(|word| word == self.username);
+        let background = self.tabs.get_selected().get_name() != channel;
+        let notify = match self.notify_levels.get(&channel) {
+            Some(NotifyLevel::Mentions) => mentioned,
+            Some(NotifyLevel::Silent) => false,
+            Some(NotifyLevel::All) | None => true,
+        };
+        let name = channel::display_name(&name, identified);
+        self.tabs
+            .add_message(channel, id, timestamp, name, message, notify);
+        self.bell && mentioned && notify && background
+    }
+
+    // Check the id of a channel message against the next expected one.
+    //
+    // Returns false for a message that is already shown. If messages are missing before it, they
+    // are fetched from the channel and shown first.
+    fn check_sequence(&mut self, channel: &str, id: u64) -> bool {
+        let expected = match self.sequences.insert(channel.to_owned(), id + 1) {
+            Some(expected) => expected,
+            None => return true,
+        };
+        // A channel that lost its history starts counting again
+        if id < expected && id != 0 {
+            self.sequences.insert(channel.to_owned(), expected);
+            return false;
+        }
+        if id > expected {
+            self.resync(channel, expected, id);
+        }
+        true
+    }
+
+    // The channel skips batches until we caught up, the gap is not fetched again.
+    fn messages_skipped(&mut self, channel: String) {
+        self.sequences.remove(&channel);
+        let text = i18n::text(self.locale, Text::MessagesMissing);
+        self.tabs
+            .add_system_message(channel, system_line(text.to_string()));
+        self.frontend.render();
+    }
+
+    // Fetch the missing messages with ids from `from` up to `to` and show them, with a marker if
+    // not all of them are available anymore.
+    fn resync(&mut self, channel: &str, from: u64, to: u64) {
+        let missing = (to - from).min(RESYNC_LIMIT as u64) as usize;
+        let page = match self.tabs.get_channel(channel) {
+            Some(process) => process.get_history(Some(to), missing),
+            None => Vec::new(),
+        };
+        if page.first().map_or(true, |(id, _)| *id > from) {
+            let text = i18n::text(self.locale, Text::MessagesMissing);
+            self.tabs
+                .add_system_message(channel.to_owned(), system_line(text.to_string()));
+        }
+        for (id, (timestamp, name, message, identified)) in page {
+            if !self.is_blocked(&name, identified, None) {
+                let name = channel::display_name(&name, identified);
+                self.tabs
+                    .add_message(channel.to_owned(), id, timestamp, name, message, false);
+            }
+        }
+    }
+
+    // Switch to the channel's tab, joining the channel first if needed.
+    fn open_channel(&mut self, channel_name: &str) {
+        // Already joined, just switch to the tab
+        let open = self.tabs.names().iter().any(|name| name == channel_name);
+        if open && !self.following.contains(channel_name) {
+            self.tabs.switch(channel_name);
+            return;
+        }
+        // Incorrect channel name
+        if !channel_name.starts_with('#') || channel_name[1..].split('/').any(|s| s.is_empty()) {
+            return;
+        }
+        let channel = match self.join_channel(channel_name.to_owned()) {
+            Ok(channel) => channel,
+            Err(JoinError::TooManyChannels(max)) => {
+                let text = i18n::text(self.locale, Text::TooManyChannels);
+                self.tabs.set_status(format!("{} {}", text, max));
+                return;
+            }
+            Err(JoinError::GuestChannelLimit(max)) => {
+                let text = i18n::text(self.locale, Text::GuestChannelLimit);
+                self.tabs.set_status(format!("{} {}", text, max));
+                return;
+            }
+            Err(JoinError::CreationLimit { limit, minutes }) => {
+                let text = i18n::text(self.locale, Text::CreationLimit);
+                self.tabs
+                    .set_status(format!("{} {} ({} min)", text, limit, minutes));
+                return;
+            }
+            Err(JoinError::Banned) => {
+                let text = i18n::text(self.locale, Text::Banned);
+                self.tabs.set_status(format!("{} {}", text, channel_name));
+                return;
+            }
+            Err(JoinError::Unavailable) | Err(JoinError::UnknownClient) => {
+                let text = i18n::text(self.locale, Text::ChannelUnavailable);
+                self.tabs.set_status(format!("{} {}", text, channel_name));
+                return;
+            }
+        };
+
+        // A follower becomes a member, the tab stays the same
+        if self.following.remove(channel_name) {
+            self.tabs.switch(channel_name);
+            return;
+        }
+        self.add_channel_tab(channel_name, channel);
+    }
+
+    // Join a channel that is closed after the `ttl`, e.g. `2h`. An existing channel only becomes
+    // temporary if the user is its operator.
+    fn open_temporary_channel(&mut self, channel_name: &str, ttl: &str) {
+        let ttl = match Announcement::parse_interval(ttl) {
+            Some(ttl) if ttl >= directory::MIN_TTL && ttl <= directory::MAX_TTL => ttl,
+            _ => {
+                let text = i18n::text(self.locale, Text::InvalidTtl);
+                self.tabs.set_status(text.to_string());
+                return;
+            }
+        };
+        self.open_channel(channel_name);
+        if self.tabs.get_selected().get_name() != channel_name {
+            return;
+        }
+        let shard = match self.shard(channel_name) {
+            Some(shard) => shard,
+            None => return,
+        };
+        if !shard.expire_channel(self.session, channel_name.to_owned(), ttl) {
+            let text = i18n::text(self.locale, Text::NotOperator);
+            self.tabs.set_status(text.to_string());
+            return;
+        }
+        let text = i18n::text(self.locale, Text::ChannelExpires);
+        let minutes = ttl.as_secs() / 60;
+        self.tabs
+            .set_status(format!("{} {}h {}m", text, minutes / 60, minutes % 60));
+    }
+
+    // Follow a channel read-only, or switch to its tab if it's already open.
+    fn follow_channel(&mut self, channel_name: &str) {
+        if self.tabs.names().iter().any(|name| name == channel_name) {
+            self.tabs.switch(channel_name);
+            return;
+        }
+        let shard = match self.shard(channel_name) {
+            Some(shard) => shard,
+            None => return,
+        };
+        let result =
+            shard.follow_channel(self.session, self.username.clone(), channel_name.to_owned());
+        let channel = match result {
+            Ok(channel) => channel,
+            Err(err) => {
+                let text = match err {
+                    FollowError::NoSuchChannel => Text::NoSuchChannel,
+                    FollowError::Banned => Text::Banned,
+                };
+                let text = i18n::text(self.locale, text);
+                self.tabs.set_status(format!("{} {}", text, channel_name));
+                return;
+            }
+        };
+        self.following.insert(channel_name.to_owned());
+        self.add_channel_tab(channel_name, channel);
+    }
+
+    // Open a tab bound to the channel, showing its description and latest messages.
+    fn add_channel_tab(&mut self, channel_name: &str, channel: ProcessRef<ChannelProcess>) {
+        // Get last messages from channel
+        let page_size = match self.coordinator.history_limit(self.session) {
+            Some(limit) => limit.min(HISTORY_PAGE),
+            None => HISTORY_PAGE,
+        };
+        let page = channel.get_history(None, page_size);
+        let expired = reaches_expired(&page, channel.expired_before());
+        match page.last() {
+            Some((id, _)) => self.sequences.insert(channel_name.to_owned(), id + 1),
+            None => self.sequences.remove(channel_name),
+        };
+        let mut history = History::from_page(history_lines(page));
+        if expired {
+            history.truncate_older(system_line(self.expired_line()));
+        }
+        // Show the channel description above the messages
+        let meta = channel.get_meta();
+        if !meta.description.is_empty() {
+            let description = if meta.language.is_empty() {
+                meta.description
+            } else {
+                format!("[{}] {}", meta.language, meta.description)
+            };
+            history.push_front(system_line(description));
+        }
+        // Create new tab bound to channel
+        let tab = Tab::new(
+            channel_name.to_owned(),
+            Some(channel),
+            TabType::Channel(history),
+        );
+        self.tabs.add_or_switch(tab);
+    }
+
+    // Identify with the checked password hash, as another session of `account` if it's given.
+    fn identify(&mut self, account: Option<String>, password: PasswordHash) -> Text {
+        if let Some(account) = account {
+            match self
+                .coordinator
+                .add_session(self.session, account, password)
+            {
+                Some(username) => {
+                    self.username = username;
+                    self.secure.clear();
+                    self.load_account();
+                    Text::Identified
+                }
+                None => Text::IdentifyFailed,
+            }
+        } else if self.coordinator.identify(self.session, password) {
+            self.load_account();
+            Text::Identified
+        } else {
+            Text::IdentifyFailed
+        }
+    }
+
+    // Load the block list of the account we just identified with and publish our key.
+    fn load_account(&mut self) {
+        self.identified = true;
+        let BlockList { accounts, sessions } = self.coordinator.blocked_users(self.session);
+        self.blocked = accounts.into_iter().collect();
+        self.blocked_sessions = sessions.into_iter().collect();
+        if let Some(AccountSettings { aliases, plain }) = self.coordinator.settings(self.session) {
+            // Aliases defined before identifying are kept, unless the account has its own
+            self.aliases.extend(aliases);
+            self.plain = plain;
+            self.frontend.set_formatting(!plain);
+        }
+        self.save_settings();
+        self.coordinator
+            .publish_key(self.session, self.keys.public());
+    }
+
+    // Join a channel through the coordinator, which checks the channel limit, and the directory
+    // shard in charge of the channel.
+    fn join_channel(&mut self, name: String) -> Result<ProcessRef<ChannelProcess>, JoinError> {
+        self.coordinator.join_channel(self.session, name.clone())?;
+        let result = match self.directory.shard(&name) {
+            Some(shard) => shard.join_channel(self.session, self.username.clone(), name.clone()),
+            None => Err(JoinError::Unavailable),
+        };
+        if result.is_err() {
+            // Give back the place reserved by the coordinator
+            self.coordinator.leave_channel(self.session, name);
+        }
+        result
+    }
+
+    // Run a message through the word filters before sending it.
+    //
+    // Returns `None` if the message must not be sent, the reason is shown in the status bar.
+    fn filter(&mut self, message: &str) -> Option<String> {
+        if let Some(until) = self.muted_until {
+            let now = Instant::now();
+            if now < until {
+                let text = i18n::text(self.locale, Text::Muted);
+                let seconds = (until - now).as_secs() + 1;
+                self.tabs.set_status(format!("{} {}", text, seconds));
+                return None;
+            }
+            self.muted_until = None;
+        }
+        let verdict = self.filters.apply(message);
+        if verdict.mute {
+            self.muted_until = Some(Instant::now() + filter::MUTE_DURATION);
+        }
+        let text = match (&verdict.message, verdict.warn) {
+            (None, _) => Some(Text::FilterDropped),
+            (Some(_), true) => Some(Text::FilterWarning),
+            (Some(_), false) => None,
+        };
+        if let Some(text) = text {
+            self.tabs
+                .set_status(i18n::text(self.locale, text).to_string());
+        }
+        verdict.message
+    }
+
+    // Render the received direct messages for the "Inbox" tab.
+    fn inbox_content(&self) -> String {
+        let messages = self
+            .inbox
+            .iter()
+            .map(|direct| {
+                let mut from = channel::display_name(&direct.from, direct.identified);
+                if direct.sender_key.is_some() {
+                    let encrypted = i18n::text(self.locale, Text::Encrypted);
+                    from = format!("{} ({})", from, encrypted);
+                }
+                (direct.timestamp.clone(), from, direct.message.clone())
+            })
+            .collect();
+        i18n::inbox(self.locale, messages)
+    }
+
+    fn server_events_content(&self) -> String {
+        let events = self
+            .server_events
+            .iter()
+            .flatten()
+            .map(|(time, event)| (time.clone(), i18n::server_event(self.locale, event)))
+            .collect();
+        i18n::server_events(self.locale, events)
+    }
+
+    // Returns the channels of a namespace for `/list`.
+    //
+    // While subscribed to the same namespace, the list pushed by the coordinator is reused.
+    // Otherwise the directory is asked once and we subscribe to updates of the namespace.
+    fn list_channels(&mut self, namespace: String) -> Vec<(String, usize, ChannelMeta)> {
+        if let Some((current, received, list)) = &self.channel_list {
+            // Missing pushes mean that the subscription was lost
+            if *current == namespace && received.elapsed() < 2 * LIST_PUSH_INTERVAL {
+                return list.clone();
+            }
+        }
+        let list = self.directory.list_channels(&namespace);
+        self.coordinator
+            .subscribe_list(self.session, namespace.clone());
+        self.channel_list = Some((namespace, Instant::now(), list.clone()));
+        list
+    }
+
+    // Replace an alias at the start of the input, the arguments are appended to its expansion.
+    //
+    // Aliases are expanded only once, so an alias can't call itself.
+    fn expand_alias(&self, input: &str) -> String {
+        if let Some(command) = input.strip_prefix('/') {
+            let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+            if let Some(expansion) = self.aliases.get(name) {
+                return format!("{} {}", expansion, args);
+            }
+        }
+        input.to_owned()
+    }
+
+    // Open the channel and select the message in copy mode, loading older history until it's
+    // found.
+    fn goto_message(&mut self, channel: &str, id: u64) {
+        self.open_channel(channel);
+        if self.tabs.get_selected().get_name() != channel {
+            // Joining failed, the status bar tells why
+            return;
+        }
+        loop {
+            if self.tabs.select_message(id) {
+                return;
+            }
+            match self.tabs.get_selected().history_before() {
+                Some(before) if before > id => self.load_history(),
+                _ => break,
+            }
+        }
+        let text = i18n::text(self.locale, Text::MessageNotFound);
+        self.tabs
+            .set_status(format!("{} {}", text, permalink(channel, id)));
+    }
+
+    // Prepend an older page of the channel's history to the selected tab.
+    fn load_history(&mut self) {
+        let selected = self.tabs.get_selected();
+        if let (Some(channel), Some(before)) = (selected.get_channel(), selected.history_before()) {
+            // Guests can only scroll back to the message `limit` ids before the newest one
+            let page_size = match self.coordinator.history_limit(self.session) {
+                Some(limit) => {
+                    let next = self.sequences.get(&selected.get_name()).copied();
+                    let oldest = next.unwrap_or(before).saturating_sub(limit as u64);
+                    (before.saturating_sub(oldest) as usize).min(HISTORY_PAGE)
+                }
+                None => HISTORY_PAGE,
+            };
+            if page_size == 0 {
+                return;
+            }
+            // Don't ask for messages the channel's retention policy already expired
+            let expired_before = channel.expired_before();
+            if matches!(expired_before, Some(boundary) if before <= boundary) {
+                self.tabs.truncate_older(system_line(self.expired_line()));
+                return;
+            }
+            // Older pages can come from the storage
+            let page = channel.get_history(Some(before), page_size);
+            let expired = reaches_expired(&page, expired_before);
+            self.tabs.prepend_history(history_lines(page));
+            if expired {
+                self.tabs.truncate_older(system_line(self.expired_line()));
+            }
+        }
+    }
+
+    // Add a line to the paste, or share it once the line with only a dot arrives.
+    fn paste_line(&mut self, line: String) {
+        let (channel, mut lines) = match self.pasting.take() {
+            Some(pasting) => pasting,
+            None => return,
+        };
+        match line.trim() {
+            "/cancel" => return,
+            "." => {
+                self.share_paste(channel, lines);
+                return;
+            }
+            _ => {}
+        }
+        lines.push(line.trim_end().to_owned());
+        let text = i18n::text(self.locale, Text::Pasting);
+        self.tabs.set_status(format!("{} {}", text, lines.len()));
+        if lines.len() >= paste::MAX_LINES {
+            self.share_paste(channel, lines);
+        } else {
+            self.pasting = Some((channel, lines));
+        }
+    }
+
+    // Save the paste to the storage and tell the channel how to open it.
+    fn share_paste(&mut self, channel: String, lines: Vec<String>) {
+        let process = match self.tabs.get_channel(&channel) {
+            Some(process) if !lines.is_empty() => process,
+            _ => return,
+        };
+        // The lines are filtered together, a filter can match across them
+        let lines = match self.filter(&lines.join("\n")) {
+            Some(content) => content.lines().map(str::to_owned).collect(),
+            None => return,
+        };
+        let storage = match self.storage() {
+            Some(storage) => storage,
+            None => return,
+        };
+        let mut paste = Paste {
+            id: paste::new_id(),
+            author: self.username.clone(),
+            channel: channel.clone(),
+            created: Utc::now().timestamp(),
+            lines,
+        };
+        // Ids are random, another paste may have the same one already
+        let mut attempts = 0;
+        while !storage.save_paste(paste.clone()) {
+            attempts += 1;
+            if attempts == PASTE_ATTEMPTS {
+                let text = i18n::text(self.locale, Text::PasteFailed);
+                self.tabs.set_status(text.to_string());
+                return;
+            }
+            paste.id = paste::new_id();
+        }
+        let id = paste.id;
+        let now: DateTime<Local> = Local::now();
+        let timestamp = format!("[{}] ", now.format("%H:%M UTC"));
+        let text = i18n::text(self.locale, Text::PasteShared);
+        let trace = self.next_trace();
+        process.broadcast_message(
+            channel,
+            timestamp,
+            self.username.clone(),
+            Some(self.session),
+            format!("{} /show {}", text, id),
+            trace,
+        );
+    }
+
+    // Open a paste in its own tab.
+    fn show_paste(&mut self, id: &str) {
+        let paste = match self
+            .storage()
+            .and_then(|storage| storage.paste(id.to_owned()))
+        {
+            Some(paste) => paste,
+            None => {
+                let text = i18n::text(self.locale, Text::UnknownPaste);
+                self.tabs.set_status(text.to_string());
+                return;
+            }
+        };
+        let name = format!("Paste {}", paste.id);
+        let content = i18n::paste(self.locale, &paste);
+        let tab = Tab::new(name.clone(), None, TabType::Info(content.clone()));
+        self.tabs.add_or_switch(tab);
+        self.tabs.set_info(&name, content);
+    }
+
+    // Keep the aliases and the formatting with the account, if the user is identified.
+    fn save_settings(&self) {
+        if !self.identified {
+            return;
+        }
+        let settings = AccountSettings {
+            aliases: self.aliases.clone(),
+            plain: self.plain,
+        };
+        self.coordinator.save_settings(self.session, settings);
+    }
+
+    // Returns the directory shard in charge of the channel, the user is told if it's not running.
+    fn shard(&mut self, channel: &str) -> Option<ProcessRef<DirectoryProcess>> {
+        let shard = self.directory.shard(channel);
+        if shard.is_none() {
+            let text = i18n::text(self.locale, Text::ChannelUnavailable);
+            self.tabs.set_status(format!("{} {}", text, channel));
+        }
+        shard
+    }
+
+    // Returns `true` if messages of the sender are hidden, `identified` if it's identified with the
+    // account of its `name`. Guests are blocked by their `session`.
+    fn is_blocked(&self, name: &str, identified: bool, session: Option<u64>) -> bool {
+        (identified && self.blocked.contains(&name.to_lowercase()))
+            || session.map_or(false, |session| self.blocked_sessions.contains(&session))
+    }
+
+    // Drop the tab of a channel the user is no longer part of and tell why.
+    fn channel_gone(&mut self, channel: String, reason: Text) {
+        if !self.tabs.names().contains(&channel) {
+            return;
+        }
+        // Followed channels are not known to the coordinator
+        if !self.following.remove(&channel) {
+            self.coordinator
+                .leave_channel(self.session, channel.clone());
+        }
+        let selected = self.tabs.get_selected().get_name();
+        self.tabs.switch(&channel);
+        self.tabs.drop();
+        self.tabs.switch(&selected);
+        let text = i18n::text(self.locale, reason);
+        self.tabs.set_status(format!("{} {}", text, channel));
+        self.frontend.render();
+    }
+
+    // Looks up the storage of the network on each use, it may have been restarted.
+    fn storage(&self) -> Option<ProcessRef<StorageProcess>> {
+        ProcessRef::<StorageProcess>::lookup(&storage::registry_name(&self.network))
+    }
+
+    // Let the selected channel play the game of a registry command, it posts the result itself.
+    fn play(&mut self, command: &str, args: &str) {
+        let parse = match commands::find(command).and_then(|command| command.game) {
+            Some(parse) => parse,
+            None => return,
+        };
+        let game = match parse(args) {
+            Ok(game) => game,
+            Err(text) => {
+                let text = i18n::text(self.locale, text);
+                self.tabs.set_status(text.to_string());
+                return;
+            }
+        };
+        let selected = self.tabs.get_selected();
+        if self.following.contains(&selected.get_name()) {
+            let text = i18n::text(self.locale, Text::ReadOnly);
+            self.tabs.set_status(text.to_string());
+            return;
+        }
+        if let Some(channel) = selected.get_channel() {
+            let now: DateTime<Local> = Local::now();
+            let timestamp = format!("[{}] ", now.format("%H:%M UTC"));
+            channel.play(self.session, timestamp, self.username.clone(), game);
+        }
+    }
+
+    // Let the selected channel repeat a message every `interval`.
+    fn schedule_announcement(&mut self, interval: Option<Duration>, message: &str) {
+        let selected = self.tabs.get_selected();
+        let channel = match selected.get_channel() {
+            Some(channel) => channel,
+            None => return,
+        };
+        let interval = match interval {
+            Some(interval) => interval,
+            None => {
+                let text = i18n::text(self.locale, Text::InvalidInterval);
+                self.tabs.set_status(text.to_string());
+                return;
+            }
+        };
+        let message = match message.len() {
+            1..=299 => self.filter(message),
+            _ => None,
+        };
+        let message = match message {
+            Some(message) => message,
+            None => return,
+        };
+        let result =
+            channel.schedule_announcement(self.session, self.username.clone(), interval, message);
+        let text = match result {
+            Ok(id) => {
+                let text = i18n::text(self.locale, Text::AnnouncementScheduled);
+                format!("{} {}", text, id)
+            }
+            Err(err) => i18n::text(self.locale, announcement_error(err)).to_string(),
+        };
+        self.tabs
+            .add_system_message(selected.get_name(), system_line(text));
+    }
+
+    fn cancel_announcement(&mut self, id: u64) {
+        let selected = self.tabs.get_selected();
+        if let Some(channel) = selected.get_channel() {
+            let text = match channel.cancel_announcement(self.session, id) {
+                Ok(()) => {
+                    let text = i18n::text(self.locale, Text::AnnouncementCancelled);
+                    format!("{} {}", text, id)
+                }
+                Err(err) => i18n::text(self.locale, announcement_error(err)).to_string(),
+            };
+            self.tabs
+                .add_system_message(selected.get_name(), system_line(text));
+        }
+    }
+
+    // Show the announcements of the selected channel in the "Announcements" tab.
+    fn show_announcements(&mut self) {
+        let selected = self.tabs.get_selected();
+        if let Some(channel) = selected.get_channel() {
+            let content =
+                i18n::announcements(self.locale, &selected.get_name(), channel.announcements());
+            let tab = Tab::new(
+                "Announcements".to_string(),
+                None,
+                TabType::Info(content.clone()),
+            );
+            self.tabs.add_or_switch(tab);
+            self.tabs.set_info("Announcements", content);
+        }
+    }
+
+    // Let the selected channel poll a feed.
+    fn add_feed(&mut self, url: &str, interval: Option<Duration>) {
+        let selected = self.tabs.get_selected();
+        let channel = match selected.get_channel() {
+            Some(channel) => channel,
+            None => return,
+        };
+        let interval = match interval {
+            Some(interval) => interval,
+            None => {
+                let text = i18n::text(self.locale, Text::InvalidInterval);
+                self.tabs.set_status(text.to_string());
+                return;
+            }
+        };
+        let result = channel.add_feed(
+            self.session,
+            self.username.clone(),
+            url.to_owned(),
+            interval,
+        );
+        let text = match result {
+            Ok(id) => {
+                let text = i18n::text(self.locale, Text::FeedAdded);
+                format!("{} {}", text, id)
+            }
+            Err(err) => i18n::text(self.locale, feed_error(err)).to_string(),
+        };
+        self.tabs
+            .add_system_message(selected.get_name(), system_line(text));
+    }
+
+    fn remove_feed(&mut self, id: u64) {
+        let selected = self.tabs.get_selected();
+        if let Some(channel) = selected.get_channel() {
+            let text = match channel.remove_feed(self.session, id) {
+                Ok(()) => {
+                    let text = i18n::text(self.locale, Text::FeedRemoved);
+                    format!("{} {}", text, id)
+                }
+                Err(err) => i18n::text(self.locale, feed_error(err)).to_string(),
+            };
+            self.tabs
+                .add_system_message(selected.get_name(), system_line(text));
+        }
+    }
+
+    // Show the feeds of the selected channel in the "Feeds" tab.
+    fn show_feeds(&mut self) {
+        let selected = self.tabs.get_selected();
+        if let Some(channel) = selected.get_channel() {
+            let content = i18n::feeds(self.locale, &selected.get_name(), channel.feeds());
+            let tab = Tab::new("Feeds".to_string(), None, TabType::Info(content.clone()));
+            self.tabs.add_or_switch(tab);
+            self.tabs.set_info("Feeds", content);
+        }
+    }
+
+    // Send the text in large letters to the selected channel, one message per row.
+    fn send_banner(&mut self, text: &str) {
+        let selected = self.tabs.get_selected();
+        let channel = match selected.get_channel() {
+            Some(channel) if !self.following.contains(&selected.get_name()) => channel,
+            _ => return,
+        };
+        if text.is_empty() {
+            return;
+        }
+        if !self.banner_everyone && !channel.is_operator(self.session) {
+            let text = i18n::text(self.locale, Text::NotOperator);
+            self.tabs
+                .add_system_message(selected.get_name(), system_line(text.to_string()));
+            return;
+        }
+        if let Some(last) = self.last_banner {
+            let wait = BANNER_INTERVAL.saturating_sub(last.elapsed());
+            if !wait.is_zero() {
+                let text = i18n::text(self.locale, Text::BannerWait);
+                self.tabs
+                    .set_status(format!("{} {}", text, wait.as_secs() + 1));
+                return;
+            }
+        }
+        let text = match self.filter(text) {
+            Some(text) => text,
+            None => return,
+        };
+        self.last_banner = Some(Instant::now());
+        let now: DateTime<Local> = Local::now();
+        let timestamp = format!("[{}] ", now.format("%H:%M UTC"));
+        for row in banner::render(&text) {
+            let trace = self.next_trace();
+            selected.message(
+                timestamp.clone(),
+                self.username.clone(),
+                self.session,
+                row,
+                trace,
+            );
+        }
+    }
+
+    // Start recording the session to a new file, or stop the running recording.
+    fn toggle_recording(&mut self) {
+        let dir = match &self.record_dir {
+            Some(dir) => dir,
+            None => {
+                let text = i18n::text(self.locale, Text::RecordingDisabled);
+                self.tabs.set_status(text.to_owned());
+                return;
+            }
+        };
+        if let Some(path) = self.recording.take() {
+            self.frontend.stop_recording();
+            let text = i18n::text(self.locale, Text::RecordingStopped);
+            self.tabs.set_status(format!("{} {}", text, path));
+            return;
+        }
+        // Recordings must not fill up the disk
+        let has_room = recording::has_room(dir).unwrap_or(false);
+        if self.recordings >= recording::MAX_PER_SESSION || !has_room {
+            let text = i18n::text(self.locale, Text::RecordingLimit);
+            self.tabs.set_status(text.to_owned());
+            return;
+        }
+        let path = recording::path(dir, &self.username, self.session.id());
+        match self.frontend.start_recording(path.clone()) {
+            Ok(()) => {
+                let text = i18n::text(self.locale, Text::RecordingStarted);
+                self.tabs.set_status(format!("{} {}", text, path));
+                self.recording = Some(path);
+                self.recordings += 1;
+            }
+            Err(err) => {
+                let text = i18n::text(self.locale, Text::RecordingFailed);
+                self.tabs.set_status(format!("{} {}", text, err));
+            }
+        }
+    }
+
+    // The line shown above the oldest message of a channel whose older messages expired.
+    fn expired_line(&self) -> String {
+        i18n::text(self.locale, Text::MessagesExpired).to_owned()
+    }
+
+    // Returns a trace id for the next sent message if tracing is enabled.
+    fn next_trace(&mut self) -> Option<String> {
+        if !self.trace {
+            return None;
+        }
+        self.sent += 1;
+        let id = Some(trace::id(self.this.id(), self.sent));
+        trace::log(&id, "client");
+        id
+    }
+
+    // Returns what the client is allowed to do, the operator rights are checked with each of its
+    // channels.
+    fn permissions(&self) -> Permissions {
+        let session = self.coordinator.snapshot_session(self.session.id());
+        let admin = session.as_ref().map_or(false, |session| session.admin);
+        let operator = session
+            .iter()
+            .flat_map(|session| &session.channels)
+            .any(|name| {
+                self.tabs
+                    .get_channel(name)
+                    .map_or(false, |channel| channel.is_operator(self.session))
+            });
+        Permissions {
+            identified: self.identified,
+            operator,
+            admin,
+        }
+    }
+
+    // Open the help tab or switch to it if it's already open.
+    fn show_help(&mut self) {
+        let instructions = i18n::instructions(self.locale, &self.templates, self.permissions());
+        let tab = Tab::new(
+            "Help".to_string(),
+            None,
+            TabType::Info(instructions.clone()),
+        );
+        self.tabs.add_or_switch(tab);
+        // An open help tab is updated, the permissions could have changed since
+        self.tabs.set_info("Help", instructions);
     }
 }
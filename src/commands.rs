@@ -0,0 +1,613 @@
+//! Registry of the chat commands.
+//!
+//! The help tab lists the commands from this registry, only the ones the client is allowed to use
+//! are shown. A new command has to be added here to show up in `/help`.
+//!
+//! Games (`/roll`, `/flip`, `/8ball`) are run from the registry too: the client looks the command
+//! up with `find` and lets the selected channel play the parsed `Game`, see `chat_core::fun`.
+
+use chat_core::fun::Game;
+
+use crate::i18n::{self, Locale, Text};
+use Permission::*;
+
+// Width of the help text, longer descriptions are wrapped.
+const WIDTH: usize = 76;
+// Indentation of the wrapped lines of a description.
+const INDENT: usize = 23;
+
+/// Who can use a command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Permission {
+    /// Every client.
+    Everyone,
+    /// Clients that are not identified yet, e.g. `/register`.
+    Guest,
+    /// Clients that identified with their account.
+    Identified,
+    /// Operators of a channel, the client that created it.
+    Operator,
+    /// Clients that became an administrator with `/admin`.
+    Admin,
+}
+
+/// What a client is allowed to do, it decides which commands are shown in the help.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Permissions {
+    pub identified: bool,
+    /// The client is the operator of at least one of its channels.
+    pub operator: bool,
+    pub admin: bool,
+}
+
+impl Permissions {
+    pub fn allows(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::Everyone => true,
+            Permission::Guest => !self.identified,
+            Permission::Identified => self.identified,
+            Permission::Operator => self.operator,
+            Permission::Admin => self.admin,
+        }
+    }
+}
+
+/// Parses the arguments of a game command, the error is shown to the user.
+pub type GameParser = fn(&str) -> Result<Game, Text>;
+
+/// A command with its arguments and descriptions.
+pub struct Command {
+    pub permission: Permission,
+    /// Only available in debug builds.
+    pub debug: bool,
+    /// Plays a game in the selected channel, the client doesn't handle the command itself.
+    pub game: Option<GameParser>,
+    // The command and its arguments, e.g. `/join <#channel>`.
+    usage: (&'static str, &'static str),
+    description: (&'static str, &'static str),
+}
+
+impl Command {
+    const fn new(
+        permission: Permission,
+        usage: (&'static str, &'static str),
+        description: (&'static str, &'static str),
+    ) -> Self {
+        Command {
+            usage,
+            permission,
+            debug: false,
+            game: None,
+            description,
+        }
+    }
+
+    const fn debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    const fn game(mut self, parse: GameParser) -> Self {
+        self.game = Some(parse);
+        self
+    }
+
+    /// The command without its arguments, e.g. `/join`.
+    pub fn name(&self) -> &'static str {
+        self.usage.0.split(' ').next().unwrap_or_default()
+    }
+
+    pub fn usage(&self, locale: Locale) -> &'static str {
+        match locale {
+            Locale::En => self.usage.0,
+            Locale::Es => self.usage.1,
+        }
+    }
+
+    pub fn description(&self, locale: Locale) -> &'static str {
+        match locale {
+            Locale::En => self.description.0,
+            Locale::Es => self.description.1,
+        }
+    }
+}
+
+/// All commands in the order they are listed, usages and descriptions in English and Spanish.
+pub const COMMANDS: &[Command] = &[
+    Command::new(
+        Everyone,
+        ("/nick <username>", "/nick <nombre>"),
+        ("Change your username", "Cambia tu nombre de usuario"),
+    ),
+    Command::new(
+        Everyone,
+        ("/sessions", "/sessions"),
+        (
+            "Show the terminals connected to your account",
+            "Muestra los terminales conectados a tu cuenta",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/list [ns/]", "/list [ns/]"),
+        (
+            "Lists most popular #channels, optionally only inside a namespace (e.g. /list games/ for #games/chess)",
+            "Lista los #canales más populares, opcionalmente solo dentro de un espacio (p. ej. /list games/ para #games/chess)",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/join <#channel>", "/join <#canal>"),
+        (
+            "Join a channel, if it doesn't exist create it.",
+            "Entra en un canal, si no existe lo crea.",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/join <#channel> --ttl <time>", "/join <#canal> --ttl <tiempo>"),
+        (
+            "Create a channel that is closed after the time, e.g. 2h",
+            "Crea un canal que se cierra tras el tiempo, p. ej. 2h",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/drop", "/drop"),
+        ("Drop out of a channel", "Sal de un canal"),
+    ),
+    Command::new(
+        Everyone,
+        ("/follow <#channel>", "/follow <#canal>"),
+        (
+            "Read a channel without joining it, e.g. for announcements",
+            "Lee un canal sin unirte, p. ej. para anuncios",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/invite <username>", "/invite <nombre>"),
+        (
+            "Invite a user to the current channel",
+            "Invita a un usuario al canal actual",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/accept", "/accept"),
+        (
+            "Join the channel of the last invitation",
+            "Entra en el canal de la última invitación",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/who", "/who"),
+        ("List the users of a channel", "Lista los usuarios de un canal"),
+    ),
+    Command::new(
+        Everyone,
+        ("/link [id]", "/link [id]"),
+        (
+            "Show a link to a message, by default the one selected in copy mode (e.g. #general/1234)",
+            "Muestra un enlace a un mensaje, por defecto el elegido en el modo copia (p. ej. #general/1234)",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/goto <link>", "/goto <enlace>"),
+        (
+            "Open the channel of a link and select its message",
+            "Abre el canal de un enlace y elige su mensaje",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/msg <username> <text>", "/msg <nombre> <texto>"),
+        (
+            "Send a direct message, registered users get it on their next /identify if offline",
+            "Envía un mensaje directo, los usuarios registrados lo reciben al identificarse",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/inbox", "/inbox"),
+        ("Show your direct messages", "Muestra tus mensajes directos"),
+    ),
+    Command::new(
+        Everyone,
+        ("/events", "/events"),
+        (
+            "Show users joining and leaving and new channels live in a Server tab",
+            "Muestra en vivo en una pestaña Server los usuarios que entran y salen y los canales nuevos",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/admin <password>", "/admin <clave>"),
+        ("Become an administrator", "Conviértete en administrador"),
+    ),
+    Command::new(
+        Everyone,
+        ("/all", "/all"),
+        (
+            "Show messages from all joined channels in one tab",
+            "Muestra los mensajes de todos tus canales juntos",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/drafts", "/drafts"),
+        (
+            "List unsent messages, tabs with drafts are marked with ✎",
+            "Lista los mensajes sin enviar, las pestañas con borradores se marcan con ✎",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/copy", "/copy"),
+        (
+            "Toggle copy mode, select messages with <UP>/<DOWN> (<ESC> also toggles it)",
+            "Activa el modo copia, elige mensajes con <ARRIBA>/<ABAJO> (<ESC> también lo activa)",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/alias <name> \"<command>\"", "/alias <nombre> \"<comando>\""),
+        (
+            "Define /<name> as a shortcut, arguments are appended (/alias <name> removes it)",
+            "Define /<nombre> como atajo, los argumentos se añaden (/alias <nombre> lo elimina)",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/aliases", "/aliases"),
+        ("List your aliases", "Lista tus alias"),
+    ),
+    Command::new(
+        Everyone,
+        (
+            "/notify-level <#channel> <all|mentions|none>",
+            "/notify-level <#canal> <all|mentions|none>",
+        ),
+        (
+            "Which messages of a channel count as unread and ring the bell",
+            "Qué mensajes de un canal cuentan como no leídos y hacen sonar la campana",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/bell <on|off>", "/bell <on|off>"),
+        (
+            "Ring the terminal bell when mentioned in another tab",
+            "Suena la campana al ser mencionado en otra pestaña",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/format <on|off>", "/format <on|off>"),
+        (
+            "Show *bold*, _italic_ and `code` in messages",
+            "Muestra *negrita*, _cursiva_ y `código` en los mensajes",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/lang <code>", "/lang <código>"),
+        (
+            "Change the language (en, es)",
+            "Cambia el idioma (en, es)",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/stats channels", "/stats channels"),
+        (
+            "Show the load of all channels",
+            "Muestra la carga de todos los canales",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/ping", "/ping"),
+        (
+            "Measure the latency to the server and channel",
+            "Mide la latencia al servidor y al canal",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/paste", "/paste"),
+        (
+            "Share several lines, e.g. logs or code, without flooding the channel",
+            "Comparte varias líneas, p. ej. logs o código, sin inundar el canal",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/show <id>", "/show <id>"),
+        ("Open a shared paste", "Abre un texto compartido"),
+    ),
+    Command::new(
+        Everyone,
+        ("/roll [dice]", "/roll [dados]"),
+        (
+            "Roll dice in the channel, e.g. /roll 2d6",
+            "Tira dados en el canal, p. ej. /roll 2d6",
+        ),
+    )
+    .game(roll),
+    Command::new(
+        Everyone,
+        ("/flip", "/flip"),
+        ("Flip a coin in the channel", "Lanza una moneda en el canal"),
+    )
+    .game(flip),
+    Command::new(
+        Everyone,
+        ("/8ball <question>", "/8ball <pregunta>"),
+        (
+            "Ask the magic 8-ball in the channel",
+            "Pregunta a la bola 8 mágica en el canal",
+        ),
+    )
+    .game(eight_ball),
+    Command::new(
+        Everyone,
+        ("/help", "/help"),
+        ("Display this instructions", "Muestra estas instrucciones"),
+    ),
+    Command::new(
+        Everyone,
+        ("/keys", "/keys"),
+        (
+            "Show decoded keys, useful to debug telnet clients",
+            "Muestra las teclas decodificadas, útil para depurar clientes telnet",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/caps", "/caps"),
+        (
+            "Show what your telnet client negotiated, useful to debug rendering problems",
+            "Muestra lo que negoció tu cliente telnet, útil para depurar problemas de dibujo",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/record", "/record"),
+        (
+            "Start or stop recording your session, if the server allows it",
+            "Inicia o detiene la grabación de tu sesión, si el servidor lo permite",
+        ),
+    ),
+    Command::new(
+        Everyone,
+        ("/exit", "/exit"),
+        ("Leave server", "Sal del servidor"),
+    ),
+    Command::new(
+        Guest,
+        ("/register <password>", "/register <clave>"),
+        (
+            "Register your username, identified users are marked with ✓",
+            "Registra tu nombre, los usuarios identificados se marcan con ✓",
+        ),
+    ),
+    Command::new(
+        Guest,
+        ("/identify [username] <password>", "/identify [nombre] <clave>"),
+        (
+            "Identify as the owner of your username, or connect another terminal to an account",
+            "Identifícate como dueño de tu nombre, o conecta otro terminal a una cuenta",
+        ),
+    ),
+    Command::new(
+        Identified,
+        ("/block <username>", "/block <nombre>"),
+        (
+            "Hide messages of a user, kept with your account (/unblock to show them again)",
+            "Oculta los mensajes de un usuario, se guarda en tu cuenta (/unblock para volver a verlos)",
+        ),
+    ),
+    Command::new(
+        Identified,
+        ("/secure <username> [off]", "/secure <nombre> [off]"),
+        (
+            "Encrypt direct messages to an identified user for this session, shows the fingerprint",
+            "Cifra los mensajes directos a un usuario identificado durante esta sesión, muestra la huella",
+        ),
+    ),
+    Command::new(
+        Operator,
+        ("/describe <lang> <text>", "/describe <idioma> <texto>"),
+        (
+            "Set the language and description of a channel you created",
+            "Cambia el idioma y la descripción de un canal que has creado",
+        ),
+    ),
+    Command::new(
+        Operator,
+        ("/ban <username>", "/ban <nombre>"),
+        (
+            "Ban a user from a channel you created",
+            "Veta a un usuario de un canal que has creado",
+        ),
+    ),
+    Command::new(
+        Operator,
+        (
+            "/hook <create|revoke <token>|list>",
+            "/hook <create|revoke <token>|list>",
+        ),
+        (
+            "Manage the webhook tokens of a channel you created",
+            "Gestiona los tokens de webhook de un canal que has creado",
+        ),
+    ),
+    Command::new(
+        Operator,
+        (
+            "/announce-every <interval> \"<message>\"",
+            "/announce-every <intervalo> \"<mensaje>\"",
+        ),
+        (
+            "Repeat a message in a channel you created, e.g. /announce-every 1h \"Backup at midnight\"",
+            "Repite un mensaje en un canal que has creado, p. ej. /announce-every 1h \"Copia a medianoche\"",
+        ),
+    ),
+    Command::new(
+        Operator,
+        ("/announcements [cancel <id>]", "/announcements [cancel <id>]"),
+        (
+            "List the announcements of the channel or cancel one",
+            "Lista los anuncios del canal o cancela uno",
+        ),
+    ),
+    Command::new(
+        Operator,
+        (
+            "/feed [add <url> <interval> | remove <id>]",
+            "/feed [add <url> <intervalo> | remove <id>]",
+        ),
+        (
+            "List the RSS/Atom feeds posted to the channel, add or remove one",
+            "Lista las fuentes RSS/Atom publicadas en el canal, añade o quita una",
+        ),
+    ),
+    Command::new(
+        Operator,
+        ("/banner <text>", "/banner <texto>"),
+        (
+            "Write the text in large letters to the channel",
+            "Escribe el texto en letras grandes en el canal",
+        ),
+    ),
+    Command::new(
+        Admin,
+        ("/broadcast <group> <text>", "/broadcast <grupo> <texto>"),
+        (
+            "Send a message to all channels of a group",
+            "Envía un mensaje a todos los canales de un grupo",
+        ),
+    ),
+    Command::new(
+        Admin,
+        ("/rehash", "/rehash"),
+        (
+            "Reload the word filters and custom screens",
+            "Recarga los filtros de palabras y las pantallas personalizadas",
+        ),
+    ),
+    Command::new(
+        Admin,
+        ("/whois <username>", "/whois <nombre>"),
+        (
+            "Show where the sessions of a user connected from",
+            "Muestra desde dónde se conectaron las sesiones de un usuario",
+        ),
+    ),
+    Command::new(
+        Admin,
+        ("/history <user>", "/history <usuario>"),
+        (
+            "List the connections of a username, account or IP address",
+            "Lista las conexiones de un nombre, cuenta o dirección IP",
+        ),
+    ),
+    Command::new(
+        Admin,
+        ("/dump", "/dump"),
+        (
+            "Write the server state to a JSON file",
+            "Escribe el estado del servidor en un archivo JSON",
+        ),
+    ),
+    Command::new(
+        Admin,
+        (
+            "/crash <channel #x|client <user>|coordinator>",
+            "/crash <channel #x|client <usuario>|coordinator>",
+        ),
+        (
+            "Panic a process to test recovery",
+            "Hace fallar un proceso para probar la recuperación",
+        ),
+    )
+    .debug(),
+];
+
+/// Returns the command of the registry with the `name`, e.g. `/roll`.
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name() == name)
+}
+
+fn roll(args: &str) -> Result<Game, Text> {
+    Game::roll(args.split_whitespace().next()).ok_or(Text::InvalidDice)
+}
+
+fn flip(_: &str) -> Result<Game, Text> {
+    Ok(Game::Flip)
+}
+
+fn eight_ball(question: &str) -> Result<Game, Text> {
+    let game = Game::EightBall(question.trim().to_owned());
+    if game.is_valid() {
+        Ok(game)
+    } else {
+        Err(Text::InvalidQuestion)
+    }
+}
+
+/// Returns the list of commands shown in the help tab, grouped by who can use them.
+///
+/// Groups the client has no permission for are left out.
+pub fn help(locale: Locale, permissions: Permissions) -> String {
+    let groups = [
+        (Everyone, Text::CommandsEveryone),
+        (Guest, Text::CommandsGuest),
+        (Identified, Text::CommandsIdentified),
+        (Operator, Text::CommandsOperator),
+        (Admin, Text::CommandsAdmin),
+    ];
+    let mut lines = Vec::new();
+    for (permission, title) in groups {
+        if !permissions.allows(permission) {
+            continue;
+        }
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push(format!("  {}", i18n::text(locale, title)));
+        lines.push(String::new());
+        let commands = COMMANDS.iter().filter(|command| {
+            command.permission == permission && (!command.debug || cfg!(debug_assertions))
+        });
+        for command in commands {
+            push_entry(
+                &mut lines,
+                command.usage(locale),
+                command.description(locale),
+            );
+        }
+    }
+    lines.join("\n")
+}
+
+// Add a `* <usage> - <description>` entry, the description is wrapped at `WIDTH`.
+fn push_entry(lines: &mut Vec<String>, usage: &str, description: &str) {
+    let mut line = format!("  * {:<16} - ", usage);
+    let mut empty = true;
+    for word in description.split(' ') {
+        if !empty && line.chars().count() + 1 + word.chars().count() > WIDTH {
+            lines.push(std::mem::replace(&mut line, " ".repeat(INDENT)));
+            empty = true;
+        }
+        if !empty {
+            line.push(' ');
+        }
+        line.push_str(word);
+        empty = false;
+    }
+    lines.push(line);
+}
@@ -0,0 +1,186 @@
+//! Remote administration over a control socket (`--control-port`).
+//!
+//! Operators can manage the server from scripts without logging in through the chat UI. The
+//! control listener only accepts connections from localhost, unless `--control-bind` says
+//! otherwise. Every line is a command and gets a single line answer starting with `ok` or
+//! `error`:
+//!
+//! ```text
+//! network <name>             select the network of the following commands (default)
+//! stats                      connected clients and open channels
+//! kick <username>            disconnect all sessions of the user
+//! ban <#channel> <username>  ban the user from the channel
+//! rehash                     reload the filter file and custom screens
+//! shutdown                   disconnect everyone and stop the server
+//! drain [seconds]            stop accepting clients, stop the server when they left (default 30)
+//! ```
+//!
+//! `drain` is meant for rolling deploys behind a load balancer. New connections are closed right
+//! away, connected users are told to reconnect. The answer comes when the last user left or the
+//! grace period is over, right before the server stops.
+//!
+//! ```bash
+//! > echo stats | nc -q1 localhost 2324
+//! ok clients 12 channels 4
+//! ```
+
+use std::io::{BufRead, BufReader, Write};
+use std::time::{Duration, Instant};
+
+use lunatic::{
+    ap::ProcessRef,
+    net::{TcpListener, TcpStream},
+    Mailbox, Process,
+};
+
+use chat_core::coordinator::{self, CoordinatorProcess, CoordinatorProcessRequests, RehashError};
+use chat_core::directory::{Directory, DirectoryProcessMessages};
+use chat_core::storage::{self, StorageProcess, StorageProcessRequests};
+
+// Used if `drain` is not given a grace period.
+const DRAIN_SECONDS: u64 = 30;
+// How often a draining server checks if all clients left.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts listening for control connections on `address`, linked to the calling process.
+///
+/// `main` is sent a message once the server should stop, the server exits when it returns.
+pub fn start(address: String, networks: Vec<String>, main: Process<()>) -> Result<(), String> {
+    let listener = TcpListener::bind(address.clone())
+        .map_err(|err| format!("can't listen on {}: {}", address, err))?;
+    Process::spawn_link((listener, networks, main), accept);
+    Ok(())
+}
+
+// Accepts control connections and handles each of them in its own linked process.
+fn accept((listener, networks, main): (TcpListener, Vec<String>, Process<()>), _: Mailbox<()>) {
+    while let Ok((stream, _)) = listener.accept() {
+        Process::spawn_link((stream, networks.clone(), main), handle);
+    }
+}
+
+// Answers the commands of one connection until it's closed.
+fn handle((stream, networks, main): (TcpStream, Vec<String>, Process<()>), _: Mailbox<()>) {
+    let mut writer = stream.clone();
+    let mut network = "default".to_owned();
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let mut args = line.split_whitespace();
+        let answer = match args.next() {
+            Some("network") => match args.next() {
+                Some(name) if networks.iter().any(|n| n == name) => {
+                    network = name.to_owned();
+                    Ok(format!("network {}", network))
+                }
+                _ => Err("unknown network".to_owned()),
+            },
+            Some("shutdown") => {
+                let _ = writeln!(writer, "ok shutting down");
+                stop(&networks, main);
+                return;
+            }
+            Some("drain") => match args
+                .next()
+                .map(str::parse::<u64>)
+                .unwrap_or(Ok(DRAIN_SECONDS))
+            {
+                Ok(seconds) => {
+                    let clients = drain(&networks, seconds);
+                    let _ = writeln!(writer, "ok draining {} clients for {}s", clients, seconds);
+                    wait_idle(&networks, Duration::from_secs(seconds));
+                    let _ = writeln!(writer, "ok shutting down");
+                    stop(&networks, main);
+                    return;
+                }
+                Err(_) => Err("invalid grace period".to_owned()),
+            },
+            Some(command) => execute(&network, command, args.collect()),
+            None => continue,
+        };
+        let answer = match answer {
+            Ok(text) => format!("ok {}", text),
+            Err(text) => format!("error {}", text),
+        };
+        if writeln!(writer, "{}", answer).is_err() {
+            return;
+        }
+    }
+}
+
+// Runs a command against the coordinator of a network.
+fn execute(network: &str, command: &str, args: Vec<&str>) -> Result<String, String> {
+    let coordinator = lookup(network)?;
+    match (command, args.as_slice()) {
+        ("stats", []) => Ok(format!(
+            "clients {} channels {}",
+            coordinator.client_count(),
+            Directory::new(network).channel_count()
+        )),
+        ("kick", [username]) => match coordinator.kick(username.to_string()) {
+            0 => Err(format!("{} is not connected", username)),
+            sessions => Ok(format!("kicked {} session(s) of {}", sessions, username)),
+        },
+        ("ban", [channel, username]) if channel.starts_with('#') => {
+            Directory::new(network)
+                .shard(channel)
+                .ok_or_else(|| format!("the directory of {} is not running", channel))?
+                .force_ban(channel.to_string(), username.to_string());
+            Ok(format!("banned {} from {}", username, channel))
+        }
+        ("rehash", []) => match coordinator.reload() {
+            Ok(rules) => Ok(format!("loaded {} filter rules", rules)),
+            Err(RehashError::InvalidFilters(err)) => Err(format!("invalid filter file: {}", err)),
+            Err(RehashError::InvalidTemplates(err)) => {
+                Err(format!("invalid template directory: {}", err))
+            }
+            Err(RehashError::NotAdmin) => Err("not allowed".to_owned()),
+        },
+        _ => Err(format!("can't run {}", command)),
+    }
+}
+
+// Stops accepting clients on all networks, returns the number of connected clients.
+fn drain(networks: &[String], seconds: u64) -> usize {
+    networks
+        .iter()
+        .filter_map(|name| lookup(name).ok())
+        .map(|coordinator| coordinator.drain(seconds))
+        .sum()
+}
+
+// Waits until all clients left or the grace period is over.
+fn wait_idle(networks: &[String], grace: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < grace {
+        let clients: usize = networks
+            .iter()
+            .filter_map(|name| lookup(name).ok())
+            .map(|coordinator| coordinator.client_count())
+            .sum();
+        if clients == 0 {
+            return;
+        }
+        lunatic::sleep(DRAIN_POLL_INTERVAL);
+    }
+}
+
+// Disconnects everyone, flushes the storage and lets the main process stop the server.
+fn stop(networks: &[String], main: Process<()>) {
+    for name in networks {
+        if let Ok(coordinator) = lookup(name) {
+            coordinator.disconnect_all();
+        }
+        if let Some(storage) = ProcessRef::<StorageProcess>::lookup(&storage::registry_name(name)) {
+            storage.flush();
+        }
+    }
+    main.send(());
+}
+
+fn lookup(network: &str) -> Result<ProcessRef<CoordinatorProcess>, String> {
+    ProcessRef::lookup(&coordinator::registry_name(network))
+        .ok_or_else(|| format!("network {} is not running", network))
+}
@@ -0,0 +1,81 @@
+//! Demo mode (`--demo`).
+//!
+//! A handful of scripted bots chat in `#lobby`, so that someone trying out the server sees
+//! activity right after connecting. The bots are not clients, they post through the directory
+//! shard of the channel and keep the channel open while nobody joined it.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use lunatic::{Mailbox, Process};
+
+use chat_core::directory::{Directory, DirectoryProcessMessages};
+
+/// The channel the bots chat in.
+pub const CHANNEL: &str = "#lobby";
+
+// Time between two lines of the script.
+const LINE_INTERVAL: Duration = Duration::from_secs(6);
+
+const BOTS: [&str; 3] = ["demo_ada", "demo_grace", "demo_linus"];
+
+// Lines of the conversation and the index of the bot saying them, repeated forever.
+const SCRIPT: &[(usize, &str)] = &[
+    (0, "Hi everyone! Welcome to the lunatic chat demo."),
+    (
+        1,
+        "Hey Ada! Every connection here is its own lunatic process.",
+    ),
+    (2, "And each channel too, messages are passed between them."),
+    (0, "Try /join #somewhere to create your own channel."),
+    (
+        1,
+        "/list shows all channels, /help lists the other commands.",
+    ),
+    (2, "Use <TAB> or <ALT> + <1-9> to switch between tabs."),
+    (
+        0,
+        "If a process crashes, only that one connection is affected.",
+    ),
+    (1, "The whole server runs inside a WebAssembly sandbox."),
+    (2, "We are just bots, but feel free to say hello anyway!"),
+];
+
+/// Spawns the bots of a network.
+///
+/// The bots are not linked to the caller, a bot that fails (e.g. while a shard restarts) just
+/// stops chatting.
+pub fn start(network: &str) {
+    for bot in 0..BOTS.len() {
+        Process::spawn((network.to_owned(), bot), run);
+    }
+}
+
+// Posts the lines of one bot. All bots start at the same time and wait for their turn.
+fn run((network, bot): (String, usize), _: Mailbox<()>) {
+    let directory = Directory::new(&network);
+    let start = Instant::now();
+    for (turn, (speaker, line)) in (0u32..).zip(SCRIPT.iter().cycle()) {
+        if *speaker != bot {
+            continue;
+        }
+        let at = start + LINE_INTERVAL * turn;
+        lunatic::sleep(at.saturating_duration_since(Instant::now()));
+        let now: DateTime<Local> = Local::now();
+        let timestamp = format!("[{}] ", now.format("%H:%M UTC"));
+        // Reopen the channel each time, in case the shard was restarted in the meantime
+        let shard = match directory.shard(CHANNEL) {
+            Some(shard) => shard,
+            None => continue,
+        };
+        shard.keep_open(CHANNEL.to_owned());
+        shard.broadcast(
+            CHANNEL.to_owned(),
+            timestamp,
+            BOTS[bot].to_owned(),
+            None,
+            (*line).to_owned(),
+            None,
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! The connection a client session is shown on.
+//!
+//! `ClientProcess` implements everything a user can do in a session: the commands, the tabs and
+//! the events the backend pushes. It doesn't talk to the connection itself. The client is started
+//! with a `Connection` and starts the matching `Frontend` on it. Input arrives as `Input`
+//! messages through `ClientProcess::process`, sent by a process that reads the connection, and
+//! everything the user sees goes out through the `Frontend`.
+//!
+//! The telnet server uses the `TelnetFrontend`. Other ways to connect, e.g. SSH, WebSocket or a
+//! plain line mode, add a `Connection`, implement `Frontend` for their output and turn their input
+//! into `Input`, so that the command handling isn't duplicated. Messages that only concern one
+//! frontend, e.g. the frame pacing of telnet, reach it through `Frontend::as_any`.
+
+use std::any::Any;
+use std::io::Write;
+use std::net::SocketAddr;
+
+use lunatic::{ap::ProcessRef, net::TcpStream};
+use serde::{Deserialize, Serialize};
+
+use crate::client::ClientProcess;
+use crate::i18n::Locale;
+use crate::proxy;
+use crate::telnet::Capabilities;
+use crate::telnet_reader::TelnetReaderProcess;
+use crate::telnet_writer::{TelnetWriterProcess, TelnetWriterProcessRequests};
+use crate::ui::telnet_backend::WindowSize;
+use crate::ui::{Ui, UiConfig, UiTabs};
+
+/// Input of the user, decoded by the frontend.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Input {
+    Char(char),
+    Backspace,
+    Enter,
+    CtrlA,
+    CtrlC,
+    CtrlE,
+    CtrlU,
+    CtrlW,
+    Tab,
+    Key(Key),
+    /// The user's screen has a new size in characters.
+    Resize(u16, u16),
+    /// The frontend learned more about the user's terminal.
+    Terminal(Capabilities),
+    /// The connection is gone.
+    Closed,
+}
+
+/// Special keys decoded from terminal escape sequences.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Key {
+    Esc,
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// Function key `F1` - `F12`.
+    F(u8),
+    /// A character pressed while holding Alt (sent by terminals as `Esc` + character).
+    Alt(u8),
+}
+
+/// The connection of a new client, the client starts the matching frontend on it.
+#[derive(Serialize, Deserialize)]
+pub enum Connection {
+    Telnet(TcpStream),
+}
+
+impl Connection {
+    /// Read the PROXY protocol header that comes before anything else, see `proxy`.
+    pub fn read_proxy_header(&mut self) -> Result<Option<SocketAddr>, String> {
+        match self {
+            Connection::Telnet(stream) => proxy::read_header(&mut proxy::Deadline::new(stream)),
+        }
+    }
+
+    /// Tell the user why the session can't start, before a frontend is running.
+    pub fn refuse(&mut self, reason: &str) {
+        match self {
+            Connection::Telnet(stream) => {
+                let _ = write!(stream, "{}\r\n", reason);
+            }
+        }
+    }
+
+    /// Start the frontend of the connection for `client`.
+    pub fn start(
+        self,
+        client: ProcessRef<ClientProcess>,
+        tabs: UiTabs,
+        config: UiConfig,
+    ) -> Result<Box<dyn Frontend>, ()> {
+        match self {
+            Connection::Telnet(stream) => {
+                let frontend = TelnetFrontend::start(client, stream, tabs, config)?;
+                Ok(Box::new(frontend))
+            }
+        }
+    }
+}
+
+/// What a client session needs from the connection of its user.
+pub trait Frontend {
+    /// Show the current state of the tabs.
+    fn render(&mut self);
+
+    /// Get the user's attention, e.g. for a mention in a background tab.
+    fn bell(&mut self);
+
+    /// The size of the user's screen in characters, `(0, 0)` if it's unknown.
+    fn size(&self) -> (u16, u16);
+
+    /// The user's screen changed its size, show everything again.
+    fn resize(&mut self, width: u16, height: u16);
+
+    /// Show the frontend's own texts in another language.
+    fn set_locale(&mut self, locale: Locale);
+
+    /// Show `*bold*`, `_italic_` and `` `code` `` in messages, or the plain text with the markers.
+    fn set_formatting(&mut self, formatting: bool);
+
+    /// Record everything that is shown from now on to a new file at `path`.
+    fn start_recording(&mut self, path: String) -> Result<(), String>;
+
+    /// Stop the recording, returns if there was one.
+    fn stop_recording(&mut self) -> bool;
+
+    /// Give the user back a clean screen and stop showing anything, the session ends.
+    ///
+    /// Returns the processes of the frontend, the client stops them once it's gone.
+    fn close(&mut self) -> FrontendProcesses;
+
+    /// The concrete frontend, for messages that its own processes send through `ClientProcess`.
+    fn as_any(&mut self) -> &mut dyn Any;
+}
+
+/// The processes of a closed frontend, see `Frontend::close`.
+#[derive(Serialize, Deserialize)]
+pub enum FrontendProcesses {
+    Telnet(
+        ProcessRef<TelnetReaderProcess>,
+        ProcessRef<TelnetWriterProcess>,
+    ),
+}
+
+impl FrontendProcesses {
+    pub fn kill(self) {
+        match self {
+            FrontendProcesses::Telnet(reader, writer) => {
+                reader.kill();
+                writer.kill();
+            }
+        }
+    }
+}
+
+/// The tui rendered over a telnet connection.
+///
+/// The `TelnetReaderProcess` sends the decoded input to the client and the `TelnetWriterProcess`
+/// sends the rendered frames, both are linked to the client.
+pub struct TelnetFrontend {
+    ui: Ui,
+    reader: ProcessRef<TelnetReaderProcess>,
+    writer: ProcessRef<TelnetWriterProcess>,
+    // Shared with the terminal backend of the UI.
+    window_size: WindowSize,
+    // The session ended, nothing is drawn anymore.
+    closed: bool,
+}
+
+impl TelnetFrontend {
+    /// Start the reader and the writer of the connection.
+    pub fn start(
+        client: ProcessRef<ClientProcess>,
+        stream: TcpStream,
+        tabs: UiTabs,
+        config: UiConfig,
+    ) -> Result<Self, ()> {
+        // This process is in charge of turning the raw tcp stream into higher level messages that are
+        // sent back to the client. It's linked to the client and if one of them fails the other will too.
+        let reader = TelnetReaderProcess::link()
+            .start((client, stream.clone(), config.max_input))
+            .map_err(|_| ())?;
+        // Rendered frames are written to the socket by a linked process, so that a slow
+        // connection doesn't block the client.
+        let writer = TelnetWriterProcess::link()
+            .start((client, stream))
+            .map_err(|_| ())?;
+        let window_size = WindowSize::new();
+        let ui = Ui::new(client, writer, window_size.clone(), tabs, config);
+        Ok(TelnetFrontend {
+            ui,
+            reader,
+            writer,
+            window_size,
+            closed: false,
+        })
+    }
+
+    /// The writer sent the last frame.
+    pub fn frame_written(&mut self) {
+        if !self.closed {
+            self.ui.frame_written();
+        }
+    }
+
+    /// A frame that was put off to stay within the frame rate limit can be shown now.
+    pub fn render_due(&mut self) {
+        if !self.closed {
+            self.ui.render_due();
+        }
+    }
+}
+
+impl Frontend for TelnetFrontend {
+    fn render(&mut self) {
+        if !self.closed {
+            self.ui.render();
+        }
+    }
+
+    fn bell(&mut self) {
+        if !self.closed {
+            self.ui.bell();
+        }
+    }
+
+    fn size(&self) -> (u16, u16) {
+        self.window_size.get()
+    }
+
+    fn resize(&mut self, width: u16, height: u16) {
+        self.window_size.set(width, height);
+        if !self.closed {
+            self.ui.resize();
+        }
+    }
+
+    fn set_locale(&mut self, locale: Locale) {
+        self.ui.set_locale(locale);
+    }
+
+    fn set_formatting(&mut self, formatting: bool) {
+        self.ui.set_formatting(formatting);
+    }
+
+    fn start_recording(&mut self, path: String) -> Result<(), String> {
+        self.writer.start_recording(path, self.window_size.get())?;
+        // Start the recording with the whole screen, not just what changes next
+        self.ui.resize();
+        Ok(())
+    }
+
+    fn stop_recording(&mut self) -> bool {
+        self.writer.stop_recording()
+    }
+
+    fn close(&mut self) -> FrontendProcesses {
+        if !self.closed {
+            self.closed = true;
+            self.ui.restore();
+        }
+        FrontendProcesses::Telnet(self.reader, self.writer)
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
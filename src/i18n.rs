@@ -0,0 +1,968 @@
+//! Translations of the UI.
+//!
+//! Each locale has its own set of templates under `templates/<code>/`. Shorter system messages
+//! that don't need a template are kept in the [`text`] catalog.
+
+use std::collections::BTreeMap;
+
+use askama::Template;
+use chrono::{TimeZone, Utc};
+use serde_json::json;
+
+use chat_core::channel::{Announcement, ChannelMeta, ChannelStats};
+use chat_core::coordinator::{Connection, ServerEvent, Session};
+use chat_core::feed::Feed;
+use chat_core::paste::Paste;
+use chat_core::storage::ConnectionRecord;
+use chat_core::templates::CustomTemplates;
+
+use crate::commands::{self, Permissions};
+use crate::telnet::{Capabilities, TelnetClient};
+
+pub use chat_core::templates::Locale;
+
+/// System messages that are not rendered from templates.
+#[derive(Clone, Copy)]
+pub enum Text {
+    ResizeWarning,
+    KeysPlaceholder,
+    NotOperator,
+    TooManyChannels,
+    GuestChannelLimit,
+    CreationLimit,
+    Banned,
+    UserBanned,
+    AdminGranted,
+    AdminDenied,
+    NotAdmin,
+    UnknownGroup,
+    PingCoordinator,
+    PingChannel,
+    NameTaken,
+    Registered,
+    RegisterFailed,
+    Identified,
+    IdentifyFailed,
+    Blocked,
+    Unblocked,
+    BlockNeedsAccount,
+    MessageSent,
+    MessageStored,
+    UnknownUser,
+    RecipientBlocked,
+    NewMessage,
+    InvitationSent,
+    FilterWarning,
+    FilterDropped,
+    Muted,
+    Rehashed,
+    RehashFailed,
+    HookCreated,
+    HookRevoked,
+    HookList,
+    Dumped,
+    DumpFailed,
+    Crashed,
+    CrashDisabled,
+    UnknownTarget,
+    Permalink,
+    MessageNotFound,
+    ReadOnly,
+    NoSuchChannel,
+    NotifyLevel,
+    SecureSession,
+    SecureEnded,
+    SecureUnavailable,
+    CannotDecrypt,
+    Encrypted,
+    ServerDraining,
+    MessagesMissing,
+    ChooseNickname,
+    NicknameTaken,
+    NicknameInvalid,
+    MessagesExpired,
+    CommandsEveryone,
+    CommandsGuest,
+    CommandsIdentified,
+    CommandsOperator,
+    CommandsAdmin,
+    RecordingStarted,
+    RecordingStopped,
+    RecordingDisabled,
+    RecordingFailed,
+    BannerWait,
+    InvalidDice,
+    InvalidInterval,
+    AnnouncementScheduled,
+    AnnouncementCancelled,
+    TooManyAnnouncements,
+    AnnouncementTooFrequent,
+    UnknownAnnouncement,
+    Pasting,
+    PasteShared,
+    UnknownPaste,
+    InvalidTtl,
+    ChannelExpires,
+    ChannelExpired,
+    FeedAdded,
+    FeedRemoved,
+    TooManyFeeds,
+    FeedTooFrequent,
+    InvalidFeedUrl,
+    UnknownFeed,
+    PuttyHint,
+    NetcatHint,
+    WindowsTelnetHint,
+    MudletHint,
+    InvalidWindowSize,
+    PressAgainToQuit,
+    FeedsDisabled,
+    NameInvalid,
+    SecureFingerprint,
+    InvalidQuestion,
+    PasteFailed,
+    ChannelClosed,
+    ChannelUnavailable,
+    ChannelClosesIn,
+    TooManyAttempts,
+    RecordingLimit,
+}
+
+pub fn text(locale: Locale, text: Text) -> &'static str {
+    match (locale, text) {
+        (Locale::En, Text::ResizeWarning) => "Please resize your terminal window to at least",
+        (Locale::Es, Text::ResizeWarning) => {
+            "Por favor, amplía la ventana de tu terminal a al menos"
+        }
+        (Locale::En, Text::KeysPlaceholder) => {
+            "  Decoded keys will be displayed here, type /keys to stop."
+        }
+        (Locale::Es, Text::KeysPlaceholder) => {
+            "  Las teclas decodificadas aparecerán aquí, escribe /keys para parar."
+        }
+        (Locale::En, Text::NotOperator) => "Only the channel operator can do this.",
+        (Locale::Es, Text::NotOperator) => "Solo el operador del canal puede hacer esto.",
+        (Locale::En, Text::TooManyChannels) => "Maximum number of joined channels reached:",
+        (Locale::Es, Text::TooManyChannels) => "Has alcanzado el máximo de canales:",
+        (Locale::En, Text::GuestChannelLimit) => {
+            "Guests can join this many channels, /register or /identify to join more:"
+        }
+        (Locale::Es, Text::GuestChannelLimit) => {
+            "Los invitados pueden unirse a estos canales, usa /register o /identify para más:"
+        }
+        (Locale::En, Text::CreationLimit) => "Too many new channels, you can create per hour:",
+        (Locale::Es, Text::CreationLimit) => "Demasiados canales nuevos, puedes crear por hora:",
+        (Locale::En, Text::Banned) => "You are banned from",
+        (Locale::Es, Text::Banned) => "Tienes prohibida la entrada en",
+        (Locale::En, Text::UserBanned) => "Banned from this channel:",
+        (Locale::Es, Text::UserBanned) => "Vetado en este canal:",
+        (Locale::En, Text::AdminGranted) => "You are now an administrator.",
+        (Locale::Es, Text::AdminGranted) => "Ahora eres administrador.",
+        (Locale::En, Text::AdminDenied) => "Wrong administrator password.",
+        (Locale::Es, Text::AdminDenied) => "Contraseña de administrador incorrecta.",
+        (Locale::En, Text::NotAdmin) => "Only administrators can do this.",
+        (Locale::Es, Text::NotAdmin) => "Solo los administradores pueden hacer esto.",
+        (Locale::En, Text::UnknownGroup) => "Unknown group:",
+        (Locale::Es, Text::UnknownGroup) => "Grupo desconocido:",
+        (Locale::En, Text::PingCoordinator) => "Coordinator round trip:",
+        (Locale::Es, Text::PingCoordinator) => "Ida y vuelta al coordinador:",
+        (Locale::En, Text::PingChannel) => "Channel echo:",
+        (Locale::Es, Text::PingChannel) => "Eco del canal:",
+        (Locale::En, Text::NameTaken) => "This name is already taken:",
+        (Locale::Es, Text::NameTaken) => "Este nombre ya está en uso:",
+        (Locale::En, Text::Registered) => "Your name is now registered.",
+        (Locale::Es, Text::Registered) => "Tu nombre está registrado.",
+        (Locale::En, Text::RegisterFailed) => "This name can't be registered.",
+        (Locale::Es, Text::RegisterFailed) => "Este nombre no se puede registrar.",
+        (Locale::En, Text::Identified) => "You are now identified.",
+        (Locale::Es, Text::Identified) => "Te has identificado.",
+        (Locale::En, Text::IdentifyFailed) => "Wrong password or unregistered name.",
+        (Locale::Es, Text::IdentifyFailed) => "Contraseña incorrecta o nombre no registrado.",
+        (Locale::En, Text::Blocked) => "Blocked:",
+        (Locale::Es, Text::Blocked) => "Bloqueado:",
+        (Locale::En, Text::Unblocked) => "Unblocked:",
+        (Locale::Es, Text::Unblocked) => "Desbloqueado:",
+        (Locale::En, Text::BlockNeedsAccount) => "Identify with /register or /identify first.",
+        (Locale::Es, Text::BlockNeedsAccount) => "Identifícate antes con /register o /identify.",
+        (Locale::En, Text::MessageSent) => "Message sent to",
+        (Locale::Es, Text::MessageSent) => "Mensaje enviado a",
+        (Locale::En, Text::MessageStored) => "Offline, the message waits in the mailbox of",
+        (Locale::Es, Text::MessageStored) => "Desconectado, el mensaje espera en el buzón de",
+        (Locale::En, Text::UnknownUser) => "Unknown user:",
+        (Locale::Es, Text::UnknownUser) => "Usuario desconocido:",
+        (Locale::En, Text::RecipientBlocked) => "You can't send messages to",
+        (Locale::Es, Text::RecipientBlocked) => "No puedes enviar mensajes a",
+        (Locale::En, Text::NewMessage) => "New message (/inbox) from",
+        (Locale::Es, Text::NewMessage) => "Nuevo mensaje (/inbox) de",
+        (Locale::En, Text::InvitationSent) => "Invitation sent to",
+        (Locale::Es, Text::InvitationSent) => "Invitación enviada a",
+        (Locale::En, Text::FilterWarning) => "Please keep to the rules of this server.",
+        (Locale::Es, Text::FilterWarning) => "Por favor, respeta las normas de este servidor.",
+        (Locale::En, Text::FilterDropped) => "Your message was blocked by a filter.",
+        (Locale::Es, Text::FilterDropped) => "Un filtro ha bloqueado tu mensaje.",
+        (Locale::En, Text::Muted) => "You are muted, seconds left:",
+        (Locale::Es, Text::Muted) => "Estás silenciado, segundos restantes:",
+        (Locale::En, Text::Rehashed) => "Filter rules loaded:",
+        (Locale::Es, Text::Rehashed) => "Reglas de filtro cargadas:",
+        (Locale::En, Text::RehashFailed) => "Can't load the filter rules:",
+        (Locale::Es, Text::RehashFailed) => "No se pueden cargar las reglas de filtro:",
+        (Locale::En, Text::HookCreated) => "New webhook token:",
+        (Locale::Es, Text::HookCreated) => "Nuevo token de webhook:",
+        (Locale::En, Text::HookRevoked) => "Webhook token revoked:",
+        (Locale::Es, Text::HookRevoked) => "Token de webhook revocado:",
+        (Locale::En, Text::HookList) => "Webhook tokens:",
+        (Locale::Es, Text::HookList) => "Tokens de webhook:",
+        (Locale::En, Text::Dumped) => "State written to",
+        (Locale::Es, Text::Dumped) => "Estado escrito en",
+        (Locale::En, Text::DumpFailed) => "Can't write the state:",
+        (Locale::Es, Text::DumpFailed) => "No se puede escribir el estado:",
+        (Locale::En, Text::Crashed) => "Crash requested.",
+        (Locale::Es, Text::Crashed) => "Fallo solicitado.",
+        (Locale::En, Text::CrashDisabled) => "Crashing processes is only possible in debug builds.",
+        (Locale::Es, Text::CrashDisabled) => {
+            "Solo se pueden hacer fallar procesos en compilaciones de depuración."
+        }
+        (Locale::En, Text::UnknownTarget) => "No such channel or user.",
+        (Locale::Es, Text::UnknownTarget) => "No existe ese canal o usuario.",
+        (Locale::En, Text::Permalink) => "Link to the message:",
+        (Locale::Es, Text::Permalink) => "Enlace al mensaje:",
+        (Locale::En, Text::MessageNotFound) => "Message not found:",
+        (Locale::Es, Text::MessageNotFound) => "Mensaje no encontrado:",
+        (Locale::En, Text::ReadOnly) => "You follow this channel, /join it to write.",
+        (Locale::Es, Text::ReadOnly) => "Sigues este canal, únete con /join para escribir.",
+        (Locale::En, Text::NoSuchChannel) => "No such channel:",
+        (Locale::Es, Text::NoSuchChannel) => "No existe el canal:",
+        (Locale::En, Text::NotifyLevel) => "Notifications for",
+        (Locale::Es, Text::NotifyLevel) => "Notificaciones de",
+        (Locale::En, Text::SecureSession) => "Direct messages are encrypted for",
+        (Locale::Es, Text::SecureSession) => "Los mensajes directos se cifran para",
+        (Locale::En, Text::SecureEnded) => "Direct messages are no longer encrypted for",
+        (Locale::Es, Text::SecureEnded) => "Los mensajes directos ya no se cifran para",
+        (Locale::En, Text::SecureUnavailable) => {
+            "Encryption needs both users identified and connected:"
+        }
+        (Locale::Es, Text::SecureUnavailable) => {
+            "El cifrado requiere que ambos usuarios estén identificados y conectados:"
+        }
+        (Locale::En, Text::CannotDecrypt) => "Can't decrypt a message from",
+        (Locale::Es, Text::CannotDecrypt) => "No se puede descifrar un mensaje de",
+        (Locale::En, Text::Encrypted) => "encrypted",
+        (Locale::Es, Text::Encrypted) => "cifrado",
+        (Locale::En, Text::MessagesMissing) => "History truncated, some messages were skipped.",
+        (Locale::Es, Text::MessagesMissing) => "Historial truncado, se omitieron algunos mensajes.",
+        (Locale::En, Text::ServerDraining) => {
+            "The server restarts soon, reconnect in a moment. Closing in"
+        }
+        (Locale::Es, Text::ServerDraining) => {
+            "El servidor se reinicia pronto, vuelve a conectarte en un momento. Cierre en"
+        }
+        (Locale::En, Text::ChooseNickname) => "Choose a nickname:",
+        (Locale::Es, Text::ChooseNickname) => "Elige un apodo:",
+        (Locale::En, Text::NicknameTaken) => "This nickname is taken, choose another one:",
+        (Locale::Es, Text::NicknameTaken) => "Este apodo ya está en uso, elige otro:",
+        (Locale::En, Text::NicknameInvalid) => {
+            "Only letters, digits, _ and - (at most 24), choose another one:"
+        }
+        (Locale::Es, Text::NicknameInvalid) => {
+            "Solo letras, dígitos, _ y - (máximo 24), elige otro:"
+        }
+        (Locale::En, Text::MessagesExpired) => "Older messages expired.",
+        (Locale::Es, Text::MessagesExpired) => "Los mensajes más antiguos expiraron.",
+        (Locale::En, Text::CommandsEveryone) => "Commands:",
+        (Locale::Es, Text::CommandsEveryone) => "Comandos:",
+        (Locale::En, Text::CommandsGuest) => "Until you are identified:",
+        (Locale::Es, Text::CommandsGuest) => "Hasta que te identifiques:",
+        (Locale::En, Text::CommandsIdentified) => "As an identified user:",
+        (Locale::Es, Text::CommandsIdentified) => "Como usuario identificado:",
+        (Locale::En, Text::CommandsOperator) => "As a channel operator:",
+        (Locale::Es, Text::CommandsOperator) => "Como operador de canal:",
+        (Locale::En, Text::CommandsAdmin) => "As an administrator:",
+        (Locale::Es, Text::CommandsAdmin) => "Como administrador:",
+        (Locale::En, Text::RecordingStarted) => "Recording to",
+        (Locale::Es, Text::RecordingStarted) => "Grabando en",
+        (Locale::En, Text::RecordingStopped) => "Recording saved to",
+        (Locale::Es, Text::RecordingStopped) => "Grabación guardada en",
+        (Locale::En, Text::RecordingDisabled) => "Recording is disabled on this server.",
+        (Locale::Es, Text::RecordingDisabled) => "La grabación está desactivada en este servidor.",
+        (Locale::En, Text::RecordingFailed) => "Can't record:",
+        (Locale::Es, Text::RecordingFailed) => "No se puede grabar:",
+        (Locale::En, Text::BannerWait) => "Too many banners, seconds left:",
+        (Locale::Es, Text::BannerWait) => "Demasiados banners, segundos restantes:",
+        (Locale::En, Text::InvalidDice) => "Roll dice like 2d6, at most 20 dice with 1000 sides.",
+        (Locale::Es, Text::InvalidDice) => {
+            "Tira dados como 2d6, como mucho 20 dados de 1000 caras."
+        }
+        (Locale::En, Text::InvalidInterval) => "Intervals look like 30m, 1h or 2d.",
+        (Locale::Es, Text::InvalidInterval) => "Los intervalos son como 30m, 1h o 2d.",
+        (Locale::En, Text::AnnouncementScheduled) => "Announcement scheduled, id",
+        (Locale::Es, Text::AnnouncementScheduled) => "Anuncio programado, id",
+        (Locale::En, Text::AnnouncementCancelled) => "Announcement cancelled, id",
+        (Locale::Es, Text::AnnouncementCancelled) => "Anuncio cancelado, id",
+        (Locale::En, Text::TooManyAnnouncements) => "This channel has too many announcements.",
+        (Locale::Es, Text::TooManyAnnouncements) => "Este canal tiene demasiados anuncios.",
+        (Locale::En, Text::AnnouncementTooFrequent) => {
+            "Announcements can repeat at most every 10 minutes."
+        }
+        (Locale::Es, Text::AnnouncementTooFrequent) => {
+            "Los anuncios se pueden repetir como mucho cada 10 minutos."
+        }
+        (Locale::En, Text::UnknownAnnouncement) => "There is no announcement with this id.",
+        (Locale::Es, Text::UnknownAnnouncement) => "No hay ningún anuncio con este id.",
+        (Locale::En, Text::Pasting) => {
+            "Pasting, end with a line with only a dot, /cancel discards it. Lines:"
+        }
+        (Locale::Es, Text::Pasting) => {
+            "Pegando, termina con una línea con solo un punto, /cancel lo descarta. Líneas:"
+        }
+        (Locale::En, Text::PasteShared) => "shared a paste, open it with",
+        (Locale::Es, Text::PasteShared) => "compartió un texto, ábrelo con",
+        (Locale::En, Text::UnknownPaste) => "There is no paste with this id.",
+        (Locale::Es, Text::UnknownPaste) => "No hay ningún texto con este id.",
+        (Locale::En, Text::InvalidTtl) => "The time to live is 10m to 7d, e.g. 2h.",
+        (Locale::Es, Text::InvalidTtl) => "El tiempo de vida es de 10m a 7d, p. ej. 2h.",
+        (Locale::En, Text::ChannelExpires) => "The channel is closed in",
+        (Locale::Es, Text::ChannelExpires) => "El canal se cierra en",
+        (Locale::En, Text::ChannelExpired) => "The temporary channel was closed:",
+        (Locale::Es, Text::ChannelExpired) => "Se cerró el canal temporal:",
+        (Locale::En, Text::FeedAdded) => "Feed added, id",
+        (Locale::Es, Text::FeedAdded) => "Fuente añadida, id",
+        (Locale::En, Text::FeedRemoved) => "Feed removed, id",
+        (Locale::Es, Text::FeedRemoved) => "Fuente quitada, id",
+        (Locale::En, Text::TooManyFeeds) => "This channel has too many feeds.",
+        (Locale::Es, Text::TooManyFeeds) => "Este canal tiene demasiadas fuentes.",
+        (Locale::En, Text::FeedTooFrequent) => "Feeds are polled at most every 5 minutes.",
+        (Locale::Es, Text::FeedTooFrequent) => {
+            "Las fuentes se consultan como mucho cada 5 minutos."
+        }
+        (Locale::En, Text::InvalidFeedUrl) => "Feeds need an http:// or https:// URL.",
+        (Locale::Es, Text::InvalidFeedUrl) => "Las fuentes necesitan una URL http:// o https://.",
+        (Locale::En, Text::UnknownFeed) => "There is no feed with this id.",
+        (Locale::Es, Text::UnknownFeed) => "No hay ninguna fuente con este id.",
+        (Locale::En, Text::PuttyHint) => {
+            "PuTTY: set Terminal → Keyboard → Backspace key to Control-? so that backspace deletes."
+        }
+        (Locale::Es, Text::PuttyHint) => {
+            "PuTTY: elige Terminal → Keyboard → Backspace key = Control-? para que la tecla de retroceso borre."
+        }
+        (Locale::En, Text::NetcatHint) => {
+            "netcat doesn't speak telnet, keys only arrive after Enter. Connect with `telnet` instead."
+        }
+        (Locale::Es, Text::NetcatHint) => {
+            "netcat no habla telnet, las teclas solo llegan tras Enter. Conéctate con `telnet`."
+        }
+        (Locale::En, Text::WindowsTelnetHint) => {
+            "Windows telnet: if lines look garbled, reconnect with `telnet -t vt100` or use PuTTY."
+        }
+        (Locale::Es, Text::WindowsTelnetHint) => {
+            "Telnet de Windows: si las líneas se ven mal, reconecta con `telnet -t vt100` o usa PuTTY."
+        }
+        (Locale::En, Text::MudletHint) => {
+            "Mudlet shows the chat as a plain log, a terminal (xterm, Windows Terminal) shows the full screen."
+        }
+        (Locale::Es, Text::MudletHint) => {
+            "Mudlet muestra el chat como un registro, una terminal (xterm, Windows Terminal) muestra la pantalla completa."
+        }
+        (Locale::En, Text::InvalidWindowSize) => {
+            "The terminal reported an unusable window size, 80x24 is used instead"
+        }
+        (Locale::Es, Text::InvalidWindowSize) => {
+            "La terminal informó un tamaño de ventana inservible, se usa 80x24"
+        }
+        (Locale::En, Text::PressAgainToQuit) => "Press Ctrl+C again to quit",
+        (Locale::Es, Text::PressAgainToQuit) => "Pulsa Ctrl+C otra vez para salir",
+        (Locale::En, Text::FeedsDisabled) => "Feeds are not enabled on this server.",
+        (Locale::Es, Text::FeedsDisabled) => "Las fuentes no están activadas en este servidor.",
+        (Locale::En, Text::NameInvalid) => {
+            "Only letters, digits, _ and - (at most 24), not user_<n>:"
+        }
+        (Locale::Es, Text::NameInvalid) => {
+            "Solo letras, dígitos, _ y - (máximo 24), no user_<n>:"
+        }
+        (Locale::En, Text::SecureFingerprint) => "compare the fingerprint",
+        (Locale::Es, Text::SecureFingerprint) => "comparad la huella",
+        (Locale::En, Text::InvalidQuestion) => "Ask the 8-ball a question of at most 200 characters.",
+        (Locale::Es, Text::InvalidQuestion) => {
+            "Haz a la bola 8 una pregunta de como mucho 200 caracteres."
+        }
+        (Locale::En, Text::PasteFailed) => "The paste couldn't be saved.",
+        (Locale::Es, Text::PasteFailed) => "No se pudo guardar el texto.",
+        (Locale::En, Text::ChannelClosed) => "The channel was closed:",
+        (Locale::Es, Text::ChannelClosed) => "Se cerró el canal:",
+        (Locale::En, Text::ChannelUnavailable) => "The channel is not available right now:",
+        (Locale::Es, Text::ChannelUnavailable) => "El canal no está disponible ahora mismo:",
+        (Locale::En, Text::ChannelClosesIn) => "This channel closes in",
+        (Locale::Es, Text::ChannelClosesIn) => "Este canal se cierra en",
+        (Locale::En, Text::TooManyAttempts) => "Too many wrong passwords, try again later.",
+        (Locale::Es, Text::TooManyAttempts) => {
+            "Demasiadas contraseñas incorrectas, inténtalo más tarde."
+        }
+        (Locale::En, Text::RecordingLimit) => "No more recordings can be started.",
+        (Locale::Es, Text::RecordingLimit) => "No se pueden iniciar más grabaciones.",
+    }
+}
+
+/// Returns the hint for the settings of a recognized telnet client.
+pub fn client_hint(locale: Locale, client: TelnetClient) -> &'static str {
+    let hint = match client {
+        TelnetClient::Putty => Text::PuttyHint,
+        TelnetClient::Netcat => Text::NetcatHint,
+        TelnetClient::WindowsTelnet => Text::WindowsTelnetHint,
+        TelnetClient::Mudlet => Text::MudletHint,
+    };
+    text(locale, hint)
+}
+
+// The template for the welcome screen.
+#[derive(Template)]
+#[template(path = "en/welcome.txt", escape = "none")]
+struct WelcomeEn<'a> {
+    username: &'a str,
+    clients: usize,
+    channels: usize,
+    motd: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "es/welcome.txt", escape = "none")]
+struct WelcomeEs<'a> {
+    username: &'a str,
+    clients: usize,
+    channels: usize,
+    motd: &'a str,
+}
+
+pub fn welcome(
+    locale: Locale,
+    custom: &CustomTemplates,
+    username: &str,
+    clients: usize,
+    channels: usize,
+    motd: &str,
+) -> String {
+    let context = json!({
+        "username": username,
+        "clients": clients,
+        "channels": channels,
+        "motd": motd,
+    });
+    if let Some(welcome) = custom.render(locale, "welcome.txt", context) {
+        return welcome;
+    }
+    match locale {
+        Locale::En => WelcomeEn {
+            username,
+            clients,
+            channels,
+            motd,
+        }
+        .render(),
+        Locale::Es => WelcomeEs {
+            username,
+            clients,
+            channels,
+            motd,
+        }
+        .render(),
+    }
+    .unwrap()
+}
+
+// The template for the list of all channels screen.
+#[derive(Template)]
+#[template(path = "en/list.txt", escape = "none")]
+struct ChannelListEn {
+    list: Vec<(String, usize, ChannelMeta)>,
+}
+
+#[derive(Template)]
+#[template(path = "es/list.txt", escape = "none")]
+struct ChannelListEs {
+    list: Vec<(String, usize, ChannelMeta)>,
+}
+
+pub fn channel_list(
+    locale: Locale,
+    custom: &CustomTemplates,
+    list: Vec<(String, usize, ChannelMeta)>,
+) -> String {
+    let channels: Vec<_> = list
+        .iter()
+        .map(|(name, users, meta)| {
+            json!({
+                "name": name,
+                "users": users,
+                "language": meta.language,
+                "description": meta.description,
+            })
+        })
+        .collect();
+    if let Some(content) = custom.render(locale, "list.txt", json!({ "list": channels })) {
+        return content;
+    }
+    match locale {
+        Locale::En => ChannelListEn { list }.render(),
+        Locale::Es => ChannelListEs { list }.render(),
+    }
+    .unwrap()
+}
+
+// The template for the channel statistics screen.
+#[derive(Template)]
+#[template(path = "en/stats.txt", escape = "none")]
+struct ChannelStatsEn {
+    list: Vec<(String, usize, ChannelStats)>,
+}
+
+#[derive(Template)]
+#[template(path = "es/stats.txt", escape = "none")]
+struct ChannelStatsEs {
+    list: Vec<(String, usize, ChannelStats)>,
+}
+
+pub fn channel_stats(locale: Locale, list: Vec<(String, usize, ChannelStats)>) -> String {
+    match locale {
+        Locale::En => ChannelStatsEn { list }.render(),
+        Locale::Es => ChannelStatsEs { list }.render(),
+    }
+    .unwrap()
+}
+
+// The template for the members of a channel.
+#[derive(Template)]
+#[template(path = "en/who.txt", escape = "none")]
+struct MembersEn<'a> {
+    channel: &'a str,
+    members: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "es/who.txt", escape = "none")]
+struct MembersEs<'a> {
+    channel: &'a str,
+    members: Vec<String>,
+}
+
+pub fn members(locale: Locale, channel: &str, members: Vec<String>) -> String {
+    match locale {
+        Locale::En => MembersEn { channel, members }.render(),
+        Locale::Es => MembersEs { channel, members }.render(),
+    }
+    .unwrap()
+}
+
+// The template for an invitation to a channel.
+#[derive(Template)]
+#[template(path = "en/invitation.txt", escape = "none")]
+struct InvitationEn<'a> {
+    from: &'a str,
+    channel: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "es/invitation.txt", escape = "none")]
+struct InvitationEs<'a> {
+    from: &'a str,
+    channel: &'a str,
+}
+
+pub fn invitation(locale: Locale, from: &str, channel: &str) -> String {
+    match locale {
+        Locale::En => InvitationEn { from, channel }.render(),
+        Locale::Es => InvitationEs { from, channel }.render(),
+    }
+    .unwrap()
+}
+
+// The template for the received direct messages.
+#[derive(Template)]
+#[template(path = "en/inbox.txt", escape = "none")]
+struct InboxEn {
+    messages: Vec<(String, String, String)>,
+}
+
+#[derive(Template)]
+#[template(path = "es/inbox.txt", escape = "none")]
+struct InboxEs {
+    messages: Vec<(String, String, String)>,
+}
+
+/// Renders direct messages given as timestamp, sender and text.
+// The template for the negotiated capabilities of the session.
+#[derive(Template)]
+#[template(path = "en/caps.txt", escape = "none")]
+struct CapabilitiesEn<'a> {
+    width: u16,
+    height: u16,
+    terminal_type: Option<&'a str>,
+    charset: Option<&'a str>,
+    colors: Option<&'a str>,
+    client: Option<&'a str>,
+}
+
+#[derive(Template)]
+#[template(path = "es/caps.txt", escape = "none")]
+struct CapabilitiesEs<'a> {
+    width: u16,
+    height: u16,
+    terminal_type: Option<&'a str>,
+    charset: Option<&'a str>,
+    colors: Option<&'a str>,
+    client: Option<&'a str>,
+}
+
+pub fn capabilities(locale: Locale, width: u16, height: u16, caps: &Capabilities) -> String {
+    let terminal_type = caps.terminal_type.as_deref();
+    let charset = caps.charset.as_deref();
+    let colors = caps.color_depth();
+    let client = caps.client().map(|client| client.name());
+    match locale {
+        Locale::En => CapabilitiesEn {
+            width,
+            height,
+            terminal_type,
+            charset,
+            colors,
+            client,
+        }
+        .render(),
+        Locale::Es => CapabilitiesEs {
+            width,
+            height,
+            terminal_type,
+            charset,
+            colors,
+            client,
+        }
+        .render(),
+    }
+    .unwrap()
+}
+
+/// Returns the description of a server event shown in the Server tab.
+pub fn server_event(locale: Locale, event: &ServerEvent) -> String {
+    match (locale, event) {
+        (Locale::En, ServerEvent::UserJoined(user)) => format!("{} joined the server", user),
+        (Locale::Es, ServerEvent::UserJoined(user)) => format!("{} entró al servidor", user),
+        (Locale::En, ServerEvent::UserLeft(user)) => format!("{} left the server", user),
+        (Locale::Es, ServerEvent::UserLeft(user)) => format!("{} salió del servidor", user),
+        (Locale::En, ServerEvent::ChannelCreated(name)) => format!("Channel {} was created", name),
+        (Locale::Es, ServerEvent::ChannelCreated(name)) => format!("Se creó el canal {}", name),
+        (Locale::En, ServerEvent::UserCount(count)) => format!("Users online: {}", count),
+        (Locale::Es, ServerEvent::UserCount(count)) => format!("Usuarios conectados: {}", count),
+    }
+}
+
+// The template for the Server tab.
+#[derive(Template)]
+#[template(path = "en/server_events.txt", escape = "none")]
+struct ServerEventsEn {
+    events: Vec<(String, String)>,
+}
+
+#[derive(Template)]
+#[template(path = "es/server_events.txt", escape = "none")]
+struct ServerEventsEs {
+    events: Vec<(String, String)>,
+}
+
+/// Renders the timestamps and descriptions of the latest server events, the newest first.
+pub fn server_events(locale: Locale, events: Vec<(String, String)>) -> String {
+    match locale {
+        Locale::En => ServerEventsEn { events }.render(),
+        Locale::Es => ServerEventsEs { events }.render(),
+    }
+    .unwrap()
+}
+
+// The template for the list of aliases.
+#[derive(Template)]
+#[template(path = "en/aliases.txt", escape = "none")]
+struct AliasesEn<'a> {
+    aliases: &'a BTreeMap<String, String>,
+}
+
+#[derive(Template)]
+#[template(path = "es/aliases.txt", escape = "none")]
+struct AliasesEs<'a> {
+    aliases: &'a BTreeMap<String, String>,
+}
+
+pub fn aliases(locale: Locale, aliases: &BTreeMap<String, String>) -> String {
+    match locale {
+        Locale::En => AliasesEn { aliases }.render(),
+        Locale::Es => AliasesEs { aliases }.render(),
+    }
+    .unwrap()
+}
+
+// The template for the announcements of a channel.
+#[derive(Template)]
+#[template(path = "en/announcements.txt", escape = "none")]
+struct AnnouncementsEn<'a> {
+    channel: &'a str,
+    announcements: Vec<(u64, String, String, String)>,
+}
+
+#[derive(Template)]
+#[template(path = "es/announcements.txt", escape = "none")]
+struct AnnouncementsEs<'a> {
+    channel: &'a str,
+    announcements: Vec<(u64, String, String, String)>,
+}
+
+pub fn announcements(locale: Locale, channel: &str, list: Vec<Announcement>) -> String {
+    let announcements = list
+        .into_iter()
+        .map(|announcement| {
+            let interval = announcement.interval_text();
+            (
+                announcement.id,
+                interval,
+                announcement.by,
+                announcement.message,
+            )
+        })
+        .collect();
+    match locale {
+        Locale::En => AnnouncementsEn {
+            channel,
+            announcements,
+        }
+        .render(),
+        Locale::Es => AnnouncementsEs {
+            channel,
+            announcements,
+        }
+        .render(),
+    }
+    .unwrap()
+}
+
+// The template for the feeds of a channel.
+#[derive(Template)]
+#[template(path = "en/feeds.txt", escape = "none")]
+struct FeedsEn<'a> {
+    channel: &'a str,
+    feeds: Vec<(u64, String, String, String)>,
+}
+
+#[derive(Template)]
+#[template(path = "es/feeds.txt", escape = "none")]
+struct FeedsEs<'a> {
+    channel: &'a str,
+    feeds: Vec<(u64, String, String, String)>,
+}
+
+pub fn feeds(locale: Locale, channel: &str, list: Vec<Feed>) -> String {
+    let feeds = list
+        .into_iter()
+        .map(|feed| (feed.id, feed.interval_text(), feed.by, feed.url))
+        .collect();
+    match locale {
+        Locale::En => FeedsEn { channel, feeds }.render(),
+        Locale::Es => FeedsEs { channel, feeds }.render(),
+    }
+    .unwrap()
+}
+
+// The template for a paste opened with `/show`.
+#[derive(Template)]
+#[template(path = "en/paste.txt", escape = "none")]
+struct PasteEn<'a> {
+    id: &'a str,
+    author: &'a str,
+    channel: &'a str,
+    created: String,
+    lines: &'a [String],
+}
+
+#[derive(Template)]
+#[template(path = "es/paste.txt", escape = "none")]
+struct PasteEs<'a> {
+    id: &'a str,
+    author: &'a str,
+    channel: &'a str,
+    created: String,
+    lines: &'a [String],
+}
+
+pub fn paste(locale: Locale, paste: &Paste) -> String {
+    let created = Utc
+        .timestamp_opt(paste.created, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default();
+    match locale {
+        Locale::En => PasteEn {
+            id: &paste.id,
+            author: &paste.author,
+            channel: &paste.channel,
+            created,
+            lines: &paste.lines,
+        }
+        .render(),
+        Locale::Es => PasteEs {
+            id: &paste.id,
+            author: &paste.author,
+            channel: &paste.channel,
+            created,
+            lines: &paste.lines,
+        }
+        .render(),
+    }
+    .unwrap()
+}
+
+// The template for the sessions of the account.
+#[derive(Template)]
+#[template(path = "en/sessions.txt", escape = "none")]
+struct SessionsEn {
+    sessions: Vec<Session>,
+}
+
+#[derive(Template)]
+#[template(path = "es/sessions.txt", escape = "none")]
+struct SessionsEs {
+    sessions: Vec<Session>,
+}
+
+pub fn sessions(locale: Locale, sessions: Vec<Session>) -> String {
+    match locale {
+        Locale::En => SessionsEn { sessions }.render(),
+        Locale::Es => SessionsEs { sessions }.render(),
+    }
+    .unwrap()
+}
+
+// The template for the connections of a user.
+#[derive(Template)]
+#[template(path = "en/whois.txt", escape = "none")]
+struct WhoisEn<'a> {
+    username: &'a str,
+    connections: Vec<Connection>,
+}
+
+#[derive(Template)]
+#[template(path = "es/whois.txt", escape = "none")]
+struct WhoisEs<'a> {
+    username: &'a str,
+    connections: Vec<Connection>,
+}
+
+pub fn whois(locale: Locale, username: &str, connections: Vec<Connection>) -> String {
+    match locale {
+        Locale::En => WhoisEn {
+            username,
+            connections,
+        }
+        .render(),
+        Locale::Es => WhoisEs {
+            username,
+            connections,
+        }
+        .render(),
+    }
+    .unwrap()
+}
+
+// The template for the connection history of a user.
+#[derive(Template)]
+#[template(path = "en/connections.txt", escape = "none")]
+struct ConnectionsEn<'a> {
+    user: &'a str,
+    // Time, minutes connected if finished, usernames and address.
+    connections: Vec<(String, Option<u64>, String, String)>,
+}
+
+#[derive(Template)]
+#[template(path = "es/connections.txt", escape = "none")]
+struct ConnectionsEs<'a> {
+    user: &'a str,
+    connections: Vec<(String, Option<u64>, String, String)>,
+}
+
+pub fn connections(locale: Locale, user: &str, connections: Vec<ConnectionRecord>) -> String {
+    let connections = connections
+        .into_iter()
+        .map(|connection| {
+            let time = Utc
+                .timestamp_opt(connection.connected, 0)
+                .single()
+                .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+                .unwrap_or_default();
+            (
+                time,
+                connection.duration.map(|seconds| seconds / 60),
+                connection.usernames.join(", "),
+                connection.address.to_string(),
+            )
+        })
+        .collect();
+    match locale {
+        Locale::En => ConnectionsEn { user, connections }.render(),
+        Locale::Es => ConnectionsEs { user, connections }.render(),
+    }
+    .unwrap()
+}
+
+pub fn inbox(locale: Locale, messages: Vec<(String, String, String)>) -> String {
+    match locale {
+        Locale::En => InboxEn { messages }.render(),
+        Locale::Es => InboxEs { messages }.render(),
+    }
+    .unwrap()
+}
+
+// The template for the list of unsent drafts.
+#[derive(Template)]
+#[template(path = "en/drafts.txt", escape = "none")]
+struct DraftsEn {
+    drafts: Vec<(String, String)>,
+}
+
+#[derive(Template)]
+#[template(path = "es/drafts.txt", escape = "none")]
+struct DraftsEs {
+    drafts: Vec<(String, String)>,
+}
+
+pub fn drafts(locale: Locale, drafts: Vec<(String, String)>) -> String {
+    match locale {
+        Locale::En => DraftsEn { drafts }.render(),
+        Locale::Es => DraftsEs { drafts }.render(),
+    }
+    .unwrap()
+}
+
+// The template for the instructions screen
+#[derive(Template)]
+#[template(path = "en/instructions.txt", escape = "none")]
+struct InstructionsEn<'a> {
+    commands: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "es/instructions.txt", escape = "none")]
+struct InstructionsEs<'a> {
+    commands: &'a str,
+}
+
+/// Renders the help, it only lists the commands the client has `permissions` for.
+pub fn instructions(locale: Locale, custom: &CustomTemplates, permissions: Permissions) -> String {
+    let commands = commands::help(locale, permissions);
+    let context = json!({ "commands": commands });
+    if let Some(instructions) = custom.render(locale, "instructions.txt", context) {
+        return instructions;
+    }
+    match locale {
+        Locale::En => InstructionsEn {
+            commands: &commands,
+        }
+        .render(),
+        Locale::Es => InstructionsEs {
+            commands: &commands,
+        }
+        .render(),
+    }
+    .unwrap()
+}
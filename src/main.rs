@@ -1,41 +1,623 @@
-mod channel;
+mod banner;
+mod bench;
 mod client;
-mod coordinator;
+mod commands;
+mod control;
+mod demo;
+mod frontend;
+mod i18n;
+mod proxy;
+mod recording;
+mod secure;
+mod setup;
+mod short;
 mod telnet;
+mod telnet_reader;
+mod telnet_writer;
 mod ui;
 
-use clap::{Arg, Command};
-use lunatic::{net::TcpListener, AbstractProcess, Mailbox, ProcessConfig};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
 
-use crate::{client::ClientProcess, coordinator::CoordinatorSup};
+use clap::{parser::ValueSource, Arg, ArgAction, Command};
+use lunatic::{net::TcpListener, AbstractProcess, Mailbox, Process, ProcessConfig};
+
+use chat_core::coordinator::{CoordinatorConfig, CoordinatorSup};
+use chat_core::storage::{self, StorageConfig};
+use chat_core::{filter, geoip, templates};
+
+use crate::{client::ClientProcess, frontend::Connection, i18n::Locale, ui::UiConfig};
 
 #[lunatic::main]
-fn main(_: Mailbox<()>) {
-    let matches = Command::new("lunatic.chat")
+fn main(mailbox: Mailbox<()>) {
+    let cli = command().get_matches();
+    let explicit = cli.get_one::<String>("config").cloned();
+    let path = explicit
+        .clone()
+        .unwrap_or_else(|| setup::DEFAULT_PATH.to_owned());
+    let args: Vec<String> = std::env::args().collect();
+    let entries = match std::fs::read_to_string(&path) {
+        Ok(content) => match setup::parse(&content) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!("Invalid config file {}: {}", path, err);
+                return;
+            }
+        },
+        Err(err) if explicit.is_some() => {
+            eprintln!("Can't read config file {}: {}", path, err);
+            return;
+        }
+        // Newcomers that start the server without options are walked through the setup
+        Err(_) if args.len() == 1 => setup::run(&path),
+        Err(_) => Vec::new(),
+    };
+    let matches = if entries.is_empty() {
+        cli
+    } else {
+        // Options of the configuration file come first, so that the command line overrides them
+        let with_port = cli.value_source("PORT") == Some(ValueSource::DefaultValue);
+        let config_args = setup::args(&entries, with_port);
+        let args = args[..1].iter().chain(&config_args).chain(&args[1..]);
+        command().args_override_self(true).get_matches_from(args)
+    };
+
+    if matches.get_flag("bench") {
+        bench::run();
+        return;
+    }
+    if let Some(path) = matches.get_one::<String>("play") {
+        if let Err(err) = recording::play(path) {
+            eprintln!("Can't play {}: {}", path, err);
+        }
+        return;
+    }
+
+    // The default network is always hosted, additional ones are selected by the listening port.
+    let port: u16 = *matches.get_one("PORT").unwrap();
+    let mut networks = vec![("default".to_owned(), port)];
+    if let Some(additional) = matches.get_many::<(String, u16)>("network") {
+        networks.extend(additional.cloned());
+    }
+    if let Err(err) = check_networks(&networks) {
+        eprintln!("Invalid networks: {}", err);
+        return;
+    }
+
+    let ui_config = UiConfig {
+        min_width: *matches.get_one("min-width").unwrap(),
+        min_height: *matches.get_one("min-height").unwrap(),
+        locale: Locale::from_code(matches.get_one::<String>("lang").unwrap()).unwrap(),
+        max_input: *matches.get_one("max-input").unwrap(),
+        max_fps: *matches.get_one("max-fps").unwrap(),
+        nick_prompt: matches.get_flag("nick-prompt"),
+        banner_everyone: matches.get_flag("banner-everyone"),
+    };
+
+    let acceptors: u16 = *matches.get_one("acceptors").unwrap();
+    let groups: HashMap<String, Vec<String>> = matches
+        .get_many::<(String, Vec<String>)>("group")
+        .map(|groups| groups.cloned().collect())
+        .unwrap_or_default();
+    let reserved_channels: Vec<(String, String)> = matches
+        .get_many::<(String, String)>("reserve")
+        .map(|reserved| reserved.cloned().collect())
+        .unwrap_or_default();
+    let retention: HashMap<String, Duration> = matches
+        .get_many::<(String, Duration)>("retention")
+        .map(|retention| retention.cloned().collect())
+        .unwrap_or_default();
+
+    // Refuse to start with broken filter rules, later reloads keep the old rules instead
+    let filter_file = matches.get_one::<String>("filter-file").cloned();
+    if let Some(path) = &filter_file {
+        if let Err(err) = filter::load(path) {
+            eprintln!("Invalid filter file: {}", err);
+            return;
+        }
+    }
+
+    let template_dir = matches.get_one::<String>("template-dir").cloned();
+    if let Some(dir) = &template_dir {
+        if let Err(err) = templates::CustomTemplates::load(dir) {
+            eprintln!("Invalid template directory: {}", err);
+            return;
+        }
+    }
+
+    let record_dir = matches.get_one::<String>("record-dir").cloned();
+    if let Some(dir) = &record_dir {
+        if let Err(err) = std::fs::read_dir(dir) {
+            eprintln!("Invalid record directory: {}", err);
+            return;
+        }
+    }
+
+    let storage_config = matches.get_one::<StorageConfig>("storage").unwrap().clone();
+    if let Err(err) = storage::open(&storage_config, "default") {
+        eprintln!("Invalid storage: {}", err);
+        return;
+    }
+
+    let geoip = matches.get_one::<String>("geoip").cloned();
+    if let Some(path) = &geoip {
+        if let Err(err) = geoip::Database::open(path) {
+            eprintln!("Invalid GeoIP database: {}", err);
+            return;
+        }
+    }
+
+    let network_names: Vec<String> = networks.iter().map(|(name, _)| name.clone()).collect();
+    let proxied: Vec<String> = matches
+        .get_many::<String>("proxy-protocol")
+        .map(|networks| networks.cloned().collect())
+        .unwrap_or_default();
+    if let Some(unknown) = proxied.iter().find(|name| !network_names.contains(name)) {
+        eprintln!("Unknown network for the PROXY protocol: {}", unknown);
+        return;
+    }
+    for (network, port) in networks {
+        let coordinator_config = CoordinatorConfig {
+            network: network.clone(),
+            max_channels_per_client: *matches.get_one("max-channels").unwrap(),
+            max_channels_per_guest: matches.get_one("guest-max-channels").copied(),
+            guest_history: matches.get_one("guest-history").copied(),
+            channels_created_per_hour: *matches.get_one("channel-creation-limit").unwrap(),
+            admin_password: matches.get_one::<String>("admin-password").cloned(),
+            groups: groups.clone(),
+            reserved_channels: reserved_channels.clone(),
+            retention: retention.clone(),
+            feeds: matches.get_flag("feeds"),
+            auto_join: matches.get_one::<String>("auto-join").cloned(),
+            motd_plugin: matches.get_one::<String>("motd-plugin").cloned(),
+            filter_file: filter_file.clone(),
+            template_dir: template_dir.clone(),
+            storage: storage_config.clone(),
+            geoip: geoip.clone(),
+            trace: matches.get_flag("trace"),
+        };
+        // Create a coordinator supervisor for each network, the coordinator is registered under
+        // the network's name.
+        CoordinatorSup::link().start(coordinator_config).unwrap();
+        if matches.get_flag("demo") {
+            demo::start(&network);
+        }
+
+        let address = format!("0.0.0.0:{}", port);
+        let listener = match TcpListener::bind(address.clone()) {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("Can't listen on {}: {}", address, err);
+                return;
+            }
+        };
+        println!("Started network {} on port {}", network, port);
+
+        // Accept connections from a pool of processes, so that a slow client startup doesn't
+        // block other connections. The acceptors are linked to the main process.
+        let proxy_protocol = proxied.contains(&network);
+        for _ in 0..acceptors {
+            Process::spawn_link(
+                (
+                    listener.clone(),
+                    proxy_protocol,
+                    ui_config,
+                    network.clone(),
+                    record_dir.clone(),
+                ),
+                acceptor,
+            );
+        }
+    }
+
+    if let Some(port) = matches.get_one::<u16>("control-port") {
+        let bind = matches.get_one::<String>("control-bind").unwrap();
+        let address = format!("{}:{}", bind, port);
+        if let Err(err) = control::start(address.clone(), network_names.clone(), mailbox.this()) {
+            eprintln!("Can't start the control socket: {}", err);
+            return;
+        }
+        println!("Control socket listening on {}", address);
+    }
+
+    if let Some(port) = matches.get_one::<u16>("short-port") {
+        let address = format!("0.0.0.0:{}", port);
+        if let Err(err) = short::start(address, network_names) {
+            eprintln!("Can't start the short mode listener: {}", err);
+            return;
+        }
+        println!("Short mode listening on port {}", port);
+    }
+
+    // Keep the main process alive until the control socket stops the server, if it exits all
+    // acceptors are killed and the server exits with a success status.
+    let _ = mailbox.receive();
+}
+
+// The command line options, they can also be set in the configuration file (see `setup`).
+fn command() -> Command {
+    Command::new("lunatic.chat")
         .version("0.1")
         .author("Bernard K. <me@kolobara.com>")
         .about("A telnet chat server")
-        .arg(Arg::new("PORT").help("Sets the listening port for the server"))
-        .get_matches();
+        .arg(
+            Arg::new("PORT")
+                .value_parser(clap::value_parser!(u16))
+                .default_value("2323")
+                .help("Sets the listening port for the server"),
+        )
+        .arg(
+            Arg::new("network")
+                .long("network")
+                .value_name("NAME=PORT")
+                .value_parser(parse_network)
+                .action(ArgAction::Append)
+                .help("Host an additional isolated chat network on its own port"),
+        )
+        .arg(
+            Arg::new("min-width")
+                .long("min-width")
+                .value_parser(clap::value_parser!(u16))
+                .default_value("40")
+                .help("Minimal terminal width required to render the UI"),
+        )
+        .arg(
+            Arg::new("min-height")
+                .long("min-height")
+                .value_parser(clap::value_parser!(u16))
+                .default_value("12")
+                .help("Minimal terminal height required to render the UI"),
+        )
+        .arg(
+            Arg::new("max-input")
+                .long("max-input")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("16384")
+                .help("Maximal size in bytes of a single telnet input sequence"),
+        )
+        .arg(
+            Arg::new("max-fps")
+                .long("max-fps")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .default_value("30")
+                .help("Most frames rendered per second for each client"),
+        )
+        .arg(
+            Arg::new("nick-prompt")
+                .long("nick-prompt")
+                .action(ArgAction::SetTrue)
+                .help("Ask new clients to choose a nickname instead of assigning user_<n>"),
+        )
+        .arg(
+            Arg::new("banner-everyone")
+                .long("banner-everyone")
+                .action(ArgAction::SetTrue)
+                .help("Let everyone use /banner, not only channel operators"),
+        )
+        .arg(
+            Arg::new("lang")
+                .long("lang")
+                .value_parser(["en", "es"])
+                .default_value("en")
+                .help("Default language of the UI"),
+        )
+        .arg(
+            Arg::new("max-channels")
+                .long("max-channels")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("20")
+                .help("Maximal number of channels a client can join at the same time"),
+        )
+        .arg(
+            Arg::new("guest-max-channels")
+                .long("guest-max-channels")
+                .value_parser(clap::value_parser!(usize))
+                .help("Lower channel limit for clients that are not identified with an account"),
+        )
+        .arg(
+            Arg::new("guest-history")
+                .long("guest-history")
+                .value_parser(clap::value_parser!(usize))
+                .help("How many messages of a channel's history guests can scroll back"),
+        )
+        .arg(
+            Arg::new("channel-creation-limit")
+                .long("channel-creation-limit")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("10")
+                .help("How many new channels a client can create per hour, 0 for no limit"),
+        )
+        .arg(
+            Arg::new("admin-password")
+                .long("admin-password")
+                .help("Password that grants administrator rights with /admin"),
+        )
+        .arg(
+            Arg::new("group")
+                .long("group")
+                .value_name("NAME=#CHANNEL,...")
+                .value_parser(parse_group)
+                .action(ArgAction::Append)
+                .help("Define a group of channels that administrators can /broadcast to"),
+        )
+        .arg(
+            Arg::new("reserve")
+                .long("reserve")
+                .value_name("#CHANNEL=TOPIC")
+                .value_parser(parse_reserved)
+                .action(ArgAction::Append)
+                .help("Channel that always exists with the given topic, e.g. #lobby=Say hi"),
+        )
+        .arg(
+            Arg::new("retention")
+                .long("retention")
+                .value_name("#CHANNEL=HOURS")
+                .value_parser(parse_retention)
+                .action(ArgAction::Append)
+                .help("Expire messages of a channel after the given hours, e.g. #support=72"),
+        )
+        .arg(
+            Arg::new("feeds")
+                .long("feeds")
+                .action(ArgAction::SetTrue)
+                .help("Let channel operators post RSS and Atom feeds with /feed"),
+        )
+        .arg(
+            Arg::new("auto-join")
+                .long("auto-join")
+                .value_name("#CHANNEL")
+                .value_parser(parse_channel)
+                .help("Channel that new clients join right after connecting"),
+        )
+        .arg(
+            Arg::new("motd-plugin")
+                .long("motd-plugin")
+                .value_name("PATH")
+                .help("WASM module that generates the message of the day on the welcome screen"),
+        )
+        .arg(
+            Arg::new("filter-file")
+                .long("filter-file")
+                .value_name("PATH")
+                .help("File with word filter rules (ACTION REGEX per line), reloaded with /rehash"),
+        )
+        .arg(
+            Arg::new("template-dir")
+                .long("template-dir")
+                .value_name("DIR")
+                .help("Directory with custom screens (runtime-templates feature), reloaded with /rehash"),
+        )
+        .arg(
+            Arg::new("storage")
+                .long("storage")
+                .value_name("BACKEND")
+                .value_parser(parse_storage)
+                .default_value("memory")
+                .help("Where messages and accounts are kept: memory, file:PATH or sqlite:PATH (sqlite feature)"),
+        )
+        .arg(
+            Arg::new("control-port")
+                .long("control-port")
+                .value_parser(clap::value_parser!(u16))
+                .help("Port of the control socket for remote administration (stats, kick, ban, rehash, shutdown)"),
+        )
+        .arg(
+            Arg::new("control-bind")
+                .long("control-bind")
+                .value_name("ADDRESS")
+                .default_value("127.0.0.1")
+                .help("Address the control socket listens on, only localhost by default"),
+        )
+        .arg(
+            Arg::new("short-port")
+                .long("short-port")
+                .value_parser(clap::value_parser!(u16))
+                .help("Port for devices that post to a single channel with JSON lines, e.g. telemetry"),
+        )
+        .arg(
+            Arg::new("geoip")
+                .long("geoip")
+                .value_name("PATH")
+                .help("MaxMind database (MMDB) to look up the country of clients shown by /whois (geoip feature)"),
+        )
+        .arg(
+            Arg::new("record-dir")
+                .long("record-dir")
+                .value_name("DIR")
+                .help("Let clients record their session with /record, as asciinema casts in this directory"),
+        )
+        .arg(
+            Arg::new("proxy-protocol")
+                .long("proxy-protocol")
+                .value_name("NETWORK")
+                .action(ArgAction::Append)
+                .help("Expect a PROXY protocol header on connections to the network, e.g. behind HAProxy"),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .action(ArgAction::SetTrue)
+                .help("Log a trace id of each message at every process it passes through"),
+        )
+        .arg(
+            Arg::new("demo")
+                .long("demo")
+                .action(ArgAction::SetTrue)
+                .help("Spawn scripted bots that chat in #lobby"),
+        )
+        .arg(
+            Arg::new("acceptors")
+                .long("acceptors")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .default_value("4")
+                .help("Number of processes accepting new connections"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("PATH")
+                .help("Read options from this file instead of chat.conf"),
+        )
+        .arg(
+            Arg::new("bench")
+                .long("bench")
+                .action(ArgAction::SetTrue)
+                .help("Run the telnet parsing and drawing benchmarks instead of the server"),
+        )
+        .arg(
+            Arg::new("play")
+                .long("play")
+                .value_name("PATH")
+                .help("Replay a session recorded with /record instead of running the server"),
+        )
+}
+
+// Parses a `NAME=PORT` network definition.
+fn parse_network(network: &str) -> Result<(String, u16), String> {
+    let (name, port) = network
+        .split_once('=')
+        .ok_or_else(|| "expected NAME=PORT".to_owned())?;
+    let port = port
+        .parse()
+        .map_err(|_| format!("invalid port: {}", port))?;
+    Ok((name.to_owned(), port))
+}
 
-    // Create a coordinator supervisor and register the coordinator under the "coordinator" name.
-    CoordinatorSup::link()
-        .start("coordinator".to_owned())
-        .unwrap();
+// Networks are registered under their names and listen on their own ports, neither can be shared.
+fn check_networks(networks: &[(String, u16)]) -> Result<(), String> {
+    for (index, (name, port)) in networks.iter().enumerate() {
+        for (other, other_port) in &networks[..index] {
+            if other == name {
+                return Err(format!("network {} is defined twice", name));
+            }
+            if other_port == port {
+                return Err(format!(
+                    "networks {} and {} both use port {}",
+                    other, name, port
+                ));
+            }
+        }
+    }
+    Ok(())
+}
 
-    let port: u16 = *matches.get_one("PORT").unwrap_or(&2323);
-    println!("Started server on port {}", port);
-    let address = format!("0.0.0.0:{}", port);
-    let listener = TcpListener::bind(address).unwrap();
+// Parses a `NAME=#CHANNEL,...` group definition.
+fn parse_group(group: &str) -> Result<(String, Vec<String>), String> {
+    let (name, channels) = group
+        .split_once('=')
+        .ok_or_else(|| "expected NAME=#CHANNEL,...".to_owned())?;
+    let channels: Vec<String> = channels.split(',').map(|c| c.to_owned()).collect();
+    if let Some(invalid) = channels.iter().find(|c| !c.starts_with('#')) {
+        return Err(format!("invalid channel: {}", invalid));
+    }
+    Ok((name.to_owned(), channels))
+}
 
-    // Limit client's memory usage to 5 Mb & allow sub-processes.
-    let mut client_conf = ProcessConfig::new().unwrap();
-    client_conf.set_max_memory(5_000_000);
-    client_conf.set_can_spawn_processes(true);
+// Parses a channel name.
+fn parse_channel(channel: &str) -> Result<String, String> {
+    if !channel.starts_with('#') {
+        return Err(format!("invalid channel: {}", channel));
+    }
+    Ok(channel.to_owned())
+}
 
-    while let Ok((stream, _)) = listener.accept() {
-        ClientProcess::configure(&client_conf)
-            .start(stream)
-            .unwrap();
+// Parses a `#CHANNEL=TOPIC` reserved channel, the topic can be empty.
+fn parse_reserved(reserved: &str) -> Result<(String, String), String> {
+    let (name, topic) = reserved.split_once('=').unwrap_or((reserved, ""));
+    if !name.starts_with('#') {
+        return Err(format!("invalid channel: {}", name));
     }
+    Ok((name.to_owned(), topic.to_owned()))
+}
+
+// Parses a `#CHANNEL=HOURS` retention policy.
+fn parse_retention(retention: &str) -> Result<(String, Duration), String> {
+    let (name, hours) = retention
+        .split_once('=')
+        .ok_or_else(|| "expected #CHANNEL=HOURS".to_owned())?;
+    if !name.starts_with('#') {
+        return Err(format!("invalid channel: {}", name));
+    }
+    let hours: u64 = hours
+        .parse()
+        .map_err(|_| format!("invalid hours: {}", hours))?;
+    if hours == 0 {
+        return Err("hours must be greater than 0".to_owned());
+    }
+    Ok((name.to_owned(), Duration::from_secs(hours * 60 * 60)))
+}
+
+// Parses a `memory`, `file:PATH` or `sqlite:PATH` storage backend.
+fn parse_storage(storage: &str) -> Result<StorageConfig, String> {
+    match storage.split_once(':') {
+        _ if storage == "memory" => Ok(StorageConfig::Memory),
+        Some(("file", path)) if !path.is_empty() => Ok(StorageConfig::File(path.to_owned())),
+        Some(("sqlite", path)) if !path.is_empty() => Ok(StorageConfig::Sqlite(path.to_owned())),
+        _ => Err("expected memory, file:PATH or sqlite:PATH".to_owned()),
+    }
+}
+
+// Accepts new connections to a network and starts a `ClientProcess` for each of them.
+//
+// With `proxy_protocol` the real source address is read from the PROXY header first. That happens
+// in a process of its own, the acceptor doesn't wait for slow balancers.
+fn acceptor(
+    (listener, proxy_protocol, ui_config, network, record_dir): (
+        TcpListener,
+        bool,
+        UiConfig,
+        String,
+        Option<String>,
+    ),
+    _: Mailbox<()>,
+) {
+    let client_conf = client_config();
+    while let Ok((stream, address)) = listener.accept() {
+        let args = (
+            Connection::Telnet(stream),
+            address,
+            ui_config,
+            network.clone(),
+            record_dir.clone(),
+        );
+        if proxy_protocol {
+            Process::spawn_config(&client_conf, args, proxied_client);
+        } else {
+            // The client closes the connection itself if it can't start, e.g. while the
+            // coordinator is not registered yet.
+            let _ = ClientProcess::configure(&client_conf).start(args);
+        }
+    }
+}
+
+// Limit client's memory usage to 5 Mb & allow sub-processes.
+fn client_config() -> ProcessConfig {
+    let mut config = ProcessConfig::new().unwrap();
+    config.set_max_memory(5_000_000);
+    config.set_can_spawn_processes(true);
+    config
+}
+
+// Reads the PROXY header of a connection and starts the client with the real source address.
+fn proxied_client(
+    (mut connection, address, ui_config, network, record_dir): (
+        Connection,
+        SocketAddr,
+        UiConfig,
+        String,
+        Option<String>,
+    ),
+    _: Mailbox<()>,
+) {
+    let address = match connection.read_proxy_header() {
+        Ok(Some(source)) => source,
+        Ok(None) => address,
+        Err(err) => {
+            eprintln!("Dropped connection from {}: {}", address, err);
+            return;
+        }
+    };
+    let _ = ClientProcess::configure(&client_config())
+        .start((connection, address, ui_config, network, record_dir));
 }
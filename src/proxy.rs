@@ -0,0 +1,259 @@
+//! PROXY protocol (v1 and v2) support for listeners behind a load balancer (`--proxy-protocol`).
+//!
+//! Load balancers like HAProxy open their own connection to the server, so the peer address of
+//! the stream is the balancer's. With the PROXY protocol the balancer sends the address of the
+//! real client in a header before any other data. The header is read in its own process before
+//! the client is started, so that a slow balancer doesn't hold up the acceptor.
+//!
+//! See <https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt>.
+
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use lunatic::net::TcpStream;
+
+/// How long the balancer has to send the whole header. A connection that sends it slowly, one
+/// byte at a time, must not keep the process waiting either.
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The v1 header is a text line of at most 107 bytes, including the `\r\n`.
+const V1_MAX_LENGTH: usize = 107;
+// The largest addresses are the 216 bytes of unix sockets, the rest is left for the extensions
+// balancers add, e.g. the TLS version.
+const V2_MAX_LENGTH: usize = 512;
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Reads the PROXY protocol header from the start of `stream`.
+///
+/// Returns the source address of the real client. `None` means that the balancer didn't proxy a
+/// client, e.g. for its health checks, and the peer address of the stream should be used.
+pub fn read_header(stream: &mut impl Read) -> Result<Option<SocketAddr>, String> {
+    // Both versions are longer than the v2 signature, reading it never consumes chat data
+    let mut start = [0; 12];
+    read(stream, &mut start)?;
+    if start == V2_SIGNATURE {
+        read_v2(stream)
+    } else if start.starts_with(V1_PREFIX) {
+        read_v1(stream, &start)
+    } else {
+        Err("missing PROXY protocol header".to_owned())
+    }
+}
+
+// Reads the rest of the `PROXY TCP4 <source> <destination> <source port> <destination port>\r\n`
+// line.
+fn read_v1(stream: &mut impl Read, start: &[u8]) -> Result<Option<SocketAddr>, String> {
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LENGTH {
+            return Err("PROXY header is too long".to_owned());
+        }
+        let mut byte = [0];
+        read(stream, &mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = std::str::from_utf8(&line[V1_PREFIX.len()..line.len() - 2])
+        .map_err(|_| "PROXY header is not ASCII".to_owned())?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["UNKNOWN", ..] => Ok(None),
+        ["TCP4" | "TCP6", source, _, port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| format!("invalid PROXY source address: {}", source))?;
+            let port: u16 = port
+                .parse()
+                .map_err(|_| format!("invalid PROXY source port: {}", port))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(format!("invalid PROXY header: {}", line)),
+    }
+}
+
+// Reads the binary header that follows the v2 signature.
+fn read_v2(stream: &mut impl Read) -> Result<Option<SocketAddr>, String> {
+    let mut header = [0; 4];
+    read(stream, &mut header)?;
+    let [version_command, family, length @ ..] = header;
+    if version_command >> 4 != 2 {
+        return Err(format!(
+            "unsupported PROXY version {}",
+            version_command >> 4
+        ));
+    }
+    // The addresses are followed by optional extensions, they are part of the length too
+    let length = u16::from_be_bytes(length) as usize;
+    if length > V2_MAX_LENGTH {
+        return Err("PROXY header is too long".to_owned());
+    }
+    let mut addresses = vec![0; length];
+    read(stream, &mut addresses)?;
+    // LOCAL connections come from the balancer itself
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+    match family {
+        // TCP over IPv4
+        0x11 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[0..4].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port)))
+        }
+        // TCP over IPv6
+        0x21 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[0..16].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        0x11 | 0x21 => Err("PROXY header is too short".to_owned()),
+        // UDP and unix sockets don't carry a chat client
+        _ => Ok(None),
+    }
+}
+
+fn read(stream: &mut impl Read, buffer: &mut [u8]) -> Result<(), String> {
+    stream
+        .read_exact(buffer)
+        .map_err(|err| format!("can't read PROXY header: {}", err))
+}
+
+/// A stream that fails once `deadline` has passed, however little each read waits.
+pub struct Deadline<'a> {
+    stream: &'a mut TcpStream,
+    deadline: Instant,
+}
+
+impl<'a> Deadline<'a> {
+    /// The header must be read from `stream` within `HEADER_TIMEOUT`.
+    pub fn new(stream: &'a mut TcpStream) -> Self {
+        Deadline {
+            stream,
+            deadline: Instant::now() + HEADER_TIMEOUT,
+        }
+    }
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buffer)
+    }
+}
+
+impl Drop for Deadline<'_> {
+    fn drop(&mut self) {
+        let _ = self.stream.set_read_timeout(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20 | command, family]);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+        header
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 23\r\nhello";
+        assert_eq!(
+            read_header(&mut stream),
+            Ok(Some("192.0.2.1:56324".parse().unwrap()))
+        );
+        // The chat data after the header is left in the stream
+        assert_eq!(stream, b"hello");
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let mut stream: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 23\r\n";
+        assert_eq!(
+            read_header(&mut stream),
+            Ok(Some("[2001:db8::1]:4000".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn v1_unknown() {
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut stream), Ok(None));
+    }
+
+    #[test]
+    fn v1_invalid() {
+        for header in [
+            &b"PROXY TCP4 192.0.2.1 198.51.100.1 port 23\r\n"[..],
+            b"PROXY TCP4 192.0.2.1\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 23",
+        ] {
+            let mut stream = header;
+            assert!(read_header(&mut stream).is_err());
+        }
+        let mut long = b"PROXY UNKNOWN ".to_vec();
+        long.resize(200, b'x');
+        assert_eq!(
+            read_header(&mut long.as_slice()),
+            Err("PROXY header is too long".to_owned())
+        );
+    }
+
+    #[test]
+    fn v2_tcp4() {
+        let header = v2(1, 0x11, &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0, 23]);
+        assert_eq!(
+            read_header(&mut header.as_slice()),
+            Ok(Some("192.0.2.1:56324".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn v2_tcp6() {
+        let mut addresses = [0; 36];
+        addresses[0..16].copy_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        addresses[32..34].copy_from_slice(&4000u16.to_be_bytes());
+        let header = v2(1, 0x21, &addresses);
+        assert_eq!(
+            read_header(&mut header.as_slice()),
+            Ok(Some("[2001:db8::1]:4000".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn v2_local_and_other_families() {
+        let local = v2(0, 0x11, &[0; 12]);
+        assert_eq!(read_header(&mut local.as_slice()), Ok(None));
+        let unix = v2(1, 0x31, &[0; 216]);
+        assert_eq!(read_header(&mut unix.as_slice()), Ok(None));
+    }
+
+    #[test]
+    fn v2_invalid() {
+        let short = v2(1, 0x11, &[0; 8]);
+        assert!(read_header(&mut short.as_slice()).is_err());
+        let long = v2(1, 0x11, &[0; V2_MAX_LENGTH + 1]);
+        assert_eq!(
+            read_header(&mut long.as_slice()),
+            Err("PROXY header is too long".to_owned())
+        );
+        let mut version = v2(1, 0x11, &[0; 12]);
+        version[12] = 0x11;
+        assert!(read_header(&mut version.as_slice()).is_err());
+    }
+
+    #[test]
+    fn missing_header() {
+        let mut stream: &[u8] = b"hello there, chat\r\n";
+        assert!(read_header(&mut stream).is_err());
+    }
+}
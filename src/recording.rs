@@ -0,0 +1,150 @@
+//! Recordings of what clients see, in the [asciinema](https://asciinema.org) cast format (v2).
+//!
+//! With `--record-dir`, clients can record their own session with `/record`. The telnet writer
+//! appends every frame it sends to `<DIR>/<username>-<session>-<timestamp>.cast`, until the client
+//! runs `/record` again, disconnects or the file reaches `MAX_SIZE`. Only letters, digits, `_` and
+//! `-` of the username end up in the file name, and existing files are never overwritten. A
+//! session can start `MAX_PER_SESSION` recordings, and none are started once the cast files of
+//! the directory leave no room for another one of `MAX_SIZE` below `MAX_TOTAL_SIZE`. The
+//! files can be replayed with `asciinema play` or by the server itself, e.g. to look at a
+//! rendering problem offline:
+//!
+//! ```bash
+//! > lunatic --dir recordings target/wasm32-wasi/release/telnet-chat.wasm --play recordings/user_1-4-1700000000.cast
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde_json::json;
+
+// Used for the header if the client didn't tell its window size.
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+// Longer pauses are shortened to this during playback.
+const MAX_IDLE: Duration = Duration::from_secs(2);
+/// Recordings are stopped once the file would grow beyond this many bytes.
+pub const MAX_SIZE: u64 = 50 * 1024 * 1024;
+/// How many recordings a session can start.
+pub const MAX_PER_SESSION: u32 = 3;
+/// How many bytes the cast files of the directory can take up together.
+pub const MAX_TOTAL_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// A cast file that frames are appended to.
+pub struct Recording {
+    file: File,
+    start: Instant,
+    // Bytes written to the file so far.
+    size: u64,
+}
+
+impl Recording {
+    /// Create the file and write the header, a terminal size of `(0, 0)` is unknown. Fails if the
+    /// file already exists.
+    pub fn create(path: &str, (width, height): (u16, u16)) -> io::Result<Self> {
+        let (width, height) = if width == 0 || height == 0 {
+            DEFAULT_SIZE
+        } else {
+            (width, height)
+        };
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let header = json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": Utc::now().timestamp(),
+            "env": { "TERM": "xterm-256color" },
+        });
+        let mut recording = Recording {
+            file,
+            start: Instant::now(),
+            size: 0,
+        };
+        recording.append(&header.to_string())?;
+        Ok(recording)
+    }
+
+    /// Append a frame as it was sent to the terminal.
+    pub fn frame(&mut self, bytes: &[u8]) -> io::Result<()> {
+        // The frames are escaped for telnet, which only changes bytes that are never part of UTF-8
+        let event = json!([
+            self.start.elapsed().as_secs_f64(),
+            "o",
+            String::from_utf8_lossy(bytes),
+        ]);
+        self.append(&event.to_string())
+    }
+
+    // Write a line, unless the file would get larger than `MAX_SIZE`.
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        let size = self.size + line.len() as u64 + 1;
+        if size > MAX_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the recording reached its maximum size",
+            ));
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size = size;
+        Ok(())
+    }
+}
+
+/// Returns the path of a new recording of the session `session` of `username` in `dir`.
+pub fn path(dir: &str, username: &str, session: u64) -> String {
+    // Names come from clients, they must not leave the directory
+    let username: String = username
+        .chars()
+        .filter(|ch| ch.is_ascii_alphanumeric() || *ch == '_' || *ch == '-')
+        .collect();
+    format!(
+        "{}/{}-{}-{}.cast",
+        dir.trim_end_matches('/'),
+        username,
+        session,
+        Utc::now().timestamp()
+    )
+}
+
+/// Returns `true` if another recording of `MAX_SIZE` fits into `dir` next to the existing ones.
+pub fn has_room(dir: &str) -> io::Result<bool> {
+    let mut used = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.path().extension().map_or(false, |ext| ext == "cast") {
+            used += entry.metadata()?.len();
+        }
+    }
+    Ok(used + MAX_SIZE <= MAX_TOTAL_SIZE)
+}
+
+/// Replay a cast file on stdout in real time (`--play`).
+///
+/// Pauses longer than a few seconds are shortened.
+pub fn play(path: &str) -> Result<(), String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let mut lines = BufReader::new(file).lines();
+    // The header only describes the terminal
+    lines
+        .next()
+        .ok_or("empty recording")?
+        .map_err(|err| err.to_string())?;
+
+    let mut stdout = io::stdout();
+    let mut previous = 0.0;
+    for (number, line) in lines.enumerate() {
+        let line = line.map_err(|err| err.to_string())?;
+        let (time, kind, data): (f64, String, String) =
+            serde_json::from_str(&line).map_err(|err| format!("line {}: {}", number + 2, err))?;
+        if kind != "o" {
+            continue;
+        }
+        let pause = Duration::from_secs_f64((time - previous).max(0.0));
+        lunatic::sleep(pause.min(MAX_IDLE));
+        previous = time;
+        let _ = stdout.write_all(data.as_bytes());
+        let _ = stdout.flush();
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! Encryption of direct messages between identified users.
+//!
+//! Every client generates a key pair when it connects and publishes the public key to the
+//! coordinator once the user identifies. `/secure @user` fetches the public key of the other user
+//! and from then on direct messages to them are encrypted with a key derived from both key pairs
+//! (X25519 + HKDF-SHA256 + ChaCha20-Poly1305). The coordinator only forwards the ciphertext.
+//!
+//! The coordinator hands out the public keys, so both users are shown a fingerprint of the pair of
+//! keys. If they compare it over another channel, they know that nobody swapped the keys.
+//!
+//! Keys only live as long as the connection, so encrypted messages can't be kept in the mailbox.
+//! The client processes run on the server too, so this protects messages from the rest of the
+//! server, not from its operator.
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const NONCE_LEN: usize = 12;
+// Binds derived keys to this use of the key pairs.
+const KEY_INFO: &[u8] = b"telnet-chat direct message key";
+// Bytes of the fingerprint shown to users.
+const FINGERPRINT_LEN: usize = 8;
+
+/// The key pair of a client.
+pub struct KeyPair {
+    secret: StaticSecret,
+}
+
+impl KeyPair {
+    pub fn generate() -> Self {
+        KeyPair {
+            secret: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    pub fn public(&self) -> [u8; 32] {
+        PublicKey::from(&self.secret).to_bytes()
+    }
+
+    /// Returns `true` if a shared key can be derived with the `peer` public key. Low order points
+    /// would give a key that doesn't depend on our secret.
+    pub fn accepts(&self, peer: &[u8; 32]) -> bool {
+        self.cipher(peer).is_some()
+    }
+
+    /// Returns the fingerprint of our and the `peer` public key, both sides get the same one.
+    pub fn fingerprint(&self, peer: &[u8; 32]) -> String {
+        let (first, second) = self.ordered(peer);
+        let digest = Sha256::new()
+            .chain_update(first)
+            .chain_update(second)
+            .finalize();
+        digest[..FINGERPRINT_LEN]
+            .chunks(2)
+            .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Encrypts the message for the owner of the `peer` public key.
+    ///
+    /// Returns the nonce followed by the ciphertext, hex encoded. `None` if the key isn't
+    /// accepted.
+    pub fn seal(&self, peer: &[u8; 32], message: &str) -> Option<String> {
+        let cipher = self.cipher(peer)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, message.as_bytes())
+            .expect("encrypt message");
+        let sealed = nonce
+            .iter()
+            .chain(&ciphertext)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Some(sealed)
+    }
+
+    /// Decrypts a message that the owner of the `peer` public key sealed for us.
+    ///
+    /// Returns `None` if the message was not sealed with this key pair or was modified.
+    pub fn open(&self, peer: &[u8; 32], sealed: &str) -> Option<String> {
+        if sealed.len() % 2 != 0 || !sealed.is_ascii() {
+            return None;
+        }
+        let bytes = (0..sealed.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&sealed[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        if bytes.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher(peer)?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+
+    // Both sides derive the same cipher from their secret and the other's public key. The raw
+    // shared secret isn't uniformly random, the key is derived from it together with both public
+    // keys.
+    fn cipher(&self, peer: &[u8; 32]) -> Option<ChaCha20Poly1305> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(*peer));
+        if !shared.was_contributory() {
+            return None;
+        }
+        let (first, second) = self.ordered(peer);
+        let salt = [first, second].concat();
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+            .expand(KEY_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 length");
+        Some(ChaCha20Poly1305::new(&key.into()))
+    }
+
+    // Our and the peer's public key in the same order on both sides.
+    fn ordered(&self, peer: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+        let public = self.public();
+        if public <= *peer {
+            (public, *peer)
+        } else {
+            (*peer, public)
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! The configuration file and the setup that writes it on the first start.
+//!
+//! The configuration file holds command line options, one `name = value` per line, without the
+//! leading `--`. Options that are repeated on the command line are repeated in the file, flags
+//! are written without a value and lines starting with `#` are comments:
+//!
+//! ```text
+//! port = 2323
+//! admin-password = 1f0e8c3a9b7d6e5f4a3b2c1d
+//! reserve = #lobby=Say hi
+//! auto-join = #lobby
+//! demo
+//! ```
+//!
+//! `port` is the listening port of the default network. Options given on the command line take
+//! precedence over the file.
+//!
+//! If the server is started without any options and there is no configuration file yet, the
+//! setup asks for the most important settings on stdin and writes them to the file. Questions
+//! that get no answer, e.g. because stdin is closed, fall back to generated defaults.
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+
+/// Where the configuration is read from if `--config` is not used.
+pub const DEFAULT_PATH: &str = "chat.conf";
+
+// Settings suggested by the setup.
+const DEFAULT_PORT: u16 = 2323;
+const DEFAULT_CHANNELS: &str = "#lobby";
+
+/// An option of the configuration file, the value is empty for flags.
+pub type Entry = (String, String);
+
+/// Parses the content of a configuration file.
+pub fn parse(content: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line.split_once('=').unwrap_or((line, ""));
+        let name = name.trim();
+        if name.is_empty() || name.starts_with('-') || name.contains(char::is_whitespace) {
+            return Err(format!("line {}: invalid option: {}", number + 1, name));
+        }
+        entries.push((name.to_owned(), value.trim().to_owned()));
+    }
+    Ok(entries)
+}
+
+/// Turns the entries into command line arguments.
+///
+/// The `port` entry is only used if `with_port` is set, so that a port given on the command line
+/// isn't passed twice.
+pub fn args(entries: &[Entry], with_port: bool) -> Vec<String> {
+    let mut args = Vec::new();
+    for (name, value) in entries {
+        if name == "port" {
+            if with_port {
+                args.push(value.clone());
+            }
+            continue;
+        }
+        args.push(format!("--{}", name));
+        if !value.is_empty() {
+            args.push(value.clone());
+        }
+    }
+    args
+}
+
+/// Asks for the settings on stdin and writes them to the configuration file at `path`.
+///
+/// Returns the entries even if the file can't be written, so that the server still starts with
+/// them.
+pub fn run(path: &str) -> Vec<Entry> {
+    println!("No configuration found, let's create {}.", path);
+    println!("Press enter to accept the suggestion in brackets.");
+    println!();
+    let port = loop {
+        let port = ask("Port of the chat server", &DEFAULT_PORT.to_string());
+        match port.parse::<u16>() {
+            Ok(port) if port > 0 => break port,
+            _ => println!("Please enter a port between 1 and 65535."),
+        }
+    };
+    let password = ask("Password for /admin", &new_password());
+    let channels = loop {
+        let channels = ask(
+            "Channels that always exist (comma separated)",
+            DEFAULT_CHANNELS,
+        );
+        let channels: Vec<String> = channels
+            .split(',')
+            .map(|channel| channel.trim().to_owned())
+            .filter(|channel| !channel.is_empty())
+            .collect();
+        if channels.iter().all(|channel| channel.starts_with('#')) {
+            break channels;
+        }
+        println!("Channel names start with #, e.g. #lobby.");
+    };
+
+    let mut entries = vec![
+        ("port".to_owned(), port.to_string()),
+        ("admin-password".to_owned(), password),
+    ];
+    for channel in &channels {
+        entries.push(("reserve".to_owned(), channel.clone()));
+    }
+    // New clients land in the first channel
+    if let Some(first) = channels.first() {
+        entries.push(("auto-join".to_owned(), first.clone()));
+    }
+
+    println!();
+    match std::fs::write(path, render(&entries)) {
+        Ok(()) => println!(
+            "Wrote {}, edit it or delete it to run the setup again.",
+            path
+        ),
+        Err(err) => {
+            // Lunatic only gives access to directories that are passed with `--dir`
+            println!("Could not write {}: {}", path, err);
+            println!("Run lunatic with `--dir .` to keep the configuration.");
+        }
+    }
+    println!();
+    entries
+}
+
+// Prints the question and returns the answer, or the suggestion if there is none.
+fn ask(question: &str, suggestion: &str) -> String {
+    print!("{} [{}]: ", question, suggestion);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(_) if !answer.trim().is_empty() => answer.trim().to_owned(),
+        Ok(read) if read > 0 => suggestion.to_owned(),
+        // Stdin is closed, end the line of the question
+        _ => {
+            println!();
+            suggestion.to_owned()
+        }
+    }
+}
+
+// Returns the content of a configuration file with the entries.
+fn render(entries: &[Entry]) -> String {
+    let mut content = String::from(
+        "# lunatic.chat configuration, one command line option per line without the leading --.\n\
+         # Run the server with --help to see all options.\n",
+    );
+    for (name, value) in entries {
+        if value.is_empty() {
+            let _ = writeln!(content, "{}", name);
+        } else {
+            let _ = writeln!(content, "{} = {}", name, value);
+        }
+    }
+    content
+}
+
+// Returns a random password that is hard to guess.
+fn new_password() -> String {
+    let mut bytes = [0u8; 12];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_skips_comments_and_blank_lines() {
+        let content = "# settings\n\n  port = 2323 \nreserve = #lobby=Say hi\ndemo\n";
+        let entries = parse(content).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("port".to_owned(), "2323".to_owned()),
+                ("reserve".to_owned(), "#lobby=Say hi".to_owned()),
+                ("demo".to_owned(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_names() {
+        assert_eq!(
+            parse("port = 2323\n--demo").unwrap_err(),
+            "line 2: invalid option: --demo"
+        );
+        assert_eq!(
+            parse("\nauto join = #lobby").unwrap_err(),
+            "line 2: invalid option: auto join"
+        );
+        assert!(parse("= 2323").is_err());
+    }
+
+    #[test]
+    fn args_skip_the_port_unless_asked() {
+        let entries = parse("port = 2323\nauto-join = #lobby\ndemo").unwrap();
+        assert_eq!(
+            args(&entries, false),
+            vec!["--auto-join", "#lobby", "--demo"]
+        );
+        assert_eq!(
+            args(&entries, true),
+            vec!["2323", "--auto-join", "#lobby", "--demo"]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Short mode for small devices (`--short-port`).
+//!
+//! Microcontrollers and other IoT clients can't drive the terminal UI, e.g. to post telemetry
+//! into a channel. They connect to the short mode port instead and speak JSON lines, limited to a
+//! single channel. The first line joins the channel, `name` and `network` are optional and
+//! `"receive": false` keeps the connection send-only. The answer is `{"ok":"<username>"}` or
+//! `{"error":"<reason>"}`, after an error the connection is closed.
+//!
+//! Every following line is posted to the channel. A JSON string or an object with a `text` field
+//! is unpacked, any other line is posted as it is. Messages of the channel arrive as objects:
+//!
+//! ```text
+//! > {"channel":"#sensors","name":"probe_1"}
+//! < {"ok":"probe_1"}
+//! > {"text":"temperature=21.5"}
+//! > humidity=40
+//! < {"from":"alice","text":"thanks!","time":"[12:00 UTC] "}
+//! ```
+//!
+//! Devices are guests: they never identify, the word filters apply to their lines and a line is
+//! only posted every `MIN_POST_INTERVAL`. The first line has to arrive within `HELLO_TIMEOUT` and
+//! a line longer than `MAX_INPUT_LINE` closes the connection.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::process::exit;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use lunatic::{
+    ap::ProcessRef,
+    net::{TcpListener, TcpStream},
+    Mailbox, Process, ProcessConfig,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use chat_core::channel::{self, ChannelProcess, ChannelProcessMessages};
+use chat_core::coordinator::{
+    self, CoordinatorProcess, CoordinatorProcessMessages, CoordinatorProcessRequests, JoinError,
+    HEARTBEAT_INTERVAL,
+};
+use chat_core::directory::{Directory, DirectoryProcessRequests};
+use chat_core::filter::Filters;
+use chat_core::protocol::{SessionEvent, SessionRef};
+
+// Lines sent faster are dropped.
+const MIN_POST_INTERVAL: Duration = Duration::from_secs(1);
+// Longest line that is posted, in bytes, the same as in the terminal UI.
+const MAX_LINE: usize = 299;
+// Longest line that is read, in bytes, with room for the JSON around a posted line.
+const MAX_INPUT_LINE: usize = 4096;
+// How long a new connection has to send its first line.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+// Memory limit of a connection's process, devices need much less than terminal clients.
+const MAX_MEMORY: u64 = 2_000_000;
+
+// The first line of a connection.
+#[derive(Deserialize)]
+struct Hello {
+    channel: String,
+    name: Option<String>,
+    network: Option<String>,
+    #[serde(default = "yes")]
+    receive: bool,
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// Starts listening for short mode connections on `address`, linked to the calling process.
+pub fn start(address: String, networks: Vec<String>) -> Result<(), String> {
+    let listener = TcpListener::bind(address.clone())
+        .map_err(|err| format!("can't listen on {}: {}", address, err))?;
+    Process::spawn_link((listener, networks), accept);
+    Ok(())
+}
+
+// Accepts connections and handles each of them in its own process. The processes are not linked,
+// a misbehaving device only takes down its own connection.
+fn accept((listener, networks): (TcpListener, Vec<String>), _: Mailbox<()>) {
+    // Limit the memory of connections & allow the session and heartbeat sub-processes.
+    let mut config = ProcessConfig::new().unwrap();
+    config.set_max_memory(MAX_MEMORY);
+    config.set_can_spawn_processes(true);
+    while let Ok((stream, address)) = listener.accept() {
+        Process::spawn_config(&config, (stream, address, networks.clone()), handle);
+    }
+}
+
+fn handle((stream, address, networks): (TcpStream, SocketAddr, Vec<String>), _: Mailbox<()>) {
+    let mut writer = stream.clone();
+    let mut reader = BufReader::new(stream);
+    if reader
+        .get_mut()
+        .set_read_timeout(Some(HELLO_TIMEOUT))
+        .is_err()
+    {
+        return;
+    }
+    let hello = match next_line(&mut reader) {
+        Some(line) => serde_json::from_str::<Hello>(&line),
+        None => return,
+    };
+    if reader.get_mut().set_read_timeout(None).is_err() {
+        return;
+    }
+    let hello = match hello {
+        Ok(hello) => hello,
+        Err(_) => {
+            let _ = writeln!(writer, "{}", json!({ "error": "invalid first line" }));
+            return;
+        }
+    };
+    let joined = match join(hello, address, &networks, writer.clone()) {
+        Ok(joined) => joined,
+        Err(err) => {
+            let _ = writeln!(writer, "{}", json!({ "error": err }));
+            // `exit(1)` also kills the linked session and heartbeat processes
+            exit(1);
+        }
+    };
+    let _ = writeln!(writer, "{}", json!({ "ok": joined.username }));
+
+    let mut last_post: Option<Instant> = None;
+    while let Some(line) = next_line(&mut reader) {
+        let text = match serde_json::from_str::<Value>(&line) {
+            Ok(Value::String(text)) => text,
+            Ok(Value::Object(object)) => match object.get("text") {
+                Some(Value::String(text)) => text.clone(),
+                _ => continue,
+            },
+            _ => line,
+        };
+        // Devices are not trusted with the terminals of the users, e.g. escape sequences or line
+        // breaks are shown as spaces
+        let text: String = text
+            .chars()
+            .map(|ch| if ch.is_control() { ' ' } else { ch })
+            .collect();
+        let text = text.trim();
+        if text.is_empty() || text.len() > MAX_LINE {
+            continue;
+        }
+        if last_post.map_or(false, |last| last.elapsed() < MIN_POST_INTERVAL) {
+            let _ = writeln!(writer, "{}", json!({ "error": "too fast" }));
+            continue;
+        }
+        let text = match joined.filters.apply(text).message {
+            Some(text) => text,
+            None => continue,
+        };
+        last_post = Some(Instant::now());
+        let now: DateTime<Local> = Local::now();
+        let timestamp = format!("[{}] ", now.format("%H:%M UTC"));
+        joined.channel_proc.broadcast_message(
+            joined.channel.clone(),
+            timestamp,
+            joined.username.clone(),
+            Some(joined.session),
+            text,
+            None,
+        );
+    }
+    // The device disconnected, the coordinator takes the session out of the channel
+    joined.coordinator.leave_server(joined.session);
+    exit(1);
+}
+
+// Reads the next line without the line break. Returns `None` at the end of the connection, after
+// an error and for a line longer than `MAX_INPUT_LINE`.
+fn next_line(reader: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut line = String::new();
+    let read = reader
+        .by_ref()
+        .take(MAX_INPUT_LINE as u64)
+        .read_line(&mut line)
+        .ok()?;
+    if read == 0 || (read == MAX_INPUT_LINE && !line.ends_with('\n')) {
+        return None;
+    }
+    let length = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(length);
+    Some(line)
+}
+
+// A device that joined its channel.
+struct Joined {
+    coordinator: ProcessRef<CoordinatorProcess>,
+    session: SessionRef,
+    channel: String,
+    username: String,
+    channel_proc: ProcessRef<ChannelProcess>,
+    // The word filters of the server when the device joined.
+    filters: Filters,
+}
+
+// Join the server and the channel of the first line.
+fn join(
+    hello: Hello,
+    address: SocketAddr,
+    networks: &[String],
+    writer: TcpStream,
+) -> Result<Joined, &'static str> {
+    let network = hello.network.unwrap_or_else(|| "default".to_owned());
+    if !networks.contains(&network) {
+        return Err("unknown network");
+    }
+    if !hello.channel.starts_with('#') || hello.channel[1..].split('/').any(|s| s.is_empty()) {
+        return Err("invalid channel");
+    }
+    let coordinator_name = coordinator::registry_name(&network);
+    let coordinator = match ProcessRef::<CoordinatorProcess>::lookup(&coordinator_name) {
+        Some(coordinator) if coordinator.accepts_clients() => coordinator,
+        _ => return Err("server is starting or stopping"),
+    };
+    // The events of the session are written to the connection by a linked process
+    let session = SessionRef::new(Process::spawn_link(
+        (writer, network.clone(), hello.receive),
+        events,
+    ));
+    let info = coordinator
+        .join_server(session, address, None, hello.name)
+        .map_err(|_| "invalid or taken name")?;
+    // The heartbeat stops the connection once the coordinator was restarted, the device connects
+    // again and joins the new one.
+    Process::spawn_link(
+        (coordinator, coordinator_name, session),
+        |(coordinator, coordinator_name, session), _: Mailbox<()>| loop {
+            lunatic::sleep(HEARTBEAT_INTERVAL);
+            match ProcessRef::<CoordinatorProcess>::lookup(&coordinator_name) {
+                Some(current) if current == coordinator => coordinator.heartbeat(session),
+                _ => exit(1),
+            }
+        },
+    );
+    let channel = hello.channel;
+    coordinator
+        .join_channel(session, channel.clone())
+        .map_err(|_| "can't join the channel")?;
+    let result = match Directory::new(&network).shard(&channel) {
+        Some(shard) => shard.join_channel(session, info.username.clone(), channel.clone()),
+        None => Err(JoinError::Unavailable),
+    };
+    let channel_proc = match result {
+        Ok(channel_proc) => channel_proc,
+        Err(err) => {
+            coordinator.leave_channel(session, channel);
+            return Err(match err {
+                JoinError::Unavailable => "the channel is not available",
+                _ => "banned from the channel",
+            });
+        }
+    };
+    Ok(Joined {
+        coordinator,
+        session,
+        channel,
+        username: info.username,
+        channel_proc,
+        filters: Filters::new(&info.filters),
+    })
+}
+
+// Writes the messages of the channel to the connection, or only acknowledges them if the device
+// doesn't receive.
+fn events(
+    (mut writer, network, receive): (TcpStream, String, bool),
+    mailbox: Mailbox<SessionEvent>,
+) {
+    let session = SessionRef::new(Process::this());
+    loop {
+        match mailbox.receive() {
+            SessionEvent::Messages(batch) => {
+                let channel = batch.first().map(|message| message.channel.clone());
+                if receive {
+                    for message in batch {
+                        let line = json!({
+                            "from": message.name,
+                            "text": message.message,
+                            "time": message.timestamp,
+                        });
+                        if writeln!(writer, "{}", line).is_err() {
+                            return;
+                        }
+                    }
+                }
+                let channel = channel.and_then(|channel| {
+                    ProcessRef::<ChannelProcess>::lookup(&channel::registry_name(
+                        &network, &channel,
+                    ))
+                });
+                if let Some(channel) = channel {
+                    channel.ack(session);
+                }
+            }
+            // Kicked, the server stops or the channel is gone, the linked connection is closed too
+            SessionEvent::Exit
+            | SessionEvent::ChannelExpired(_)
+            | SessionEvent::Banned(_)
+            | SessionEvent::ChannelClosed(_) => exit(1),
+            _ => {}
+        }
+    }
+}
@@ -2,7 +2,9 @@ use anyhow::{anyhow, Result};
 use itertools::Itertools;
 use lunatic::net::TcpStream;
 use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+
+use crate::frontend::Key;
 
 const IAC: u8 = 255;
 
@@ -14,53 +16,109 @@ const DONT: u8 = 254;
 const SE: u8 = 240;
 const SB: u8 = 250;
 
+// Size of the first read buffer, it grows if a sequence doesn't fit into it.
+const INITIAL_BUFFER: usize = 1024;
+
 const ECHO: u8 = 1;
 const LINEMODE: u8 = 34;
 const NAWS: u8 = 31;
+const TTYPE: u8 = 24;
+const CHARSET: u8 = 42;
+// Only offered by some clients on their own, they help to recognize the client
+const TSPEED: u8 = 32;
+const NEW_ENVIRON: u8 = 39;
 
-pub struct Telnet {
-    stream: TcpStream,
+// Subnegotiation commands of TTYPE (RFC 1091) and CHARSET (RFC 2066)
+const TTYPE_IS: u8 = 0;
+const TTYPE_SEND: u8 = 1;
+const CHARSET_REQUEST: u8 = 1;
+const CHARSET_ACCEPTED: u8 = 2;
+const CHARSET_REJECTED: u8 = 3;
+
+/// Reads telnet messages from a client's stream, usually a `TcpStream`.
+///
+/// Negotiation replies are written back to the same stream.
+pub struct Telnet<S = TcpStream> {
+    stream: S,
     start: usize,
     end: usize,
-    buffer: [u8; 1024],
+    buffer: Vec<u8>,
+    // The buffer never grows beyond this size, longer sequences are dropped.
+    max_buffer: usize,
     naws: bool,
     linemode: bool,
     echo: bool,
+    // The last read ended with a CR.
+    after_cr: bool,
+    // Options the client offered on its own with WILL, the server never asks for them.
+    offered: Vec<u8>,
+    // The client sent data instead of answering the negotiation, it doesn't speak telnet.
+    raw: bool,
+    // Input read during the negotiation, returned by the next call to `next`.
+    pending: Option<TelnetMessage>,
 }
 
-impl Telnet {
-    pub fn new(stream: TcpStream) -> Self {
+impl<S: Read + Write> Telnet<S> {
+    pub fn new(stream: S, max_buffer: usize) -> Self {
         Self {
             stream,
-            buffer: [0; 1024],
+            buffer: vec![0; INITIAL_BUFFER.min(max_buffer)],
+            max_buffer,
             start: 0,
             end: 0,
             naws: false,
             linemode: false,
             echo: false,
+            after_cr: false,
+            offered: Vec::new(),
+            raw: false,
+            pending: None,
         }
     }
 
+    // Ask the client to send each character on its own. This is the first negotiation, a client
+    // that answers with data instead (e.g. netcat) is not asked anything else.
     pub fn iac_do_linemode(&mut self) -> Result<()> {
         let buffer: [u8; 3] = [IAC, DO, LINEMODE];
         self.stream.write_all(&buffer)?;
 
         while !self.linemode {
-            if let TelnetMessage::IacWontLinemode = self.next()? {
-                return Err(anyhow!("Linemode not supported"));
+            match self.next()? {
+                TelnetMessage::IacWontLinemode => return Err(anyhow!("Linemode not supported")),
+                message if message.is_input() => {
+                    self.raw = true;
+                    // The first input of the user is not lost
+                    self.pending = Some(message);
+                    return Ok(());
+                }
+                _ => {}
             }
         }
         Ok(())
     }
 
+    /// Returns how the client answered the negotiation so far.
+    pub fn negotiation(&self) -> Negotiation {
+        Negotiation {
+            offered: self.offered.clone(),
+            raw: self.raw,
+        }
+    }
+
     // Tell the client not to do local editing
     pub fn iac_linemode_zero(&mut self) {
+        if self.raw {
+            return;
+        }
         let buffer: [u8; 7] = [IAC, SB, LINEMODE, 1, 0, IAC, SE];
         self.stream.write_all(&buffer).unwrap();
     }
 
     // Tell the client to report window size changes
     pub fn iac_do_naws(&mut self) -> Result<()> {
+        if self.raw {
+            return Ok(());
+        }
         let buffer: [u8; 3] = [IAC, DO, NAWS];
         self.stream.write_all(&buffer)?;
 
@@ -72,8 +130,32 @@ impl Telnet {
         Ok(())
     }
 
+    // Ask the client for its terminal type. The answer arrives later as `TerminalType`, many
+    // clients never send one.
+    pub fn iac_do_ttype(&mut self) -> Result<()> {
+        if self.raw {
+            return Ok(());
+        }
+        let buffer: [u8; 3] = [IAC, DO, TTYPE];
+        self.stream.write_all(&buffer)?;
+        Ok(())
+    }
+
+    // Offer to negotiate the charset. The answer arrives later as `Charset`.
+    pub fn iac_will_charset(&mut self) -> Result<()> {
+        if self.raw {
+            return Ok(());
+        }
+        let buffer: [u8; 3] = [IAC, WILL, CHARSET];
+        self.stream.write_all(&buffer)?;
+        Ok(())
+    }
+
     // Tell the client that we will be doing the echoing
     pub fn iac_will_echo(&mut self) -> Result<()> {
+        if self.raw {
+            return Ok(());
+        }
         let buffer: [u8; 3] = [IAC, WILL, ECHO];
         self.stream.write_all(&buffer)?;
 
@@ -87,17 +169,24 @@ impl Telnet {
 
     /// Get next message from client
     pub fn next(&mut self) -> Result<TelnetMessage> {
+        if let Some(message) = self.pending.take() {
+            return Ok(message);
+        }
         // If we reached the end of the buffer read more from tcp stream
         if self.start == self.end {
-            match self.stream.read(&mut self.buffer)? {
-                0 => return Err(anyhow!("Stream closed")),
-                size => {
-                    self.start = 0;
-                    self.end = size;
-                }
+            self.fill()?;
+        }
+        // Sequences can be split over multiple reads, wait for the rest of them
+        while incomplete(&self.buffer[self.start..self.end]) {
+            if self.end - self.start >= self.max_buffer {
+                // Too large to ever be complete, drop it
+                self.start = self.end;
+                return Ok(TelnetMessage::IacOther);
             }
+            self.fill()?;
         }
 
+        let after_cr = std::mem::take(&mut self.after_cr);
         let result = match self.buffer.get(self.start..self.end).unwrap() {
             [IAC, WILL, LINEMODE, ..] => {
                 self.start += 3;
@@ -126,63 +215,113 @@ impl Telnet {
                 self.start += 3;
                 TelnetMessage::IacDontEcho
             }
-            // Ignore other 3 byte patterns
-            [IAC, DO | DONT | WILL | WONT, _, ..] => {
+            // The client agreed to tell us its terminal type, ask for it
+            [IAC, WILL, TTYPE, ..] => {
                 self.start += 3;
+                self.stream
+                    .write_all(&[IAC, SB, TTYPE, TTYPE_SEND, IAC, SE])?;
                 TelnetMessage::IacOther
             }
-            // Handle NAWS
-            multibyte @ [IAC, SB, NAWS, .., IAC, SE] => {
-                let len = multibyte.len();
-                let (width, height) = if len == 9 {
-                    // If there are no double 255s
-                    (
-                        u16::from_be_bytes([multibyte[3], multibyte[4]]),
-                        u16::from_be_bytes([multibyte[5], multibyte[6]]),
-                    )
-                } else {
-                    // First deduplicate 255 values
-                    let slice = multibyte.get(3..len - 2).unwrap();
-                    let vec: Vec<&u8> = slice
-                        .iter()
-                        .dedup_by(|first, second| **first == 255 && **second == 255)
-                        .collect();
-                    (
-                        u16::from_be_bytes([*vec[0], *vec[1]]),
-                        u16::from_be_bytes([*vec[2], *vec[3]]),
-                    )
-                };
-                self.start += len;
-                TelnetMessage::Naws(width, height)
+            // The client agreed to negotiate the charset, the only one we speak is UTF-8
+            [IAC, DO, CHARSET, ..] => {
+                self.start += 3;
+                let mut request = vec![IAC, SB, CHARSET, CHARSET_REQUEST];
+                request.extend_from_slice(b";UTF-8");
+                request.extend_from_slice(&[IAC, SE]);
+                self.stream.write_all(&request)?;
+                TelnetMessage::IacOther
+            }
+            [IAC, DONT, CHARSET, ..] => {
+                self.start += 3;
+                TelnetMessage::Charset(None)
             }
-            // Ignore multibyte SB patterns
-            multibyte @ [IAC, SB, .., IAC, SE] => {
-                self.start += multibyte.len();
+            [IAC, WILL, option, ..] => {
+                self.start += 3;
+                self.offered.push(*option);
                 TelnetMessage::IacOther
             }
-            // Escape characters
-            [0x1b, 0x5b, esc, ..] => {
+            // Ignore other 3 byte patterns
+            [IAC, DO | DONT | WONT, _, ..] => {
                 self.start += 3;
-                match esc {
-                    65 => TelnetMessage::Up,
-                    66 => TelnetMessage::Down,
-                    67 => TelnetMessage::Right,
-                    68 => TelnetMessage::Left,
-                    _ => TelnetMessage::Ignore,
+                TelnetMessage::IacOther
+            }
+            // An escaped 255 data byte
+            [IAC, IAC, ..] => {
+                self.start += 2;
+                TelnetMessage::Char(IAC)
+            }
+            // Ignore 2 byte commands (NOP, Are You There, ...)
+            [IAC, 241..=249, ..] => {
+                self.start += 2;
+                TelnetMessage::IacOther
+            }
+            // Subnegotiation, it's complete because of the `incomplete` check above
+            multibyte @ [IAC, SB, ..] => {
+                let len = subnegotiation_len(multibyte).unwrap_or(multibyte.len());
+                self.start += len;
+                match &multibyte[..len] {
+                    // Handle NAWS
+                    [IAC, SB, NAWS, data @ .., IAC, SE] => {
+                        // First deduplicate 255 values
+                        let data: Vec<&u8> = data
+                            .iter()
+                            .dedup_by(|first, second| **first == 255 && **second == 255)
+                            .collect();
+                        match data[..] {
+                            [w1, w2, h1, h2] => TelnetMessage::Naws(
+                                u16::from_be_bytes([*w1, *w2]),
+                                u16::from_be_bytes([*h1, *h2]),
+                            ),
+                            _ => TelnetMessage::IacOther,
+                        }
+                    }
+                    [IAC, SB, TTYPE, TTYPE_IS, name @ .., IAC, SE] => {
+                        TelnetMessage::TerminalType(String::from_utf8_lossy(name).into_owned())
+                    }
+                    [IAC, SB, CHARSET, CHARSET_ACCEPTED, charset @ .., IAC, SE] => {
+                        TelnetMessage::Charset(Some(String::from_utf8_lossy(charset).into_owned()))
+                    }
+                    [IAC, SB, CHARSET, CHARSET_REJECTED, ..] => TelnetMessage::Charset(None),
+                    // Ignore other subnegotiations
+                    _ => TelnetMessage::IacOther,
                 }
             }
-            // Enter (NL CR)
-            [13, 0, ..] => {
+            // Escape sequences (CSI, SS3, Alt+key or a lone Esc)
+            escape @ [0x1b, ..] => {
+                let (len, key) = parse_escape(escape);
+                self.start += len;
+                match key {
+                    Some(key) => TelnetMessage::Key(key),
+                    None => TelnetMessage::Ignore,
+                }
+            }
+            [0 | 10, ..] if after_cr => {
+                self.start += 1;
+                TelnetMessage::Ignore
+            }
+            // Enter (CR NUL or CR LF)
+            [13, 0 | 10, ..] => {
                 self.start += 2;
                 TelnetMessage::Enter
             }
+            // Enter (bare CR or LF). If the CR was the last byte of a read, the LF or NUL
+            // belonging to it may arrive with the next read and must be skipped.
+            [13 | 10, ..] => {
+                self.start += 1;
+                self.after_cr = self.buffer[self.start - 1] == 13 && self.start == self.end;
+                TelnetMessage::Enter
+            }
             [ch, ..] => {
                 self.start += 1;
                 match ch {
+                    1 => TelnetMessage::CtrlA,
                     3 => TelnetMessage::CtrlC,
-                    127 => TelnetMessage::Backspace,
+                    5 => TelnetMessage::CtrlE,
+                    21 => TelnetMessage::CtrlU,
+                    23 => TelnetMessage::CtrlW,
+                    // Some clients (e.g. Windows telnet) send BS instead of DEL
+                    8 | 127 => TelnetMessage::Backspace,
                     9 => TelnetMessage::Tab,
-                    27 => TelnetMessage::Esc,
                     _ => TelnetMessage::Char(*ch),
                 }
             }
@@ -190,9 +329,82 @@ impl Telnet {
         };
         Ok(result)
     }
+
+    // Read more data from the stream, keeping the bytes that were not processed yet.
+    fn fill(&mut self) -> Result<()> {
+        self.buffer.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+        // Grow the buffer if it's full
+        if self.end == self.buffer.len() {
+            let size = (self.buffer.len() * 2).min(self.max_buffer);
+            self.buffer.resize(size, 0);
+        }
+        match self.stream.read(&mut self.buffer[self.end..])? {
+            0 => Err(anyhow!("Stream closed")),
+            size => {
+                self.end += size;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Escapes data written to a telnet client.
+///
+/// A 255 byte would be interpreted as the start of a telnet command (IAC) by the client, so it's
+/// doubled as required by the telnet spec.
+pub struct TelnetWriter<W: Write>(pub W);
+
+impl<W: Write> Write for TelnetWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.split_inclusive(|byte| *byte == IAC) {
+            self.0.write_all(chunk)?;
+            if chunk.last() == Some(&IAC) {
+                self.0.write_all(&[IAC])?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// Returns `true` if `bytes` start with a telnet command or escape sequence whose end was not
+// received yet.
+fn incomplete(bytes: &[u8]) -> bool {
+    match bytes {
+        [IAC] | [IAC, WILL | WONT | DO | DONT] => true,
+        [IAC, SB, ..] => subnegotiation_len(bytes).is_none(),
+        // A lone Esc is a key press, but a CSI needs its final byte. The `[` of the Linux console
+        // function keys is in the range of final bytes, they need the letter after it.
+        [0x1b, b'[', b'['] => true,
+        [0x1b, b'[', rest @ ..] => !rest.iter().any(|b| (0x40..=0x7e).contains(b)),
+        // SS3 needs the byte after the `O`
+        [0x1b, b'O'] => true,
+        _ => false,
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+// Returns the length of the subnegotiation at the start of `bytes` including the final IAC SE,
+// or `None` if it didn't end yet.
+fn subnegotiation_len(bytes: &[u8]) -> Option<usize> {
+    // Skip IAC SB and the option
+    let mut index = 3;
+    while index + 1 < bytes.len() {
+        match bytes[index..] {
+            [IAC, SE, ..] => return Some(index + 2),
+            // An escaped 255 data byte
+            [IAC, IAC, ..] => index += 2,
+            _ => index += 1,
+        }
+    }
+    None
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub enum TelnetMessage {
     IacWillLinemode,
     IacWontLinemode,
@@ -202,16 +414,243 @@ pub enum TelnetMessage {
     IacWontNaws,
     IacOther,
     Naws(u16, u16),
+    TerminalType(String),
+    /// The charset accepted by the client, `None` if it rejected UTF-8.
+    Charset(Option<String>),
+    /// Sent once after the negotiation.
+    Negotiated(Negotiation),
     Char(u8),
     Backspace,
     Enter,
+    CtrlA,
     CtrlC,
+    CtrlE,
+    CtrlU,
+    CtrlW,
     Tab,
-    Esc,
-    Up,
-    Down,
-    Right,
-    Left,
+    Key(Key),
     Ignore,
     Error,
 }
+
+impl TelnetMessage {
+    // Returns `true` for input of the user, as opposed to answers of the negotiation.
+    fn is_input(&self) -> bool {
+        !matches!(
+            self,
+            TelnetMessage::IacWillLinemode
+                | TelnetMessage::IacWontLinemode
+                | TelnetMessage::IacDoEcho
+                | TelnetMessage::IacDontEcho
+                | TelnetMessage::IacWillNaws
+                | TelnetMessage::IacWontNaws
+                | TelnetMessage::IacOther
+                | TelnetMessage::Naws(..)
+                | TelnetMessage::TerminalType(_)
+                | TelnetMessage::Charset(_)
+                | TelnetMessage::Ignore
+        )
+    }
+}
+
+/// How the client answered the negotiation, used to recognize it.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Negotiation {
+    /// Options the client offered on its own, in the order they arrived.
+    pub offered: Vec<u8>,
+    /// The client sent data instead of answering, it doesn't speak telnet.
+    pub raw: bool,
+}
+
+/// Clients that are recognized from the negotiation, they get hints for their settings on the
+/// welcome screen.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TelnetClient {
+    Putty,
+    Netcat,
+    WindowsTelnet,
+    Mudlet,
+}
+
+impl TelnetClient {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TelnetClient::Putty => "PuTTY",
+            TelnetClient::Netcat => "netcat",
+            TelnetClient::WindowsTelnet => "Windows telnet",
+            TelnetClient::Mudlet => "Mudlet",
+        }
+    }
+}
+
+/// What was negotiated with a client during its session, shown by `/caps`.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Capabilities {
+    /// The terminal type reported by the client, e.g. `XTERM-256COLOR`.
+    pub terminal_type: Option<String>,
+    pub charset: Option<String>,
+    /// `None` until the negotiation is over.
+    pub negotiation: Option<Negotiation>,
+}
+
+impl Capabilities {
+    /// Recognizes the client from its answers to the negotiation and its terminal type.
+    pub fn client(&self) -> Option<TelnetClient> {
+        let negotiation = self.negotiation.as_ref()?;
+        if negotiation.raw {
+            return Some(TelnetClient::Netcat);
+        }
+        let terminal = self.terminal_type.as_deref().unwrap_or_default();
+        let terminal = terminal.to_lowercase();
+        let offered = |option| negotiation.offered.contains(&option);
+        if terminal.starts_with("mudlet") {
+            Some(TelnetClient::Mudlet)
+        } else if terminal == "vtnt" || terminal == "ansi" {
+            Some(TelnetClient::WindowsTelnet)
+        } else if offered(TSPEED) && offered(NEW_ENVIRON) && terminal.starts_with("xterm") {
+            // PuTTY offers its speed and environment right away, other clients wait to be asked
+            Some(TelnetClient::Putty)
+        } else {
+            None
+        }
+    }
+
+    /// Guesses the color depth from the terminal type, `None` if it's unknown.
+    pub fn color_depth(&self) -> Option<&'static str> {
+        let terminal = self.terminal_type.as_ref()?.to_lowercase();
+        if terminal.contains("truecolor")
+            || terminal.contains("24bit")
+            || terminal.contains("direct")
+        {
+            Some("24-bit")
+        } else if terminal.contains("256") {
+            Some("256")
+        } else if terminal.contains("dumb") {
+            Some("1")
+        } else if ["xterm", "color", "ansi", "vt100", "linux", "screen", "tmux"]
+            .iter()
+            .any(|known| terminal.contains(known))
+        {
+            Some("16")
+        } else {
+            None
+        }
+    }
+}
+
+// Decodes an escape sequence at the start of `bytes`.
+//
+// Returns the number of bytes consumed and the decoded key, or `None` if the sequence is valid
+// but not understood.
+fn parse_escape(bytes: &[u8]) -> (usize, Option<Key>) {
+    match bytes {
+        // Linux console function keys `F1` - `F5`
+        [0x1b, b'[', b'[', ch @ b'A'..=b'E', ..] => (4, Some(Key::F(ch - b'A' + 1))),
+        // Control Sequence Introducer
+        [0x1b, b'[', rest @ ..] => {
+            // Parameter and intermediate bytes are followed by a single final byte.
+            let final_at = match rest.iter().position(|b| (0x40..=0x7e).contains(b)) {
+                Some(index) => index,
+                None => return (bytes.len(), None),
+            };
+            let params = &rest[..final_at];
+            // Only the first parameter is important, the others are key modifiers.
+            let first = params
+                .split(|b| *b == b';')
+                .next()
+                .and_then(|param| std::str::from_utf8(param).ok())
+                .and_then(|param| param.parse::<u8>().ok());
+            let key = match (rest[final_at], first) {
+                (b'A', _) => Some(Key::Up),
+                (b'B', _) => Some(Key::Down),
+                (b'C', _) => Some(Key::Right),
+                (b'D', _) => Some(Key::Left),
+                (b'H', _) => Some(Key::Home),
+                (b'F', _) => Some(Key::End),
+                (b'P'..=b'S', _) => Some(Key::F(rest[final_at] - b'P' + 1)),
+                (b'~', Some(1 | 7)) => Some(Key::Home),
+                (b'~', Some(2)) => Some(Key::Insert),
+                (b'~', Some(3)) => Some(Key::Delete),
+                (b'~', Some(4 | 8)) => Some(Key::End),
+                (b'~', Some(5)) => Some(Key::PageUp),
+                (b'~', Some(6)) => Some(Key::PageDown),
+                (b'~', Some(n @ 11..=15)) => Some(Key::F(n - 10)),
+                (b'~', Some(n @ 17..=21)) => Some(Key::F(n - 11)),
+                (b'~', Some(n @ 23..=24)) => Some(Key::F(n - 12)),
+                _ => None,
+            };
+            (2 + final_at + 1, key)
+        }
+        // Single Shift Three
+        [0x1b, b'O', ch, ..] => {
+            let key = match ch {
+                b'A' => Some(Key::Up),
+                b'B' => Some(Key::Down),
+                b'C' => Some(Key::Right),
+                b'D' => Some(Key::Left),
+                b'H' => Some(Key::Home),
+                b'F' => Some(Key::End),
+                b'P'..=b'S' => Some(Key::F(ch - b'P' + 1)),
+                _ => None,
+            };
+            (3, key)
+        }
+        // Alt + printable character
+        [0x1b, ch @ 0x20..=0x7e, ..] => (2, Some(Key::Alt(*ch))),
+        _ => (1, Some(Key::Esc)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    // A stream that returns one chunk per read.
+    struct Chunks(VecDeque<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let chunk = self.0.pop_front().unwrap_or_default();
+            buffer[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl Write for Chunks {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn key(chunks: &[&'static [u8]]) -> Option<Key> {
+        let mut telnet = Telnet::new(Chunks(chunks.iter().copied().collect()), 64);
+        match telnet.next().unwrap() {
+            TelnetMessage::Key(key) => Some(key),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn linux_console_function_key_split_after_brackets() {
+        assert_eq!(key(&[b"\x1b[[", b"A"]), Some(Key::F(1)));
+        assert_eq!(key(&[b"\x1b[", b"[E"]), Some(Key::F(5)));
+    }
+
+    #[test]
+    fn ss3_split_after_o() {
+        assert_eq!(key(&[b"\x1bO", b"P"]), Some(Key::F(1)));
+        assert_eq!(key(&[b"\x1bO", b"A"]), Some(Key::Up));
+    }
+
+    #[test]
+    fn csi_split_before_final_byte() {
+        assert_eq!(key(&[b"\x1b[1", b"5~"]), Some(Key::F(5)));
+        assert_eq!(key(&[b"\x1b[A"]), Some(Key::Up));
+    }
+}
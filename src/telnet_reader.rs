@@ -0,0 +1,110 @@
+use lunatic::{
+    abstract_process,
+    ap::{Config, ProcessRef},
+    net::TcpStream,
+};
+
+use crate::client::{ClientProcess, ClientProcessRequests};
+use crate::frontend::Input;
+use crate::telnet::{Capabilities, Telnet, TelnetMessage};
+
+/// The telnet reader turns the raw tcp stream of a client into `Input` messages.
+///
+/// It's linked to the client and if one of them fails the other will too. The reader negotiates
+/// the telnet options first and then reads one message at a time, sending itself a `read` message
+/// after each of them. Each input is a request that the client answers once it's handled, so the
+/// reader doesn't read ahead while the client is busy, e.g. while it loads and renders a lot of
+/// messages. Input that isn't read stays in the socket buffer, where TCP slows the sender down.
+///
+/// Answers of the negotiation are collected into the `Capabilities` of the terminal, the client
+/// gets all of them again after each change.
+pub struct TelnetReaderProcess {
+    this: ProcessRef<TelnetReaderProcess>,
+    client: ProcessRef<ClientProcess>,
+    telnet: Telnet,
+    caps: Capabilities,
+}
+
+#[abstract_process(visibility = pub)]
+impl TelnetReaderProcess {
+    #[init]
+    fn init(
+        config: Config<Self>,
+        args: (ProcessRef<ClientProcess>, TcpStream, usize),
+    ) -> Result<Self, ()> {
+        let (client, stream, max_input) = args;
+        // The negotiation waits for replies of the telnet client, it doesn't block the start of
+        // the client process.
+        config.self_ref().negotiate();
+        Ok(TelnetReaderProcess {
+            this: config.self_ref(),
+            client,
+            telnet: Telnet::new(stream, max_input),
+            caps: Capabilities::default(),
+        })
+    }
+
+    #[handle_message]
+    fn negotiate(&mut self) {
+        self.telnet.iac_do_linemode().unwrap();
+        self.telnet.iac_linemode_zero();
+        self.telnet.iac_will_echo().unwrap();
+        self.telnet.iac_do_naws().unwrap();
+        self.telnet.iac_do_ttype().unwrap();
+        self.telnet.iac_will_charset().unwrap();
+        self.forward(TelnetMessage::Negotiated(self.telnet.negotiation()));
+        self.this.read();
+    }
+
+    /// Read the next message and forward it to the client.
+    #[handle_message]
+    fn read(&mut self) {
+        match self.telnet.next() {
+            Ok(message) => self.forward(message),
+            Err(err) => panic!("A telnet error ocurred: {:?}", err),
+        };
+        self.this.read();
+    }
+}
+
+impl TelnetReaderProcess {
+    // Send the input of a telnet message to the client, answers of the negotiation update the
+    // capabilities.
+    fn forward(&mut self, message: TelnetMessage) {
+        let input = match message {
+            TelnetMessage::Char(byte) => Input::Char(byte.into()),
+            TelnetMessage::Backspace => Input::Backspace,
+            TelnetMessage::Enter => Input::Enter,
+            TelnetMessage::CtrlA => Input::CtrlA,
+            TelnetMessage::CtrlC => Input::CtrlC,
+            TelnetMessage::CtrlE => Input::CtrlE,
+            TelnetMessage::CtrlU => Input::CtrlU,
+            TelnetMessage::CtrlW => Input::CtrlW,
+            TelnetMessage::Tab => Input::Tab,
+            TelnetMessage::Key(key) => Input::Key(key),
+            TelnetMessage::Naws(width, height) => Input::Resize(width, height),
+            TelnetMessage::Error => Input::Closed,
+            TelnetMessage::TerminalType(terminal_type) => {
+                self.caps.terminal_type = Some(terminal_type);
+                Input::Terminal(self.caps.clone())
+            }
+            TelnetMessage::Charset(charset) => {
+                self.caps.charset = charset;
+                Input::Terminal(self.caps.clone())
+            }
+            TelnetMessage::Negotiated(negotiation) => {
+                self.caps.negotiation = Some(negotiation);
+                Input::Terminal(self.caps.clone())
+            }
+            TelnetMessage::IacWillLinemode
+            | TelnetMessage::IacWontLinemode
+            | TelnetMessage::IacDoEcho
+            | TelnetMessage::IacDontEcho
+            | TelnetMessage::IacWillNaws
+            | TelnetMessage::IacWontNaws
+            | TelnetMessage::IacOther
+            | TelnetMessage::Ignore => return,
+        };
+        self.client.process(input);
+    }
+}
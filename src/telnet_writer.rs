@@ -0,0 +1,81 @@
+use std::io::Write;
+
+use lunatic::{
+    abstract_process,
+    ap::{Config, ProcessRef},
+    net::TcpStream,
+};
+
+use crate::client::{ClientProcess, ClientProcessMessages};
+use crate::recording::Recording;
+
+/// The telnet writer owns the sending side of a client's tcp stream.
+///
+/// The `TelnetBackend` sends it each rendered frame as one buffer, so that a slow connection never
+/// blocks the client while it handles messages. Every written frame is acknowledged to the client,
+/// which stops rendering while too many frames are in flight and draws the latest state once the
+/// writer catches up. Frames in between are never sent, they are coalesced into the next one.
+///
+/// While the client records its session, every sent frame is appended to the recording too.
+///
+/// It's linked to the client and dies together with it.
+pub struct TelnetWriterProcess {
+    client: ProcessRef<ClientProcess>,
+    stream: TcpStream,
+    recording: Option<Recording>,
+}
+
+#[abstract_process(visibility = pub)]
+impl TelnetWriterProcess {
+    #[init]
+    fn init(_: Config<Self>, args: (ProcessRef<ClientProcess>, TcpStream)) -> Result<Self, ()> {
+        let (client, stream) = args;
+        Ok(TelnetWriterProcess {
+            client,
+            stream,
+            recording: None,
+        })
+    }
+
+    /// Write a frame and let the client know when it's sent.
+    #[handle_message]
+    fn write(&mut self, frame: Vec<u8>) {
+        // A closed connection is noticed by the telnet reader
+        let _ = self.stream.write_all(&frame);
+        self.record(&frame);
+        self.client.frame_written();
+    }
+
+    /// Write the bytes and return once they are sent, e.g. before the client exits.
+    #[handle_request]
+    fn write_now(&mut self, bytes: Vec<u8>) {
+        let _ = self.stream.write_all(&bytes);
+        self.record(&bytes);
+    }
+
+    /// Append all following frames to a new recording at `path`, replacing the current one.
+    #[handle_request]
+    fn start_recording(&mut self, path: String, size: (u16, u16)) -> Result<(), String> {
+        let recording = Recording::create(&path, size).map_err(|err| err.to_string())?;
+        self.recording = Some(recording);
+        Ok(())
+    }
+
+    /// Stop recording, returns if there was a recording.
+    #[handle_request]
+    fn stop_recording(&mut self) -> bool {
+        self.recording.take().is_some()
+    }
+}
+
+impl TelnetWriterProcess {
+    // Append the bytes to the recording, a recording that can't be written anymore is stopped.
+    fn record(&mut self, bytes: &[u8]) {
+        if let Some(recording) = &mut self.recording {
+            if let Err(err) = recording.frame(bytes) {
+                eprintln!("Stopped a recording: {}", err);
+                self.recording = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Inline formatting of messages: `*bold*`, `_italic_` and `` `code` ``.
+//!
+//! Markers are only taken as formatting if they close on the same message, the text right inside
+//! of them doesn't start or end with a space and, for `*` and `_`, they aren't part of a word. So
+//! `snake_case_name`, `2*3*4` and `a * b * c` stay as they are. A backslash in front of a marker
+//! shows the marker itself (`\*not bold\*`), code is shown exactly as written.
+
+use tui::style::{Color, Modifier, Style};
+use tui::text::Span;
+
+const MARKERS: [char; 3] = ['*', '_', '`'];
+
+/// Returns the spans of a message, without the markers of its formatting.
+pub fn spans(text: &str) -> Vec<Span<'static>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut spans = Vec::new();
+    let mut plain = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let ch = chars[index];
+        match chars.get(index + 1) {
+            Some(next) if ch == '\\' && MARKERS.contains(next) => {
+                plain.push(*next);
+                index += 2;
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(end) = closing(&chars, index) {
+            if !plain.is_empty() {
+                spans.push(Span::raw(std::mem::take(&mut plain)));
+            }
+            let inner = &chars[index + 1..end];
+            let inner = if ch == '`' {
+                inner.iter().collect()
+            } else {
+                unescape(inner)
+            };
+            spans.push(Span::styled(inner, style(ch)));
+            index = end + 1;
+            continue;
+        }
+        plain.push(ch);
+        index += 1;
+    }
+    if !plain.is_empty() {
+        spans.push(Span::raw(plain));
+    }
+    spans
+}
+
+// Returns the index of the marker closing the one at `start`, if it is an opening marker.
+fn closing(chars: &[char], start: usize) -> Option<usize> {
+    let marker = chars[start];
+    if !MARKERS.contains(&marker) {
+        return None;
+    }
+    let code = marker == '`';
+    let in_word = |ch: Option<&char>| ch.map_or(false, |ch| ch.is_alphanumeric());
+    if !code && start > 0 && in_word(chars.get(start - 1)) {
+        return None;
+    }
+    if chars.get(start + 1).map_or(true, |ch| ch.is_whitespace()) {
+        return None;
+    }
+    let mut index = start + 2;
+    while index < chars.len() {
+        if !code && chars[index - 1] == '\\' {
+            index += 1;
+            continue;
+        }
+        if chars[index] == marker
+            && !chars[index - 1].is_whitespace()
+            && (code || !in_word(chars.get(index + 1)))
+        {
+            return Some(index);
+        }
+        index += 1;
+    }
+    None
+}
+
+fn unescape(chars: &[char]) -> String {
+    let mut text = String::with_capacity(chars.len());
+    let mut chars = chars.iter().peekable();
+    while let Some(ch) = chars.next() {
+        match chars.peek() {
+            Some(next) if *ch == '\\' && MARKERS.contains(next) => {}
+            _ => text.push(*ch),
+        }
+    }
+    text
+}
+
+fn style(marker: char) -> Style {
+    match marker {
+        '*' => Style::default().add_modifier(Modifier::BOLD),
+        '_' => Style::default().add_modifier(Modifier::ITALIC),
+        _ => Style::default().fg(Color::Cyan),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markers_inside_words_stay() {
+        assert_eq!(spans("snake_case_name"), vec![Span::raw("snake_case_name")]);
+        assert_eq!(spans("2*3*4"), vec![Span::raw("2*3*4")]);
+        assert_eq!(spans("a * b * c"), vec![Span::raw("a * b * c")]);
+    }
+
+    #[test]
+    fn escaped_markers_are_shown() {
+        assert_eq!(spans("\\*not bold\\*"), vec![Span::raw("*not bold*")]);
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(
+            spans("a *b* _c_ `d_e`"),
+            vec![
+                Span::raw("a "),
+                Span::styled("b", style('*')),
+                Span::raw(" "),
+                Span::styled("c", style('_')),
+                Span::raw(" "),
+                Span::styled("d_e", style('`')),
+            ]
+        );
+    }
+
+    #[test]
+    fn code_is_shown_as_written() {
+        assert_eq!(spans("`a\\*b`"), vec![Span::styled("a\\*b", style('`'))]);
+    }
+}
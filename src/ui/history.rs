@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A message shown in a channel tab: the timestamp, username and text.
+pub type Line = (String, String, String);
+
+// How many bytes of messages a single tab can keep in memory.
+const MEMORY_LIMIT: usize = 64 * 1024;
+// How many messages are evicted at once when the memory limit is reached.
+const EVICT_PAGE: usize = 20;
+
+// Source of `History::version`, unique across all histories of the process.
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+/// Messages of a channel or aggregate tab, the oldest first.
+///
+/// The memory used by the messages is capped. Once the limit is reached, the oldest page of
+/// messages is evicted. Channel messages keep their id, so that evicted messages can be fetched
+/// again from the channel when the user scrolls back.
+#[derive(Clone)]
+pub struct History {
+    lines: VecDeque<(Option<u64>, Line)>,
+    bytes: usize,
+    // If the channel has messages older than the first loaded one.
+    has_older: bool,
+    version: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            lines: VecDeque::new(),
+            bytes: 0,
+            has_older: false,
+            version: next_version(),
+        }
+    }
+}
+
+impl History {
+    /// Create a history from lines that are not part of a channel, e.g. the aggregate tab.
+    pub fn new(lines: Vec<Line>) -> Self {
+        let mut history = History::default();
+        for line in lines {
+            history.push(None, line);
+        }
+        history
+    }
+
+    /// Create a history from the newest page of a channel's history.
+    pub fn from_page(page: Vec<(u64, Line)>) -> Self {
+        let mut history = History::default();
+        history.prepend(page);
+        history
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Line> {
+        self.lines.get(index).map(|(_, line)| line)
+    }
+
+    /// Returns the channel id of the message at `index`.
+    pub fn id(&self, index: usize) -> Option<u64> {
+        self.lines.get(index).and_then(|(id, _)| *id)
+    }
+
+    /// Returns the index of the channel message with the id, if it's loaded.
+    pub fn position(&self, id: u64) -> Option<usize> {
+        self.lines
+            .iter()
+            .position(|(line_id, _)| *line_id == Some(id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Line> {
+        self.lines.iter().map(|(_, line)| line)
+    }
+
+    /// Changes whenever lines are inserted or removed anywhere but at the end.
+    ///
+    /// As long as the version stays the same, lines are only appended and the indexes of the
+    /// existing ones stay valid. Versions are never reused, not even by other histories.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the id before which older messages can be fetched from the channel, or `None` if
+    /// the beginning of the channel's history is loaded.
+    pub fn older_than(&self) -> Option<u64> {
+        if !self.has_older {
+            return None;
+        }
+        self.lines.iter().find_map(|(id, _)| *id)
+    }
+
+    /// Add a line above all messages, e.g. the channel description.
+    pub fn push_front(&mut self, line: Line) {
+        self.bytes += size(&line);
+        self.lines.push_front((None, line));
+        self.version = next_version();
+    }
+
+    /// Mark the beginning of the channel's visible history with a line above all messages, older
+    /// messages are not fetched anymore.
+    ///
+    /// Used for channels whose older messages expired.
+    pub fn truncate_older(&mut self, line: Line) {
+        self.has_older = false;
+        self.push_front(line);
+    }
+
+    /// Add a new message, `id` is `None` for lines that don't come from a channel.
+    ///
+    /// Returns how many of the oldest messages were evicted to stay inside the memory limit.
+    pub fn push(&mut self, id: Option<u64>, line: Line) -> usize {
+        self.bytes += size(&line);
+        self.lines.push_back((id, line));
+        let mut evicted = 0;
+        while self.bytes > MEMORY_LIMIT && self.lines.len() > EVICT_PAGE {
+            for (id, line) in self.lines.drain(..EVICT_PAGE) {
+                self.bytes -= size(&line);
+                self.has_older |= id.is_some();
+            }
+            evicted += EVICT_PAGE;
+        }
+        if evicted > 0 {
+            self.version = next_version();
+        }
+        evicted
+    }
+
+    /// Insert a page of older channel messages above the loaded ones.
+    ///
+    /// An empty page means that the beginning of the channel's history was reached. Returns the
+    /// number of inserted messages.
+    pub fn prepend(&mut self, page: Vec<(u64, Line)>) -> usize {
+        self.has_older = !page.is_empty();
+        let count = page.len();
+        for (id, line) in page.into_iter().rev() {
+            self.bytes += size(&line);
+            self.lines.push_front((Some(id), line));
+        }
+        if count > 0 {
+            self.version = next_version();
+        }
+        count
+    }
+}
+
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+// Approximate memory used by a line.
+fn size(line: &Line) -> usize {
+    line.0.len() + line.1.len() + line.2.len()
+}
@@ -1,7 +1,14 @@
+mod format;
+pub mod history;
 pub mod telnet_backend;
 pub mod termion;
 
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use tui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
@@ -17,134 +24,473 @@ use tui::{
     widgets::{Block, Borders, Tabs},
 };
 
-use lunatic::{ap::ProcessRef, net::TcpStream};
+use chat_core::channel::{ChannelProcess, ChannelProcessMessages};
+use chat_core::protocol::SessionRef;
+use history::{History, Line};
+use lunatic::{ap::ProcessRef, Mailbox, Process};
+use serde::{Deserialize, Serialize};
 use telnet_backend::TelnetBackend;
 
-use crate::channel::{ChannelProcess, ChannelProcessMessages};
+use crate::client::{ClientProcess, ClientProcessMessages};
+use crate::i18n::{self, Locale, Text};
+use crate::telnet_writer::TelnetWriterProcess;
+
+// Terminals narrower than this get the compact layout.
+const NARROW_WIDTH: u16 = 80;
+// How many frames can wait for the writer before rendering is put off. Renders in the meantime
+// are coalesced into one frame drawn when the writer catches up.
+const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+/// Server wide UI settings.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct UiConfig {
+    /// The UI is only rendered if the terminal is at least this wide.
+    pub min_width: u16,
+    /// The UI is only rendered if the terminal is at least this high.
+    pub min_height: u16,
+    /// Language used until the client picks another one.
+    pub locale: Locale,
+    /// Longest telnet input sequence (e.g. a paste) that is buffered, in bytes.
+    pub max_input: usize,
+    /// Most frames rendered per second, renders in between are collapsed into one.
+    pub max_fps: u16,
+    /// New clients choose their username on the welcome screen instead of getting `user_<n>`.
+    pub nick_prompt: bool,
+    /// Everyone can use `/banner`, not only channel operators.
+    pub banner_everyone: bool,
+}
 
 pub struct Ui {
     terminal: Terminal<TelnetBackend>,
     tabs: UiTabs,
+    config: UiConfig,
+    // If the last frame contained raw copy mode output that tui doesn't know about.
+    copy_mode_drawn: bool,
+    // A render was put off because the writer is behind or the last frame was too recent.
+    dirty: bool,
+    // Notified with `render_due` when a put off frame can be rendered.
+    client: ProcessRef<ClientProcess>,
+    last_frame: Option<Instant>,
+    // A `render_due` timer is running.
+    frame_scheduled: bool,
+    // Built lines of the channel and aggregate tabs, by tab name.
+    line_cache: HashMap<String, LineCache>,
+    // Messages are shown with their inline formatting, turned off with `/format off`.
+    formatting: bool,
+}
+
+// The styled lines of a tab's history, so that a frame only builds the lines of new messages.
+#[derive(Default)]
+struct LineCache {
+    // `History::version` the lines were built for.
+    version: u64,
+    // The narrow layout shortens the timestamps.
+    narrow: bool,
+    formatting: bool,
+    // One line per message with its width.
+    lines: Vec<(Spans<'static>, usize)>,
+}
+
+impl LineCache {
+    // Build the lines of messages added since the last frame, or all of them if the history
+    // was changed in another way.
+    fn update(&mut self, content: &History, narrow: bool, formatting: bool) {
+        if self.version != content.version()
+            || self.narrow != narrow
+            || self.formatting != formatting
+            || self.lines.len() > content.len()
+        {
+            self.version = content.version();
+            self.narrow = narrow;
+            self.formatting = formatting;
+            self.lines.clear();
+        }
+        let new = content.iter().skip(self.lines.len()).map(|line| {
+            let spans = Self::build(line, narrow, formatting);
+            let width = spans.width();
+            (spans, width)
+        });
+        self.lines.extend(new);
+    }
+
+    fn build(line: &Line, narrow: bool, formatting: bool) -> Spans<'static> {
+        let timestamp = if narrow {
+            short_timestamp(&line.0)
+        } else {
+            line.0.clone()
+        };
+        let mut spans = vec![
+            Span::styled(timestamp, Style::default().fg(Color::Yellow)),
+            Span::styled(
+                line.1.clone(),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(": ", Style::default().add_modifier(Modifier::BOLD)),
+        ];
+        if formatting {
+            spans.extend(format::spans(&line.2));
+        } else {
+            spans.push(Span::raw(line.2.clone()));
+        }
+        Spans::from(spans)
+    }
 }
 
 impl Ui {
     pub fn new(
-        tcp_stream: TcpStream,
+        client: ProcessRef<ClientProcess>,
+        writer: ProcessRef<TelnetWriterProcess>,
         window_size: telnet_backend::WindowSize,
         tabs: UiTabs,
+        config: UiConfig,
     ) -> Self {
-        let backend = TelnetBackend::new(tcp_stream, window_size);
+        let backend = TelnetBackend::new(writer, window_size);
         let terminal = Terminal::new(backend).unwrap();
-        Self { terminal, tabs }
+        Self {
+            terminal,
+            tabs,
+            config,
+            copy_mode_drawn: false,
+            dirty: false,
+            client,
+            last_frame: None,
+            frame_scheduled: false,
+            line_cache: HashMap::new(),
+            formatting: true,
+        }
     }
 
     pub fn render(&mut self) {
+        // A slow connection gets fewer frames instead of a growing queue of them
+        if self.terminal.backend().frames_in_flight() >= MAX_FRAMES_IN_FLIGHT {
+            self.dirty = true;
+            return;
+        }
+        // Renders within the frame budget are collapsed into one at the end of it
+        let interval = Duration::from_secs(1) / self.config.max_fps.max(1) as u32;
+        if let Some(elapsed) = self.last_frame.map(|last| last.elapsed()) {
+            if elapsed < interval {
+                self.dirty = true;
+                if !self.frame_scheduled {
+                    self.frame_scheduled = true;
+                    Process::spawn_link(
+                        (self.client, interval - elapsed),
+                        |(client, delay), _: Mailbox<()>| {
+                            lunatic::sleep(delay);
+                            client.render_due();
+                        },
+                    );
+                }
+                return;
+            }
+        }
+        self.dirty = false;
+        self.last_frame = Some(Instant::now());
         let tabs = self.tabs.widget();
+        let tabs_indicator = self.tabs.indicator();
+        let latency = self.tabs.get_latency();
         let selected_tab = self.tabs.get_selected();
+        let status = self.tabs.get_status();
+        let copy_text = selected_tab.get_selection_text();
+        // The raw copy mode line is written around tui, force a full redraw to get rid of it.
+        if copy_text.is_some() || self.copy_mode_drawn {
+            let _ = self.terminal.clear();
+        }
+        self.copy_mode_drawn = copy_text.is_some();
+        let mut copy_area = None;
+        let config = self.config;
+        let formatting = self.formatting;
+        // Lines of closed tabs are dropped
+        let names = self.tabs.names();
+        self.line_cache.retain(|name, _| names.contains(name));
+        let line_cache = self.line_cache.entry(selected_tab.get_name()).or_default();
         let _ = self.terminal.draw(|f| {
             let size = f.size();
-            if size.width < 80 || size.height < 24 {
-                return Self::render_size_warning(f);
+            // Messages keep accumulating in the tabs while the warning is shown and are all
+            // rendered again once the terminal is large enough.
+            if size.width < config.min_width || size.height < config.min_height {
+                return Self::render_size_warning(f, config);
             }
 
-            let layout = Layout::default()
-                .direction(Direction::Vertical)
-                .margin(1)
+            // Narrow terminals (e.g. phones) get a compact layout without borders and margins.
+            let narrow = size.width < NARROW_WIDTH;
+            let layout = if narrow {
+                Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints(
+                        [
+                            Constraint::Length(1),
+                            Constraint::Min(0),
+                            Constraint::Length(1),
+                        ]
+                        .as_ref(),
+                    )
+                    .split(size)
+            } else {
+                Layout::default()
+                    .direction(Direction::Vertical)
+                    .margin(1)
+                    .constraints(
+                        [
+                            Constraint::Percentage(5),
+                            Constraint::Percentage(85),
+                            Constraint::Percentage(10),
+                        ]
+                        .as_ref(),
+                    )
+                    .split(size)
+            };
+
+            // Render the connection health on the right side of the tab bar
+            let latency = Self::latency_indicator(latency);
+            let top = Layout::default()
+                .direction(Direction::Horizontal)
                 .constraints(
                     [
-                        Constraint::Percentage(5),
-                        Constraint::Percentage(85),
-                        Constraint::Percentage(10),
+                        Constraint::Min(0),
+                        Constraint::Length(latency.width() as u16 + 1),
                     ]
                     .as_ref(),
                 )
-                .split(size);
+                .split(layout[0]);
+            f.render_widget(Paragraph::new(latency).alignment(Alignment::Right), top[1]);
 
-            // Render tabs
-            f.render_widget(tabs, layout[0]);
+            // Render tabs, or only the selected one if there is not enough space
+            if narrow {
+                let indicator = Paragraph::new(Span::styled(
+                    tabs_indicator,
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::UNDERLINED),
+                ));
+                f.render_widget(indicator, top[0]);
+            } else {
+                f.render_widget(tabs, top[0]);
+            }
 
-            // Render selected tab content
-            match selected_tab.get_type() {
+            // Render selected tab content, without copying the history
+            match &selected_tab.tab_type {
                 TabType::Info(content) => {
                     // Render selected tab content
-                    Self::render_info(f, content, layout[1]);
+                    Self::render_info(f, content.clone(), narrow, layout[1]);
                     // Render input box
-                    Self::render_input(f, selected_tab.get_input(), layout[2])
+                    Self::render_input(f, &selected_tab, status.clone(), narrow, layout[2])
                 }
-                TabType::Channel(content) => {
-                    // Render channel
-                    Self::render_channel(f, content, layout[1]);
+                TabType::Channel(content) | TabType::Aggregate(content) => {
+                    if let Some(copy_text) = &copy_text {
+                        // Reserve enough empty lines below the channel for the unwrapped text.
+                        let lines = (copy_text.chars().count() as u16 / size.width + 1)
+                            .min(layout[1].height / 2);
+                        let split = Layout::default()
+                            .direction(Direction::Vertical)
+                            .constraints([Constraint::Min(0), Constraint::Length(lines)].as_ref())
+                            .split(layout[1]);
+                        let selection = selected_tab.get_selection();
+                        line_cache.update(content, narrow, formatting);
+                        Self::render_channel(f, line_cache, selection, narrow, split[0]);
+                        copy_area = Some(split[1]);
+                    } else {
+                        // Render channel
+                        line_cache.update(content, narrow, formatting);
+                        Self::render_channel(f, line_cache, None, narrow, layout[1]);
+                    }
                     // Render input box
-                    Self::render_input(f, selected_tab.get_input(), layout[2])
+                    Self::render_input(f, &selected_tab, status.clone(), narrow, layout[2])
                 }
             }
         });
+        // Write the selected message without any wrapping, so that the terminal's native
+        // selection can be used to copy it.
+        if let (Some(copy_text), Some(area)) = (copy_text, copy_area) {
+            let _ = self
+                .terminal
+                .backend_mut()
+                .write_raw(area.x, area.y, &copy_text);
+        }
+    }
+
+    /// The writer sent a frame, draw the latest state if a render was put off.
+    pub fn frame_written(&mut self) {
+        self.terminal.backend_mut().frame_written();
+        if self.dirty && !self.frame_scheduled {
+            self.render();
+        }
+    }
+
+    /// The frame budget is over, draw the latest state if a render was put off.
+    pub fn render_due(&mut self) {
+        self.frame_scheduled = false;
+        if self.dirty {
+            self.render();
+        }
+    }
+
+    /// Ring the terminal bell.
+    pub fn bell(&mut self) {
+        let _ = self.terminal.backend_mut().bell();
+    }
+
+    /// Leave the alternate screen and reset all terminal modes.
+    pub fn restore(&mut self) {
+        let _ = self.terminal.backend_mut().restore();
+    }
+
+    pub fn set_locale(&mut self, locale: Locale) {
+        self.config.locale = locale;
     }
 
-    fn render_size_warning(frame: &mut Frame<TelnetBackend>) {
+    pub fn set_formatting(&mut self, formatting: bool) {
+        self.formatting = formatting;
+    }
+
+    /// Redraw the whole screen after the terminal window was resized.
+    pub fn resize(&mut self) {
+        let _ = self.terminal.clear();
+        self.render();
+    }
+
+    fn render_size_warning(frame: &mut Frame<TelnetBackend>, config: UiConfig) {
         let layout = Layout::default()
             .direction(Direction::Vertical)
             .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
             .split(frame.size());
-        let warning = Paragraph::new("Please resize your terminal window to at least: 80x24")
-            .alignment(Alignment::Center)
-            .wrap(Wrap { trim: true });
+        let warning = Paragraph::new(format!(
+            "{}: {}x{}",
+            i18n::text(config.locale, Text::ResizeWarning),
+            config.min_width,
+            config.min_height
+        ))
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true });
         frame.render_widget(warning, layout[1]);
     }
 
-    fn render_info(frame: &mut Frame<TelnetBackend>, content: String, area: Rect) {
+    fn render_info(frame: &mut Frame<TelnetBackend>, content: String, narrow: bool, area: Rect) {
         let welcome = Paragraph::new(content)
-            .block(Block::default().borders(Borders::ALL))
+            .block(Self::block(narrow))
             .wrap(Wrap { trim: false });
         frame.render_widget(welcome, area);
     }
 
     fn render_channel(
         frame: &mut Frame<TelnetBackend>,
-        content: Vec<(String, String, String)>,
+        content: &LineCache,
+        selection: Option<usize>,
+        narrow: bool,
         area: Rect,
     ) {
-        let mut lines = Vec::with_capacity(content.len());
+        // Only messages that can be visible are rendered, each of them takes at least one line.
+        let start = content.lines.len().saturating_sub(area.height as usize);
+        let start = selection.map_or(start, |selection| start.min(selection));
+        let mut lines = Vec::with_capacity(content.lines.len() - start);
+        let borders = if narrow { 0 } else { 2 };
         // +2 to calculate boarders
-        let mut vertical_space_used = 2;
-        for line in content {
-            let spans = Spans::from(vec![
-                Span::styled(line.0, Style::default().fg(Color::Yellow)),
-                Span::styled(line.1, Style::default().add_modifier(Modifier::BOLD)),
-                Span::styled(": ", Style::default().add_modifier(Modifier::BOLD)),
-                Span::raw(line.2),
-            ]);
-            let width = spans.width();
+        let mut vertical_space_used = borders;
+        // Vertical offset of the selected message in copy mode
+        let mut selection_offset = None;
+        for (index, (spans, width)) in content.lines.iter().enumerate().skip(start) {
+            let mut spans = spans.clone();
+            if selection == Some(index) {
+                spans.0.iter_mut().for_each(|span| {
+                    span.style = span.style.add_modifier(Modifier::REVERSED);
+                });
+                selection_offset = Some(vertical_space_used - borders);
+            }
+            let width = *width;
             lines.push(spans);
             // -2 for boarders, -1 to only add if overflown
-            vertical_space_used += (width as i16 / (area.width - 3) as i16) + 1;
+            vertical_space_used += (width as i16 / (area.width - borders as u16 - 1) as i16) + 1;
         }
         // Calculate scroll
         let scroll = vertical_space_used - area.height as i16 + 1; // 1 line as buffer
         let scroll = if scroll < 0 { 0 } else { scroll };
+        // Keep the selected message visible
+        let scroll = match selection_offset {
+            Some(offset) if offset < scroll => offset,
+            _ => scroll,
+        };
 
         let chat = Paragraph::new(lines)
-            .block(Block::default().borders(Borders::ALL))
+            .block(Self::block(narrow))
             .scroll((scroll as u16, 0))
             .wrap(Wrap { trim: true });
         frame.render_widget(chat, area);
     }
 
-    fn render_input(frame: &mut Frame<TelnetBackend>, content: String, area: Rect) {
+    fn render_input(
+        frame: &mut Frame<TelnetBackend>,
+        tab: &Tab,
+        status: Option<String>,
+        narrow: bool,
+        area: Rect,
+    ) {
         let arrow_style = Style::default().add_modifier(Modifier::ITALIC);
         let arrow = Span::styled("> ", arrow_style);
 
-        let content = Span::raw(content);
+        let content = tab.get_input();
+        let (before, after) = content.split_at(tab.cursor_byte_index());
+        let mut after = after.chars();
 
-        let cursor_style = Style::default().add_modifier(Modifier::RAPID_BLINK);
-        let cursor = Span::styled(bar::FULL, cursor_style);
+        // At the end of the line draw a block, otherwise highlight the character under the cursor.
+        let cursor = match after.next() {
+            Some(ch) => Span::styled(
+                ch.to_string(),
+                Style::default().add_modifier(Modifier::REVERSED),
+            ),
+            None => Span::styled(
+                bar::FULL,
+                Style::default().add_modifier(Modifier::RAPID_BLINK),
+            ),
+        };
 
-        let input = Spans::from(vec![arrow, content, cursor]);
-        let welcome = Paragraph::new(input)
-            .block(Block::default().borders(Borders::ALL))
-            .wrap(Wrap { trim: true });
+        let input = Spans::from(vec![
+            arrow,
+            Span::raw(before.to_string()),
+            cursor,
+            Span::raw(after.as_str().to_string()),
+        ]);
+        let mut block = Self::block(narrow);
+        if let Some(status) = status {
+            block = block.title(Span::styled(status, Style::default().fg(Color::Red)));
+        }
+        let welcome = Paragraph::new(input).block(block).wrap(Wrap { trim: true });
         frame.render_widget(welcome, area);
     }
+
+    // Shows the round-trip latency to the coordinator, colored by how healthy the connection is.
+    fn latency_indicator(latency: Option<Duration>) -> Span<'static> {
+        match latency {
+            Some(latency) => {
+                let millis = latency.as_millis();
+                let color = match millis {
+                    0..=99 => Color::Green,
+                    100..=499 => Color::Yellow,
+                    _ => Color::Red,
+                };
+                Span::styled(format!("● {}ms", millis), Style::default().fg(color))
+            }
+            None => Span::styled("● --", Style::default().fg(Color::DarkGray)),
+        }
+    }
+
+    // Content is surrounded by borders, except on narrow screens.
+    fn block(narrow: bool) -> Block<'static> {
+        if narrow {
+            Block::default()
+        } else {
+            Block::default().borders(Borders::ALL)
+        }
+    }
+}
+
+// Turns a "[12:30 UTC] " timestamp into "12:30 ".
+fn short_timestamp(timestamp: &str) -> String {
+    let time = timestamp
+        .trim_start_matches('[')
+        .split(|ch| ch == ' ' || ch == ']')
+        .next()
+        .unwrap_or_default();
+    format!("{} ", time)
 }
 
 #[derive(Clone)]
@@ -155,6 +501,20 @@ pub struct UiTabs {
 struct UiTabsInner {
     tabs: Vec<Tab>,
     selected: usize,
+    // A notice shown above the input box, e.g. a failed command.
+    status: Option<String>,
+    // Last measured round-trip latency to the coordinator.
+    latency: Option<Duration>,
+}
+
+impl UiTabsInner {
+    // Select a tab, its messages count as read.
+    fn select(&mut self, index: usize) {
+        self.selected = index;
+        if let Some(tab) = self.tabs.get_mut(index) {
+            tab.unread = 0;
+        }
+    }
 }
 
 impl UiTabs {
@@ -162,6 +522,8 @@ impl UiTabs {
         let inner = UiTabsInner {
             tabs: vec![tab],
             selected: 0,
+            status: None,
+            latency: None,
         };
         Self {
             inner: Rc::new(RefCell::new(inner)),
@@ -173,7 +535,7 @@ impl UiTabs {
         let tabs = immutable
             .tabs
             .iter()
-            .map(|tab| Spans::from(tab.get_name()))
+            .map(|tab| Spans::from(tab.get_title()))
             .collect();
         Tabs::new(tabs)
             .style(Style::default().fg(Color::White))
@@ -186,16 +548,61 @@ impl UiTabs {
             .select(immutable.selected)
     }
 
+    /// Returns a single line description of the selected tab, e.g. "[2/3] #lunatic".
+    pub fn indicator(&self) -> String {
+        let immutable = self.inner.as_ref().borrow();
+        let selected = immutable.tabs.get(immutable.selected).unwrap();
+        format!(
+            "[{}/{}] {}",
+            immutable.selected + 1,
+            immutable.tabs.len(),
+            selected.get_title()
+        )
+    }
+
+    pub fn set_status(&self, status: String) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        mutable.status = Some(status);
+    }
+
+    pub fn clear_status(&self) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        mutable.status = None;
+    }
+
+    pub fn get_status(&self) -> Option<String> {
+        let immutable = self.inner.as_ref().borrow();
+        immutable.status.clone()
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        mutable.latency = Some(latency);
+    }
+
+    pub fn get_latency(&self) -> Option<Duration> {
+        let immutable = self.inner.as_ref().borrow();
+        immutable.latency
+    }
+
     pub fn add(&self, tab: Tab) {
         let mut mutable = self.inner.as_ref().borrow_mut();
         mutable.tabs.push(tab);
-        mutable.selected = mutable.tabs.len() - 1;
+        let last = mutable.tabs.len() - 1;
+        mutable.select(last);
     }
 
     pub fn switch(&self, name: &str) {
         if let Some(index) = self.names().iter().position(|n| n == name) {
             let mut mutable = self.inner.as_ref().borrow_mut();
-            mutable.selected = index;
+            mutable.select(index);
+        }
+    }
+
+    pub fn select(&self, index: usize) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        if index < mutable.tabs.len() {
+            mutable.select(index);
         }
     }
 
@@ -215,32 +622,100 @@ impl UiTabs {
         }
         let index = mutable.selected;
         mutable.tabs.remove(index);
-        if index != 0 {
-            mutable.selected -= 1;
+        mutable.select(index.saturating_sub(1));
+    }
+
+    /// Add a message to the channel's tab and the aggregate tab.
+    ///
+    /// If `unread` is set and the channel's tab is not selected, its unread counter goes up.
+    pub fn add_message(
+        &self,
+        channel: String,
+        id: u64,
+        timestamp: String,
+        user: String,
+        message: String,
+        unread: bool,
+    ) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        let selected = mutable.selected;
+        for (index, tab) in mutable.tabs.iter_mut().enumerate() {
+            match tab.tab_type {
+                TabType::Channel(_) if tab.name == channel => {
+                    tab.push_message(Some(id), (timestamp.clone(), user.clone(), message.clone()));
+                    if unread && index != selected {
+                        tab.unread += 1;
+                    }
+                }
+                // Prefix messages with the channel name in the aggregate tab
+                TabType::Aggregate(_) => {
+                    let user = format!("{} {}", channel, user);
+                    tab.push_message(None, (timestamp.clone(), user, message.clone()));
+                }
+                _ => {}
+            }
         }
     }
 
-    pub fn add_message(&self, channel: String, timestamp: String, user: String, message: String) {
+    /// Add a line to a channel tab that is only visible to this client.
+    pub fn add_system_message(&self, channel: String, line: Line) {
         let mut mutable = self.inner.as_ref().borrow_mut();
-        let tab = mutable
+        if let Some(tab) = mutable.tabs.iter_mut().find(|tab| tab.name == channel) {
+            tab.push_message(None, line);
+        }
+    }
+
+    /// Returns the messages of all channel tabs merged by time, prefixed with the channel name.
+    pub fn aggregate(&self) -> History {
+        let immutable = self.inner.as_ref().borrow();
+        let mut messages: Vec<Line> = immutable
             .tabs
-            .iter_mut()
-            .find(|tab| tab.name == channel)
-            .unwrap();
-        match &mut tab.tab_type {
-            TabType::Channel(content) => {
-                content.push((timestamp, user, message));
-                if content.len() > 100 {
-                    content.drain(0..50);
-                }
+            .iter()
+            .flat_map(|tab| match &tab.tab_type {
+                TabType::Channel(content) => content
+                    .iter()
+                    .map(|(timestamp, user, message)| {
+                        let user = format!("{} {}", tab.name, user);
+                        (timestamp.clone(), user, message.clone())
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            })
+            .collect();
+        // Stable sort, messages with the same timestamp keep their order
+        messages.sort_by(|a, b| a.0.cmp(&b.0));
+        History::new(messages)
+    }
+
+    /// Replace the content of an info tab, e.g. when a fresher channel list arrives.
+    pub fn set_info(&self, name: &str, content: String) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        if let Some(tab) = mutable.tabs.iter_mut().find(|tab| tab.name == name) {
+            if let TabType::Info(current) = &mut tab.tab_type {
+                *current = content;
             }
-            _ => unimplemented!(),
+        }
+    }
+
+    /// Append a line to the content of an info tab, keeping only the last `max_lines` lines.
+    pub fn append_info(&self, name: &str, line: String, max_lines: usize) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        let tab = match mutable.tabs.iter_mut().find(|tab| tab.name == name) {
+            Some(tab) => tab,
+            None => return,
+        };
+        if let TabType::Info(content) = &mut tab.tab_type {
+            let mut lines: Vec<&str> = content.lines().collect();
+            lines.push(&line);
+            let skip = lines.len().saturating_sub(max_lines);
+            *content = lines[skip..].join("\n");
         }
     }
 
     pub fn next(&self) {
         let mut mutable = self.inner.as_ref().borrow_mut();
-        mutable.selected = (mutable.selected + 1) % mutable.tabs.len();
+        let next = (mutable.selected + 1) % mutable.tabs.len();
+        mutable.select(next);
     }
 
     pub fn get_selected(&self) -> Tab {
@@ -263,6 +738,20 @@ impl UiTabs {
         selected.input_add_char(ch);
     }
 
+    pub fn input_del_word(&mut self) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        let index = mutable.selected;
+        let selected = mutable.tabs.get_mut(index).unwrap();
+        selected.input_del_word();
+    }
+
+    pub fn input_move_cursor(&mut self, movement: CursorMove) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        let index = mutable.selected;
+        let selected = mutable.tabs.get_mut(index).unwrap();
+        selected.input_move_cursor(movement);
+    }
+
     pub fn clear(&mut self) -> String {
         let mut mutable = self.inner.as_ref().borrow_mut();
         let index = mutable.selected;
@@ -270,6 +759,76 @@ impl UiTabs {
         selected.clear()
     }
 
+    pub fn toggle_copy_mode(&mut self) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        let index = mutable.selected;
+        let selected = mutable.tabs.get_mut(index).unwrap();
+        selected.toggle_copy_mode();
+    }
+
+    pub fn move_selection(&mut self, up: bool) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        let index = mutable.selected;
+        let selected = mutable.tabs.get_mut(index).unwrap();
+        selected.move_selection(up);
+    }
+
+    /// Select the channel message with the id in copy mode, which scrolls it into view.
+    ///
+    /// Returns `false` if the message is not loaded in the selected tab.
+    pub fn select_message(&self, id: u64) -> bool {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        let index = mutable.selected;
+        let selected = mutable.tabs.get_mut(index).unwrap();
+        selected.select_message(id)
+    }
+
+    /// Insert a page of older channel history into the selected tab.
+    pub fn prepend_history(&self, page: Vec<(u64, Line)>) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        let index = mutable.selected;
+        let selected = mutable.tabs.get_mut(index).unwrap();
+        selected.prepend_history(page);
+    }
+
+    /// Mark the beginning of the visible history in the selected tab, see
+    /// `History::truncate_older`.
+    pub fn truncate_older(&self, line: Line) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        let index = mutable.selected;
+        let selected = mutable.tabs.get_mut(index).unwrap();
+        selected.truncate_older(line);
+    }
+
+    /// Returns the channel process the tab with the name is bound to.
+    pub fn get_channel(&self, name: &str) -> Option<ProcessRef<ChannelProcess>> {
+        let immutable = self.inner.as_ref().borrow();
+        immutable
+            .tabs
+            .iter()
+            .find(|tab| tab.name == name)
+            .and_then(|tab| tab.notifier)
+    }
+
+    /// Bind the tab to a different channel process.
+    pub fn set_channel(&self, name: &str, channel: ProcessRef<ChannelProcess>) {
+        let mut mutable = self.inner.as_ref().borrow_mut();
+        if let Some(tab) = mutable.tabs.iter_mut().find(|tab| tab.name == name) {
+            tab.notifier = Some(channel);
+        }
+    }
+
+    /// Returns the name and unsent input of all tabs with a draft.
+    pub fn drafts(&self) -> Vec<(String, String)> {
+        let immutable = self.inner.as_ref().borrow();
+        immutable
+            .tabs
+            .iter()
+            .filter(|tab| !tab.input.is_empty())
+            .map(|tab| (tab.name.clone(), tab.input.clone()))
+            .collect()
+    }
+
     pub fn names(&self) -> Vec<String> {
         let immutable = self.inner.as_ref().borrow();
         immutable.tabs.iter().map(|tab| tab.name.clone()).collect()
@@ -282,6 +841,12 @@ pub struct Tab {
     tab_type: TabType,
     notifier: Option<ProcessRef<ChannelProcess>>,
     input: String,
+    // Position of the cursor inside `input`, counted in characters.
+    cursor: usize,
+    // Index of the selected message if the tab is in copy mode.
+    selection: Option<usize>,
+    // Messages received while another tab was selected.
+    unread: usize,
 }
 
 impl Tab {
@@ -295,6 +860,9 @@ impl Tab {
             tab_type,
             notifier,
             input: String::new(),
+            cursor: 0,
+            selection: None,
+            unread: 0,
         }
     }
 
@@ -306,31 +874,212 @@ impl Tab {
         self.name.clone()
     }
 
+    /// The name shown in the tabs bar with the number of unread messages, marked with a pencil if
+    /// there is an unsent draft.
+    pub fn get_title(&self) -> String {
+        let mut title = self.name.clone();
+        if self.unread > 0 {
+            title.push_str(&format!(" ({})", self.unread));
+        }
+        if !self.input.is_empty() {
+            title.push_str(" ✎");
+        }
+        title
+    }
+
+    /// Returns the channel this tab is bound to.
+    pub fn get_channel(&self) -> Option<ProcessRef<ChannelProcess>> {
+        self.notifier
+    }
+
     pub fn get_input(&self) -> String {
         self.input.clone()
     }
 
     pub fn clear(&mut self) -> String {
+        self.cursor = 0;
         std::mem::take(&mut self.input)
     }
 
+    /// Returns the byte offset of the cursor inside the input.
+    pub fn cursor_byte_index(&self) -> usize {
+        self.input
+            .char_indices()
+            .nth(self.cursor)
+            .map(|(index, _)| index)
+            .unwrap_or(self.input.len())
+    }
+
     pub fn input_del_char(&mut self) {
-        self.input.pop();
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            let index = self.cursor_byte_index();
+            self.input.remove(index);
+        }
     }
 
     pub fn input_add_char(&mut self, ch: char) {
-        self.input.push(ch);
+        let index = self.cursor_byte_index();
+        self.input.insert(index, ch);
+        self.cursor += 1;
     }
 
-    pub fn message(&self, timestamp: String, user: String, message: String) {
+    /// Delete the word in front of the cursor, including trailing spaces.
+    pub fn input_del_word(&mut self) {
+        let end = self.cursor_byte_index();
+        let start = self.input[..end]
+            .trim_end_matches(' ')
+            .rfind(' ')
+            .map(|index| index + 1)
+            .unwrap_or(0);
+        self.cursor -= self.input[start..end].chars().count();
+        self.input.replace_range(start..end, "");
+    }
+
+    pub fn input_move_cursor(&mut self, movement: CursorMove) {
+        let len = self.input.chars().count();
+        self.cursor = match movement {
+            CursorMove::Left => self.cursor.saturating_sub(1),
+            CursorMove::Right => (self.cursor + 1).min(len),
+            CursorMove::Start => 0,
+            CursorMove::End => len,
+        };
+    }
+
+    pub fn get_selection(&self) -> Option<usize> {
+        self.selection
+    }
+
+    /// Returns the id before which older messages can be fetched from the channel, if there are
+    /// any.
+    pub fn history_before(&self) -> Option<u64> {
+        match &self.tab_type {
+            TabType::Channel(content) => content.older_than(),
+            _ => None,
+        }
+    }
+
+    /// Insert a page of older channel history above the loaded messages.
+    ///
+    /// The copy mode selection keeps pointing to the same message.
+    pub fn prepend_history(&mut self, page: Vec<(u64, Line)>) {
+        if let TabType::Channel(content) = &mut self.tab_type {
+            let count = content.prepend(page);
+            if let Some(selection) = self.selection.as_mut() {
+                *selection += count;
+            }
+        }
+    }
+
+    /// Mark the beginning of the visible channel history with a line above the messages.
+    ///
+    /// The copy mode selection keeps pointing to the same message.
+    pub fn truncate_older(&mut self, line: Line) {
+        if let TabType::Channel(content) = &mut self.tab_type {
+            content.truncate_older(line);
+            if let Some(selection) = self.selection.as_mut() {
+                *selection += 1;
+            }
+        }
+    }
+
+    /// Returns the channel id of the message selected in copy mode.
+    pub fn get_selection_id(&self) -> Option<u64> {
+        match (&self.tab_type, self.selection) {
+            (TabType::Channel(content), Some(index)) => content.id(index),
+            _ => None,
+        }
+    }
+
+    fn select_message(&mut self, id: u64) -> bool {
+        if let TabType::Channel(content) = &self.tab_type {
+            if let Some(index) = content.position(id) {
+                self.selection = Some(index);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns the whole message selected in copy mode.
+    pub fn get_selection_text(&self) -> Option<String> {
+        match (&self.tab_type, self.selection) {
+            (TabType::Channel(content) | TabType::Aggregate(content), Some(index)) => content
+                .get(index)
+                .map(|(timestamp, user, message)| format!("{}{}: {}", timestamp, user, message)),
+            _ => None,
+        }
+    }
+
+    /// Enter copy mode by selecting the last message, or leave it.
+    ///
+    /// Only tabs bound to a channel with messages can enter copy mode.
+    pub fn toggle_copy_mode(&mut self) {
+        self.selection = match (&self.tab_type, self.selection) {
+            (TabType::Channel(content) | TabType::Aggregate(content), None)
+                if !content.is_empty() =>
+            {
+                Some(content.len() - 1)
+            }
+            _ => None,
+        };
+    }
+
+    pub fn move_selection(&mut self, up: bool) {
+        if let (TabType::Channel(content) | TabType::Aggregate(content), Some(selection)) =
+            (&self.tab_type, self.selection)
+        {
+            self.selection = if up {
+                Some(selection.saturating_sub(1))
+            } else {
+                Some((selection + 1).min(content.len() - 1))
+            };
+        }
+    }
+
+    // Add a message to a channel or aggregate tab, evicting old ones if they use too much memory.
+    fn push_message(&mut self, id: Option<u64>, line: Line) {
+        if let TabType::Channel(content) | TabType::Aggregate(content) = &mut self.tab_type {
+            let evicted = content.push(id, line);
+            // Keep the copy mode selection pointing to the same message
+            if let Some(selection) = self.selection.as_mut() {
+                *selection = selection.saturating_sub(evicted);
+            }
+        }
+    }
+
+    pub fn message(
+        &self,
+        timestamp: String,
+        user: String,
+        sender: SessionRef,
+        message: String,
+        trace: Option<String>,
+    ) {
         if let Some(notifier) = &self.notifier {
-            notifier.broadcast_message(self.name.clone(), timestamp, user, message);
+            notifier.broadcast_message(
+                self.name.clone(),
+                timestamp,
+                user,
+                Some(sender),
+                message,
+                trace,
+            );
         }
     }
 }
 
+pub enum CursorMove {
+    Left,
+    Right,
+    Start,
+    End,
+}
+
 #[derive(Clone)]
 pub enum TabType {
     Info(String),
-    Channel(Vec<(String, String, String)>),
+    Channel(History),
+    /// Messages of all joined channels, prefixed with the channel name.
+    Aggregate(History),
 }
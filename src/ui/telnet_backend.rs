@@ -1,11 +1,23 @@
 use super::termion;
+use crate::telnet::TelnetWriter;
+use crate::telnet_writer::{
+    TelnetWriterProcess, TelnetWriterProcessMessages, TelnetWriterProcessRequests,
+};
 use std::{cell::RefCell, fmt, io::Write, rc::Rc};
 
-use lunatic::net::TcpStream;
+use lunatic::ap::ProcessRef;
+use numtoa::NumToA;
 use tui::{
     backend::Backend,
     style::{Color, Modifier},
 };
+use unicode_width::UnicodeWidthStr;
+
+/// Largest window size taken from a client. Some proxies report sizes like 2000x2000, which would
+/// only make the UI allocate giant buffers.
+pub const MAX_WINDOW_SIZE: (u16, u16) = (1000, 500);
+/// Used instead of a window size of 0 or above `MAX_WINDOW_SIZE`.
+pub const FALLBACK_WINDOW_SIZE: (u16, u16) = (80, 24);
 
 #[derive(Clone)]
 pub struct WindowSize {
@@ -19,7 +31,18 @@ impl WindowSize {
         }
     }
 
+    /// Returns `true` if a terminal can plausibly have this size.
+    pub fn is_valid(width: u16, height: u16) -> bool {
+        (1..=MAX_WINDOW_SIZE.0).contains(&width) && (1..=MAX_WINDOW_SIZE.1).contains(&height)
+    }
+
+    /// Sets the size, or `FALLBACK_WINDOW_SIZE` if it isn't valid.
     pub fn set(&self, width: u16, height: u16) {
+        let (width, height) = if Self::is_valid(width, height) {
+            (width, height)
+        } else {
+            FALLBACK_WINDOW_SIZE
+        };
         let mut size = self.inner.as_ref().borrow_mut();
         size.0 = width;
         size.1 = height;
@@ -32,19 +55,88 @@ impl WindowSize {
 }
 
 pub struct TelnetBackend {
-    tcp_stream: TcpStream,
+    // Everything written to the terminal is escaped for telnet and buffered. It's only sent to the
+    // writer when flushed, so that a frame doesn't get split into many small packets.
+    frame: TelnetWriter<Vec<u8>>,
+    writer: ProcessRef<TelnetWriterProcess>,
+    // Frames sent to the writer that it didn't acknowledge yet.
+    frames_in_flight: usize,
     window_size: WindowSize,
+    // Reused between frames, so that drawing doesn't allocate once it's large enough.
+    buffer: Vec<u8>,
 }
 
 impl TelnetBackend {
-    pub fn new(mut tcp_stream: TcpStream, window_size: WindowSize) -> Self {
-        // Start at top left always
-        write!(tcp_stream, "\u{001B}[{};{}H", 0, 0).unwrap();
-        Self {
-            tcp_stream,
+    pub fn new(writer: ProcessRef<TelnetWriterProcess>, window_size: WindowSize) -> Self {
+        let mut backend = Self {
+            frame: TelnetWriter(Vec::new()),
+            writer,
+            frames_in_flight: 0,
             window_size,
+            buffer: Vec::new(),
+        };
+        // Draw on the alternate screen, so that the user's terminal can be restored on exit.
+        write!(backend.frame, "{}", termion::screen::ToAlternateScreen).unwrap();
+        // Start at top left always
+        write!(backend.frame, "\u{001B}[{};{}H", 0, 0).unwrap();
+        backend.send();
+        backend
+    }
+
+    /// Returns how many frames the writer didn't send yet.
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
+    /// The writer sent a frame.
+    pub fn frame_written(&mut self) {
+        self.frames_in_flight = self.frames_in_flight.saturating_sub(1);
+    }
+
+    // Hand the buffered frame to the writer.
+    fn send(&mut self) {
+        let frame = std::mem::take(&mut self.frame.0);
+        if !frame.is_empty() {
+            self.frames_in_flight += 1;
+            self.writer.write(frame);
         }
     }
+
+    /// Send a BEL character, terminals will ring or flash.
+    pub fn bell(&mut self) -> Result<(), std::io::Error> {
+        self.frame.write_all(&[0x07])?;
+        self.send();
+        Ok(())
+    }
+
+    /// Reset the terminal to the state it was in before the client connected.
+    pub fn restore(&mut self) -> Result<(), std::io::Error> {
+        write!(
+            self.frame,
+            "{}{}{}{}{}{}",
+            Fg(Color::Reset),
+            Bg(Color::Reset),
+            termion::style::Reset,
+            termion::cursor::Show,
+            termion::screen::DisableMouse,
+            termion::screen::ToMainScreen,
+        )?;
+        // Written right away, the writer dies together with the exiting client
+        self.writer.write_now(std::mem::take(&mut self.frame.0));
+        Ok(())
+    }
+
+    /// Write text at the given position, bypassing tui's buffer and letting the terminal wrap it.
+    pub fn write_raw(&mut self, x: u16, y: u16, text: &str) -> Result<(), std::io::Error> {
+        write!(
+            self.frame,
+            "{}{}",
+            termion::cursor::Goto(x + 1, y + 1),
+            text
+        )?;
+        self.send();
+        Ok(())
+    }
 }
 
 impl Backend for TelnetBackend {
@@ -52,59 +144,18 @@ impl Backend for TelnetBackend {
     where
         I: Iterator<Item = (u16, u16, &'a tui::buffer::Cell)>,
     {
-        use std::fmt::Write;
-
-        let mut string = String::with_capacity(content.size_hint().0 * 3);
-        let mut fg = Color::Reset;
-        let mut bg = Color::Reset;
-        let mut modifier = Modifier::empty();
-        let mut last_pos: Option<(u16, u16)> = None;
-        for (x, y, cell) in content {
-            // Move the cursor if the previous location was not (x - 1, y)
-            if !matches!(last_pos, Some(p) if x == p.0 + 1 && y == p.1) {
-                write!(string, "{}", termion::cursor::Goto(x + 1, y + 1)).unwrap();
-            }
-            last_pos = Some((x, y));
-            if cell.modifier != modifier {
-                write!(
-                    string,
-                    "{}",
-                    ModifierDiff {
-                        from: modifier,
-                        to: cell.modifier
-                    }
-                )
-                .unwrap();
-                modifier = cell.modifier;
-            }
-            if cell.fg != fg {
-                write!(string, "{}", Fg(cell.fg)).unwrap();
-                fg = cell.fg;
-            }
-            if cell.bg != bg {
-                write!(string, "{}", Bg(cell.bg)).unwrap();
-                bg = cell.bg;
-            }
-            string.push_str(&cell.symbol);
-        }
-        write!(
-            self.tcp_stream,
-            "{}{}{}{}",
-            string,
-            Fg(Color::Reset),
-            Bg(Color::Reset),
-            termion::style::Reset,
-        )
+        self.buffer.clear();
+        draw_cells(&mut self.buffer, content);
+        // Sent together with the cursor updates once tui flushes at the end of the frame
+        self.frame.write_all(&self.buffer)
     }
 
     fn hide_cursor(&mut self) -> Result<(), std::io::Error> {
-        write!(self.tcp_stream, "{}", termion::cursor::Hide)?;
-        self.tcp_stream.flush()
+        write!(self.frame, "{}", termion::cursor::Hide)
     }
 
     fn show_cursor(&mut self) -> Result<(), std::io::Error> {
-        write!(self.tcp_stream, "{}", termion::cursor::Show)?;
-        self.tcp_stream.flush()
+        write!(self.frame, "{}", termion::cursor::Show)
     }
 
     fn get_cursor(&mut self) -> Result<(u16, u16), std::io::Error> {
@@ -113,14 +164,17 @@ impl Backend for TelnetBackend {
     }
 
     fn set_cursor(&mut self, x: u16, y: u16) -> Result<(), std::io::Error> {
-        write!(self.tcp_stream, "{}", termion::cursor::Goto(x + 1, y + 1))?;
-        self.tcp_stream.flush()
+        write!(self.frame, "{}", termion::cursor::Goto(x + 1, y + 1))
     }
 
+    // The UI always redraws after clearing, the flush happens at the end of the next frame.
     fn clear(&mut self) -> Result<(), std::io::Error> {
-        write!(self.tcp_stream, "{}", termion::clear::All)?;
-        write!(self.tcp_stream, "{}", termion::cursor::Goto(1, 1))?;
-        self.tcp_stream.flush()
+        write!(
+            self.frame,
+            "{}{}",
+            termion::clear::All,
+            termion::cursor::Goto(1, 1)
+        )
     }
 
     fn size(&self) -> Result<tui::layout::Rect, std::io::Error> {
@@ -129,8 +183,53 @@ impl Backend for TelnetBackend {
     }
 
     fn flush(&mut self) -> Result<(), std::io::Error> {
-        self.tcp_stream.flush()
+        self.send();
+        Ok(())
+    }
+}
+
+/// Writes the escape sequences that draw the changed cells of a frame to `buffer`.
+pub fn draw_cells<'a, I>(buffer: &mut Vec<u8>, content: I)
+where
+    I: Iterator<Item = (u16, u16, &'a tui::buffer::Cell)>,
+{
+    let mut fg = Color::Reset;
+    let mut bg = Color::Reset;
+    let mut modifier = Modifier::empty();
+    // Where the terminal's cursor is after the last written symbol
+    let mut cursor_pos: Option<(u16, u16)> = None;
+    for (x, y, cell) in content {
+        // Skip continuation cells that follow a double-width symbol
+        if cell.symbol.is_empty() {
+            continue;
+        }
+        // Move the cursor if it's not already at (x, y)
+        if cursor_pos != Some((x, y)) {
+            push_goto(buffer, x + 1, y + 1);
+        }
+        // Wide symbols advance the cursor by 2 columns and zero-width ones don't move it
+        cursor_pos = Some((x + cell.symbol.width() as u16, y));
+        if cell.modifier != modifier {
+            ModifierDiff {
+                from: modifier,
+                to: cell.modifier,
+            }
+            .push_to(buffer);
+            modifier = cell.modifier;
+        }
+        // Writing to a `Vec` can't fail
+        if cell.fg != fg {
+            write!(buffer, "{}", Fg(cell.fg)).unwrap();
+            fg = cell.fg;
+        }
+        if cell.bg != bg {
+            write!(buffer, "{}", Bg(cell.bg)).unwrap();
+            bg = cell.bg;
+        }
+        buffer.extend_from_slice(cell.symbol.as_bytes());
     }
+    write!(buffer, "{}{}", Fg(Color::Reset), Bg(Color::Reset)).unwrap();
+    push(buffer, termion::style::Reset);
 }
 
 struct Fg(Color);
@@ -195,67 +294,81 @@ impl fmt::Display for Bg {
     }
 }
 
-impl fmt::Display for ModifierDiff {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl ModifierDiff {
+    // Write the escape sequences that change the `from` modifiers into the `to` ones.
+    fn push_to(&self, buffer: &mut Vec<u8>) {
         let remove = self.from - self.to;
         if remove.contains(Modifier::REVERSED) {
-            write!(f, "{}", termion::style::NoInvert)?;
+            push(buffer, termion::style::NoInvert);
         }
         if remove.contains(Modifier::BOLD) {
             // XXX: the termion NoBold flag actually enables double-underline on ECMA-48 compliant
             // terminals, and NoFaint additionally disables bold... so we use this trick to get
             // the right semantics.
-            write!(f, "{}", termion::style::NoFaint)?;
+            push(buffer, termion::style::NoFaint);
 
             if self.to.contains(Modifier::DIM) {
-                write!(f, "{}", termion::style::Faint)?;
+                push(buffer, termion::style::Faint);
             }
         }
         if remove.contains(Modifier::ITALIC) {
-            write!(f, "{}", termion::style::NoItalic)?;
+            push(buffer, termion::style::NoItalic);
         }
         if remove.contains(Modifier::UNDERLINED) {
-            write!(f, "{}", termion::style::NoUnderline)?;
+            push(buffer, termion::style::NoUnderline);
         }
         if remove.contains(Modifier::DIM) {
-            write!(f, "{}", termion::style::NoFaint)?;
+            push(buffer, termion::style::NoFaint);
 
             // XXX: the NoFaint flag additionally disables bold as well, so we need to re-enable it
             // here if we want it.
             if self.to.contains(Modifier::BOLD) {
-                write!(f, "{}", termion::style::Bold)?;
+                push(buffer, termion::style::Bold);
             }
         }
         if remove.contains(Modifier::CROSSED_OUT) {
-            write!(f, "{}", termion::style::NoCrossedOut)?;
+            push(buffer, termion::style::NoCrossedOut);
         }
         if remove.contains(Modifier::SLOW_BLINK) || remove.contains(Modifier::RAPID_BLINK) {
-            write!(f, "{}", termion::style::NoBlink)?;
+            push(buffer, termion::style::NoBlink);
         }
 
         let add = self.to - self.from;
         if add.contains(Modifier::REVERSED) {
-            write!(f, "{}", termion::style::Invert)?;
+            push(buffer, termion::style::Invert);
         }
         if add.contains(Modifier::BOLD) {
-            write!(f, "{}", termion::style::Bold)?;
+            push(buffer, termion::style::Bold);
         }
         if add.contains(Modifier::ITALIC) {
-            write!(f, "{}", termion::style::Italic)?;
+            push(buffer, termion::style::Italic);
         }
         if add.contains(Modifier::UNDERLINED) {
-            write!(f, "{}", termion::style::Underline)?;
+            push(buffer, termion::style::Underline);
         }
         if add.contains(Modifier::DIM) {
-            write!(f, "{}", termion::style::Faint)?;
+            push(buffer, termion::style::Faint);
         }
         if add.contains(Modifier::CROSSED_OUT) {
-            write!(f, "{}", termion::style::CrossedOut)?;
+            push(buffer, termion::style::CrossedOut);
         }
         if add.contains(Modifier::SLOW_BLINK) || add.contains(Modifier::RAPID_BLINK) {
-            write!(f, "{}", termion::style::Blink)?;
+            push(buffer, termion::style::Blink);
         }
-
-        Ok(())
     }
 }
+
+// Append a pre-computed escape sequence.
+fn push(buffer: &mut Vec<u8>, sequence: impl AsRef<[u8]>) {
+    buffer.extend_from_slice(sequence.as_ref());
+}
+
+// Append a (1,1)-based cursor movement, same as `termion::cursor::Goto` but without formatting.
+fn push_goto(buffer: &mut Vec<u8>, x: u16, y: u16) {
+    let mut number = [0u8; 20];
+    buffer.extend_from_slice(b"\x1B[");
+    buffer.extend_from_slice(y.numtoa(10, &mut number));
+    buffer.push(b';');
+    buffer.extend_from_slice(x.numtoa(10, &mut number));
+    buffer.push(b'H');
+}
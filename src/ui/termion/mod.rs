@@ -6,4 +6,5 @@
 pub mod clear;
 pub mod color;
 pub mod cursor;
+pub mod screen;
 pub mod style;
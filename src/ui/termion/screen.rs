@@ -0,0 +1,37 @@
+use std::fmt;
+
+/// Create a CSI-introduced sequence.
+macro_rules! csi {
+    ($( $l:expr ),*) => { concat!("\x1B[", $( $l ),*) };
+}
+
+/// Derive a CSI sequence struct.
+macro_rules! derive_csi_sequence {
+    ($doc:expr, $name:ident, $value:expr) => {
+        #[doc = $doc]
+        #[derive(Copy, Clone)]
+        pub struct $name;
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, csi!($value))
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &'static [u8] { csi!($value).as_bytes() }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &'static str { csi!($value) }
+        }
+    };
+}
+
+derive_csi_sequence!("Switch to the alternate screen buffer.", ToAlternateScreen, "?1049h");
+derive_csi_sequence!("Switch back to the main screen buffer.", ToMainScreen, "?1049l");
+derive_csi_sequence!(
+    "Disable all mouse reporting modes.",
+    DisableMouse,
+    "?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l"
+);